@@ -0,0 +1,99 @@
+use axum::{
+    body::Body,
+    http::{Request, StatusCode},
+};
+use ipnet::IpNet;
+use std::net::IpAddr;
+
+use crate::config::AccessRule;
+use crate::geoip::GeoInfo;
+use crate::path::longest_prefix;
+use crate::proxy::{AppState, peer_ip};
+
+/// Client address for access checks.
+///
+/// This is the connected peer, unless the peer is one of `trusted_proxies`. In that case
+/// `X-Forwarded-For` is walked from the right, skipping entries that are themselves trusted,
+/// so a client can't pick its own address by sending the header.
+pub fn client_ip(req: &Request<Body>, trusted_proxies: &[IpNet]) -> Option<IpAddr> {
+    let is_trusted = |ip: &IpAddr| trusted_proxies.iter().any(|net| net.contains(ip));
+    let mut client = peer_ip(req)?.to_canonical();
+    if !is_trusted(&client) {
+        return Some(client);
+    }
+
+    let hops: Vec<&str> = req
+        .headers()
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .collect();
+    for hop in hops.iter().rev() {
+        // Stop at garbage rather than skipping it; the last good hop is still a trusted proxy.
+        let Ok(ip) = hop.trim().parse::<IpAddr>() else {
+            break;
+        };
+        client = ip.to_canonical();
+        if !is_trusted(&client) {
+            break;
+        }
+    }
+    Some(client)
+}
+
+impl AccessRule {
+    fn is_open(&self) -> bool {
+        self.allow.is_empty() && self.deny.is_empty()
+    }
+
+    /// Deny wins over allow; an empty allow list admits everyone not denied.
+    fn permits(&self, ip: IpAddr) -> bool {
+        !self.deny.iter().any(|net| net.contains(&ip))
+            && (self.allow.is_empty() || self.allow.iter().any(|net| net.contains(&ip)))
+    }
+}
+
+/// Apply the IP rule for `path`, the request's [`route_path`](crate::path::route_path): the
+/// longest matching `access_route` if any, otherwise the server-level `allow_ips`/`deny_ips`. A route rule replaces the server rule
+/// entirely rather than adding to it.
+///
+/// Requests whose client IP can't be determined are refused whenever a rule applies.
+pub fn check_access(state: &AppState, req: &Request<Body>, path: &str) -> Result<(), StatusCode> {
+    let rule = longest_prefix(&state.access_routes, path, |r| &r.path_prefix)
+        .map_or(&*state.access, |r| &r.rule);
+    if rule.is_open() {
+        return Ok(());
+    }
+
+    match client_ip(req, &state.trusted_proxies) {
+        Some(ip) if rule.permits(ip) => Ok(()),
+        ip => {
+            tracing::warn!("denying access to {} from {:?}", path, ip);
+            Err(if rule.conceal {
+                StatusCode::NOT_FOUND
+            } else {
+                StatusCode::FORBIDDEN
+            })
+        }
+    }
+}
+
+/// Refuse clients from a `deny_countries` entry. A client whose country is unknown (no lookup,
+/// or no database loaded) is let through.
+pub fn check_country(state: &AppState, req: &Request<Body>) -> Result<(), StatusCode> {
+    if state.deny_countries.is_empty() {
+        return Ok(());
+    }
+    let country = req
+        .extensions()
+        .get::<GeoInfo>()
+        .and_then(|g| g.country.as_deref());
+    match country {
+        Some(c) if state.deny_countries.iter().any(|d| d == c) => {
+            tracing::warn!("denying access to {} from country {}", req.uri().path(), c);
+            Err(StatusCode::FORBIDDEN)
+        }
+        _ => Ok(()),
+    }
+}
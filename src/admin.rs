@@ -0,0 +1,456 @@
+use axum::{
+    Json, Router,
+    extract::{Path, Query, State},
+    http::{HeaderMap, StatusCode, Uri},
+    routing::{delete, get, post},
+};
+use serde::de::IgnoredAny;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+use std::sync::atomic::Ordering;
+use std::time::Instant;
+
+use crate::backend_queue::BackendQueueStats;
+use crate::backend_stats::BackendStatus;
+use crate::ban::BanInfo;
+use crate::block::BlockRuleStats;
+use crate::cache_warm::{self, WarmStatus};
+use crate::config;
+use crate::conn_limit::ConnectionStats;
+use crate::drain::DrainStatus;
+use crate::experiment::ExperimentStats;
+use crate::head_limit::HeadRejectionStats;
+use crate::listener::BindFailure;
+use crate::memory::MemoryStats;
+use crate::proxy::AppState;
+use crate::supervisor::ServerTaskStatus;
+
+/// Query parameters for `GET /admin/cache/entry`.
+#[derive(Debug, Deserialize)]
+pub struct CacheEntryQuery {
+    /// The key as built by the proxy, e.g. `GET /a?b=1` (`GET example.com/a?b=1` with
+    /// `cache_key_include_host`; `path_decoding = "decoded"` normalizes the path's escapes).
+    pub key: String,
+}
+
+/// Cache entry metadata returned by the debug endpoint (the body is never included).
+#[derive(Debug, Serialize)]
+pub struct CacheEntryInfo {
+    pub key: String,
+    pub cached: bool,
+    pub expired: bool,
+    pub ttl_remaining_secs: Option<u64>,
+    pub size: Option<usize>,
+    pub status: Option<u16>,
+    pub vary: Option<String>,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+}
+
+// Compare without short-circuiting on the first mismatching byte.
+pub(crate) fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Verify the `Authorization: Bearer <token>` header against the configured admin token.
+pub fn check_admin_token(state: &AppState, headers: &HeaderMap) -> Result<(), StatusCode> {
+    let expected = match &state.admin_token {
+        Some(t) => t.expose(),
+        None => return Err(StatusCode::NOT_FOUND),
+    };
+
+    let provided = headers
+        .get("authorization")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .map(str::trim);
+
+    match provided {
+        Some(token) if constant_time_eq(token.as_bytes(), expected.as_bytes()) => Ok(()),
+        _ => {
+            tracing::warn!("rejected admin request with missing or invalid token");
+            Err(StatusCode::UNAUTHORIZED)
+        }
+    }
+}
+
+pub async fn cache_entry_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(query): Query<CacheEntryQuery>,
+) -> Result<Json<CacheEntryInfo>, StatusCode> {
+    check_admin_token(&state, &headers)?;
+
+    let mut info = CacheEntryInfo {
+        key: query.key,
+        cached: false,
+        expired: false,
+        ttl_remaining_secs: None,
+        size: None,
+        status: None,
+        vary: None,
+        etag: None,
+        last_modified: None,
+    };
+
+    let cache = match &state.response_cache {
+        Some(c) => c,
+        None => return Ok(Json(info)),
+    };
+
+    if let Some(entry) = cache.get(&info.key) {
+        let now = Instant::now();
+        info.expired = now >= entry.expires_at;
+        info.cached = !info.expired;
+        info.ttl_remaining_secs = Some(entry.expires_at.saturating_duration_since(now).as_secs());
+        info.size = Some(entry.size);
+        info.status = Some(entry.status);
+        info.vary = entry.header("vary").map(str::to_string);
+        info.etag = entry.header("etag").map(str::to_string);
+        info.last_modified = entry.header("last-modified").map(str::to_string);
+    }
+
+    Ok(Json(info))
+}
+
+/// `GET /admin/upstream/errors`: upstream failure counts by category.
+pub async fn upstream_errors_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<BTreeMap<&'static str, u64>>, StatusCode> {
+    check_admin_token(&state, &headers)?;
+    Ok(Json(state.upstream_errors.snapshot().into_iter().collect()))
+}
+
+/// `GET /admin/backends`: requests, errors (failed or 5xx) and in-flight requests per backend.
+pub async fn backends_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<Vec<BackendStatus>>, StatusCode> {
+    check_admin_token(&state, &headers)?;
+    Ok(Json(
+        state.backend_stats.snapshot(&state.backends.snapshot()),
+    ))
+}
+
+/// Body of `POST /admin/backends`.
+///
+/// `weight`, `tier` and `persist` are accepted but ignored, with a warning: backends are picked
+/// round robin without weights or tiers, and the config file is never rewritten.
+#[derive(Debug, Deserialize)]
+pub struct AddBackendRequest {
+    /// A backend URL, validated as in the config file.
+    pub url: String,
+    pub weight: Option<IgnoredAny>,
+    pub tier: Option<IgnoredAny>,
+    pub persist: Option<IgnoredAny>,
+}
+
+/// `POST /admin/backends`: add a backend to the default pool. It takes requests at once, in
+/// round-robin order after the existing ones. Answers 409 if it is already in the pool. The
+/// config file is not changed, so a restart brings back the configured list.
+pub async fn add_backend_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(body): Json<AddBackendRequest>,
+) -> Result<StatusCode, StatusCode> {
+    check_admin_token(&state, &headers)?;
+    let url = config::parse_backend_url(body.url.trim()).map_err(|e| {
+        tracing::warn!("refusing to add backend via admin endpoint: {}", e);
+        StatusCode::BAD_REQUEST
+    })?;
+    if config::is_fastcgi(&url) && state.fastcgi.is_none() {
+        tracing::warn!(
+            "refusing to add FastCGI backend {} to a server without [servers.proxy.fastcgi]",
+            url
+        );
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    // Counters and caps first, so the backend is never selectable without them.
+    state.backend_stats.register(&url);
+    if let Some(limiter) = &state.backend_limiter {
+        limiter.register(&url);
+    }
+    if !state.backends.add(url.clone()) {
+        return Err(StatusCode::CONFLICT);
+    }
+    if !url.path().ends_with('/') {
+        tracing::warn!(
+            "backend {} has a base path without a trailing slash; requests are forwarded below {}/",
+            url,
+            url.path()
+        );
+    }
+    tracing::info!("backend {} added via admin endpoint", url);
+    let ignored: Vec<&str> = [
+        ("weight", body.weight.is_some()),
+        ("tier", body.tier.is_some()),
+        ("persist", body.persist.is_some()),
+    ]
+    .into_iter()
+    .filter_map(|(name, given)| given.then_some(name))
+    .collect();
+    if !ignored.is_empty() {
+        tracing::warn!(
+            "ignoring {} for backend {}: not supported (round robin only, config file unchanged)",
+            ignored.join(", "),
+            url
+        );
+    }
+    Ok(StatusCode::CREATED)
+}
+
+/// `DELETE /admin/backends/{backend}`: remove a backend from the default pool, by index or
+/// (percent-encoded) URL. Requests already sent to it finish normally. Answers 404 if no
+/// backend matches.
+pub async fn remove_backend_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(backend): Path<String>,
+) -> Result<StatusCode, StatusCode> {
+    check_admin_token(&state, &headers)?;
+    let removed = state
+        .backends
+        .remove(backend.trim())
+        .ok_or(StatusCode::NOT_FOUND)?;
+    // Match rules may still send to it; keep reporting it for them.
+    let routed = state
+        .match_routes
+        .iter()
+        .any(|r| r.rule.backends.contains(&removed));
+    if !routed {
+        state.backend_stats.unregister(&removed);
+        if let Some(limiter) = &state.backend_limiter {
+            limiter.unregister(&removed);
+        }
+    }
+    tracing::info!("backend {} removed via admin endpoint", removed);
+    if state.backends.snapshot().is_empty() {
+        tracing::warn!("default backend pool is now empty; requests get 503 until one is added");
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Body of `POST /admin/cache/warm`.
+#[derive(Debug, Deserialize)]
+pub struct CacheWarmRequest {
+    /// Paths (with optional query) or absolute URLs; only the path and query are used.
+    pub urls: Vec<String>,
+    /// Requests in flight at once (default 4).
+    pub concurrency: Option<usize>,
+}
+
+/// `POST /admin/cache/warm`: fetch the given URLs through the proxy in the background to
+/// populate the response cache. Answers 202 with the new job's status.
+pub async fn cache_warm_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(body): Json<CacheWarmRequest>,
+) -> Result<(StatusCode, Json<WarmStatus>), StatusCode> {
+    check_admin_token(&state, &headers)?;
+    if state.response_cache.is_none() {
+        return Err(StatusCode::CONFLICT);
+    }
+
+    let uris = body
+        .urls
+        .iter()
+        .map(|u| {
+            let uri: Uri = u.parse().map_err(|_| StatusCode::BAD_REQUEST)?;
+            uri.path_and_query()
+                .map(|pq| Uri::from(pq.clone()))
+                .ok_or(StatusCode::BAD_REQUEST)
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    if !state.cache_warmer.try_start(uris.len()) {
+        return Err(StatusCode::CONFLICT);
+    }
+    tracing::info!("cache warm started for {} URL(s)", uris.len());
+    let status = state.cache_warmer.status();
+    tokio::spawn(cache_warm::run(state, uris, body.concurrency.unwrap_or(4)));
+    Ok((StatusCode::ACCEPTED, Json(status)))
+}
+
+/// `GET /admin/cache/warm`: progress of the most recent warm job.
+pub async fn cache_warm_status_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<WarmStatus>, StatusCode> {
+    check_admin_token(&state, &headers)?;
+    Ok(Json(state.cache_warmer.status()))
+}
+
+/// Body of `GET /admin/stats`.
+#[derive(Debug, Serialize)]
+pub struct StatsResponse {
+    pub connections: ConnectionStats,
+    /// Connections answered 431/408 for their first request head.
+    pub head_rejections: HeadRejectionStats,
+    /// Requests matched by each `block_rule`, including log-only ones.
+    pub block_rules: Vec<BlockRuleStats>,
+    /// Requests that panicked and were answered with a 500.
+    pub panics: u64,
+    /// Requests refused with a 400 for conflicting Content-Length/Transfer-Encoding headers.
+    pub framing_rejections: u64,
+    /// Requests to an `auth_route` that presented wrong credentials (all servers).
+    pub auth_failures: u64,
+    /// State-changing requests refused by `origin_check` (all servers).
+    pub origin_rejections: u64,
+    /// Servers that failed to start (only possible with `allow_partial_startup`).
+    pub bind_failures: Vec<BindFailure>,
+    /// Every accept loop with its restart count and last failure.
+    pub servers: Vec<ServerTaskStatus>,
+    /// Cache memory per component and in total, against `[limits] total_cache_bytes`.
+    pub memory: MemoryStats,
+    /// Requests waiting for a backend slot, with outcomes and wait times (None = no queue).
+    pub backend_queue: Option<BackendQueueStats>,
+    /// New visitors per experiment bucket and bot requests (None = no experiment).
+    pub experiment: Option<ExperimentStats>,
+}
+
+/// `GET /admin/stats`: live server statistics.
+pub async fn stats_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<StatsResponse>, StatusCode> {
+    check_admin_token(&state, &headers)?;
+    Ok(Json(StatsResponse {
+        connections: state.connections.stats(),
+        head_rejections: state.head_rejections.stats(),
+        block_rules: state.block_rules.stats(),
+        panics: state.panics.load(Ordering::Relaxed),
+        framing_rejections: state.framing_rejections.load(Ordering::Relaxed),
+        auth_failures: state.auth_failures.total(),
+        origin_rejections: state.origin_rejections.load(Ordering::Relaxed),
+        bind_failures: state
+            .bind_failures
+            .lock()
+            .unwrap_or_else(|p| p.into_inner())
+            .clone(),
+        servers: state.supervisor.snapshot(),
+        memory: state.memory.stats(),
+        backend_queue: state.backend_queue.as_ref().map(|q| q.stats()),
+        experiment: state.experiment.as_ref().map(|e| e.stats()),
+    }))
+}
+
+/// `GET /admin/bans`: clients currently banned by `[auto_ban]`.
+pub async fn bans_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<Vec<BanInfo>>, StatusCode> {
+    check_admin_token(&state, &headers)?;
+    Ok(Json(
+        state.auto_ban.as_ref().map_or_else(Vec::new, |b| b.list()),
+    ))
+}
+
+/// Query parameters for `DELETE /admin/bans`.
+#[derive(Debug, Deserialize)]
+pub struct UnbanQuery {
+    /// The client to unban; every ban is lifted when absent.
+    pub ip: Option<String>,
+}
+
+/// `DELETE /admin/bans[?ip=<addr>]`: lift one ban, or all of them. Answers 404 when the
+/// address isn't banned or banning is disabled.
+pub async fn unban_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(query): Query<UnbanQuery>,
+) -> Result<StatusCode, StatusCode> {
+    check_admin_token(&state, &headers)?;
+    let auto_ban = state.auto_ban.as_ref().ok_or(StatusCode::NOT_FOUND)?;
+    match query.ip {
+        Some(ip) => {
+            let ip: IpAddr = ip.trim().parse().map_err(|_| StatusCode::BAD_REQUEST)?;
+            if !auto_ban.unban(ip.to_canonical()) {
+                return Err(StatusCode::NOT_FOUND);
+            }
+            tracing::info!("ban on {} lifted via admin endpoint", ip);
+        }
+        None => {
+            let lifted = auto_ban.clear();
+            tracing::info!("{} ban(s) lifted via admin endpoint", lifted);
+        }
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// `GET /admin/drain`: drain state and what is still in flight, for polling until idle.
+pub async fn drain_status_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<DrainStatus>, StatusCode> {
+    check_admin_token(&state, &headers)?;
+    Ok(Json(state.drain.status()))
+}
+
+/// `POST /admin/drain`: fail readiness and turn away new proxied requests.
+pub async fn drain_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<DrainStatus>, StatusCode> {
+    check_admin_token(&state, &headers)?;
+    if !state.drain.set(true) {
+        tracing::warn!("drain started via admin endpoint");
+    }
+    Ok(Json(state.drain.status()))
+}
+
+/// `POST /admin/undrain`: resume normal service.
+pub async fn undrain_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<DrainStatus>, StatusCode> {
+    check_admin_token(&state, &headers)?;
+    if state.drain.set(false) {
+        tracing::info!("drain cancelled via admin endpoint");
+    }
+    Ok(Json(state.drain.status()))
+}
+
+/// `GET /admin/debug/panic`: panics on purpose (only mounted with `debug_panic_route`).
+pub async fn debug_panic_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<(), StatusCode> {
+    check_admin_token(&state, &headers)?;
+    panic!("deliberate panic from /admin/debug/panic");
+}
+
+/// All `/admin/*` routes, mounted on the public router or on the `admin_listen` server.
+pub fn router(debug_panic_route: bool) -> Router<AppState> {
+    let router = Router::new()
+        .route("/admin/cache/entry", get(cache_entry_handler))
+        .route(
+            "/admin/cache/warm",
+            get(cache_warm_status_handler).post(cache_warm_handler),
+        )
+        .route("/admin/upstream/errors", get(upstream_errors_handler))
+        .route(
+            "/admin/backends",
+            get(backends_handler).post(add_backend_handler),
+        )
+        .route("/admin/backends/{backend}", delete(remove_backend_handler))
+        .route("/admin/stats", get(stats_handler))
+        .route(
+            "/admin/bans",
+            get(bans_handler).merge(delete(unban_handler)),
+        )
+        .route(
+            "/admin/drain",
+            get(drain_status_handler).post(drain_handler),
+        )
+        .route("/admin/undrain", post(undrain_handler));
+    if debug_panic_route {
+        router.route("/admin/debug/panic", get(debug_panic_handler))
+    } else {
+        router
+    }
+}
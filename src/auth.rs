@@ -0,0 +1,341 @@
+use axum::{
+    body::Body,
+    http::{HeaderValue, Request, Response, StatusCode, header},
+};
+use base64::{Engine, engine::general_purpose::STANDARD};
+use dashmap::DashMap;
+use ring::digest;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+
+use crate::access::client_ip;
+use crate::admin::constant_time_eq;
+use crate::config::{AuthRoute, AuthScheme};
+use crate::path::longest_prefix;
+use crate::proxy::AppState;
+
+/// Identity of an authenticated request, as seen by the backend. Never taken from the client.
+pub const AUTHENTICATED_USER_HEADER: &str = "x-authenticated-user";
+
+// Distinct client IPs kept in the failure table; later ones only count towards the total.
+const MAX_TRACKED_IPS: usize = 10_000;
+
+enum Secret {
+    Bcrypt(String),
+    Sha1(Vec<u8>),
+}
+
+enum Credentials {
+    Basic {
+        users: HashMap<String, Secret>,
+        // Any bcrypt hash from the file, checked for unknown users so they take as long.
+        dummy: Option<String>,
+    },
+    Bearer(Vec<(String, Vec<u8>)>),
+}
+
+impl Credentials {
+    fn load(scheme: AuthScheme, text: &str) -> Result<Self, String> {
+        let mut users = HashMap::new();
+        let mut tokens = Vec::new();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let n = i + 1;
+            let Some((name, secret)) = line.split_once(':') else {
+                return Err(format!("line {}: expected 'name:secret'", n));
+            };
+            if name.is_empty() || HeaderValue::from_str(name).is_err() {
+                return Err(format!("line {}: invalid name", n));
+            }
+            match scheme {
+                AuthScheme::Basic => {
+                    let secret = if secret.starts_with("$2a$")
+                        || secret.starts_with("$2b$")
+                        || secret.starts_with("$2y$")
+                    {
+                        Secret::Bcrypt(secret.to_string())
+                    } else if let Some(b64) = secret.strip_prefix("{SHA}") {
+                        Secret::Sha1(
+                            STANDARD
+                                .decode(b64)
+                                .map_err(|_| format!("line {}: invalid {{SHA}} hash", n))?,
+                        )
+                    } else {
+                        return Err(format!(
+                            "line {}: unsupported hash for '{}' (use bcrypt or {{SHA}})",
+                            n, name
+                        ));
+                    };
+                    if users.insert(name.to_string(), secret).is_some() {
+                        return Err(format!("line {}: user '{}' is listed twice", n, name));
+                    }
+                }
+                AuthScheme::Bearer => {
+                    if secret.is_empty() {
+                        return Err(format!("line {}: empty token", n));
+                    }
+                    tokens.push((name.to_string(), secret.as_bytes().to_vec()));
+                }
+            }
+        }
+        Ok(match scheme {
+            AuthScheme::Basic => {
+                let dummy = users.values().find_map(|s| match s {
+                    Secret::Bcrypt(h) => Some(h.clone()),
+                    Secret::Sha1(_) => None,
+                });
+                Credentials::Basic { users, dummy }
+            }
+            AuthScheme::Bearer => Credentials::Bearer(tokens),
+        })
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            Credentials::Basic { users, .. } => users.len(),
+            Credentials::Bearer(tokens) => tokens.len(),
+        }
+    }
+}
+
+/// An `auth_route` together with its current credentials.
+pub struct AuthStore {
+    route: AuthRoute,
+    credentials: RwLock<Arc<Credentials>>,
+}
+
+impl std::fmt::Debug for AuthStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AuthStore")
+            .field("route", &self.route)
+            .finish()
+    }
+}
+
+impl AuthStore {
+    /// Load the route's credentials file, failing on any line that can't be used.
+    pub fn open(route: AuthRoute) -> Result<Self, String> {
+        let credentials = Self::read(&route)?;
+        Ok(Self {
+            route,
+            credentials: RwLock::new(Arc::new(credentials)),
+        })
+    }
+
+    fn read(route: &AuthRoute) -> Result<Credentials, String> {
+        let text = std::fs::read_to_string(&route.credentials_file)
+            .map_err(|e| format!("{}: {}", route.credentials_file.display(), e))?;
+        Credentials::load(route.scheme, &text)
+            .map_err(|e| format!("{}: {}", route.credentials_file.display(), e))
+    }
+
+    /// Re-read the credentials file; on error the previous credentials stay in use.
+    pub fn reload(&self) -> Result<usize, String> {
+        let credentials = Self::read(&self.route)?;
+        let count = credentials.len();
+        *self.credentials.write().unwrap_or_else(|p| p.into_inner()) = Arc::new(credentials);
+        Ok(count)
+    }
+
+    // The identity for a valid Authorization header, or None.
+    async fn verify(&self, authorization: &str) -> Option<String> {
+        let credentials = self
+            .credentials
+            .read()
+            .unwrap_or_else(|p| p.into_inner())
+            .clone();
+        let (scheme, value) = authorization.trim().split_once(' ')?;
+        let value = value.trim();
+        match &*credentials {
+            Credentials::Basic { users, dummy } => {
+                if !scheme.eq_ignore_ascii_case("basic") {
+                    return None;
+                }
+                let decoded = String::from_utf8(STANDARD.decode(value).ok()?).ok()?;
+                let (user, password) = decoded.split_once(':')?;
+                let password = password.to_string();
+                match users.get(user) {
+                    Some(Secret::Sha1(expected)) => {
+                        let actual =
+                            digest::digest(&digest::SHA1_FOR_LEGACY_USE_ONLY, password.as_bytes());
+                        constant_time_eq(actual.as_ref(), expected).then(|| user.to_string())
+                    }
+                    Some(Secret::Bcrypt(hash)) => {
+                        let hash = hash.clone();
+                        // bcrypt is deliberately slow; keep it off the async workers.
+                        let ok = tokio::task::spawn_blocking(move || {
+                            bcrypt::verify(password, &hash).unwrap_or(false)
+                        })
+                        .await
+                        .unwrap_or(false);
+                        ok.then(|| user.to_string())
+                    }
+                    None => {
+                        if let Some(hash) = dummy.clone() {
+                            let _ = tokio::task::spawn_blocking(move || {
+                                bcrypt::verify(password, &hash)
+                            })
+                            .await;
+                        }
+                        None
+                    }
+                }
+            }
+            Credentials::Bearer(tokens) => {
+                if !scheme.eq_ignore_ascii_case("bearer") {
+                    return None;
+                }
+                // Compare against every token so the time taken doesn't depend on which matched.
+                let mut found = None;
+                for (name, token) in tokens {
+                    if constant_time_eq(value.as_bytes(), token) && found.is_none() {
+                        found = Some(name.clone());
+                    }
+                }
+                found
+            }
+        }
+    }
+
+    fn challenge(&self, presented: bool) -> String {
+        match (self.route.scheme, presented) {
+            (AuthScheme::Basic, _) => {
+                format!("Basic realm=\"{}\", charset=\"UTF-8\"", self.route.realm)
+            }
+            (AuthScheme::Bearer, false) => format!("Bearer realm=\"{}\"", self.route.realm),
+            (AuthScheme::Bearer, true) => format!(
+                "Bearer realm=\"{}\", error=\"invalid_token\"",
+                self.route.realm
+            ),
+        }
+    }
+}
+
+/// Failed authentication attempts, in total and per client IP.
+#[derive(Debug, Default)]
+pub struct AuthFailures {
+    total: AtomicU64,
+    by_ip: DashMap<IpAddr, u64>,
+}
+
+impl AuthFailures {
+    pub fn record(&self, ip: Option<IpAddr>) {
+        self.total.fetch_add(1, Ordering::Relaxed);
+        let Some(ip) = ip else {
+            return;
+        };
+        if let Some(mut count) = self.by_ip.get_mut(&ip) {
+            *count += 1;
+        } else if self.by_ip.len() < MAX_TRACKED_IPS {
+            *self.by_ip.entry(ip).or_insert(0) += 1;
+        }
+    }
+
+    pub fn total(&self) -> u64 {
+        self.total.load(Ordering::Relaxed)
+    }
+
+    pub fn for_ip(&self, ip: IpAddr) -> u64 {
+        self.by_ip.get(&ip).map_or(0, |c| *c)
+    }
+}
+
+/// Marks a request that passed an `auth_route`. Such requests bypass the response cache, and
+/// `credentials` holds the Authorization header when it is to be forwarded.
+#[derive(Debug, Clone)]
+pub struct Authenticated {
+    pub credentials: Option<HeaderValue>,
+}
+
+/// Require credentials for requests under an `auth_route` (longest prefix wins), matched
+/// against `path`, the request's [`route_path`](crate::path::route_path).
+///
+/// On success the Authorization header is removed (kept aside when `forward_credentials` is
+/// set) and the identity is put in `X-Authenticated-User`. A client-supplied
+/// `X-Authenticated-User` is always dropped.
+pub async fn check_auth(
+    state: &AppState,
+    req: &mut Request<Body>,
+    path: &str,
+) -> Result<(), Response<Body>> {
+    req.headers_mut().remove(AUTHENTICATED_USER_HEADER);
+    let Some(store) = longest_prefix(&state.auth_routes, path, |s| &s.route.path_prefix) else {
+        return Ok(());
+    };
+
+    let authorization = req.headers_mut().remove(header::AUTHORIZATION);
+    let presented = authorization.is_some();
+    let identity = match authorization.as_ref().and_then(|v| v.to_str().ok()) {
+        Some(value) => store.verify(value).await,
+        None => None,
+    };
+
+    match identity.and_then(|id| HeaderValue::from_str(&id).ok()) {
+        Some(identity) => {
+            req.headers_mut()
+                .insert(AUTHENTICATED_USER_HEADER, identity);
+            let credentials = authorization.filter(|_| store.route.forward_credentials);
+            req.extensions_mut().insert(Authenticated { credentials });
+            Ok(())
+        }
+        None => {
+            if presented {
+                let ip = client_ip(req, &state.trusted_proxies);
+                state.auth_failures.record(ip);
+                tracing::warn!(
+                    "rejected credentials for {} from {:?}",
+                    req.uri().path(),
+                    ip
+                );
+            }
+            let mut resp = Response::new(Body::empty());
+            *resp.status_mut() = StatusCode::UNAUTHORIZED;
+            if let Ok(challenge) = HeaderValue::from_str(&store.challenge(presented)) {
+                resp.headers_mut()
+                    .insert(header::WWW_AUTHENTICATE, challenge);
+            }
+            Err(resp)
+        }
+    }
+}
+
+/// Reload every credentials file on SIGHUP.
+#[cfg(unix)]
+pub async fn reload_on_sighup(stores: Vec<Arc<AuthStore>>) {
+    use tokio::signal::unix::{SignalKind, signal};
+
+    let mut sighup = match signal(SignalKind::hangup()) {
+        Ok(s) => s,
+        Err(e) => {
+            tracing::warn!(
+                "failed to install SIGHUP handler, credentials won't be reloaded: {}",
+                e
+            );
+            return;
+        }
+    };
+    while sighup.recv().await.is_some() {
+        for store in &stores {
+            match store.reload() {
+                Ok(count) => tracing::info!(
+                    "reloaded {} credential(s) for {}",
+                    count,
+                    store.route.path_prefix
+                ),
+                Err(e) => tracing::error!(
+                    "failed to reload credentials for {}, keeping the previous ones: {}",
+                    store.route.path_prefix,
+                    e
+                ),
+            }
+        }
+    }
+}
+
+#[cfg(not(unix))]
+pub async fn reload_on_sighup(_stores: Vec<Arc<AuthStore>>) {}
@@ -0,0 +1,86 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
+use tokio::sync::Notify;
+use url::Url;
+
+/// Per-backend in-flight request counts, capped at `max_connections_per_backend`.
+#[derive(Debug)]
+pub struct BackendLimiter {
+    in_flight: RwLock<HashMap<Url, Arc<AtomicUsize>>>,
+    max: usize,
+    // Signalled whenever a permit is released, for requests queued for a slot
+    released: Arc<Notify>,
+}
+
+/// A slot on one backend, released when dropped.
+#[derive(Debug)]
+pub struct BackendPermit {
+    count: Arc<AtomicUsize>,
+    released: Arc<Notify>,
+}
+
+impl Drop for BackendPermit {
+    fn drop(&mut self) {
+        self.count.fetch_sub(1, Ordering::AcqRel);
+        self.released.notify_waiters();
+    }
+}
+
+impl BackendLimiter {
+    /// `backends` must cover every URL a request can be sent to, including match-rule groups.
+    pub fn new<'a>(backends: impl IntoIterator<Item = &'a Url>, max: usize) -> Self {
+        Self {
+            in_flight: RwLock::new(
+                backends
+                    .into_iter()
+                    .map(|b| (b.clone(), Arc::new(AtomicUsize::new(0))))
+                    .collect(),
+            ),
+            max,
+            released: Arc::new(Notify::new()),
+        }
+    }
+
+    /// Signalled whenever a slot is released on any backend.
+    pub fn released(&self) -> Arc<Notify> {
+        self.released.clone()
+    }
+
+    /// Cap `backend` too, for a backend added at runtime.
+    pub fn register(&self, backend: &Url) {
+        self.in_flight
+            .write()
+            .unwrap_or_else(|p| p.into_inner())
+            .entry(backend.clone())
+            .or_default();
+    }
+
+    /// Stop capping `backend`, removed at runtime. Permits still held release as usual.
+    pub fn unregister(&self, backend: &Url) {
+        self.in_flight
+            .write()
+            .unwrap_or_else(|p| p.into_inner())
+            .remove(backend);
+    }
+
+    /// Take a slot on `backend`, or `None` if it is already at the cap.
+    pub fn try_acquire(&self, backend: &Url) -> Option<BackendPermit> {
+        // `new` and `register` cover every reachable backend; anything else is refused rather than uncapped.
+        let count = self
+            .in_flight
+            .read()
+            .unwrap_or_else(|p| p.into_inner())
+            .get(backend)?
+            .clone();
+        count
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| {
+                (n < self.max).then_some(n + 1)
+            })
+            .ok()?;
+        Some(BackendPermit {
+            count,
+            released: self.released.clone(),
+        })
+    }
+}
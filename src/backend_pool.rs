@@ -0,0 +1,55 @@
+//! A server's default backends, which `/admin/backends` can add to and remove from while it runs.
+
+use std::sync::{Arc, RwLock};
+use url::Url;
+
+/// The default backend list. Readers take a snapshot, so a request sees one consistent list
+/// even while an admin call changes it.
+#[derive(Debug)]
+pub struct BackendPool {
+    backends: RwLock<Arc<Vec<Url>>>,
+}
+
+impl BackendPool {
+    pub fn new(backends: Vec<Url>) -> Self {
+        Self {
+            backends: RwLock::new(Arc::new(backends)),
+        }
+    }
+
+    /// The current list.
+    pub fn snapshot(&self) -> Arc<Vec<Url>> {
+        self.backends
+            .read()
+            .unwrap_or_else(|p| p.into_inner())
+            .clone()
+    }
+
+    /// Append `backend`; `false` if it is already in the pool.
+    pub fn add(&self, backend: Url) -> bool {
+        let mut backends = self.backends.write().unwrap_or_else(|p| p.into_inner());
+        if backends.contains(&backend) {
+            return false;
+        }
+        let mut next = Vec::clone(&backends);
+        next.push(backend);
+        *backends = Arc::new(next);
+        true
+    }
+
+    /// Remove the backend `hint` names, by index or URL (as for `X-Serava-Backend`).
+    pub fn remove(&self, hint: &str) -> Option<Url> {
+        let mut backends = self.backends.write().unwrap_or_else(|p| p.into_inner());
+        let idx = match hint.parse::<usize>() {
+            Ok(idx) => (idx < backends.len()).then_some(idx)?,
+            Err(_) => {
+                let url = Url::parse(hint).ok()?;
+                backends.iter().position(|b| *b == url)?
+            }
+        };
+        let mut next = Vec::clone(&backends);
+        let removed = next.remove(idx);
+        *backends = Arc::new(next);
+        Some(removed)
+    }
+}
@@ -0,0 +1,212 @@
+//! `queue_max_len`: requests finding every backend of their pool at `max_connections_per_backend`
+//! wait in line for a slot for up to `queue_max_wait_ms` instead of failing at once.
+
+use axum::{
+    http::{StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Instant;
+use tokio::sync::Notify;
+
+use crate::config::BackendQueueLimits;
+
+// Upper bounds in ms of the wait-time histogram buckets; one more bucket takes the rest.
+const WAIT_BUCKETS_MS: [u64; 8] = [1, 5, 10, 50, 100, 500, 1000, 5000];
+
+#[derive(Debug, Default)]
+struct Counters {
+    queued: AtomicU64,
+    dispatched: AtomicU64,
+    shed_full: AtomicU64,
+    shed_timeout: AtomicU64,
+    abandoned: AtomicU64,
+    wait_ms: [AtomicU64; WAIT_BUCKETS_MS.len() + 1],
+}
+
+/// Queue depth, outcomes and wait times, reported by `/admin/stats`.
+#[derive(Debug, Clone, Serialize)]
+pub struct BackendQueueStats {
+    /// Requests waiting right now, over all pools.
+    pub depth: usize,
+    /// Requests that had to wait.
+    pub queued: u64,
+    /// Waiting requests that got a backend.
+    pub dispatched: u64,
+    /// Requests refused at once because their queue was full.
+    pub shed_full: u64,
+    /// Waiting requests refused after `queue_max_wait_ms`.
+    pub shed_timeout: u64,
+    /// Waiting requests whose client went away.
+    pub abandoned: u64,
+    /// How long dispatched requests waited.
+    pub wait_ms: Vec<WaitBucket>,
+}
+
+/// One bucket of the wait-time histogram.
+#[derive(Debug, Clone, Serialize)]
+pub struct WaitBucket {
+    /// Upper bound in ms (inclusive); `None` for the last bucket.
+    pub le: Option<u64>,
+    pub count: u64,
+}
+
+/// FIFO waiting lines for backend slots, one per backend pool.
+#[derive(Debug)]
+pub struct BackendQueue {
+    limits: BackendQueueLimits,
+    // Ids of the waiting requests: [0] the default backends, [i + 1] match rule i
+    pools: Vec<Mutex<VecDeque<u64>>>,
+    next_id: AtomicU64,
+    // Signalled when a backend slot frees up or the head of a line leaves it
+    released: Arc<Notify>,
+    counters: Counters,
+}
+
+fn lock(queue: &Mutex<VecDeque<u64>>) -> MutexGuard<'_, VecDeque<u64>> {
+    queue.lock().unwrap_or_else(|p| p.into_inner())
+}
+
+// A request's place in line; leaving it (dispatched, timed out or dropped) lets the next one on.
+struct Place<'a> {
+    queue: &'a BackendQueue,
+    pool: usize,
+    id: u64,
+    // Whether the outcome was counted; a place dropped without one was abandoned by its client.
+    counted: bool,
+}
+
+impl Drop for Place<'_> {
+    fn drop(&mut self) {
+        let mut waiting = lock(&self.queue.pools[self.pool]);
+        let was_head = waiting.front() == Some(&self.id);
+        waiting.retain(|id| *id != self.id);
+        drop(waiting);
+        if was_head {
+            self.queue.released.notify_waiters();
+        }
+        if !self.counted {
+            self.queue
+                .counters
+                .abandoned
+                .fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+impl BackendQueue {
+    /// `pools` counts the default backends plus one per match rule. `released` must be
+    /// signalled whenever a backend slot frees up.
+    pub fn new(limits: BackendQueueLimits, pools: usize, released: Arc<Notify>) -> Self {
+        Self {
+            limits,
+            pools: (0..pools).map(|_| Mutex::default()).collect(),
+            next_id: AtomicU64::new(0),
+            released,
+            counters: Counters::default(),
+        }
+    }
+
+    /// Take a backend slot with `try_acquire` for a request to `pool` (0 for the default
+    /// backends, `i + 1` for match rule `i`), waiting in line while it finds none. `None` when
+    /// the line is full or the wait runs out. Dropping the future gives up the place in line.
+    pub async fn acquire<T>(
+        &self,
+        pool: usize,
+        mut try_acquire: impl FnMut() -> Option<T>,
+    ) -> Option<T> {
+        let id = {
+            let mut waiting = lock(&self.pools[pool]);
+            // Only skip the line when there is none.
+            if waiting.is_empty()
+                && let Some(slot) = try_acquire()
+            {
+                return Some(slot);
+            }
+            if waiting.len() >= self.limits.max_len {
+                self.counters.shed_full.fetch_add(1, Ordering::Relaxed);
+                tracing::warn!(
+                    "backend queue full ({} waiting), shedding request",
+                    waiting.len()
+                );
+                return None;
+            }
+            let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+            waiting.push_back(id);
+            id
+        };
+        self.counters.queued.fetch_add(1, Ordering::Relaxed);
+        let mut place = Place {
+            queue: self,
+            pool,
+            id,
+            counted: false,
+        };
+
+        let start = Instant::now();
+        let deadline = tokio::time::Instant::now() + self.limits.max_wait;
+        loop {
+            // Registered before trying, so a slot released in between still wakes us.
+            let released = self.released.notified();
+            tokio::pin!(released);
+            released.as_mut().enable();
+
+            let at_head = lock(&self.pools[pool]).front() == Some(&id);
+            if at_head && let Some(slot) = try_acquire() {
+                place.counted = true;
+                self.counters.dispatched.fetch_add(1, Ordering::Relaxed);
+                let waited = start.elapsed().as_millis() as u64;
+                let bucket = WAIT_BUCKETS_MS
+                    .iter()
+                    .position(|le| waited <= *le)
+                    .unwrap_or(WAIT_BUCKETS_MS.len());
+                self.counters.wait_ms[bucket].fetch_add(1, Ordering::Relaxed);
+                return Some(slot);
+            }
+            if tokio::time::timeout_at(deadline, released).await.is_err() {
+                place.counted = true;
+                self.counters.shed_timeout.fetch_add(1, Ordering::Relaxed);
+                tracing::warn!(
+                    "no backend slot within {:?}, shedding queued request",
+                    self.limits.max_wait
+                );
+                return None;
+            }
+        }
+    }
+
+    /// The 503 for a shed request; Retry-After is the longest a queued request waits.
+    pub fn shed(&self) -> Response {
+        let retry_after = self.limits.max_wait.as_secs_f64().ceil().max(1.0) as u64;
+        (
+            StatusCode::SERVICE_UNAVAILABLE,
+            [(header::RETRY_AFTER, retry_after)],
+            "all backends busy",
+        )
+            .into_response()
+    }
+
+    pub fn stats(&self) -> BackendQueueStats {
+        let c = &self.counters;
+        BackendQueueStats {
+            depth: self.pools.iter().map(|q| lock(q).len()).sum(),
+            queued: c.queued.load(Ordering::Relaxed),
+            dispatched: c.dispatched.load(Ordering::Relaxed),
+            shed_full: c.shed_full.load(Ordering::Relaxed),
+            shed_timeout: c.shed_timeout.load(Ordering::Relaxed),
+            abandoned: c.abandoned.load(Ordering::Relaxed),
+            wait_ms: c
+                .wait_ms
+                .iter()
+                .enumerate()
+                .map(|(i, n)| WaitBucket {
+                    le: WAIT_BUCKETS_MS.get(i).copied(),
+                    count: n.load(Ordering::Relaxed),
+                })
+                .collect(),
+        }
+    }
+}
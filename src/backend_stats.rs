@@ -0,0 +1,113 @@
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
+use url::Url;
+
+#[derive(Debug, Default)]
+struct Counters {
+    in_flight: AtomicUsize,
+    requests: AtomicU64,
+    errors: AtomicU64,
+}
+
+/// Per-backend request, error and in-flight counts, reported by `/admin/backends`.
+#[derive(Debug)]
+pub struct BackendStats {
+    counters: RwLock<HashMap<Url, Arc<Counters>>>,
+}
+
+/// One request sent to a backend; counted as in flight until dropped.
+#[derive(Debug)]
+pub struct BackendRequest {
+    counters: Arc<Counters>,
+}
+
+impl BackendRequest {
+    /// Count this request as an error: no usable response, or a 5xx.
+    pub fn failed(&self) {
+        self.counters.errors.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+impl Drop for BackendRequest {
+    fn drop(&mut self) {
+        self.counters.in_flight.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Counters for one backend, as reported by `/admin/backends`.
+#[derive(Debug, Clone, Serialize)]
+pub struct BackendStatus {
+    pub url: String,
+    /// Position in the default pool, as `DELETE /admin/backends/{index}` takes it; `None` for a
+    /// backend only a match rule sends to.
+    pub index: Option<usize>,
+    /// Requests waiting for a response or still streaming its body.
+    pub in_flight: usize,
+    pub requests: u64,
+    pub errors: u64,
+}
+
+impl BackendStats {
+    /// `backends` should cover every URL a request can be sent to, including match-rule groups.
+    pub fn new<'a>(backends: impl IntoIterator<Item = &'a Url>) -> Self {
+        Self {
+            counters: RwLock::new(
+                backends
+                    .into_iter()
+                    .map(|b| (b.clone(), Arc::default()))
+                    .collect(),
+            ),
+        }
+    }
+
+    /// Start counting requests to `backend`, added at runtime. Existing counts are kept.
+    pub fn register(&self, backend: &Url) {
+        self.counters
+            .write()
+            .unwrap_or_else(|p| p.into_inner())
+            .entry(backend.clone())
+            .or_default();
+    }
+
+    /// Stop reporting `backend`, removed at runtime. Requests still in flight finish uncounted.
+    pub fn unregister(&self, backend: &Url) {
+        self.counters
+            .write()
+            .unwrap_or_else(|p| p.into_inner())
+            .remove(backend);
+    }
+
+    /// Count a request to `backend` (`None` for a backend that wasn't registered).
+    pub fn start(&self, backend: &Url) -> Option<BackendRequest> {
+        let counters = self
+            .counters
+            .read()
+            .unwrap_or_else(|p| p.into_inner())
+            .get(backend)?
+            .clone();
+        counters.requests.fetch_add(1, Ordering::Relaxed);
+        counters.in_flight.fetch_add(1, Ordering::Relaxed);
+        Some(BackendRequest { counters })
+    }
+
+    /// Every registered backend's counts; `pool` is the default pool, for the indexes.
+    pub fn snapshot(&self, pool: &[Url]) -> Vec<BackendStatus> {
+        let mut out: Vec<BackendStatus> = self
+            .counters
+            .read()
+            .unwrap_or_else(|p| p.into_inner())
+            .iter()
+            .map(|(url, c)| BackendStatus {
+                url: url.to_string(),
+                index: pool.iter().position(|b| b == url),
+                in_flight: c.in_flight.load(Ordering::Relaxed),
+                requests: c.requests.load(Ordering::Relaxed),
+                errors: c.errors.load(Ordering::Relaxed),
+            })
+            .collect();
+        out.sort_by(|a, b| a.url.cmp(&b.url));
+        out
+    }
+}
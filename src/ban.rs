@@ -0,0 +1,170 @@
+//! `[auto_ban]`: fail2ban-style temporary bans.
+//!
+//! Every response with one of the strike statuses counts against the client IP (resolved
+//! through `trusted_proxies`, like access checks). A client collecting more than `max_strikes`
+//! in `window_secs` is banned for `ban_secs`, during which its requests are refused before
+//! reaching block rules, static files or backends. Bans are shared by every server.
+
+use axum::{extract::Request, middleware::Next, response::Response};
+use dashmap::DashMap;
+use ipnet::IpNet;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::Instant;
+
+use crate::access::client_ip;
+use crate::block;
+use crate::config::AutoBanConfig;
+
+// Distinct client IPs with recent strikes; strikes of further IPs are ignored until a sweep.
+const MAX_TRACKED_IPS: usize = 10_000;
+
+/// Strike counts and active bans per client IP.
+#[derive(Debug)]
+pub struct AutoBan {
+    config: AutoBanConfig,
+    // Strike times within the window, oldest first
+    strikes: DashMap<IpAddr, VecDeque<Instant>>,
+    // Ban expiry per IP
+    bans: DashMap<IpAddr, Instant>,
+}
+
+/// One active ban, as listed by `GET /admin/bans`.
+#[derive(Debug, Clone, Serialize)]
+pub struct BanInfo {
+    pub ip: IpAddr,
+    pub remaining_secs: u64,
+}
+
+impl AutoBan {
+    pub fn new(config: AutoBanConfig) -> Self {
+        Self {
+            config,
+            strikes: DashMap::new(),
+            bans: DashMap::new(),
+        }
+    }
+
+    fn is_exempt(&self, ip: IpAddr) -> bool {
+        self.config.exempt_ips.iter().any(|net| net.contains(&ip))
+    }
+
+    fn is_banned(&self, ip: IpAddr, now: Instant) -> bool {
+        let Some(until) = self.bans.get(&ip).map(|until| *until) else {
+            return false;
+        };
+        if until > now {
+            return true;
+        }
+        self.bans.remove_if(&ip, |_, until| *until <= now);
+        false
+    }
+
+    fn strike(&self, ip: IpAddr, now: Instant) {
+        let mut times = match self.strikes.get_mut(&ip) {
+            Some(times) => times,
+            None if self.strikes.len() >= MAX_TRACKED_IPS => return,
+            None => self.strikes.entry(ip).or_default(),
+        };
+        while times
+            .front()
+            .is_some_and(|t| now.duration_since(*t) >= self.config.window)
+        {
+            times.pop_front();
+        }
+        times.push_back(now);
+        if times.len() <= self.config.max_strikes as usize {
+            return;
+        }
+        let count = times.len();
+        drop(times);
+
+        self.strikes.remove(&ip);
+        self.bans.insert(ip, now + self.config.ban);
+        tracing::warn!(
+            "banning {} for {:?} after {} strike(s) within {:?}",
+            ip,
+            self.config.ban,
+            count,
+            self.config.window
+        );
+    }
+
+    /// Active bans, ordered by IP.
+    pub fn list(&self) -> Vec<BanInfo> {
+        let now = Instant::now();
+        let mut bans: Vec<BanInfo> = self
+            .bans
+            .iter()
+            .filter(|b| *b.value() > now)
+            .map(|b| BanInfo {
+                ip: *b.key(),
+                remaining_secs: b.value().saturating_duration_since(now).as_secs(),
+            })
+            .collect();
+        bans.sort_by_key(|b| b.ip);
+        bans
+    }
+
+    /// Lift the ban on `ip` and forget its strikes; false when it wasn't banned.
+    pub fn unban(&self, ip: IpAddr) -> bool {
+        self.strikes.remove(&ip);
+        self.bans
+            .remove(&ip)
+            .is_some_and(|(_, until)| until > Instant::now())
+    }
+
+    /// Lift every ban; returns how many were active.
+    pub fn clear(&self) -> usize {
+        let active = self.list().len();
+        self.bans.clear();
+        self.strikes.clear();
+        active
+    }
+
+    fn sweep(&self) {
+        let now = Instant::now();
+        self.bans.retain(|_, until| *until > now);
+        self.strikes.retain(|_, times| {
+            times
+                .back()
+                .is_some_and(|t| now.duration_since(*t) < self.config.window)
+        });
+    }
+}
+
+/// Drop expired bans and stale strike counts once per window.
+pub async fn sweep(bans: Arc<AutoBan>) {
+    let mut interval = tokio::time::interval(bans.config.window);
+    loop {
+        interval.tick().await;
+        bans.sweep();
+    }
+}
+
+/// Middleware refusing banned clients and counting strikes for everyone else.
+///
+/// Requests whose client IP can't be determined, and exempted IPs, are neither refused nor
+/// counted.
+pub async fn handle(
+    bans: Arc<AutoBan>,
+    trusted_proxies: Arc<Vec<IpNet>>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let Some(ip) = client_ip(&req, &trusted_proxies).filter(|ip| !bans.is_exempt(*ip)) else {
+        return next.run(req).await;
+    };
+    if bans.is_banned(ip, Instant::now()) {
+        tracing::debug!("refusing {} from banned {}", req.uri().path(), ip);
+        return block::refuse(bans.config.action);
+    }
+
+    let resp = next.run(req).await;
+    if bans.config.statuses.contains(&resp.status().as_u16()) {
+        bans.strike(ip, Instant::now());
+    }
+    resp
+}
@@ -0,0 +1,140 @@
+use axum::{
+    extract::Request,
+    http::{StatusCode, header},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use percent_encoding::percent_decode_str;
+use serde::Serialize;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::config::{BlockAction, BlockRule};
+use crate::conn_limit::CloseConnection;
+use crate::fingerprint::TlsFingerprint;
+
+/// A server's `block_rule`s with their match counters.
+#[derive(Debug)]
+pub struct BlockRules {
+    rules: Vec<(BlockRule, AtomicU64)>,
+}
+
+/// Matches so far for one rule, as reported by `/admin/stats`.
+#[derive(Debug, Clone, Serialize)]
+pub struct BlockRuleStats {
+    pub name: String,
+    pub log_only: bool,
+    pub matched: u64,
+}
+
+impl BlockAction {
+    fn as_str(self) -> &'static str {
+        match self {
+            BlockAction::Forbidden => "403",
+            BlockAction::NotFound => "404",
+            BlockAction::Drop => "drop",
+        }
+    }
+}
+
+impl BlockRule {
+    fn matches(
+        &self,
+        path: &str,
+        user_agent: Option<&str>,
+        fingerprint: Option<&TlsFingerprint>,
+    ) -> bool {
+        if self.paths.iter().any(|re| re.is_match(path)) {
+            return true;
+        }
+        if let Some(fp) = fingerprint
+            && (self.ja3.iter().any(|h| **h == *fp.ja3) || self.ja4.iter().any(|f| **f == *fp.ja4))
+        {
+            return true;
+        }
+        let Some(ua) = user_agent else {
+            return false;
+        };
+        let lower = ua.to_ascii_lowercase();
+        self.user_agents.iter().any(|s| lower.contains(s.as_str()))
+            || self.user_agent_regex.iter().any(|re| re.is_match(ua))
+    }
+}
+
+impl BlockRules {
+    pub fn new(rules: Vec<BlockRule>) -> Self {
+        Self {
+            rules: rules.into_iter().map(|r| (r, AtomicU64::new(0))).collect(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    pub fn stats(&self) -> Vec<BlockRuleStats> {
+        self.rules
+            .iter()
+            .map(|(rule, matched)| BlockRuleStats {
+                name: rule.name.clone(),
+                log_only: rule.log_only,
+                matched: matched.load(Ordering::Relaxed),
+            })
+            .collect()
+    }
+
+    // The action of the first enforcing rule matching the request. Log-only rules that match
+    // on the way are counted and logged, but don't stop the search.
+    fn check(&self, req: &Request) -> Option<BlockAction> {
+        let path = percent_decode_str(req.uri().path()).decode_utf8_lossy();
+        let user_agent = req
+            .headers()
+            .get(header::USER_AGENT)
+            .and_then(|v| v.to_str().ok());
+        let fingerprint = TlsFingerprint::of(req);
+
+        for (rule, matched) in &self.rules {
+            if !rule.matches(&path, user_agent, fingerprint) {
+                continue;
+            }
+            matched.fetch_add(1, Ordering::Relaxed);
+            if rule.log_only {
+                tracing::info!(
+                    "log-only block_rule '{}' matched {} (user agent {:?}), would {}",
+                    rule.name,
+                    path,
+                    user_agent,
+                    rule.action.as_str()
+                );
+                continue;
+            }
+            tracing::debug!("block_rule '{}' matched {}", rule.name, path);
+            return Some(rule.action);
+        }
+        None
+    }
+}
+
+/// Middleware refusing requests that match a `block_rule`, ahead of every other handler.
+///
+/// `drop` is passed to the connection layer as [`CloseConnection`]; where that isn't honoured
+/// the client gets the 403 carrying it.
+pub async fn handle(rules: Arc<BlockRules>, req: Request, next: Next) -> Response {
+    match rules.check(&req) {
+        None => next.run(req).await,
+        Some(action) => refuse(action),
+    }
+}
+
+/// The response carrying out `action`.
+pub fn refuse(action: BlockAction) -> Response {
+    match action {
+        BlockAction::NotFound => StatusCode::NOT_FOUND.into_response(),
+        BlockAction::Forbidden => StatusCode::FORBIDDEN.into_response(),
+        BlockAction::Drop => {
+            let mut resp = StatusCode::FORBIDDEN.into_response();
+            resp.extensions_mut().insert(CloseConnection);
+            resp
+        }
+    }
+}
@@ -0,0 +1,111 @@
+//! Background cache warming through the normal proxy path.
+
+use axum::{
+    body::{Body, to_bytes},
+    extract::{ConnectInfo, State},
+    http::{Method, Request, Uri},
+};
+use futures::{StreamExt, stream};
+use serde::Serialize;
+use std::net::SocketAddr;
+use std::sync::Mutex;
+
+use crate::proxy::{AppState, proxy_handler};
+
+/// Progress of the most recent warm job, as reported by `GET /admin/cache/warm`.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct WarmStatus {
+    pub running: bool,
+    pub total: usize,
+    pub succeeded: usize,
+    pub failed: usize,
+}
+
+/// Shared per-server warm job state.
+#[derive(Debug, Default)]
+pub struct CacheWarmer {
+    status: Mutex<WarmStatus>,
+}
+
+impl CacheWarmer {
+    pub fn status(&self) -> WarmStatus {
+        self.lock().clone()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, WarmStatus> {
+        self.status.lock().unwrap_or_else(|p| p.into_inner())
+    }
+
+    /// Mark a job of `total` requests as started, unless one is already running.
+    pub fn try_start(&self, total: usize) -> bool {
+        let mut status = self.lock();
+        if status.running {
+            return false;
+        }
+        *status = WarmStatus {
+            running: true,
+            total,
+            ..WarmStatus::default()
+        };
+        true
+    }
+
+    fn record(&self, ok: bool) {
+        let mut status = self.lock();
+        if ok {
+            status.succeeded += 1;
+        } else {
+            status.failed += 1;
+        }
+    }
+
+    fn finish(&self) -> WarmStatus {
+        let mut status = self.lock();
+        status.running = false;
+        status.clone()
+    }
+}
+
+/// Fetch every URI with `GET` through [`proxy_handler`], at most `concurrency` at a time, so
+/// cacheable responses land in the response cache exactly as for a client request.
+///
+/// The caller must have claimed the job with [`CacheWarmer::try_start`].
+pub async fn run(state: AppState, uris: Vec<Uri>, concurrency: usize) {
+    let warmer = state.cache_warmer.clone();
+    stream::iter(uris)
+        .for_each_concurrent(concurrency.max(1), |uri| {
+            let (state, warmer) = (state.clone(), warmer.clone());
+            async move {
+                let ok = warm_one(state, uri.clone()).await;
+                if !ok {
+                    tracing::warn!("cache warm request for {} failed", uri);
+                }
+                warmer.record(ok);
+            }
+        })
+        .await;
+    let status = warmer.finish();
+    tracing::info!(
+        "cache warm finished: {}/{} succeeded, {} failed",
+        status.succeeded,
+        status.total,
+        status.failed
+    );
+}
+
+async fn warm_one(state: AppState, uri: Uri) -> bool {
+    let mut req = Request::new(Body::empty());
+    *req.method_mut() = Method::GET;
+    *req.uri_mut() = uri;
+    // Internal requests count as coming from loopback for rate limiting.
+    req.extensions_mut()
+        .insert(ConnectInfo(SocketAddr::from(([127, 0, 0, 1], 0))));
+
+    match proxy_handler(State(state), req).await {
+        // Drain the body: streamed responses are only complete once read.
+        Ok(resp) if resp.status().is_success() => {
+            to_bytes(resp.into_body(), usize::MAX).await.is_ok()
+        }
+        _ => false,
+    }
+}
@@ -1,108 +1,2535 @@
+use axum::http::{HeaderName, HeaderValue, header};
+use ipnet::IpNet;
+use jiff::{
+    Timestamp,
+    civil::{self, Weekday},
+    tz::TimeZone,
+};
+use percent_encoding::percent_decode_str;
+use regex::Regex;
 use serde::Deserialize;
-use std::{net::SocketAddr, path::PathBuf, time::Duration};
+use std::{
+    collections::{BTreeMap, HashMap},
+    net::{IpAddr, SocketAddr},
+    path::PathBuf,
+    time::Duration,
+};
 use url::Url;
 
+use crate::path::has_path_prefix;
+use crate::security_headers;
+
 #[derive(Debug, Deserialize)]
 pub struct RawConfig {
+    /// Seconds in-flight requests get to finish after a shutdown signal (default 10).
+    pub shutdown_grace_secs: Option<u64>,
+    /// Keep extending the grace period while streamed responses are still completing, up to
+    /// this many seconds in total. No extension when unset.
+    pub shutdown_grace_max_secs: Option<u64>,
+    /// Process-wide cap on open client connections across all servers.
+    pub max_connections: Option<usize>,
+    /// Extra bind attempts when a listen address is temporarily unavailable (default 0).
+    pub bind_retry_attempts: Option<u32>,
+    /// Delay before the first bind retry, doubled after each attempt (default 500).
+    pub bind_retry_backoff_ms: Option<u64>,
+    /// Keep running when some servers fail to bind instead of exiting (default false).
+    pub allow_partial_startup: Option<bool>,
+    /// Consecutive restarts of a crashed server before the process gives up (default 5).
+    pub server_restart_limit: Option<u32>,
+    pub server_restart_backoff_ms: Option<u64>,
+    /// Probe every backend with a TCP connect at startup: "off" (default), "warn" or "fail".
+    pub startup_backend_check: Option<BackendCheckMode>,
+    /// Per-backend connect timeout for the startup check (default 2000).
+    pub startup_backend_check_timeout_ms: Option<u64>,
+    /// `Retry-After` seconds on requests refused while drained via `/admin/drain` (default 30).
+    pub drain_retry_after_secs: Option<u64>,
+    /// Keep serving `/static` while drained (default true).
+    pub drain_serve_static: Option<bool>,
+    /// Forward TRACE requests instead of answering 405 (default false).
+    pub allow_trace: Option<bool>,
+    #[serde(default)]
+    pub runtime: RawRuntime,
+    #[serde(default)]
+    pub limits: RawLimits,
+    pub auto_ban: Option<RawAutoBan>,
     pub servers: Vec<RawServer>,
 }
 
+/// `[runtime]`: tokio runtime sizing. Absent values keep tokio's defaults.
+#[derive(Debug, Default, Deserialize)]
+pub struct RawRuntime {
+    pub flavor: Option<RuntimeFlavor>,
+    /// Worker threads for the multi-thread runtime; 0 or unset means one per core.
+    pub worker_threads: Option<usize>,
+    /// Upper bound on the blocking thread pool (file I/O etc.); tokio defaults to 512.
+    pub max_blocking_threads: Option<usize>,
+    pub thread_name: Option<String>,
+}
+
+/// `[limits]`: process-wide resource budgets.
+#[derive(Debug, Default, Deserialize)]
+pub struct RawLimits {
+    /// Memory shared by every cache in the process; per-server cache limits still apply within it.
+    pub total_cache_bytes: Option<u64>,
+}
+
+/// `[auto_ban]`: temporarily ban client IPs that collect too many strike statuses.
+#[derive(Debug, Deserialize)]
+pub struct RawAutoBan {
+    /// Set to false to turn banning off without deleting the section (default true).
+    pub enabled: Option<bool>,
+    /// Response statuses counted as strikes (default 401, 403 and 404).
+    pub statuses: Option<Vec<u16>>,
+    /// Strikes allowed within `window_secs`; the next one bans the client (default 20).
+    pub max_strikes: Option<u32>,
+    /// Sliding window strikes are counted over (default 60).
+    pub window_secs: Option<u64>,
+    /// How long a ban lasts (default 600).
+    pub ban_secs: Option<u64>,
+    /// Answer to a banned client: "403" (default), "404" or "drop".
+    pub action: Option<BlockAction>,
+    /// Client addresses or CIDRs that are never banned.
+    #[serde(default)]
+    pub exempt_ips: Vec<String>,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct RawServer {
     pub listen: String,
-    pub static_dir: PathBuf,
+    /// Under socket activation, use the passed socket with this `FileDescriptorName=` instead
+    /// of matching on the listen address.
+    pub systemd_socket_name: Option<String>,
+    /// Bind several `SO_REUSEPORT` sockets to `listen`, each with its own accept loop (unix only).
+    pub reuse_port: Option<bool>,
+    /// Number of `reuse_port` acceptors; 0 or unset means one per runtime worker.
+    pub reuse_port_acceptors: Option<usize>,
+    /// Cap on open client connections for this server.
+    pub max_connections: Option<usize>,
+    /// What to do with connections over the cap: "close" (default), "503" or "429".
+    pub max_connections_action: Option<ConnectionLimitAction>,
+    /// Cap on open connections from one client address (the TCP peer) to this server.
+    pub max_connections_per_ip: Option<usize>,
+    /// What to do with connections over the per-IP cap: "close" (default), "429" or "503".
+    pub max_connections_per_ip_action: Option<ConnectionLimitAction>,
+    /// Requests served on one HTTP/1 connection before it is closed. Unlimited when unset.
+    pub max_requests_per_connection: Option<u64>,
+    /// Slowest a client may read responses, in bytes per second, before its connection is
+    /// aborted. Off when unset.
+    pub min_write_rate_bytes_per_sec: Option<u64>,
+    /// Blocked-write time the rate is measured over (default 10).
+    pub min_write_rate_window_secs: Option<u64>,
+    /// PROXY protocol header expected from `proxy_protocol_trusted` peers: "off" (default),
+    /// "v1" or "v2".
+    pub proxy_protocol: Option<ProxyProtocolMode>,
+    /// Load balancer addresses or CIDRs whose connections start with a PROXY header.
+    #[serde(default)]
+    pub proxy_protocol_trusted: Vec<String>,
+    /// Largest request head (request line and headers) accepted, in bytes. At least 8192.
+    pub max_request_head_bytes: Option<usize>,
+    /// Time a client gets to send a complete request head. Unlimited when unset.
+    pub header_read_timeout_secs: Option<u64>,
+    pub static_dir: Option<PathBuf>,
+    /// Directories searched in order for each static file, e.g. overrides before a base theme.
+    /// Used instead of `static_dir`.
+    pub static_dirs: Option<Vec<PathBuf>>,
+    /// Default documents tried in order for directory requests (default `["index.html"]`).
+    pub index_files: Option<Vec<String>>,
+    /// File extensions served from the static directories at their own path rather than
+    /// proxied, e.g. `["css", "js"]`.
+    pub static_extensions: Option<Vec<String>>,
+    /// `Content-Security-Policy` added to HTML documents served from `static_dir`.
+    pub csp: Option<String>,
+    /// `Content-Security-Policy-Report-Only` added to the same documents.
+    pub csp_report_only: Option<String>,
+    /// Replace a CSP header already present on the response (default false).
+    pub csp_override: Option<bool>,
     pub cert: Option<PathBuf>,
     pub key: Option<PathBuf>,
+    /// Max stateful TLS sessions kept for resumption (default 256, 0 disables).
+    pub tls_session_cache_size: Option<u64>,
+    /// Session ticket key rotation interval; tickets stay valid for up to twice this.
+    /// Tickets are disabled when unset.
+    pub tls_ticket_lifetime_secs: Option<u64>,
+    /// Also serve HTTP/3 over QUIC on the same port (UDP). Needs cert/key and the `http3` feature.
+    pub http3: Option<bool>,
+    /// Accept TLS 1.3 early data (0-RTT) on resumed sessions and handle early requests with this
+    /// policy. 0-RTT stays off when unset.
+    pub tls_early_data: Option<EarlyDataPolicy>,
+    /// Compute JA3/JA4 fingerprints of each client's TLS ClientHello (default false).
+    pub tls_fingerprint: Option<bool>,
+    /// Bearer token guarding the `/admin/*` endpoints. Admin routes are not mounted when unset.
+    pub admin_token: Option<RawSecret>,
+    /// Serve the `/admin/*` routes on this address instead of the public listener.
+    pub admin_listen: Option<String>,
+    /// Mount `/admin/debug/panic`, which panics on purpose, to exercise panic handling.
+    pub debug_panic_route: Option<bool>,
+    /// Path answering readiness probes (200, or 503 once shutdown has begun). Off when unset.
+    pub readiness_path: Option<String>,
+    /// File served at `/favicon.ico` ahead of the proxy.
+    pub favicon: Option<PathBuf>,
+    /// Body served at `/robots.txt`: a file path, or inline content when it spans several lines.
+    pub robots_txt: Option<String>,
+    /// `[servers.error_pages]`: HTML template per status for errors raised by the proxy, plus
+    /// `upstream_protocol_error` for malformed backend responses and `maintenance` for
+    /// maintenance windows.
+    #[serde(default)]
+    pub error_pages: BTreeMap<String, PathBuf>,
+    /// `[servers.security_headers]`: reporting headers added to proxied and static responses.
+    pub security_headers: Option<RawSecurityHeaders>,
+    /// `signed_urls`: static paths only served with a valid, unexpired signature.
+    pub signed_urls: Option<RawSignedUrls>,
+    /// `[[servers.block_rule]]`: requests refused before any other handling.
+    #[serde(default)]
+    pub block_rule: Vec<RawBlockRule>,
+    /// `[[servers.redirects]]`: redirects answered by the proxy itself, ahead of static files,
+    /// routes and backends.
+    #[serde(default)]
+    pub redirects: Vec<RawRedirect>,
+    /// `[servers.experiments]`: A/B bucket assignment for proxied requests.
+    pub experiments: Option<RawExperiments>,
     pub proxy: RawProxy,
 }
 
+/// `[servers.experiments]` as written in the config file.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RawExperiments {
+    /// Mixed into the assignment hash, so experiments under different names split visitors
+    /// independently (default "default").
+    pub name: Option<String>,
+    /// Cookie holding the visitor id (default "serava_visitor").
+    pub cookie: Option<String>,
+    /// Cookie lifetime (default 30 days).
+    pub cookie_max_age_secs: Option<u64>,
+    /// Request header telling the backend the bucket (default "X-Experiment-Bucket").
+    pub header: Option<String>,
+    /// Bucket for bots (default the first).
+    pub control: Option<String>,
+    /// Case-insensitive User-Agent substrings marking bots, which always get `control`.
+    /// Replaces the built-in list when set.
+    pub bot_user_agents: Option<Vec<String>>,
+    pub bucket: Vec<RawExperimentBucket>,
+}
+
+/// One `[[servers.experiments.bucket]]`.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RawExperimentBucket {
+    pub name: String,
+    /// Share of visitors; the buckets' shares add up to 100.
+    pub percent: u32,
+}
+
+/// Validated `[servers.experiments]`.
+#[derive(Debug, Clone)]
+pub struct ExperimentConfig {
+    pub name: String,
+    pub cookie: String,
+    pub cookie_max_age: Duration,
+    pub header: HeaderName,
+    /// Index into `buckets`.
+    pub control: usize,
+    /// Lowercased.
+    pub bot_user_agents: Vec<String>,
+    /// Names with their percentages, in config order.
+    pub buckets: Vec<(String, u32)>,
+}
+
+// User-Agent substrings of common crawlers and link previewers.
+const DEFAULT_BOT_USER_AGENTS: &[&str] = &[
+    "bot",
+    "crawl",
+    "spider",
+    "slurp",
+    "facebookexternalhit",
+    "preview",
+    "lighthouse",
+];
+
+/// One `[[servers.redirects]]` rule.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RawRedirect {
+    /// Exact path, glob (`*` and `?`, each a capture group) or, when it starts with `^`, regex.
+    pub from: String,
+    /// Target path or absolute URL; `$1`, `${1}` etc. insert capture groups.
+    pub to: String,
+    /// 301, 302 (default), 307 or 308.
+    pub status: Option<u16>,
+    /// Leave the request's query string off the target (default false).
+    #[serde(default)]
+    pub drop_query: bool,
+}
+
+/// How a `redirects` rule matches the percent-decoded request path.
+#[derive(Debug, Clone)]
+pub enum RedirectFrom {
+    Exact(String),
+    /// A glob or regex, anchored as written.
+    Pattern(Regex),
+}
+
+/// A piece of a redirect target.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RedirectPart {
+    Literal(String),
+    /// Capture group, percent-encoded as it is inserted.
+    Capture(usize),
+}
+
+/// Validated `redirects` rule.
+#[derive(Debug, Clone)]
+pub struct RedirectRule {
+    pub from: RedirectFrom,
+    /// `to` as written, for logs.
+    pub to_raw: String,
+    pub to: Vec<RedirectPart>,
+    pub status: u16,
+    pub drop_query: bool,
+}
+
+/// One `[[servers.block_rule]]` block. The rule matches when any of its patterns does.
+#[derive(Debug, Deserialize)]
+pub struct RawBlockRule {
+    /// Label used in logs and `/admin/stats` (default `block_rule[<index>]`).
+    pub name: Option<String>,
+    /// Globs matched against the whole decoded path: `*` is any run of characters, `?` one.
+    #[serde(default)]
+    pub paths: Vec<String>,
+    /// Regexes searched for in the decoded path.
+    #[serde(default)]
+    pub path_regex: Vec<String>,
+    /// Case-insensitive substrings of the `User-Agent` header.
+    #[serde(default)]
+    pub user_agents: Vec<String>,
+    /// Regexes searched for in the `User-Agent` header.
+    #[serde(default)]
+    pub user_agent_regex: Vec<String>,
+    /// JA3 hashes (hex MD5) of the client's TLS ClientHello; needs `tls_fingerprint`.
+    #[serde(default)]
+    pub ja3: Vec<String>,
+    /// JA4 fingerprints of the client's TLS ClientHello; needs `tls_fingerprint`.
+    #[serde(default)]
+    pub ja4: Vec<String>,
+    pub action: BlockAction,
+    /// Only log and count matches, letting the request through (default false).
+    #[serde(default)]
+    pub log_only: bool,
+}
+
+/// What a matching `block_rule` does with the request; also how `[auto_ban]` answers banned clients.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum BlockAction {
+    #[serde(rename = "403")]
+    Forbidden,
+    #[serde(rename = "404")]
+    NotFound,
+    /// Close the connection without answering.
+    #[serde(rename = "drop")]
+    Drop,
+}
+
+/// Validated `block_rule`, with its patterns compiled.
+#[derive(Debug, Clone)]
+pub struct BlockRule {
+    pub name: String,
+    /// Globs (anchored) and regexes for the path.
+    pub paths: Vec<Regex>,
+    /// Lowercased substrings.
+    pub user_agents: Vec<String>,
+    pub user_agent_regex: Vec<Regex>,
+    /// Lowercased, like the fingerprints they are compared with.
+    pub ja3: Vec<String>,
+    pub ja4: Vec<String>,
+    pub action: BlockAction,
+    pub log_only: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RawSecurityHeaders {
+    /// Set to false to drop the headers without deleting their values (default true).
+    pub enabled: Option<bool>,
+    pub expect_ct: Option<String>,
+    /// JSON endpoint group(s), as sent in the header.
+    pub report_to: Option<String>,
+    /// JSON Network Error Logging policy.
+    pub nel: Option<String>,
+}
+
 #[derive(Debug, Deserialize)]
 #[serde(untagged)]
 pub enum BackendField {
-    Single(String),
-    Multiple(Vec<String>),
+    Single(RawBackend),
+    Multiple(Vec<RawBackend>),
+}
+
+/// A backend URL, or a table with the URL and options for that backend.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum RawBackend {
+    Url(String),
+    Options {
+        url: String,
+        /// Open connections with a PROXY protocol header: "off" (default), "v1" or "v2". Such
+        /// connections carry one request each, as the header names a single client.
+        send_proxy_protocol: Option<ProxyProtocolMode>,
+    },
 }
 
 #[derive(Debug, Deserialize)]
 pub struct RawProxy {
     pub backend: BackendField,
     pub backend_timeout_secs: Option<u64>,
+    /// Deadline for the whole request, including streaming the response body (0 = unlimited).
+    pub request_timeout_secs: Option<u64>,
+    /// Log a warning for proxied requests taking at least this long (0 or unset = off).
+    pub slow_request_threshold_ms: Option<u64>,
     pub rate_limit_per_minute: Option<u64>,
     pub rate_limit_burst: Option<u64>,
     pub max_request_size_bytes: Option<u64>,
+    /// Longest path and query accepted before answering 414 (default 8192 bytes).
+    pub max_uri_length: Option<u64>,
     pub cache_ttl_secs: Option<u64>,
     pub cache_max_size_bytes: Option<u64>,
+    /// Largest cacheable entry as a fraction of `cache_max_size_bytes` (default 1.0).
+    pub cache_max_entry_fraction: Option<f64>,
+    /// Randomize each entry's TTL by up to this fraction either way (0 or unset = off).
+    pub cache_ttl_jitter: Option<f64>,
+    /// Include the Host header in cache keys, for servers answering several hosts (default false).
+    pub cache_key_include_host: Option<bool>,
+    /// Path form used in cache keys and the slow request log: "raw" (default) or "decoded".
+    pub path_decoding: Option<PathDecoding>,
+    /// Requests carrying any of these headers skip the cache, both lookup and store (default
+    /// `["Authorization", "Cookie"]`; an empty list turns the check off).
+    pub cache_bypass_headers: Option<Vec<String>>,
+    /// `[[servers.proxy.cache_route]]`: per-path-prefix override of response caching.
+    #[serde(default)]
+    pub cache_route: Vec<RawCacheRoute>,
+    pub debug_headers: Option<bool>,
+    /// Client IPs/CIDRs allowed to use the proxy (empty = everyone not denied).
+    #[serde(default)]
+    pub allow_ips: Vec<String>,
+    /// Client IPs/CIDRs refused by the proxy; checked before `allow_ips`.
+    #[serde(default)]
+    pub deny_ips: Vec<String>,
+    /// Answer denied requests with 404 instead of 403 (default false).
+    pub conceal: Option<bool>,
+    /// `[[servers.proxy.access_route]]`: per-path-prefix replacement for the lists above.
+    #[serde(default)]
+    pub access_route: Vec<RawAccessRoute>,
+    /// `[[servers.proxy.auth_route]]`: Basic/Bearer authentication for a path prefix.
+    #[serde(default)]
+    pub auth_route: Vec<RawAuthRoute>,
+    /// `[servers.proxy.origin_check]`: Origin/Referer check for state-changing requests.
+    pub origin_check: Option<RawOriginCheck>,
+    /// `[[servers.proxy.origin_route]]`: per-path-prefix override of `origin_check`.
+    #[serde(default)]
+    pub origin_route: Vec<RawOriginRoute>,
+    /// `[[servers.proxy.content_type_route]]`: request Content-Type allowlist for a path prefix.
+    #[serde(default)]
+    pub content_type_route: Vec<RawContentTypeRoute>,
+    /// `[[servers.proxy.maintenance_window]]`: times during which proxied requests get a 503.
+    #[serde(default)]
+    pub maintenance_window: Vec<RawMaintenanceWindow>,
+    /// Clients served normally during a maintenance window (resolved through `trusted_proxies`).
+    #[serde(default)]
+    pub maintenance_bypass_ips: Vec<String>,
+    /// `[[servers.proxy.html_rewrite]]`: edits applied to HTML responses below a path prefix.
+    #[serde(default)]
+    pub html_rewrite: Vec<RawHtmlRewrite>,
+    /// `[servers.proxy.fastcgi]`: how requests to `fastcgi://` backends map to scripts.
+    pub fastcgi: Option<RawFastCgi>,
+    /// Proxies whose X-Forwarded-For is believed when resolving the client IP for access checks.
+    #[serde(default)]
+    pub trusted_proxies: Vec<String>,
+    #[serde(default)]
+    pub rate_limit: Vec<RawRateLimitRule>,
+    pub rate_limit_on_missing_ip: Option<MissingIpPolicy>,
+    pub rate_limit_max_entries: Option<u64>,
+    /// Client addresses or CIDRs exempt from rate limits and `max_connections_per_ip`.
+    #[serde(default)]
+    pub rate_limit_exempt_ips: Vec<String>,
+    pub allow_backend_pinning: Option<bool>,
+    /// Accept absolute-form request targets whose authority matches Host (default false: 400).
+    pub allow_absolute_form: Option<bool>,
+    /// Forward only these request headers instead of everything but hop-by-hop/sensitive ones.
+    pub forward_headers_allowlist: Option<Vec<String>>,
+    /// Send the client's JA3/JA4 fingerprints upstream in `X-TLS-JA3`/`X-TLS-JA4`
+    /// (default false). Needs `tls_fingerprint`.
+    pub forward_tls_fingerprint: Option<bool>,
+    /// Send the negotiated TLS version and cipher suite upstream in `X-TLS-Version`/
+    /// `X-TLS-Cipher` (default false).
+    pub forward_tls_info: Option<bool>,
+    #[serde(default)]
+    pub backend_pinning_trusted_ips: Vec<String>,
+    #[serde(default)]
+    pub query_rewrite: Vec<RawQueryRewrite>,
+    /// `[[servers.proxy.query_route]]`: query rewrites for one path prefix, after `query_rewrite`.
+    #[serde(default)]
+    pub query_route: Vec<RawQueryRoute>,
+    #[serde(default, rename = "match")]
+    pub match_rules: Vec<RawMatchRule>,
+    pub trailing_slash: Option<TrailingSlash>,
+    /// Collapse repeated slashes and resolve `.`/`..` segments before routing (default false).
+    pub normalize_path: Option<bool>,
+    /// `[servers.proxy.request_signing]`: HMAC-sign forwarded requests for the backend.
+    pub request_signing: Option<RawRequestSigning>,
+    /// `[servers.proxy.cookie_rewrite]`: adjust upstream `Set-Cookie` attributes.
+    pub cookie_rewrite: Option<RawCookieRewrite>,
+    /// MaxMind database used to add `X-Geo-Country` to forwarded requests, and for
+    /// `deny_countries` and `match_country`.
+    pub geoip_db: Option<PathBuf>,
+    /// MaxMind ASN database used to add `X-Geo-ASN` to forwarded requests.
+    pub geoip_asn_db: Option<PathBuf>,
+    /// Clients from these countries (ISO codes, via `geoip_db`) get a 403.
+    #[serde(default)]
+    pub deny_countries: Vec<String>,
+    /// How long responses to `Idempotency-Key` requests are replayed. Off when unset or 0.
+    pub idempotency_window_secs: Option<u64>,
+    /// Largest response body kept for replay (default 1 MiB).
+    pub idempotency_max_body_bytes: Option<u64>,
+    /// Concurrent requests allowed per backend; a full backend is skipped for the next one.
+    pub max_connections_per_backend: Option<u64>,
+    /// Requests allowed to wait when every backend of their pool is full (default: none wait).
+    pub queue_max_len: Option<u64>,
+    /// Longest a queued request waits for a backend before it gets 503 (default 1000).
+    pub queue_max_wait_ms: Option<u64>,
+    /// Most request-body bytes held for a forwarded upload before the backend takes them.
+    pub max_upload_buffer_bytes: Option<u64>,
+    /// Inflate gzip/deflate request bodies before forwarding them (default false).
+    pub decompress_request_body: Option<bool>,
+    /// Largest inflated body accepted (default `max_request_size_bytes`); larger ones get 413.
+    pub max_decompressed_body_bytes: Option<u64>,
+}
+
+/// One `[[servers.proxy.match]]` rule: requests whose header or cookie matches are sent to
+/// `backend` instead of the default pool.
+#[derive(Debug, Deserialize)]
+pub struct RawMatchRule {
+    pub header: Option<String>,
+    pub cookie: Option<String>,
+    /// ISO country codes of the client, as resolved through `geoip_db`.
+    pub match_country: Option<Vec<String>>,
+    /// Exact value to match.
+    pub value: Option<String>,
+    /// Regex to match against the value (alternative to `value`).
+    pub regex: Option<String>,
+    pub backend: BackendField,
+    /// Overrides the server's `request_timeout_secs` for matching requests (0 = unlimited).
+    pub request_timeout_secs: Option<u64>,
+}
+
+#[derive(Debug, Clone)]
+pub enum MatchSource {
+    Header(String),
+    Cookie(String),
+    /// The client's GeoIP country code.
+    Country,
+}
+
+#[derive(Debug, Clone)]
+pub enum ValueMatcher {
+    Exact(String),
+    Regex(Regex),
+    AnyOf(Vec<String>),
+}
+
+impl ValueMatcher {
+    pub fn is_match(&self, value: &str) -> bool {
+        match self {
+            ValueMatcher::Exact(expected) => value == expected,
+            ValueMatcher::Regex(re) => re.is_match(value),
+            ValueMatcher::AnyOf(values) => values.iter().any(|v| v == value),
+        }
+    }
+}
+
+/// Validated request-matching route; rules are evaluated in order, first match wins.
+#[derive(Debug, Clone)]
+pub struct MatchRule {
+    pub source: MatchSource,
+    pub matcher: ValueMatcher,
+    pub backends: Vec<Url>,
+    /// Per-route total request deadline; `Some(ZERO)` disables the server's deadline.
+    pub request_timeout: Option<Duration>,
+}
+
+/// One `[[servers.proxy.rate_limit]]` rule.
+#[derive(Debug, Deserialize)]
+pub struct RawRateLimitRule {
+    /// Key source: `ip`, `header:<Name>` or `global`.
+    pub key: String,
+    pub per_minute: u64,
+    pub burst: Option<u64>,
+}
+
+/// Which request attribute a rate-limit rule buckets on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RateLimitKey {
+    Ip,
+    Header(String),
+    Global,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum QueryRewriteOp {
+    Add,
+    Set,
+    Remove,
+}
+
+/// One `[[servers.proxy.cache_route]]` override.
+#[derive(Debug, Deserialize)]
+pub struct RawCacheRoute {
+    pub path_prefix: String,
+    /// false never caches; true caches even if the backend sends `no-cache` (not `no-store`).
+    pub cache: bool,
+}
+
+/// Validated cache override for a path prefix.
+#[derive(Debug, Clone)]
+pub struct CacheRoute {
+    pub path_prefix: String,
+    pub cache: bool,
+}
+
+/// One `[[servers.proxy.access_route]]` block.
+#[derive(Debug, Deserialize)]
+pub struct RawAccessRoute {
+    pub path_prefix: String,
+    #[serde(default)]
+    pub allow_ips: Vec<String>,
+    #[serde(default)]
+    pub deny_ips: Vec<String>,
+    /// Defaults to the server's `conceal`.
+    pub conceal: Option<bool>,
+}
+
+/// Validated client IP allow/deny lists.
+#[derive(Debug, Clone, Default)]
+pub struct AccessRule {
+    pub allow: Vec<IpNet>,
+    pub deny: Vec<IpNet>,
+    /// Answer 404 rather than 403 when denied.
+    pub conceal: bool,
+}
+
+/// Access rule for a path prefix, replacing the server-level one below it.
+#[derive(Debug, Clone)]
+pub struct AccessRoute {
+    pub path_prefix: String,
+    pub rule: AccessRule,
+}
+
+/// One `[[servers.proxy.auth_route]]` block.
+#[derive(Debug, Deserialize)]
+pub struct RawAuthRoute {
+    pub path_prefix: String,
+    pub auth: RawAuth,
+    /// Pass the client's Authorization header on to the backend (default false: stripped).
+    pub forward_credentials: Option<bool>,
+}
+
+/// `auth = { type = "basic", htpasswd = "..." }` or `{ type = "bearer", tokens_file = "..." }`.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum RawAuth {
+    Basic {
+        htpasswd: PathBuf,
+        realm: Option<String>,
+    },
+    Bearer {
+        tokens_file: PathBuf,
+        realm: Option<String>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuthScheme {
+    /// htpasswd file with bcrypt or `{SHA}` entries.
+    Basic,
+    /// File with one `name:token` per line.
+    Bearer,
+}
+
+/// Validated authentication requirement for a path prefix.
+#[derive(Debug, Clone)]
+pub struct AuthRoute {
+    pub path_prefix: String,
+    pub scheme: AuthScheme,
+    /// Loaded at startup and again on SIGHUP.
+    pub credentials_file: PathBuf,
+    pub realm: String,
+    pub forward_credentials: bool,
+}
+
+/// `[servers.proxy.origin_check]` table.
+#[derive(Debug, Deserialize)]
+pub struct RawOriginCheck {
+    /// Default true once the table is present.
+    pub enabled: Option<bool>,
+    /// `scheme://host[:port]` origins accepted; empty means the request's own Host only.
+    #[serde(default)]
+    pub allowed_origins: Vec<String>,
+    pub on_missing: Option<MissingOriginPolicy>,
+}
+
+/// One `[[servers.proxy.origin_route]]` block.
+#[derive(Debug, Deserialize)]
+pub struct RawOriginRoute {
+    pub path_prefix: String,
+    /// `false` skips the check below this prefix (default true).
+    pub enabled: Option<bool>,
+    /// Defaults to the server's `allowed_origins`.
+    pub allowed_origins: Option<Vec<String>>,
+    /// Defaults to the server's `on_missing`.
+    pub on_missing: Option<MissingOriginPolicy>,
+}
+
+/// What the origin check does with a request carrying neither Origin nor Referer.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum MissingOriginPolicy {
+    /// Let it through; non-browser clients usually send neither header.
+    #[default]
+    Allow,
+    /// Refuse it like a mismatch.
+    Deny,
+}
+
+/// One `[[servers.proxy.content_type_route]]` block.
+#[derive(Debug, Deserialize)]
+pub struct RawContentTypeRoute {
+    pub path_prefix: String,
+    /// Media types (`type/subtype` or `type/*`) accepted for request bodies.
+    pub allowed_content_types: Vec<String>,
+    /// Requests with a body but no Content-Type (default deny).
+    pub on_missing: Option<MissingContentTypePolicy>,
+}
+
+/// What a `content_type_route` does with a request body that has no Content-Type.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum MissingContentTypePolicy {
+    Allow,
+    /// Refuse it with 415, like a type that isn't listed.
+    #[default]
+    Deny,
+}
+
+/// Validated Content-Type allowlist for a path prefix.
+#[derive(Debug, Clone)]
+pub struct ContentTypeRoute {
+    pub path_prefix: String,
+    /// Lowercase `type/subtype`, or `type/*` for any subtype.
+    pub allowed: Vec<String>,
+    pub on_missing: MissingContentTypePolicy,
+}
+
+/// One `[[servers.proxy.html_rewrite]]` block.
+#[derive(Debug, Deserialize)]
+pub struct RawHtmlRewrite {
+    /// Applies to responses for this path and everything below it (matched on whole segments).
+    pub path_prefix: String,
+    /// Replace any `<base>` elements with `<base href="...">` at the start of `<head>`.
+    pub base_href: Option<String>,
+    /// `[[servers.proxy.html_rewrite.inject]]`: HTML inserted at elements matching a selector.
+    #[serde(default)]
+    pub inject: Vec<RawHtmlInject>,
+    /// `[[servers.proxy.html_rewrite.rewrite_links]]`: `href`/`src` prefix replacements.
+    #[serde(default)]
+    pub rewrite_links: Vec<RawLinkRewrite>,
+}
+
+/// One `[[servers.proxy.html_rewrite.inject]]` block.
+#[derive(Debug, Deserialize)]
+pub struct RawHtmlInject {
+    /// CSS selector of the elements to insert at, e.g. `head`.
+    pub selector: String,
+    /// Where, relative to each matching element (default append: just before its end tag).
+    pub position: Option<InjectPosition>,
+    /// Raw HTML inserted as is.
+    pub html: String,
+}
+
+/// Where injected HTML goes relative to a matching element.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum InjectPosition {
+    /// Before the start tag.
+    Before,
+    /// Right after the start tag.
+    Prepend,
+    /// Right before the end tag.
+    #[default]
+    Append,
+    /// After the end tag.
+    After,
+    /// Instead of the element.
+    Replace,
+}
+
+/// One `[[servers.proxy.html_rewrite.rewrite_links]]` block.
+#[derive(Debug, Deserialize)]
+pub struct RawLinkRewrite {
+    pub from: String,
+    pub to: String,
+}
+
+/// Validated HTML insertion.
+#[derive(Debug, Clone)]
+pub struct HtmlInject {
+    pub selector: lol_html::Selector,
+    pub position: InjectPosition,
+    pub html: String,
+}
+
+/// Validated `href`/`src` prefix replacement.
+#[derive(Debug, Clone)]
+pub struct LinkRewrite {
+    pub from: String,
+    pub to: String,
+}
+
+/// Validated HTML rewriting for a path prefix.
+#[derive(Debug, Clone)]
+pub struct HtmlRewriteRoute {
+    pub path_prefix: String,
+    pub base_href: Option<String>,
+    pub inject: Vec<HtmlInject>,
+    /// Checked in order; the first matching prefix wins.
+    pub rewrite_links: Vec<LinkRewrite>,
+}
+
+/// `[servers.proxy.fastcgi]`.
+#[derive(Debug, Deserialize)]
+pub struct RawFastCgi {
+    /// Directory the request path is resolved against to name the script (`SCRIPT_FILENAME`).
+    pub document_root: PathBuf,
+    /// Script run for paths ending in `/` (default `index.php`).
+    pub index: Option<String>,
+}
+
+/// Validated `[servers.proxy.fastcgi]`.
+#[derive(Debug, Clone)]
+pub struct FastCgiConfig {
+    /// Absolute, without a trailing slash.
+    pub document_root: String,
+    pub index: String,
+}
+
+/// One `[[servers.proxy.maintenance_window]]` block.
+#[derive(Debug, Deserialize)]
+pub struct RawMaintenanceWindow {
+    /// `YYYY-MM-DDTHH:MM` for a one-off window, or `HH:MM` for one that recurs.
+    pub start: String,
+    /// Same form as `start`. A recurring window ending before its start runs past midnight.
+    pub end: String,
+    /// Weekdays a recurring window starts on (default every day).
+    pub days: Option<Vec<String>>,
+    /// IANA time zone the times are in (default UTC).
+    pub timezone: Option<String>,
+}
+
+/// Validated maintenance window.
+#[derive(Debug, Clone)]
+pub enum MaintenanceWindow {
+    Once {
+        start: Timestamp,
+        end: Timestamp,
+    },
+    Recurring {
+        start: civil::Time,
+        end: civil::Time,
+        /// Empty means every day.
+        days: Vec<Weekday>,
+        timezone: TimeZone,
+    },
+}
+
+/// Validated origin check.
+#[derive(Debug, Clone)]
+pub struct OriginRule {
+    /// Canonical `scheme://host:port` strings; empty means same-origin only.
+    pub allowed: Vec<String>,
+    pub on_missing: MissingOriginPolicy,
+}
+
+/// Origin check for a path prefix, replacing the server-level one below it.
+#[derive(Debug, Clone)]
+pub struct OriginRoute {
+    pub path_prefix: String,
+    /// `None` when the check is skipped for this prefix.
+    pub rule: Option<OriginRule>,
+}
+
+/// One `[[servers.proxy.query_rewrite]]` operation.
+#[derive(Debug, Deserialize)]
+pub struct RawQueryRewrite {
+    pub op: QueryRewriteOp,
+    pub name: String,
+    pub value: Option<String>,
+}
+
+/// Validated query-string rewrite applied to upstream requests.
+#[derive(Debug, Clone)]
+pub enum QueryRewrite {
+    /// Append `name=value`, keeping existing occurrences.
+    Add { name: String, value: String },
+    /// Replace all occurrences with a single `name=value` (appended if absent).
+    Set { name: String, value: String },
+    /// Remove every occurrence of `name`.
+    Remove { name: String },
+    /// Remove every parameter whose name matches the (anchored) pattern.
+    RemoveMatching { pattern: Regex },
+    /// Rename every occurrence of `from`, keeping its value and position.
+    Rename { from: String, to: String },
+}
+
+/// One `[[servers.proxy.query_route]]` block.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RawQueryRoute {
+    pub path_prefix: String,
+    /// Parameters set to these values, replacing any the client sent.
+    #[serde(default)]
+    pub query_set: BTreeMap<String, String>,
+    /// Names of parameters dropped; `*` and `?` are wildcards.
+    #[serde(default)]
+    pub query_remove: Vec<String>,
+    /// Parameters renamed, old name to new.
+    #[serde(default)]
+    pub query_rename: BTreeMap<String, String>,
+}
+
+/// Validated query rewrites for a path prefix: removals, then renames, then sets.
+#[derive(Debug, Clone)]
+pub struct QueryRoute {
+    pub path_prefix: String,
+    pub rewrites: Vec<QueryRewrite>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RuntimeFlavor {
+    #[default]
+    MultiThread,
+    /// Run everything on the main thread; for minimal single-core deployments.
+    CurrentThread,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RawRequestSigning {
+    pub secret: RawSecret,
+    /// Parts of the request covered by the signature, in order (default method, path, timestamp).
+    pub components: Option<Vec<SignComponent>>,
+}
+
+/// A request component included in the HMAC signature.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SignComponent {
+    Method,
+    /// Upstream path, without the query string.
+    Path,
+    /// Upstream query string (empty when absent).
+    Query,
+    /// Unix seconds, also sent as `X-Signature-Timestamp`.
+    Timestamp,
+}
+
+/// Validated request signing settings.
+#[derive(Debug, Clone)]
+pub struct RequestSigning {
+    pub secret: Secret<String>,
+    pub components: Vec<SignComponent>,
+}
+
+/// A secret setting: a literal string, `{ env = "VAR" }` or `{ file = "/run/secrets/x" }`.
+#[derive(Deserialize)]
+#[serde(
+    untagged,
+    expecting = r#"a string, { env = "VAR" } or { file = "/path" }"#
+)]
+pub enum RawSecret {
+    Inline(String),
+    Env { env: String },
+    File { file: PathBuf },
+}
+
+impl std::fmt::Debug for RawSecret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RawSecret::Inline(_) => f.write_str("Inline(<redacted>)"),
+            RawSecret::Env { env } => f.debug_struct("Env").field("env", env).finish(),
+            RawSecret::File { file } => f.debug_struct("File").field("file", file).finish(),
+        }
+    }
+}
+
+impl RawSecret {
+    /// Read the value; `field` names the setting in errors. Trailing newlines of a file are
+    /// dropped, since most editors and `echo` add one.
+    pub fn resolve(self, field: &str) -> Result<Secret<String>, ValidationError> {
+        let unresolved = |e: String| ValidationError::UnresolvedSecret(field.to_string(), e);
+        let value = match self {
+            RawSecret::Inline(value) => value,
+            RawSecret::Env { env } => std::env::var(&env).map_err(|e| match e {
+                std::env::VarError::NotPresent => {
+                    unresolved(format!("environment variable {} is not set", env))
+                }
+                std::env::VarError::NotUnicode(_) => {
+                    unresolved(format!("environment variable {} is not valid UTF-8", env))
+                }
+            })?,
+            RawSecret::File { file } => std::fs::read_to_string(&file)
+                .map(|v| v.trim_end_matches(['\r', '\n']).to_string())
+                .map_err(|e| unresolved(format!("failed to read {}: {}", file.display(), e)))?,
+        };
+        Ok(Secret(value))
+    }
+}
+
+/// A resolved secret. `Debug` never shows the value; use [`Secret::expose`] where it's needed.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Secret<T>(T);
+
+impl<T> Secret<T> {
+    pub fn expose(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> std::fmt::Debug for Secret<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Secret(<redacted>)")
+    }
+}
+
+/// `signed_urls = { secret = ..., required_prefixes = [...] }`.
+#[derive(Debug, Deserialize)]
+pub struct RawSignedUrls {
+    pub secret: RawSecret,
+    /// Request paths (under `/static`) that need a signature.
+    pub required_prefixes: Vec<String>,
+}
+
+/// Validated `signed_urls`.
+#[derive(Debug, Clone)]
+pub struct SignedUrlsConfig {
+    pub secret: Secret<String>,
+    /// Without trailing slashes, matched on whole segments.
+    pub required_prefixes: Vec<String>,
+}
+
+/// What the startup backend reachability check does with unreachable backends.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BackendCheckMode {
+    /// Don't check.
+    #[default]
+    Off,
+    /// Log unreachable backends and start anyway.
+    Warn,
+    /// Refuse to start.
+    Fail,
+}
+
+/// Handling of connections accepted while a `max_connections` or `max_connections_per_ip` cap
+/// is saturated.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+pub enum ConnectionLimitAction {
+    /// Close the connection immediately.
+    #[default]
+    #[serde(rename = "close")]
+    Close,
+    /// Answer the first request with 503 and `Connection: close`.
+    #[serde(rename = "503")]
+    ServiceUnavailable,
+    /// Answer the first request with 429 and `Connection: close`.
+    #[serde(rename = "429")]
+    TooManyRequests,
+}
+
+/// `min_write_rate_bytes_per_sec`: the slowest response reading a connection is allowed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MinWriteRate {
+    pub bytes_per_sec: u64,
+    pub window: Duration,
+}
+
+/// `proxy_protocol` setting of a server.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProxyProtocolMode {
+    #[default]
+    Off,
+    V1,
+    V2,
+}
+
+/// PROXY protocol version a server reads.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProxyProtocolVersion {
+    /// The text header.
+    V1,
+    /// The binary header.
+    V2,
+}
+
+/// Validated `queue_max_len` and `queue_max_wait_ms`.
+#[derive(Debug, Clone, Copy)]
+pub struct BackendQueueLimits {
+    pub max_len: usize,
+    pub max_wait: Duration,
+}
+
+/// Validated `proxy_protocol` and `proxy_protocol_trusted`.
+#[derive(Debug, Clone)]
+pub struct ProxyProtocolConfig {
+    pub version: ProxyProtocolVersion,
+    pub trusted: Vec<IpNet>,
+}
+
+/// What happens to requests that arrived as TLS early data, which an attacker can replay.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EarlyDataPolicy {
+    /// Answer `425 Too Early` to methods that aren't idempotent; forward the rest.
+    Reject,
+    /// Forward every request and let the backend decide.
+    Forward,
+}
+
+/// How a trailing `/` on the request path is treated before forwarding.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TrailingSlash {
+    /// Forward the path unchanged.
+    #[default]
+    Preserve,
+    /// Forward `/path/` as `/path`.
+    Strip,
+    /// Forward `/path` as `/path/` (paths ending in a file name are left alone).
+    Append,
+    /// Answer `/path/` with a 308 redirect to `/path`.
+    Redirect,
+}
+
+/// How the request path appears in cache keys and logs. The upstream URL is never affected.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PathDecoding {
+    /// As the client sent it.
+    #[default]
+    Raw,
+    /// Cache keys with escapes of unreserved characters decoded; logs fully decoded.
+    Decoded,
+}
+
+/// `[servers.proxy.cookie_rewrite]` as written in the config file.
+#[derive(Debug, Deserialize)]
+pub struct RawCookieRewrite {
+    /// Replaces an existing `Domain` attribute; `""` removes it (host-only cookie).
+    pub domain: Option<String>,
+    /// Sets `Path`, adding it when the backend didn't.
+    pub path: Option<String>,
+    /// true adds `Secure` where missing; false leaves cookies as they are.
+    pub secure: Option<bool>,
+    /// Sets `SameSite`, replacing the backend's value.
+    pub same_site: Option<SameSite>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SameSite {
+    Strict,
+    Lax,
+    None,
+}
+
+/// Validated `Set-Cookie` rewrite.
+#[derive(Debug, Clone)]
+pub struct CookieRewrite {
+    pub domain: Option<String>,
+    pub path: Option<String>,
+    pub secure: bool,
+    pub same_site: Option<SameSite>,
+}
+
+/// What per-IP rate limiting does with requests whose client IP can't be determined.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum MissingIpPolicy {
+    /// Let the request through without rate limiting it.
+    #[default]
+    Allow,
+    /// Reject the request with 429, like a tripped limit.
+    Deny,
+    /// Rate limit all such requests together in one bucket.
+    SharedBucket,
+}
+
+#[derive(Debug, Clone)]
+pub struct RateLimitRule {
+    pub key: RateLimitKey,
+    pub per_minute: f64,
+    pub burst: f64,
+}
+
+#[derive(Debug, Clone)]
+pub struct TlsConfig {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+    pub session_cache_size: usize,
+    pub ticket_lifetime_secs: Option<u32>,
+    pub http3: bool,
+    /// `None` leaves 0-RTT disabled.
+    pub early_data: Option<EarlyDataPolicy>,
+    pub fingerprint: bool,
+}
+
+const DEFAULT_TLS_SESSION_CACHE_SIZE: u64 = 256;
+const MAX_TLS_SESSION_CACHE_SIZE: u64 = 1_000_000;
+// hyper won't run with a smaller read buffer.
+const MIN_REQUEST_HEAD_BYTES: usize = 8192;
+// RFC 8446 caps ticket lifetime at 7 days; rotated keys are accepted for 2x the interval.
+const MAX_TLS_TICKET_LIFETIME_SECS: u64 = 7 * 24 * 60 * 60 / 2;
+
+/// Source of the `/robots.txt` body.
+#[derive(Debug, Clone)]
+pub enum RobotsTxt {
+    File(PathBuf),
+    Inline(String),
+}
+
+/// Validated configuration returned from `RawConfig::validate`.
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub shutdown_grace: Duration,
+    /// Upper bound on the grace period when it is extended for finishing transfers.
+    pub shutdown_grace_max: Option<Duration>,
+    pub max_connections: Option<usize>,
+    pub bind_retry: BindRetry,
+    pub allow_partial_startup: bool,
+    pub server_restart: RestartPolicy,
+    pub startup_backend_check: BackendCheckMode,
+    pub startup_backend_check_timeout: Duration,
+    pub drain_retry_after: Duration,
+    pub drain_serve_static: bool,
+    pub allow_trace: bool,
+    pub runtime: RuntimeConfig,
+    pub limits: LimitsConfig,
+    pub auto_ban: Option<AutoBanConfig>,
+    pub servers: Vec<ConfigEntry>,
+}
+
+/// Retry policy for transient bind failures.
+#[derive(Debug, Clone)]
+pub struct BindRetry {
+    pub attempts: u32,
+    pub backoff: Duration,
+}
+
+/// How a server task that panicked or whose serve loop failed is restarted.
+#[derive(Debug, Clone)]
+pub struct RestartPolicy {
+    /// Consecutive restarts allowed; the next failure makes the process exit.
+    pub limit: u32,
+    /// Delay before the first restart, doubled after each further failure.
+    pub backoff: Duration,
+}
+
+/// Validated tokio runtime settings.
+#[derive(Debug, Clone)]
+pub struct RuntimeConfig {
+    pub flavor: RuntimeFlavor,
+    /// `None` means one worker per core.
+    pub worker_threads: Option<usize>,
+    pub max_blocking_threads: Option<usize>,
+    pub thread_name: Option<String>,
+}
+
+/// Validated `[limits]` settings.
+#[derive(Debug, Clone)]
+pub struct LimitsConfig {
+    /// `None` means caches are only bounded by their own per-server limits.
+    pub total_cache_bytes: Option<usize>,
+}
+
+/// Validated `[auto_ban]`; absent when not configured or disabled.
+#[derive(Debug, Clone)]
+pub struct AutoBanConfig {
+    pub statuses: Vec<u16>,
+    pub max_strikes: u32,
+    pub window: Duration,
+    pub ban: Duration,
+    pub action: BlockAction,
+    pub exempt_ips: Vec<IpNet>,
+}
+
+/// Validated `[servers.security_headers]`; absent when disabled or empty.
+#[derive(Debug, Clone)]
+pub struct SecurityHeadersConfig {
+    pub expect_ct: Option<String>,
+    pub report_to: Option<String>,
+    pub nel: Option<String>,
+}
+
+/// Validated per-server config.
+///
+/// The per-path `*_routes` lists are sorted longest prefix first and looked up with
+/// [`longest_prefix`](crate::path::longest_prefix).
+#[derive(Debug, Clone)]
+pub struct ConfigEntry {
+    pub listen: SocketAddr,
+    pub systemd_socket_name: Option<String>,
+    /// `Some(n)` binds with `SO_REUSEPORT`; `n == 0` means one acceptor per runtime worker.
+    pub reuse_port_acceptors: Option<usize>,
+    pub max_connections: Option<usize>,
+    pub max_connections_action: ConnectionLimitAction,
+    pub max_connections_per_ip: Option<usize>,
+    pub max_connections_per_ip_action: ConnectionLimitAction,
+    pub max_requests_per_connection: Option<u64>,
+    pub min_write_rate: Option<MinWriteRate>,
+    pub proxy_protocol: Option<ProxyProtocolConfig>,
+    /// `None` keeps hyper's default read buffer limit (about 400 KiB).
+    pub max_request_head_bytes: Option<usize>,
+    pub header_read_timeout: Option<Duration>,
+    /// Searched in order; the first directory holding a file serves it.
+    pub static_dirs: Vec<PathBuf>,
+    /// `None` keeps `ServeDir`'s built-in `index.html` handling.
+    pub index_files: Option<Vec<String>>,
+    /// Lowercase, without the dot; empty when every request outside `/static` is proxied.
+    pub static_extensions: Vec<String>,
+    pub csp: Option<String>,
+    pub csp_report_only: Option<String>,
+    pub csp_override: bool,
+    pub security_headers: Option<SecurityHeadersConfig>,
+    pub signed_urls: Option<SignedUrlsConfig>,
+    /// In config order; the first enforcing rule that matches wins.
+    pub block_rules: Vec<BlockRule>,
+    /// In config order; the first matching rule wins.
+    pub redirects: Vec<RedirectRule>,
+    pub experiments: Option<ExperimentConfig>,
+    pub backends: Vec<Url>,
+    pub tls: Option<TlsConfig>,
+    pub admin_token: Option<Secret<String>>,
+    pub admin_listen: Option<SocketAddr>,
+    pub debug_panic_route: bool,
+    pub readiness_path: Option<String>,
+    pub favicon: Option<PathBuf>,
+    pub robots_txt: Option<RobotsTxt>,
+    /// Error page template per status code (400-599), loaded at startup.
+    pub error_pages: Vec<(u16, PathBuf)>,
+    /// Page for backend responses that couldn't be parsed, ahead of the 502 page.
+    pub malformed_response_page: Option<PathBuf>,
+    /// Page for requests refused during a maintenance window, ahead of the 503 page.
+    pub maintenance_page: Option<PathBuf>,
+    pub backend_timeout: Duration,
+    /// Total request deadline (None = unlimited).
+    pub request_timeout: Option<Duration>,
+    pub slow_request_threshold: Option<Duration>,
+    pub rate_limits: Vec<RateLimitRule>,
+    pub rate_limit_on_missing_ip: MissingIpPolicy,
+    pub rate_limit_max_entries: Option<u64>,
+    pub rate_limit_exempt_ips: Vec<IpNet>,
+    pub allow_backend_pinning: bool,
+    pub allow_absolute_form: bool,
+    pub forward_headers_allowlist: Option<Vec<HeaderName>>,
+    pub forward_tls_fingerprint: bool,
+    pub forward_tls_info: bool,
+    pub backend_pinning_trusted_ips: Vec<IpNet>,
+    pub query_rewrites: Vec<QueryRewrite>,
+    pub query_routes: Vec<QueryRoute>,
+    pub match_rules: Vec<MatchRule>,
+    /// Backends taking a PROXY protocol header, by URL.
+    pub send_proxy_protocol: HashMap<Url, ProxyProtocolVersion>,
+    pub trailing_slash: TrailingSlash,
+    pub normalize_path: bool,
+    pub request_signing: Option<RequestSigning>,
+    pub cookie_rewrite: Option<CookieRewrite>,
+    /// GeoIP databases (country first, then ASN) loaded at startup and on SIGHUP.
+    pub geoip_dbs: Vec<PathBuf>,
+    /// Uppercase ISO country codes refused with 403.
+    pub deny_countries: Vec<String>,
+    /// Replay window for `Idempotency-Key` requests (None = disabled).
+    pub idempotency_window: Option<Duration>,
+    pub idempotency_max_body_bytes: u64,
+    pub max_connections_per_backend: Option<u64>,
+    /// Waiting room for requests finding every backend full (None = refused at once).
+    pub backend_queue: Option<BackendQueueLimits>,
+    pub max_upload_buffer_bytes: Option<u64>,
+    /// Inflated size limit when `decompress_request_body` is on (None = bodies forwarded as is).
+    pub max_decompressed_body_bytes: Option<u64>,
+    pub max_request_size_bytes: u64,
+    pub max_uri_length: u64,
+    pub cache_ttl_secs: Option<u64>,
+    pub cache_max_size_bytes: Option<u64>,
+    pub cache_max_entry_bytes: Option<u64>,
+    /// Fraction in (0, 1) each TTL is randomized by (None = exact TTLs).
+    pub cache_ttl_jitter: Option<f64>,
+    pub cache_key_include_host: bool,
+    pub path_decoding: PathDecoding,
+    pub cache_bypass_headers: Vec<HeaderName>,
+    pub cache_routes: Vec<CacheRoute>,
+    pub access: AccessRule,
+    pub access_routes: Vec<AccessRoute>,
+    pub trusted_proxies: Vec<IpNet>,
+    pub auth_routes: Vec<AuthRoute>,
+    pub origin_check: Option<OriginRule>,
+    pub origin_routes: Vec<OriginRoute>,
+    pub content_type_routes: Vec<ContentTypeRoute>,
+    pub maintenance_windows: Vec<MaintenanceWindow>,
+    pub maintenance_bypass_ips: Vec<IpNet>,
+    pub html_rewrite_routes: Vec<HtmlRewriteRoute>,
+    /// Required when any backend is `fastcgi://` or `fastcgi+unix://`.
+    pub fastcgi: Option<FastCgiConfig>,
+    pub debug_headers: bool,
+}
+
+#[derive(Debug)]
+pub enum ValidationError {
+    InvalidListenAddress(String),
+    StaticDirDoesNotExist(String),
+    StaticDirNotADirectory(String),
+    InvalidStaticDirs(String, &'static str),
+    InvalidStaticExtension(String, String),
+    NoServersConfigured,
+    InvalidRuntime(String),
+    InvalidLimits(String),
+    InvalidAutoBan(String),
+    InvalidShutdownGraceMax(u64, u64),
+    ReusePortUnsupported(String),
+    InvalidMaxConnections(String),
+    InvalidMaxConnectionsPerIp(String),
+    InvalidMaxConnectionsPerBackend(String),
+    InvalidMaxRequestHeadBytes(String),
+    InvalidHeaderReadTimeout(String),
+    InvalidMaxRequestsPerConnection(String),
+    InvalidMinWriteRate(String, &'static str),
+    InvalidProxyProtocol(String, &'static str),
+    InvalidSendProxyProtocol(String, String),
+    InvalidBackendQueue(String, &'static str),
+    InvalidFastCgi(String, String),
+    InvalidUploadBuffer(String),
+    InvalidMaxDecompressedBody(String),
+    InvalidMaxUriLength(String),
+    AcceptorsWithoutReusePort(String),
+    NoBackendsConfigured(String),
+    InvalidBackendUrl(String, String),
+    UnsupportedBackendScheme(String),
+    TlsFileNotFound(String),
+    Http3WithoutTls(String),
+    InvalidEarlyData(String, &'static str),
+    InvalidTlsFingerprint(String, &'static str),
+    IncompleteTlsConfig(String),
+    InvalidTlsSessionCacheSize(String),
+    InvalidTlsTicketLifetime(String),
+    EmptyAdminToken(String),
+    AdminListenWithoutToken(String),
+    InvalidReadinessPath(String),
+    InvalidCsp(String, &'static str),
+    InvalidSecurityHeader(String, &'static str, String),
+    InvalidIndexFile(String, String),
+    FaviconNotFound(String),
+    InvalidErrorPage(String, String),
+    UnresolvedSecret(String, String),
+    InvalidSignedUrls(String, String),
+    RobotsTxtNotFound(String),
+    GeoIpDbRequired(String),
+    InvalidDenyCountries(String, String),
+    InvalidRequestSigning(String, String),
+    InvalidCookieRewrite(String, String),
+    InvalidRateLimitKey(String),
+    InvalidRateLimit(String),
+    InvalidRateLimitMaxEntries(String),
+    InvalidCacheMaxEntryFraction(String),
+    InvalidCacheTtlJitter(String),
+    InvalidCacheRoute(String, String),
+    InvalidCacheBypassHeader(String, String),
+    InvalidAccessRoute(String, String),
+    InvalidAuthRoute(String, String),
+    InvalidOriginCheck(String, String),
+    InvalidContentTypeRoute(String, String),
+    InvalidMaintenanceWindow(String, String),
+    InvalidHtmlRewrite(String, String),
+    InvalidBlockRule(String, String),
+    InvalidRedirect(String, String),
+    InvalidExperiments(String, String),
+    InvalidForwardHeader(String, String),
+    InvalidCidr(String),
+    InvalidQueryRewrite(String, String),
+    InvalidMatchRule(String, String),
+    NoTrustedIpsForPinning(String),
+}
+
+impl std::fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use ValidationError::*;
+        match self {
+            InvalidListenAddress(e) => write!(f, "invalid listen address: {}", e),
+            StaticDirDoesNotExist(path) => write!(f, "static_dir does not exist: {}", path),
+            StaticDirNotADirectory(path) => write!(f, "static_dir is not a directory: {}", path),
+            InvalidStaticDirs(srv, e) => {
+                write!(f, "invalid static_dirs in server '{}': {}", srv, e)
+            }
+            InvalidStaticExtension(srv, ext) if ext.is_empty() => {
+                write!(f, "static_extensions in server '{}' is empty", srv)
+            }
+            InvalidStaticExtension(srv, ext) => write!(
+                f,
+                "static_extensions entry '{}' in server '{}' is not a file extension",
+                ext, srv
+            ),
+            NoServersConfigured => write!(f, "no servers configured"),
+            InvalidRuntime(e) => write!(f, "invalid [runtime] config: {}", e),
+            InvalidLimits(e) => write!(f, "invalid [limits] config: {}", e),
+            InvalidAutoBan(e) => write!(f, "invalid [auto_ban] config: {}", e),
+            InvalidShutdownGraceMax(max, grace) => write!(
+                f,
+                "shutdown_grace_max_secs ({}) must not be less than shutdown_grace_secs ({})",
+                max, grace
+            ),
+            InvalidMaxConnections(srv) => {
+                write!(f, "max_connections must be greater than zero in {}", srv)
+            }
+            InvalidMaxConnectionsPerIp(srv) => write!(
+                f,
+                "max_connections_per_ip must be greater than zero in server '{}'",
+                srv
+            ),
+            InvalidMaxRequestHeadBytes(srv) => write!(
+                f,
+                "max_request_head_bytes must be at least {} in server '{}'",
+                MIN_REQUEST_HEAD_BYTES, srv
+            ),
+            InvalidHeaderReadTimeout(srv) => write!(
+                f,
+                "header_read_timeout_secs must be greater than zero in server '{}'",
+                srv
+            ),
+            InvalidMaxRequestsPerConnection(srv) => write!(
+                f,
+                "max_requests_per_connection must be greater than zero in server '{}'",
+                srv
+            ),
+            InvalidMinWriteRate(srv, e) => {
+                write!(f, "invalid min_write_rate in server '{}': {}", srv, e)
+            }
+            InvalidProxyProtocol(srv, e) => {
+                write!(f, "invalid proxy_protocol in server '{}': {}", srv, e)
+            }
+            InvalidSendProxyProtocol(srv, e) => {
+                write!(f, "invalid send_proxy_protocol in server '{}': {}", srv, e)
+            }
+            InvalidBackendQueue(srv, e) => {
+                write!(f, "invalid backend queue in server '{}': {}", srv, e)
+            }
+            InvalidFastCgi(srv, e) => {
+                write!(f, "invalid fastcgi in server '{}': {}", srv, e)
+            }
+            InvalidUploadBuffer(srv) => write!(
+                f,
+                "max_upload_buffer_bytes must be greater than zero in server '{}'",
+                srv
+            ),
+            InvalidMaxDecompressedBody(srv) => write!(
+                f,
+                "max_decompressed_body_bytes must be greater than zero in server '{}'",
+                srv
+            ),
+            InvalidMaxUriLength(srv) => write!(
+                f,
+                "max_uri_length must be greater than zero in server '{}'",
+                srv
+            ),
+            InvalidMaxConnectionsPerBackend(srv) => write!(
+                f,
+                "max_connections_per_backend must be greater than zero in server '{}'",
+                srv
+            ),
+            ReusePortUnsupported(srv) => write!(
+                f,
+                "reuse_port is not supported on this platform (server '{}')",
+                srv
+            ),
+            AcceptorsWithoutReusePort(srv) => write!(
+                f,
+                "reuse_port_acceptors requires reuse_port = true in server '{}'",
+                srv
+            ),
+            NoBackendsConfigured(srv) => {
+                write!(f, "no backends configured in [proxy] for server '{}'", srv)
+            }
+            InvalidBackendUrl(url, e) => write!(f, "invalid backend URL '{}': {}", url, e),
+            UnsupportedBackendScheme(scheme) => write!(
+                f,
+                "unsupported backend scheme '{}', only http, https, fastcgi and fastcgi+unix allowed",
+                scheme
+            ),
+            TlsFileNotFound(path) => write!(f, "TLS file not found: {}", path),
+            Http3WithoutTls(srv) => write!(
+                f,
+                "http3 requires 'cert' and 'key' in server '{}' (QUIC is always encrypted)",
+                srv
+            ),
+            InvalidEarlyData(srv, reason) => {
+                write!(f, "tls_early_data in server '{}' {}", srv, reason)
+            }
+            InvalidTlsFingerprint(srv, reason) => {
+                write!(
+                    f,
+                    "invalid TLS fingerprint setup in server '{}': {}",
+                    srv, reason
+                )
+            }
+            IncompleteTlsConfig(srv) => write!(
+                f,
+                "Both 'cert' and 'key' must be provided for TLS in server '{}'",
+                srv
+            ),
+            InvalidTlsSessionCacheSize(srv) => write!(
+                f,
+                "tls_session_cache_size must be at most {} in server '{}'",
+                MAX_TLS_SESSION_CACHE_SIZE, srv
+            ),
+            InvalidTlsTicketLifetime(srv) => write!(
+                f,
+                "tls_ticket_lifetime_secs must be between 1 and {} in server '{}'",
+                MAX_TLS_TICKET_LIFETIME_SECS, srv
+            ),
+            EmptyAdminToken(srv) => write!(f, "admin_token must not be empty in server '{}'", srv),
+            AdminListenWithoutToken(srv) => write!(
+                f,
+                "admin_listen requires admin_token to be set in server '{}'",
+                srv
+            ),
+            InvalidCsp(srv, field) => write!(
+                f,
+                "{} in server '{}' must be a non-empty, valid header value",
+                field, srv
+            ),
+            InvalidSecurityHeader(srv, field, e) => write!(
+                f,
+                "invalid security_headers.{} in server '{}': {}",
+                field, srv, e
+            ),
+            InvalidReadinessPath(p) => {
+                write!(f, "readiness_path must start with '/', got '{}'", p)
+            }
+            InvalidIndexFile(srv, name) => write!(
+                f,
+                "index_files entry '{}' in server '{}' must be a plain file name",
+                name, srv
+            ),
+            FaviconNotFound(path) => write!(f, "favicon file not found: {}", path),
+            UnresolvedSecret(field, e) => write!(f, "cannot resolve {}: {}", field, e),
+            InvalidSignedUrls(srv, e) => {
+                write!(f, "invalid signed_urls in server '{}': {}", srv, e)
+            }
+            InvalidErrorPage(srv, e) => write!(f, "invalid error_pages in server '{}': {}", srv, e),
+            RobotsTxtNotFound(path) => write!(f, "robots_txt file not found: {}", path),
+            GeoIpDbRequired(srv) => write!(
+                f,
+                "deny_countries and match_country need geoip_db in server '{}'",
+                srv
+            ),
+            InvalidDenyCountries(srv, e) => {
+                write!(f, "invalid deny_countries in server '{}': {}", srv, e)
+            }
+            InvalidCookieRewrite(srv, e) => {
+                write!(f, "invalid cookie_rewrite in server '{}': {}", srv, e)
+            }
+            InvalidRequestSigning(srv, e) => {
+                write!(f, "invalid request_signing in server '{}': {}", srv, e)
+            }
+            InvalidRateLimitKey(key) => write!(
+                f,
+                "invalid rate limit key '{}', expected 'ip', 'header:<name>' or 'global'",
+                key
+            ),
+            InvalidRateLimit(srv) => write!(
+                f,
+                "rate limit per_minute must be greater than zero in server '{}'",
+                srv
+            ),
+            InvalidRateLimitMaxEntries(srv) => write!(
+                f,
+                "rate_limit_max_entries must be greater than zero in server '{}'",
+                srv
+            ),
+            InvalidCacheMaxEntryFraction(srv) => write!(
+                f,
+                "cache_max_entry_fraction must be in (0, 1] in server '{}'",
+                srv
+            ),
+            InvalidCacheTtlJitter(srv) => {
+                write!(f, "cache_ttl_jitter must be in [0, 1) in server '{}'", srv)
+            }
+            InvalidCidr(v) => write!(f, "invalid IP address or CIDR '{}'", v),
+            InvalidForwardHeader(srv, name) => write!(
+                f,
+                "invalid header name '{}' in forward_headers_allowlist of server '{}'",
+                name, srv
+            ),
+            InvalidCacheRoute(srv, e) => {
+                write!(f, "invalid cache_route in server '{}': {}", srv, e)
+            }
+            InvalidCacheBypassHeader(srv, name) => write!(
+                f,
+                "invalid header name '{}' in cache_bypass_headers for server '{}'",
+                name, srv
+            ),
+            InvalidAccessRoute(srv, e) => {
+                write!(f, "invalid access_route in server '{}': {}", srv, e)
+            }
+            InvalidAuthRoute(srv, e) => {
+                write!(f, "invalid auth_route in server '{}': {}", srv, e)
+            }
+            InvalidContentTypeRoute(srv, e) => {
+                write!(f, "invalid content_type_route in server '{}': {}", srv, e)
+            }
+            InvalidMaintenanceWindow(srv, e) => {
+                write!(f, "invalid maintenance_window in server '{}': {}", srv, e)
+            }
+            InvalidHtmlRewrite(srv, e) => {
+                write!(f, "invalid html_rewrite in server '{}': {}", srv, e)
+            }
+            InvalidOriginCheck(srv, e) => {
+                write!(f, "invalid origin check in server '{}': {}", srv, e)
+            }
+            InvalidBlockRule(srv, e) => {
+                write!(f, "invalid block_rule in server '{}': {}", srv, e)
+            }
+            InvalidRedirect(srv, e) => write!(f, "invalid redirect in server '{}': {}", srv, e),
+            InvalidExperiments(srv, e) => {
+                write!(f, "invalid experiments in server '{}': {}", srv, e)
+            }
+            InvalidQueryRewrite(srv, e) => {
+                write!(f, "invalid query_rewrite in server '{}': {}", srv, e)
+            }
+            InvalidMatchRule(srv, e) => write!(f, "invalid match rule in server '{}': {}", srv, e),
+            NoTrustedIpsForPinning(srv) => write!(
+                f,
+                "allow_backend_pinning requires backend_pinning_trusted_ips in server '{}'",
+                srv
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ValidationError {}
+
+/// Parse one backend URL: http, https, `fastcgi://host:port` or `fastcgi+unix:///socket/path`.
+/// Also used for backends added through `POST /admin/backends`.
+pub fn parse_backend_url(s: &str) -> Result<Url, ValidationError> {
+    let url = Url::parse(s)
+        .map_err(|e| ValidationError::InvalidBackendUrl(s.to_string(), e.to_string()))?;
+    let invalid = |e: &str| ValidationError::InvalidBackendUrl(s.to_string(), e.to_string());
+    match url.scheme() {
+        "http" | "https" => Ok(url),
+        "fastcgi" if url.host().is_none() || url.port().is_none() => {
+            Err(invalid("fastcgi:// needs a host and port"))
+        }
+        "fastcgi+unix" if url.host_str().is_some_and(|h| !h.is_empty()) || url.path().len() < 2 => {
+            Err(invalid(
+                "fastcgi+unix:// needs an absolute socket path and no host",
+            ))
+        }
+        "fastcgi" | "fastcgi+unix" => Ok(url),
+        other => Err(ValidationError::UnsupportedBackendScheme(other.to_string())),
+    }
+}
+
+/// Whether `backend` speaks FastCGI rather than HTTP.
+pub fn is_fastcgi(backend: &Url) -> bool {
+    matches!(backend.scheme(), "fastcgi" | "fastcgi+unix")
+}
+
+/// Parse a single-or-list backend field into validated http(s) URLs. Backends given with
+/// `send_proxy_protocol` are recorded in `send_proxy_protocol`, which is shared by every backend
+/// list of the server since the setting belongs to the URL.
+fn parse_backends(
+    field: BackendField,
+    server_id: &str,
+    send_proxy_protocol: &mut HashMap<Url, ProxyProtocolVersion>,
+) -> Result<Vec<Url>, ValidationError> {
+    // backends: allow single or multiple
+    let raw_backends: Vec<RawBackend> = match field {
+        BackendField::Single(b) => vec![b],
+        BackendField::Multiple(v) => v,
+    };
+
+    if raw_backends.is_empty() {
+        return Err(ValidationError::NoBackendsConfigured(server_id.to_string()));
+    }
+
+    // parse and validate backend URLs
+    let mut backends: Vec<Url> = Vec::with_capacity(raw_backends.len());
+    for b in raw_backends {
+        let (b, mode) = match b {
+            RawBackend::Url(url) => (url, None),
+            RawBackend::Options {
+                url,
+                send_proxy_protocol,
+            } => (url, send_proxy_protocol),
+        };
+        let url = parse_backend_url(&b)?;
+        let version = match mode.unwrap_or_default() {
+            ProxyProtocolMode::Off => None,
+            ProxyProtocolMode::V1 => Some(ProxyProtocolVersion::V1),
+            ProxyProtocolMode::V2 => Some(ProxyProtocolVersion::V2),
+        };
+        if let Some(version) = version {
+            let invalid =
+                |e: String| ValidationError::InvalidSendProxyProtocol(server_id.to_string(), e);
+            if url.scheme() != "http" {
+                return Err(invalid(format!("{} must be an http:// backend", url)));
+            }
+            if send_proxy_protocol
+                .insert(url.clone(), version)
+                .is_some_and(|v| v != version)
+            {
+                return Err(invalid(format!("{} is given two versions", url)));
+            }
+        }
+        backends.push(url);
+    }
+    Ok(backends)
+}
+
+fn parse_match_rule(
+    raw: RawMatchRule,
+    server_id: &str,
+    send_proxy_protocol: &mut HashMap<Url, ProxyProtocolVersion>,
+) -> Result<MatchRule, ValidationError> {
+    let invalid = |msg: String| ValidationError::InvalidMatchRule(server_id.to_string(), msg);
+
+    let source = match (raw.header, raw.cookie, &raw.match_country) {
+        (Some(h), None, None) => {
+            if axum::http::HeaderName::from_bytes(h.as_bytes()).is_err() {
+                return Err(invalid(format!("invalid header name '{}'", h)));
+            }
+            MatchSource::Header(h.to_ascii_lowercase())
+        }
+        (None, Some(c), None) if !c.is_empty() => MatchSource::Cookie(c),
+        (None, None, Some(_)) => MatchSource::Country,
+        _ => {
+            return Err(invalid(
+                "exactly one of 'header', 'cookie' or 'match_country' is required".into(),
+            ));
+        }
+    };
+
+    let matcher = match (raw.match_country, raw.value, raw.regex) {
+        (Some(countries), None, None) => {
+            ValueMatcher::AnyOf(parse_countries(&countries).map_err(invalid)?)
+        }
+        (Some(_), _, _) => {
+            return Err(invalid(
+                "'match_country' takes no 'value' or 'regex'".into(),
+            ));
+        }
+        (None, Some(v), None) => ValueMatcher::Exact(v),
+        (None, None, Some(r)) => ValueMatcher::Regex(
+            Regex::new(&r).map_err(|e| invalid(format!("invalid regex '{}': {}", r, e)))?,
+        ),
+        _ => {
+            return Err(invalid(
+                "exactly one of 'value' or 'regex' is required".into(),
+            ));
+        }
+    };
+
+    let backends = parse_backends(raw.backend, server_id, send_proxy_protocol)?;
+
+    Ok(MatchRule {
+        source,
+        matcher,
+        backends,
+        request_timeout: raw.request_timeout_secs.map(Duration::from_secs),
+    })
+}
+
+// ISO 3166-1 alpha-2 codes, uppercased as MaxMind reports them.
+fn parse_countries(raw: &[String]) -> Result<Vec<String>, String> {
+    if raw.is_empty() {
+        return Err("country list must not be empty".into());
+    }
+    raw.iter()
+        .map(|c| {
+            if c.len() == 2 && c.bytes().all(|b| b.is_ascii_alphabetic()) {
+                Ok(c.to_ascii_uppercase())
+            } else {
+                Err(format!("'{}' is not a two-letter country code", c))
+            }
+        })
+        .collect()
+}
+
+fn parse_block_rule(
+    raw: RawBlockRule,
+    index: usize,
+    server_id: &str,
+) -> Result<BlockRule, ValidationError> {
+    let name = raw.name.unwrap_or_else(|| format!("block_rule[{}]", index));
+    let invalid = |e: String| {
+        ValidationError::InvalidBlockRule(server_id.to_string(), format!("{}: {}", name, e))
+    };
+    if raw.paths.is_empty()
+        && raw.path_regex.is_empty()
+        && raw.user_agents.is_empty()
+        && raw.user_agent_regex.is_empty()
+        && raw.ja3.is_empty()
+        && raw.ja4.is_empty()
+    {
+        return Err(invalid(
+            "needs at least one of paths, path_regex, user_agents, user_agent_regex, ja3 or ja4"
+                .into(),
+        ));
+    }
+    let regex =
+        |r: &str| Regex::new(r).map_err(|e| invalid(format!("invalid regex '{}': {}", r, e)));
+
+    let mut paths = Vec::new();
+    for glob in &raw.paths {
+        if !glob.starts_with(['/', '*']) {
+            return Err(invalid(format!(
+                "path glob '{}' must start with '/' or '*'",
+                glob
+            )));
+        }
+        paths.push(regex(&glob_to_regex(glob))?);
+    }
+    for r in &raw.path_regex {
+        paths.push(regex(r)?);
+    }
+    if raw.user_agents.iter().any(|ua| ua.is_empty()) {
+        return Err(invalid("user_agents entries must not be empty".into()));
+    }
+    let user_agent_regex = raw
+        .user_agent_regex
+        .iter()
+        .map(|r| regex(r))
+        .collect::<Result<_, _>>()?;
+    if let Some(hash) = raw
+        .ja3
+        .iter()
+        .find(|h| h.len() != 32 || !h.bytes().all(|b| b.is_ascii_hexdigit()))
+    {
+        return Err(invalid(format!("ja3 '{}' is not an MD5 hex digest", hash)));
+    }
+    if raw.ja4.iter().any(|f| f.is_empty()) {
+        return Err(invalid("ja4 entries must not be empty".into()));
+    }
+
+    Ok(BlockRule {
+        paths,
+        user_agents: raw
+            .user_agents
+            .iter()
+            .map(|ua| ua.to_ascii_lowercase())
+            .collect(),
+        user_agent_regex,
+        ja3: raw.ja3.iter().map(|h| h.to_ascii_lowercase()).collect(),
+        ja4: raw.ja4.iter().map(|f| f.to_ascii_lowercase()).collect(),
+        action: raw.action,
+        log_only: raw.log_only,
+        name,
+    })
+}
+
+// Anchored regex for a path glob: `*` matches any run of characters (including '/'), `?` one.
+// Each wildcard is a capture group, for redirect targets.
+fn glob_to_regex(glob: &str) -> String {
+    let mut out = String::from("^");
+    let mut literal = String::new();
+    for c in glob.chars() {
+        if c == '*' || c == '?' {
+            out.push_str(&regex::escape(&literal));
+            literal.clear();
+            out.push_str(if c == '*' { "(.*)" } else { "(.)" });
+        } else {
+            literal.push(c);
+        }
+    }
+    out.push_str(&regex::escape(&literal));
+    out.push('$');
+    out
+}
+
+fn parse_redirect(
+    raw: RawRedirect,
+    index: usize,
+    server_id: &str,
+) -> Result<RedirectRule, ValidationError> {
+    let invalid = |e: String| {
+        ValidationError::InvalidRedirect(
+            server_id.to_string(),
+            format!("redirects[{}]: {}", index, e),
+        )
+    };
+    let (from, groups) = if raw.from.starts_with('^') {
+        let re = Regex::new(&raw.from)
+            .map_err(|e| invalid(format!("invalid regex '{}': {}", raw.from, e)))?;
+        let groups = re.captures_len() - 1;
+        (RedirectFrom::Pattern(re), groups)
+    } else if !raw.from.starts_with('/') {
+        return Err(invalid(format!(
+            "from '{}' must be a path starting with '/' or a regex starting with '^'",
+            raw.from
+        )));
+    } else if raw.from.contains(['*', '?']) {
+        let re = Regex::new(&glob_to_regex(&raw.from)).expect("escaped glob");
+        let groups = re.captures_len() - 1;
+        (RedirectFrom::Pattern(re), groups)
+    } else {
+        (RedirectFrom::Exact(raw.from.clone()), 0)
+    };
+
+    let to = parse_redirect_target(&raw.to).map_err(invalid)?;
+    if let Some(n) = to.iter().find_map(|p| match p {
+        RedirectPart::Capture(n) if *n == 0 || *n > groups => Some(*n),
+        _ => None,
+    }) {
+        return Err(invalid(format!(
+            "to '{}' uses ${} but from '{}' has {} capture group(s)",
+            raw.to, n, raw.from, groups
+        )));
+    }
+    // Check the shape of the target with every capture filled in.
+    let sample: String = to
+        .iter()
+        .map(|p| match p {
+            RedirectPart::Literal(s) => s.as_str(),
+            RedirectPart::Capture(_) => "x",
+        })
+        .collect();
+    let absolute = Url::parse(&sample)
+        .ok()
+        .filter(|u| matches!(u.scheme(), "http" | "https"));
+    if absolute.is_none() && (!sample.starts_with('/') || sample.starts_with("//")) {
+        return Err(invalid(format!(
+            "to '{}' must be a path starting with '/' or an http(s) URL",
+            raw.to
+        )));
+    }
+    if sample.contains('#') || sample.chars().any(|c| c.is_whitespace() || c.is_control()) {
+        return Err(invalid(format!(
+            "to '{}' must not contain a fragment or whitespace",
+            raw.to
+        )));
+    }
+
+    let status = raw.status.unwrap_or(302);
+    if ![301, 302, 307, 308].contains(&status) {
+        return Err(invalid(format!(
+            "status {} is not one of 301, 302, 307 or 308",
+            status
+        )));
+    }
+    Ok(RedirectRule {
+        from,
+        to_raw: raw.to,
+        to,
+        status,
+        drop_query: raw.drop_query,
+    })
+}
+
+// Split a redirect target into literals and `$N` / `${N}` captures; `$$` is a literal '$'.
+fn parse_redirect_target(to: &str) -> Result<Vec<RedirectPart>, String> {
+    let mut parts = Vec::new();
+    let mut literal = String::new();
+    let mut rest = to;
+    while let Some(i) = rest.find('$') {
+        literal.push_str(&rest[..i]);
+        rest = &rest[i + 1..];
+        if let Some(after) = rest.strip_prefix('$') {
+            literal.push('$');
+            rest = after;
+            continue;
+        }
+        let (digits, after) = match rest.strip_prefix('{') {
+            Some(inner) => {
+                let end = inner
+                    .find('}')
+                    .ok_or_else(|| format!("unclosed '${{' in to '{}'", to))?;
+                (&inner[..end], &inner[end + 1..])
+            }
+            None => {
+                let end = rest
+                    .find(|c: char| !c.is_ascii_digit())
+                    .unwrap_or(rest.len());
+                (&rest[..end], &rest[end..])
+            }
+        };
+        let n = digits.parse::<usize>().map_err(|_| {
+            format!(
+                "'$' in to '{}' must start a capture like $1 or ${{1}} (use $$ for '$')",
+                to
+            )
+        })?;
+        if !literal.is_empty() {
+            parts.push(RedirectPart::Literal(std::mem::take(&mut literal)));
+        }
+        parts.push(RedirectPart::Capture(n));
+        rest = after;
+    }
+    literal.push_str(rest);
+    if !literal.is_empty() {
+        parts.push(RedirectPart::Literal(literal));
+    }
+    Ok(parts)
+}
+
+impl RedirectFrom {
+    /// Whether the percent-decoded `path` matches.
+    pub fn is_match(&self, path: &str) -> bool {
+        match self {
+            RedirectFrom::Exact(p) => p == path,
+            RedirectFrom::Pattern(re) => re.is_match(path),
+        }
+    }
+}
+
+// Refuse redirects that lead back to where they started. Only fixed same-site targets can be
+// followed here; targets built from captures are checked per request instead.
+fn check_redirect_loops(rules: &[RedirectRule]) -> Result<(), String> {
+    let fixed_path = |rule: &RedirectRule| -> Option<String> {
+        let [RedirectPart::Literal(to)] = rule.to.as_slice() else {
+            return None;
+        };
+        let path = to
+            .strip_prefix('/')
+            .map(|_| to.split('?').next().unwrap_or(to))?;
+        Some(percent_decode_str(path).decode_utf8_lossy().into_owned())
+    };
+    for rule in rules {
+        let Some(mut path) = fixed_path(rule) else {
+            continue;
+        };
+        let mut seen = vec![];
+        while let Some(next) = rules.iter().find(|r| r.from.is_match(&path)) {
+            if seen.contains(&path) || seen.len() > rules.len() {
+                return Err(format!(
+                    "redirect to '{}' loops back through '{}'",
+                    rule.to_raw, path
+                ));
+            }
+            seen.push(path);
+            match fixed_path(next) {
+                Some(p) => path = p,
+                None => break,
+            }
+        }
+    }
+    Ok(())
+}
+
+fn parse_query_route(raw: RawQueryRoute) -> Result<QueryRoute, String> {
+    let prefix = raw.path_prefix;
+    check_path_prefix(&prefix)?;
+    let invalid = |e: String| format!("query_route '{}': {}", prefix, e);
+    if raw.query_set.is_empty() && raw.query_remove.is_empty() && raw.query_rename.is_empty() {
+        return Err(invalid(
+            "needs at least one of query_set, query_remove or query_rename".into(),
+        ));
+    }
+    let names = raw
+        .query_set
+        .keys()
+        .chain(&raw.query_remove)
+        .chain(raw.query_rename.iter().flat_map(|(from, to)| [from, to]));
+    if names.into_iter().any(|n| n.is_empty()) {
+        return Err(invalid("parameter names must not be empty".into()));
+    }
+
+    let mut rewrites = Vec::new();
+    for name in raw.query_remove {
+        rewrites.push(if name.contains(['*', '?']) {
+            let pattern = Regex::new(&glob_to_regex(&name)).expect("escaped glob");
+            QueryRewrite::RemoveMatching { pattern }
+        } else {
+            QueryRewrite::Remove { name }
+        });
+    }
+    for (from, to) in raw.query_rename {
+        if from == to {
+            return Err(invalid(format!(
+                "query_rename renames '{}' to itself",
+                from
+            )));
+        }
+        rewrites.push(QueryRewrite::Rename { from, to });
+    }
+    for (name, value) in raw.query_set {
+        rewrites.push(QueryRewrite::Set { name, value });
+    }
+    Ok(QueryRoute {
+        path_prefix: prefix,
+        rewrites,
+    })
+}
+
+fn parse_experiments(raw: RawExperiments) -> Result<ExperimentConfig, String> {
+    // Bucket and cookie names end up in header values, so keep them to token characters.
+    let is_token = |s: &str| {
+        !s.is_empty()
+            && s.bytes()
+                .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_' | b'.'))
+    };
+    if raw.bucket.len() < 2 {
+        return Err("needs at least two [[servers.experiments.bucket]] entries".into());
+    }
+    let mut buckets: Vec<(String, u32)> = Vec::with_capacity(raw.bucket.len());
+    for b in raw.bucket {
+        if !is_token(&b.name) {
+            return Err(format!(
+                "bucket name '{}' must be letters, digits, '-', '_' or '.'",
+                b.name
+            ));
+        }
+        if buckets.iter().any(|(name, _)| *name == b.name) {
+            return Err(format!("bucket '{}' is listed more than once", b.name));
+        }
+        buckets.push((b.name, b.percent));
+    }
+    let total: u32 = buckets.iter().map(|(_, p)| p).sum();
+    if total != 100 {
+        return Err(format!("bucket percentages add up to {}, not 100", total));
+    }
+    let control = match raw.control {
+        Some(c) => buckets
+            .iter()
+            .position(|(name, _)| *name == c)
+            .ok_or_else(|| format!("control '{}' is not one of the buckets", c))?,
+        None => 0,
+    };
+
+    let cookie = raw.cookie.unwrap_or_else(|| "serava_visitor".to_string());
+    if !is_token(&cookie) {
+        return Err(format!(
+            "cookie '{}' must be letters, digits, '-', '_' or '.'",
+            cookie
+        ));
+    }
+    let header = raw
+        .header
+        .unwrap_or_else(|| "X-Experiment-Bucket".to_string());
+    let header = HeaderName::from_bytes(header.as_bytes())
+        .map_err(|_| format!("header '{}' is not a valid header name", header))?;
+    let cookie_max_age = match raw.cookie_max_age_secs {
+        Some(0) => return Err("cookie_max_age_secs must be greater than 0".into()),
+        Some(secs) => Duration::from_secs(secs),
+        None => Duration::from_secs(30 * 24 * 60 * 60),
+    };
+    let bot_user_agents = match raw.bot_user_agents {
+        Some(list) if list.iter().any(|ua| ua.is_empty()) => {
+            return Err("bot_user_agents entries must not be empty".into());
+        }
+        Some(list) => list.iter().map(|ua| ua.to_ascii_lowercase()).collect(),
+        None => DEFAULT_BOT_USER_AGENTS
+            .iter()
+            .map(|ua| ua.to_string())
+            .collect(),
+    };
+    Ok(ExperimentConfig {
+        name: raw.name.unwrap_or_else(|| "default".to_string()),
+        cookie,
+        cookie_max_age,
+        header,
+        control,
+        bot_user_agents,
+        buckets,
+    })
+}
+
+// Shared checks for `path_prefix` in per-route blocks. A route covers that path and everything
+// below it, matched on whole segments (`path::has_path_prefix`).
+fn check_path_prefix(prefix: &str) -> Result<(), String> {
+    if !prefix.starts_with('/')
+        || prefix.contains(['?', '#'])
+        || prefix.chars().any(|c| c.is_whitespace() || c.is_control())
+    {
+        return Err(format!(
+            "path_prefix '{}' must be a path starting with '/' (no query, fragment or whitespace)",
+            prefix
+        ));
+    }
+    Ok(())
+}
+
+// Sort per-path routes longest prefix first, refusing a prefix listed twice. Route lookups
+// (`path::longest_prefix`) rely on this order to take the first match as the most specific.
+fn sort_by_prefix<T>(routes: &mut [T], prefix: impl Fn(&T) -> &str) -> Result<(), String> {
+    routes.sort_by(|a, b| {
+        let (a, b) = (prefix(a), prefix(b));
+        b.len().cmp(&a.len()).then_with(|| a.cmp(b))
+    });
+    match routes.windows(2).find(|w| prefix(&w[0]) == prefix(&w[1])) {
+        Some(w) => Err(format!(
+            "path_prefix '{}' is listed more than once",
+            prefix(&w[0])
+        )),
+        None => Ok(()),
+    }
+}
+
+// An `allowed_content_types` entry, lowercased: `type/subtype` or `type/*`.
+fn parse_media_range(raw: &str) -> Option<String> {
+    let is_token = |s: &str| {
+        !s.is_empty()
+            && s.bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b"!#$&-^_.+".contains(&b))
+    };
+    let lower = raw.trim().to_ascii_lowercase();
+    let (kind, subtype) = lower.split_once('/')?;
+    (is_token(kind) && (subtype == "*" || is_token(subtype))).then_some(lower)
+}
+
+// `allowed_origins` entries in the canonical form the origin check compares against.
+fn parse_origins(list: &[String]) -> Result<Vec<String>, String> {
+    list.iter()
+        .map(|raw| {
+            let url = Url::parse(raw.trim())
+                .map_err(|e| format!("allowed origin '{}' is not a URL: {}", raw, e))?;
+            if !matches!(url.path(), "" | "/")
+                || url.query().is_some()
+                || url.fragment().is_some()
+                || !url.username().is_empty()
+            {
+                return Err(format!(
+                    "allowed origin '{}' must be just scheme://host[:port]",
+                    raw
+                ));
+            }
+            crate::origin::canonical(&url).ok_or_else(|| {
+                format!(
+                    "allowed origin '{}' must be an http or https URL with a host",
+                    raw
+                )
+            })
+        })
+        .collect()
+}
+
+/// Parse a CIDR (`10.0.0.0/8`) or a bare IP address (treated as a single-host network).
+pub fn parse_cidr(raw: &str) -> Result<IpNet, ValidationError> {
+    let trimmed = raw.trim();
+    trimmed
+        .parse::<IpNet>()
+        .or_else(|_| trimmed.parse::<IpAddr>().map(IpNet::from))
+        .map_err(|_| ValidationError::InvalidCidr(raw.to_string()))
 }
 
-#[derive(Debug, Clone)]
-pub struct TlsConfig {
-    pub cert: PathBuf,
-    pub key: PathBuf,
+fn parse_weekday(raw: &str) -> Option<Weekday> {
+    Some(match raw.trim().to_ascii_lowercase().as_str() {
+        "mon" | "monday" => Weekday::Monday,
+        "tue" | "tuesday" => Weekday::Tuesday,
+        "wed" | "wednesday" => Weekday::Wednesday,
+        "thu" | "thursday" => Weekday::Thursday,
+        "fri" | "friday" => Weekday::Friday,
+        "sat" | "saturday" => Weekday::Saturday,
+        "sun" | "sunday" => Weekday::Sunday,
+        _ => return None,
+    })
 }
 
-/// Validated per-server config returned from `RawConfig::validate`.
-#[derive(Debug, Clone)]
-pub struct ConfigEntry {
-    pub listen: SocketAddr,
-    pub static_dir: PathBuf,
-    pub backends: Vec<Url>,
-    pub tls: Option<TlsConfig>,
-    pub backend_timeout: Duration,
-    pub rate_limit_per_minute: Option<u64>,
-    pub rate_limit_burst: Option<u64>,
-    pub max_request_size_bytes: u64,
-    pub cache_ttl_secs: Option<u64>,
-    pub cache_max_size_bytes: Option<u64>,
+fn parse_maintenance_window(raw: RawMaintenanceWindow) -> Result<MaintenanceWindow, String> {
+    let timezone = match &raw.timezone {
+        Some(name) => TimeZone::get(name).map_err(|_| format!("unknown timezone '{}'", name))?,
+        None => TimeZone::UTC,
+    };
+
+    let dates = (
+        raw.start.parse::<civil::DateTime>(),
+        raw.end.parse::<civil::DateTime>(),
+    );
+    if let (Ok(start), Ok(end)) = dates {
+        if raw.days.is_some() {
+            return Err("days only applies to windows given as HH:MM times".to_string());
+        }
+        let at = |dt: civil::DateTime| {
+            dt.to_zoned(timezone.clone())
+                .map(|z| z.timestamp())
+                .map_err(|e| format!("'{}' is out of range: {}", dt, e))
+        };
+        let (start, end) = (at(start)?, at(end)?);
+        if end <= start {
+            return Err(format!(
+                "end '{}' is not after start '{}'",
+                raw.end, raw.start
+            ));
+        }
+        return Ok(MaintenanceWindow::Once { start, end });
+    }
+    if dates.0.is_ok() || dates.1.is_ok() {
+        return Err(format!(
+            "'{}' and '{}' must both be dates with times or both times of day",
+            raw.start, raw.end
+        ));
+    }
+
+    let time = |s: &str| {
+        s.parse::<civil::Time>()
+            .map_err(|_| format!("'{}' is neither YYYY-MM-DDTHH:MM nor HH:MM", s))
+    };
+    let (start, end) = (time(&raw.start)?, time(&raw.end)?);
+    if start == end {
+        return Err(format!("'{}' to '{}' is empty", raw.start, raw.end));
+    }
+    let days = raw
+        .days
+        .unwrap_or_default()
+        .iter()
+        .map(|d| parse_weekday(d).ok_or_else(|| format!("'{}' is not a weekday", d)))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(MaintenanceWindow::Recurring {
+        start,
+        end,
+        days,
+        timezone,
+    })
 }
 
-#[derive(Debug)]
-pub enum ValidationError {
-    InvalidListenAddress(String),
-    StaticDirDoesNotExist(String),
-    StaticDirNotADirectory(String),
-    NoServersConfigured,
-    NoBackendsConfigured(String),
-    InvalidBackendUrl(String, String),
-    UnsupportedBackendScheme(String),
-    TlsFileNotFound(String),
-    IncompleteTlsConfig(String),
+fn parse_rate_limit_key(raw: &str) -> Result<RateLimitKey, ValidationError> {
+    let trimmed = raw.trim();
+    if trimmed.eq_ignore_ascii_case("ip") {
+        return Ok(RateLimitKey::Ip);
+    }
+    if trimmed.eq_ignore_ascii_case("global") {
+        return Ok(RateLimitKey::Global);
+    }
+    if let Some((kind, name)) = trimmed.split_once(':')
+        && kind.trim().eq_ignore_ascii_case("header")
+        && axum::http::HeaderName::from_bytes(name.trim().as_bytes()).is_ok()
+    {
+        return Ok(RateLimitKey::Header(name.trim().to_ascii_lowercase()));
+    }
+    Err(ValidationError::InvalidRateLimitKey(raw.to_string()))
 }
 
-impl std::fmt::Display for ValidationError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        use ValidationError::*;
-        match self {
-            InvalidListenAddress(e) => write!(f, "invalid listen address: {}", e),
-            StaticDirDoesNotExist(path) => write!(f, "static_dir does not exist: {}", path),
-            StaticDirNotADirectory(path) => write!(f, "static_dir is not a directory: {}", path),
-            NoServersConfigured => write!(f, "no servers configured"),
-            NoBackendsConfigured(srv) => {
-                write!(f, "no backends configured in [proxy] for server '{}'", srv)
-            }
-            InvalidBackendUrl(url, e) => write!(f, "invalid backend URL '{}': {}", url, e),
-            UnsupportedBackendScheme(scheme) => write!(
-                f,
-                "unsupported backend scheme '{}', only http/https allowed",
-                scheme
-            ),
-            TlsFileNotFound(path) => write!(f, "TLS file not found: {}", path),
-            IncompleteTlsConfig(srv) => write!(
-                f,
-                "Both 'cert' and 'key' must be provided for TLS in server '{}'",
-                srv
-            ),
+impl RawRuntime {
+    fn validate(self) -> Result<RuntimeConfig, ValidationError> {
+        let flavor = self.flavor.unwrap_or_default();
+        let worker_threads = self.worker_threads.filter(|&n| n > 0);
+        if flavor == RuntimeFlavor::CurrentThread && worker_threads.is_some() {
+            return Err(ValidationError::InvalidRuntime(
+                "worker_threads cannot be set with flavor = \"current-thread\"".to_string(),
+            ));
+        }
+        if self.max_blocking_threads == Some(0) {
+            return Err(ValidationError::InvalidRuntime(
+                "max_blocking_threads must be greater than zero".to_string(),
+            ));
         }
+        if self
+            .thread_name
+            .as_deref()
+            .is_some_and(|n| n.trim().is_empty())
+        {
+            return Err(ValidationError::InvalidRuntime(
+                "thread_name must not be empty".to_string(),
+            ));
+        }
+        Ok(RuntimeConfig {
+            flavor,
+            worker_threads,
+            max_blocking_threads: self.max_blocking_threads,
+            thread_name: self.thread_name,
+        })
     }
 }
 
-impl std::error::Error for ValidationError {}
+impl RawLimits {
+    fn validate(self) -> Result<LimitsConfig, ValidationError> {
+        if self.total_cache_bytes == Some(0) {
+            return Err(ValidationError::InvalidLimits(
+                "total_cache_bytes must be greater than zero".to_string(),
+            ));
+        }
+        Ok(LimitsConfig {
+            total_cache_bytes: self.total_cache_bytes.map(|v| v as usize),
+        })
+    }
+}
+
+impl RawAutoBan {
+    fn validate(self) -> Result<Option<AutoBanConfig>, ValidationError> {
+        if !self.enabled.unwrap_or(true) {
+            return Ok(None);
+        }
+        let invalid = |e: &str| ValidationError::InvalidAutoBan(e.to_string());
+        let statuses = self.statuses.unwrap_or_else(|| vec![401, 403, 404]);
+        if statuses.is_empty() {
+            return Err(invalid("statuses must not be empty"));
+        }
+        if let Some(status) = statuses.iter().find(|s| !(100..=599).contains(*s)) {
+            return Err(ValidationError::InvalidAutoBan(format!(
+                "{} is not an HTTP status",
+                status
+            )));
+        }
+        let max_strikes = self.max_strikes.unwrap_or(20);
+        if max_strikes == 0 {
+            return Err(invalid("max_strikes must be greater than zero"));
+        }
+        let window_secs = self.window_secs.unwrap_or(60);
+        if window_secs == 0 {
+            return Err(invalid("window_secs must be greater than zero"));
+        }
+        let ban_secs = self.ban_secs.unwrap_or(600);
+        if ban_secs == 0 {
+            return Err(invalid("ban_secs must be greater than zero"));
+        }
+        let exempt_ips = self
+            .exempt_ips
+            .iter()
+            .map(|c| parse_cidr(c))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Some(AutoBanConfig {
+            statuses,
+            max_strikes,
+            window: Duration::from_secs(window_secs),
+            ban: Duration::from_secs(ban_secs),
+            action: self.action.unwrap_or(BlockAction::Forbidden),
+            exempt_ips,
+        }))
+    }
+}
 
 impl RawConfig {
-    pub fn validate(self) -> Result<Vec<ConfigEntry>, ValidationError> {
+    pub fn validate(self) -> Result<Config, ValidationError> {
         if self.servers.is_empty() {
             return Err(ValidationError::NoServersConfigured);
         }
 
+        let runtime = self.runtime.validate()?;
+        let limits = self.limits.validate()?;
+        let auto_ban = match self.auto_ban {
+            Some(raw) => raw.validate()?,
+            None => None,
+        };
+
+        let shutdown_grace_secs = self.shutdown_grace_secs.unwrap_or(10);
+        if let Some(max) = self.shutdown_grace_max_secs
+            && max < shutdown_grace_secs
+        {
+            return Err(ValidationError::InvalidShutdownGraceMax(
+                max,
+                shutdown_grace_secs,
+            ));
+        }
+
+        if self.max_connections == Some(0) {
+            return Err(ValidationError::InvalidMaxConnections(
+                "the top-level config".to_string(),
+            ));
+        }
+
         let mut out: Vec<ConfigEntry> = Vec::with_capacity(self.servers.len());
 
         for (idx, raw_srv) in self.servers.into_iter().enumerate() {
@@ -114,18 +2541,92 @@ impl RawConfig {
                 Err(e) => return Err(ValidationError::InvalidListenAddress(e.to_string())),
             };
 
-            let static_dir = raw_srv.static_dir;
-            if !static_dir.exists() {
-                return Err(ValidationError::StaticDirDoesNotExist(
-                    static_dir.display().to_string(),
-                ));
+            let static_dirs = match (raw_srv.static_dir, raw_srv.static_dirs) {
+                (Some(dir), None) => vec![dir],
+                (None, Some(dirs)) if dirs.is_empty() => {
+                    return Err(ValidationError::InvalidStaticDirs(
+                        server_id.clone(),
+                        "must list at least one directory",
+                    ));
+                }
+                (None, Some(dirs)) => dirs,
+                (Some(_), Some(_)) => {
+                    return Err(ValidationError::InvalidStaticDirs(
+                        server_id.clone(),
+                        "set either static_dir or static_dirs, not both",
+                    ));
+                }
+                (None, None) => {
+                    return Err(ValidationError::InvalidStaticDirs(
+                        server_id.clone(),
+                        "one of static_dir or static_dirs is required",
+                    ));
+                }
+            };
+            for static_dir in &static_dirs {
+                if !static_dir.exists() {
+                    return Err(ValidationError::StaticDirDoesNotExist(
+                        static_dir.display().to_string(),
+                    ));
+                }
+                if !static_dir.is_dir() {
+                    return Err(ValidationError::StaticDirNotADirectory(
+                        static_dir.display().to_string(),
+                    ));
+                }
             }
-            if !static_dir.is_dir() {
-                return Err(ValidationError::StaticDirNotADirectory(
-                    static_dir.display().to_string(),
-                ));
+
+            for (field, value) in [
+                ("csp", &raw_srv.csp),
+                ("csp_report_only", &raw_srv.csp_report_only),
+            ] {
+                if let Some(v) = value
+                    && (v.trim().is_empty() || HeaderValue::from_str(v).is_err())
+                {
+                    return Err(ValidationError::InvalidCsp(server_id.clone(), field));
+                }
             }
 
+            let security_headers = match raw_srv.security_headers {
+                Some(sh) if sh.enabled.unwrap_or(true) => {
+                    let check =
+                        |field, value: &Option<String>, check: fn(&str) -> Result<(), String>| {
+                            let Some(v) = value else {
+                                return Ok(());
+                            };
+                            HeaderValue::from_str(v)
+                                .map_err(|_| "not a valid header value".to_string())
+                                .and_then(|_| check(v))
+                                .map_err(|e| {
+                                    ValidationError::InvalidSecurityHeader(
+                                        server_id.clone(),
+                                        field,
+                                        e,
+                                    )
+                                })
+                        };
+                    check(
+                        "expect_ct",
+                        &sh.expect_ct,
+                        security_headers::check_expect_ct,
+                    )?;
+                    check(
+                        "report_to",
+                        &sh.report_to,
+                        security_headers::check_report_to,
+                    )?;
+                    check("nel", &sh.nel, security_headers::check_nel)?;
+
+                    let any = sh.expect_ct.is_some() || sh.report_to.is_some() || sh.nel.is_some();
+                    any.then_some(SecurityHeadersConfig {
+                        expect_ct: sh.expect_ct,
+                        report_to: sh.report_to,
+                        nel: sh.nel,
+                    })
+                }
+                _ => None,
+            };
+
             // TLS: both cert and key must be present if any is provided
             let tls = match (raw_srv.cert, raw_srv.key) {
                 (Some(cert), Some(key)) => {
@@ -135,60 +2636,1182 @@ impl RawConfig {
                     if !key.exists() {
                         return Err(ValidationError::TlsFileNotFound(key.display().to_string()));
                     }
-                    Some(TlsConfig { cert, key })
+                    let session_cache_size = raw_srv
+                        .tls_session_cache_size
+                        .unwrap_or(DEFAULT_TLS_SESSION_CACHE_SIZE);
+                    if session_cache_size > MAX_TLS_SESSION_CACHE_SIZE {
+                        return Err(ValidationError::InvalidTlsSessionCacheSize(
+                            server_id.clone(),
+                        ));
+                    }
+                    let ticket_lifetime_secs = match raw_srv.tls_ticket_lifetime_secs {
+                        Some(secs) if secs == 0 || secs > MAX_TLS_TICKET_LIFETIME_SECS => {
+                            return Err(ValidationError::InvalidTlsTicketLifetime(
+                                server_id.clone(),
+                            ));
+                        }
+                        Some(secs) => Some(secs as u32),
+                        None => None,
+                    };
+                    // rustls only accepts 0-RTT when resuming from its session cache, where each
+                    // session can be used once; never with session tickets.
+                    if raw_srv.tls_early_data.is_some()
+                        && (session_cache_size == 0 || ticket_lifetime_secs.is_some())
+                    {
+                        return Err(ValidationError::InvalidEarlyData(
+                            server_id.clone(),
+                            "needs the session cache (tls_session_cache_size > 0) and no tls_ticket_lifetime_secs",
+                        ));
+                    }
+                    Some(TlsConfig {
+                        cert,
+                        key,
+                        session_cache_size: session_cache_size as usize,
+                        ticket_lifetime_secs,
+                        http3: raw_srv.http3.unwrap_or(false),
+                        early_data: raw_srv.tls_early_data,
+                        fingerprint: raw_srv.tls_fingerprint.unwrap_or(false),
+                    })
+                }
+                (None, None) if raw_srv.http3 == Some(true) => {
+                    return Err(ValidationError::Http3WithoutTls(server_id.clone()));
+                }
+                (None, None) if raw_srv.tls_fingerprint == Some(true) => {
+                    return Err(ValidationError::InvalidTlsFingerprint(
+                        server_id.clone(),
+                        "tls_fingerprint requires 'cert' and 'key'",
+                    ));
+                }
+                (None, None) if raw_srv.tls_early_data.is_some() => {
+                    return Err(ValidationError::InvalidEarlyData(
+                        server_id.clone(),
+                        "requires 'cert' and 'key'",
+                    ));
+                }
+                (None, None)
+                    if raw_srv.tls_session_cache_size.is_some()
+                        || raw_srv.tls_ticket_lifetime_secs.is_some() =>
+                {
+                    return Err(ValidationError::IncompleteTlsConfig(server_id.clone()));
                 }
                 (None, None) => None,
                 _ => return Err(ValidationError::IncompleteTlsConfig(server_id.clone())),
             };
 
-            // backends: allow single or multiple
-            let backend_strings: Vec<String> = match raw_srv.proxy.backend {
-                BackendField::Single(s) => vec![s],
-                BackendField::Multiple(v) => v,
+            let admin_token = raw_srv
+                .admin_token
+                .map(|t| t.resolve(&format!("admin_token in server '{}'", server_id)))
+                .transpose()?;
+            if admin_token
+                .as_ref()
+                .is_some_and(|t| t.expose().trim().is_empty())
+            {
+                return Err(ValidationError::EmptyAdminToken(server_id.clone()));
+            }
+
+            let admin_listen = match raw_srv.admin_listen {
+                Some(addr) => {
+                    if admin_token.is_none() {
+                        return Err(ValidationError::AdminListenWithoutToken(server_id.clone()));
+                    }
+                    Some(addr.parse::<SocketAddr>().map_err(|e| {
+                        ValidationError::InvalidListenAddress(format!("{}: {}", addr, e))
+                    })?)
+                }
+                None => None,
+            };
+
+            let readiness_path = raw_srv.readiness_path;
+            if let Some(p) = &readiness_path
+                && !p.starts_with('/')
+            {
+                return Err(ValidationError::InvalidReadinessPath(p.clone()));
+            }
+
+            let reuse_port_acceptors = match (raw_srv.reuse_port, raw_srv.reuse_port_acceptors) {
+                (Some(true), _) if !cfg!(unix) => {
+                    return Err(ValidationError::ReusePortUnsupported(server_id.clone()));
+                }
+                (Some(true), n) => Some(n.unwrap_or(0)),
+                (_, Some(_)) => {
+                    return Err(ValidationError::AcceptorsWithoutReusePort(
+                        server_id.clone(),
+                    ));
+                }
+                _ => None,
+            };
+
+            if raw_srv.max_connections_per_ip == Some(0) {
+                return Err(ValidationError::InvalidMaxConnectionsPerIp(
+                    server_id.clone(),
+                ));
+            }
+            if raw_srv.max_connections == Some(0) {
+                return Err(ValidationError::InvalidMaxConnections(format!(
+                    "server '{}'",
+                    server_id
+                )));
+            }
+            if raw_srv
+                .max_request_head_bytes
+                .is_some_and(|n| n < MIN_REQUEST_HEAD_BYTES)
+            {
+                return Err(ValidationError::InvalidMaxRequestHeadBytes(
+                    server_id.clone(),
+                ));
+            }
+            if raw_srv.header_read_timeout_secs == Some(0) {
+                return Err(ValidationError::InvalidHeaderReadTimeout(server_id.clone()));
+            }
+            if raw_srv.max_requests_per_connection == Some(0) {
+                return Err(ValidationError::InvalidMaxRequestsPerConnection(
+                    server_id.clone(),
+                ));
+            }
+            let min_write_rate = match (
+                raw_srv.min_write_rate_bytes_per_sec,
+                raw_srv.min_write_rate_window_secs,
+            ) {
+                (Some(0), _) => {
+                    return Err(ValidationError::InvalidMinWriteRate(
+                        server_id.clone(),
+                        "min_write_rate_bytes_per_sec must be greater than zero",
+                    ));
+                }
+                (_, Some(0)) => {
+                    return Err(ValidationError::InvalidMinWriteRate(
+                        server_id.clone(),
+                        "min_write_rate_window_secs must be greater than zero",
+                    ));
+                }
+                (None, Some(_)) => {
+                    return Err(ValidationError::InvalidMinWriteRate(
+                        server_id.clone(),
+                        "min_write_rate_window_secs needs min_write_rate_bytes_per_sec",
+                    ));
+                }
+                (Some(bytes_per_sec), window) => Some(MinWriteRate {
+                    bytes_per_sec,
+                    window: Duration::from_secs(window.unwrap_or(10)),
+                }),
+                (None, None) => None,
+            };
+            let proxy_protocol_trusted = raw_srv
+                .proxy_protocol_trusted
+                .iter()
+                .map(|c| parse_cidr(c))
+                .collect::<Result<Vec<_>, _>>()?;
+            let proxy_protocol_version = match raw_srv.proxy_protocol.unwrap_or_default() {
+                ProxyProtocolMode::Off => None,
+                ProxyProtocolMode::V1 => Some(ProxyProtocolVersion::V1),
+                ProxyProtocolMode::V2 => Some(ProxyProtocolVersion::V2),
+            };
+            let proxy_protocol = match proxy_protocol_version {
+                Some(_) if proxy_protocol_trusted.is_empty() => {
+                    return Err(ValidationError::InvalidProxyProtocol(
+                        server_id.clone(),
+                        "proxy_protocol_trusted must list the load balancer addresses",
+                    ));
+                }
+                Some(version) => Some(ProxyProtocolConfig {
+                    version,
+                    trusted: proxy_protocol_trusted,
+                }),
+                None => None,
+            };
+            if raw_srv.proxy.max_upload_buffer_bytes == Some(0) {
+                return Err(ValidationError::InvalidUploadBuffer(server_id.clone()));
+            }
+            if raw_srv.proxy.max_connections_per_backend == Some(0) {
+                return Err(ValidationError::InvalidMaxConnectionsPerBackend(
+                    server_id.clone(),
+                ));
+            }
+            let backend_queue = {
+                let invalid = |e| ValidationError::InvalidBackendQueue(server_id.clone(), e);
+                match (raw_srv.proxy.queue_max_len, raw_srv.proxy.queue_max_wait_ms) {
+                    (None, None) => None,
+                    (None, Some(_)) => {
+                        return Err(invalid("queue_max_wait_ms needs queue_max_len"));
+                    }
+                    (Some(0), _) => {
+                        return Err(invalid("queue_max_len must be greater than zero"));
+                    }
+                    (Some(_), Some(0)) => {
+                        return Err(invalid("queue_max_wait_ms must be greater than zero"));
+                    }
+                    (Some(_), _) if raw_srv.proxy.max_connections_per_backend.is_none() => {
+                        // Without a cap no backend is ever full, so nothing would queue.
+                        return Err(invalid("queue_max_len needs max_connections_per_backend"));
+                    }
+                    (Some(len), wait) => Some(BackendQueueLimits {
+                        max_len: len as usize,
+                        max_wait: Duration::from_millis(wait.unwrap_or(1000)),
+                    }),
+                }
+            };
+
+            // Names are appended to request paths as-is, so keep them to safe plain file names.
+            if let Some(name) = raw_srv.index_files.iter().flatten().find(|n| {
+                n.is_empty()
+                    || *n == "."
+                    || *n == ".."
+                    || n.chars().any(|c| {
+                        matches!(c, '/' | '\\' | '%' | '?' | '#')
+                            || c.is_whitespace()
+                            || c.is_control()
+                    })
+            }) {
+                return Err(ValidationError::InvalidIndexFile(
+                    server_id.clone(),
+                    name.clone(),
+                ));
+            }
+
+            let static_extensions = match raw_srv.static_extensions {
+                None => Vec::new(),
+                Some(list) if list.is_empty() => {
+                    return Err(ValidationError::InvalidStaticExtension(
+                        server_id.clone(),
+                        String::new(),
+                    ));
+                }
+                Some(list) => list
+                    .iter()
+                    .map(|e| {
+                        let ext = e.strip_prefix('.').unwrap_or(e);
+                        if !ext.is_empty()
+                            && ext
+                                .chars()
+                                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+                        {
+                            Ok(ext.to_ascii_lowercase())
+                        } else {
+                            Err(ValidationError::InvalidStaticExtension(
+                                server_id.clone(),
+                                e.clone(),
+                            ))
+                        }
+                    })
+                    .collect::<Result<_, _>>()?,
             };
 
-            if backend_strings.is_empty() {
-                return Err(ValidationError::NoBackendsConfigured(server_id.clone()));
+            let favicon = raw_srv.favicon;
+            if let Some(path) = &favicon
+                && !path.is_file()
+            {
+                return Err(ValidationError::FaviconNotFound(path.display().to_string()));
             }
 
-            // parse and validate backend URLs
-            let mut backends: Vec<Url> = Vec::with_capacity(backend_strings.len());
-            for b in backend_strings {
-                let url = Url::parse(&b)
-                    .map_err(|e| ValidationError::InvalidBackendUrl(b.clone(), e.to_string()))?;
-                match url.scheme() {
-                    "http" | "https" => backends.push(url),
-                    other => {
-                        return Err(ValidationError::UnsupportedBackendScheme(other.to_string()));
+            let signed_urls = match raw_srv.signed_urls {
+                Some(raw) => {
+                    let invalid =
+                        |e: String| ValidationError::InvalidSignedUrls(server_id.clone(), e);
+                    let secret = raw
+                        .secret
+                        .resolve(&format!("signed_urls.secret in server '{}'", server_id))?;
+                    if secret.expose().is_empty() {
+                        return Err(invalid("secret must not be empty".to_string()));
+                    }
+                    if raw.required_prefixes.is_empty() {
+                        return Err(invalid("required_prefixes must not be empty".to_string()));
                     }
+                    let mut required_prefixes = Vec::new();
+                    for prefix in raw.required_prefixes {
+                        check_path_prefix(&prefix).map_err(invalid)?;
+                        let trimmed = prefix.trim_end_matches('/');
+                        if !has_path_prefix(trimmed, "/static") {
+                            return Err(invalid(format!(
+                                "required prefix '{}' is not under /static",
+                                prefix
+                            )));
+                        }
+                        required_prefixes.push(trimmed.to_string());
+                    }
+                    Some(SignedUrlsConfig {
+                        secret,
+                        required_prefixes,
+                    })
+                }
+                None => None,
+            };
+
+            let mut error_pages = Vec::new();
+            let mut malformed_response_page = None;
+            let mut maintenance_page = None;
+            for (key, path) in raw_srv.error_pages {
+                let invalid = |e: String| ValidationError::InvalidErrorPage(server_id.clone(), e);
+                if !path.is_file() {
+                    return Err(invalid(format!(
+                        "page for {} not found: {}",
+                        key,
+                        path.display()
+                    )));
+                }
+                if key == "upstream_protocol_error" {
+                    malformed_response_page = Some(path);
+                    continue;
+                }
+                if key == "maintenance" {
+                    maintenance_page = Some(path);
+                    continue;
                 }
+                let code = key
+                    .trim()
+                    .parse::<u16>()
+                    .ok()
+                    .filter(|c| (400..=599).contains(c))
+                    .ok_or_else(|| {
+                        invalid(format!(
+                            "'{}' is neither a status code from 400 to 599, upstream_protocol_error nor maintenance",
+                            key
+                        ))
+                    })?;
+                error_pages.push((code, path));
             }
 
+            let robots_txt = match raw_srv.robots_txt {
+                Some(body) if body.contains('\n') => Some(RobotsTxt::Inline(body)),
+                Some(path) => {
+                    let path = PathBuf::from(path);
+                    if !path.is_file() {
+                        return Err(ValidationError::RobotsTxtNotFound(
+                            path.display().to_string(),
+                        ));
+                    }
+                    Some(RobotsTxt::File(path))
+                }
+                None => None,
+            };
+
+            let mut send_proxy_protocol = HashMap::new();
+            let backends =
+                parse_backends(raw_srv.proxy.backend, &server_id, &mut send_proxy_protocol)?;
+
             let backend_timeout =
                 Duration::from_secs(raw_srv.proxy.backend_timeout_secs.unwrap_or(30));
-            let rate_limit_per_minute = raw_srv.proxy.rate_limit_per_minute;
-            let rate_limit_burst = raw_srv.proxy.rate_limit_burst;
+
+            // Legacy single per-IP limit becomes the first rule; explicit rules follow.
+            let mut rate_limits: Vec<RateLimitRule> = Vec::new();
+            if let Some(per_minute) = raw_srv.proxy.rate_limit_per_minute {
+                rate_limits.push(RateLimitRule {
+                    key: RateLimitKey::Ip,
+                    per_minute: per_minute as f64,
+                    burst: raw_srv.proxy.rate_limit_burst.unwrap_or(per_minute) as f64,
+                });
+            }
+            for rule in raw_srv.proxy.rate_limit {
+                if rule.per_minute == 0 {
+                    return Err(ValidationError::InvalidRateLimit(server_id.clone()));
+                }
+                rate_limits.push(RateLimitRule {
+                    key: parse_rate_limit_key(&rule.key)?,
+                    per_minute: rule.per_minute as f64,
+                    burst: rule.burst.unwrap_or(rule.per_minute) as f64,
+                });
+            }
+            let rate_limit_on_missing_ip =
+                raw_srv.proxy.rate_limit_on_missing_ip.unwrap_or_default();
+            let rate_limit_max_entries = raw_srv.proxy.rate_limit_max_entries;
+            if rate_limit_max_entries == Some(0) {
+                return Err(ValidationError::InvalidRateLimitMaxEntries(
+                    server_id.clone(),
+                ));
+            }
+            let rate_limit_exempt_ips = raw_srv
+                .proxy
+                .rate_limit_exempt_ips
+                .iter()
+                .map(|c| parse_cidr(c))
+                .collect::<Result<Vec<_>, _>>()?;
+
+            let mut query_rewrites = Vec::with_capacity(raw_srv.proxy.query_rewrite.len());
+            for rule in raw_srv.proxy.query_rewrite {
+                if rule.name.is_empty() {
+                    return Err(ValidationError::InvalidQueryRewrite(
+                        server_id.clone(),
+                        "name must not be empty".to_string(),
+                    ));
+                }
+                let rewrite = match (rule.op, rule.value) {
+                    (QueryRewriteOp::Add, Some(value)) => QueryRewrite::Add {
+                        name: rule.name,
+                        value,
+                    },
+                    (QueryRewriteOp::Set, Some(value)) => QueryRewrite::Set {
+                        name: rule.name,
+                        value,
+                    },
+                    (QueryRewriteOp::Remove, None) => QueryRewrite::Remove { name: rule.name },
+                    (QueryRewriteOp::Remove, Some(_)) => {
+                        return Err(ValidationError::InvalidQueryRewrite(
+                            server_id.clone(),
+                            format!("'remove' of '{}' does not take a value", rule.name),
+                        ));
+                    }
+                    (op, None) => {
+                        return Err(ValidationError::InvalidQueryRewrite(
+                            server_id.clone(),
+                            format!("{:?} of '{}' requires a value", op, rule.name),
+                        ));
+                    }
+                };
+                query_rewrites.push(rewrite);
+            }
+            let invalid = |e: String| ValidationError::InvalidQueryRewrite(server_id.clone(), e);
+            let mut query_routes = raw_srv
+                .proxy
+                .query_route
+                .into_iter()
+                .map(parse_query_route)
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(invalid)?;
+            sort_by_prefix(&mut query_routes, |r| &r.path_prefix).map_err(invalid)?;
+
+            let match_rules = raw_srv
+                .proxy
+                .match_rules
+                .into_iter()
+                .map(|r| parse_match_rule(r, &server_id, &mut send_proxy_protocol))
+                .collect::<Result<Vec<_>, _>>()?;
+
+            let allow_backend_pinning = raw_srv.proxy.allow_backend_pinning.unwrap_or(false);
+            let backend_pinning_trusted_ips = raw_srv
+                .proxy
+                .backend_pinning_trusted_ips
+                .iter()
+                .map(|c| parse_cidr(c))
+                .collect::<Result<Vec<_>, _>>()?;
+            if allow_backend_pinning && backend_pinning_trusted_ips.is_empty() {
+                return Err(ValidationError::NoTrustedIpsForPinning(server_id.clone()));
+            }
+
             let max_request_size_bytes = raw_srv
                 .proxy
                 .max_request_size_bytes
                 .unwrap_or(10 * 1024 * 1024);
+            if raw_srv.proxy.max_decompressed_body_bytes == Some(0) {
+                return Err(ValidationError::InvalidMaxDecompressedBody(
+                    server_id.clone(),
+                ));
+            }
+            let max_decompressed_body_bytes = raw_srv
+                .proxy
+                .decompress_request_body
+                .unwrap_or(false)
+                .then(|| {
+                    raw_srv
+                        .proxy
+                        .max_decompressed_body_bytes
+                        .unwrap_or(max_request_size_bytes)
+                });
+            let max_uri_length = raw_srv.proxy.max_uri_length.unwrap_or(8192);
+            if max_uri_length == 0 {
+                return Err(ValidationError::InvalidMaxUriLength(server_id.clone()));
+            }
             let cache_ttl_secs = raw_srv.proxy.cache_ttl_secs;
             let cache_max_size_bytes = raw_srv.proxy.cache_max_size_bytes;
+            let cache_max_entry_fraction = raw_srv.proxy.cache_max_entry_fraction.unwrap_or(1.0);
+            if !(cache_max_entry_fraction > 0.0 && cache_max_entry_fraction <= 1.0) {
+                return Err(ValidationError::InvalidCacheMaxEntryFraction(
+                    server_id.clone(),
+                ));
+            }
+            let cache_max_entry_bytes =
+                cache_max_size_bytes.map(|max| (max as f64 * cache_max_entry_fraction) as u64);
+            let cache_ttl_jitter = raw_srv.proxy.cache_ttl_jitter;
+            if cache_ttl_jitter.is_some_and(|j| !(0.0..1.0).contains(&j)) {
+                return Err(ValidationError::InvalidCacheTtlJitter(server_id.clone()));
+            }
+            let cache_ttl_jitter = cache_ttl_jitter.filter(|j| *j > 0.0);
+
+            let forward_headers_allowlist = match raw_srv.proxy.forward_headers_allowlist {
+                Some(names) => Some(
+                    names
+                        .into_iter()
+                        .map(|n| {
+                            HeaderName::from_bytes(n.trim().as_bytes()).map_err(|_| {
+                                ValidationError::InvalidForwardHeader(server_id.clone(), n)
+                            })
+                        })
+                        .collect::<Result<Vec<_>, _>>()?,
+                ),
+                None => None,
+            };
+
+            let cache_bypass_headers = match raw_srv.proxy.cache_bypass_headers {
+                Some(names) => names
+                    .iter()
+                    .map(|n| {
+                        HeaderName::from_bytes(n.trim().as_bytes()).map_err(|_| {
+                            ValidationError::InvalidCacheBypassHeader(server_id.clone(), n.clone())
+                        })
+                    })
+                    .collect::<Result<Vec<_>, _>>()?,
+                None => vec![header::AUTHORIZATION, header::COOKIE],
+            };
+
+            let mut cache_routes: Vec<CacheRoute> = Vec::new();
+            for route in raw_srv.proxy.cache_route {
+                let prefix = route.path_prefix;
+                check_path_prefix(&prefix)
+                    .map_err(|e| ValidationError::InvalidCacheRoute(server_id.clone(), e))?;
+                if route.cache && cache_ttl_secs.is_none_or(|ttl| ttl == 0) {
+                    return Err(ValidationError::InvalidCacheRoute(
+                        server_id.clone(),
+                        format!(
+                            "cache = true for '{}' requires caching to be enabled with cache_ttl_secs",
+                            prefix
+                        ),
+                    ));
+                }
+                cache_routes.push(CacheRoute {
+                    path_prefix: prefix,
+                    cache: route.cache,
+                });
+            }
+            sort_by_prefix(&mut cache_routes, |r| &r.path_prefix)
+                .map_err(|e| ValidationError::InvalidCacheRoute(server_id.clone(), e))?;
+
+            let conceal = raw_srv.proxy.conceal.unwrap_or(false);
+            let access = AccessRule {
+                allow: raw_srv
+                    .proxy
+                    .allow_ips
+                    .iter()
+                    .map(|c| parse_cidr(c))
+                    .collect::<Result<_, _>>()?,
+                deny: raw_srv
+                    .proxy
+                    .deny_ips
+                    .iter()
+                    .map(|c| parse_cidr(c))
+                    .collect::<Result<_, _>>()?,
+                conceal,
+            };
+            let mut access_routes: Vec<AccessRoute> = Vec::new();
+            for route in raw_srv.proxy.access_route {
+                let prefix = route.path_prefix;
+                let invalid = |e: String| ValidationError::InvalidAccessRoute(server_id.clone(), e);
+                check_path_prefix(&prefix).map_err(invalid)?;
+                let parse_list = |field: &str, list: &[String]| {
+                    list.iter()
+                        .map(|c| {
+                            parse_cidr(c).map_err(|_| {
+                                invalid(format!(
+                                    "'{}' in {} for '{}' is not an IP address or CIDR",
+                                    c, field, prefix
+                                ))
+                            })
+                        })
+                        .collect::<Result<Vec<_>, _>>()
+                };
+                let rule = AccessRule {
+                    allow: parse_list("allow_ips", &route.allow_ips)?,
+                    deny: parse_list("deny_ips", &route.deny_ips)?,
+                    conceal: route.conceal.unwrap_or(conceal),
+                };
+                access_routes.push(AccessRoute {
+                    path_prefix: prefix,
+                    rule,
+                });
+            }
+            sort_by_prefix(&mut access_routes, |r| &r.path_prefix)
+                .map_err(|e| ValidationError::InvalidAccessRoute(server_id.clone(), e))?;
+            let trusted_proxies = raw_srv
+                .proxy
+                .trusted_proxies
+                .iter()
+                .map(|c| parse_cidr(c))
+                .collect::<Result<Vec<_>, _>>()?;
+
+            let mut auth_routes: Vec<AuthRoute> = Vec::new();
+            for route in raw_srv.proxy.auth_route {
+                let prefix = route.path_prefix;
+                let invalid = |e: String| ValidationError::InvalidAuthRoute(server_id.clone(), e);
+                check_path_prefix(&prefix).map_err(invalid)?;
+                let (scheme, credentials_file, realm) = match route.auth {
+                    RawAuth::Basic { htpasswd, realm } => (AuthScheme::Basic, htpasswd, realm),
+                    RawAuth::Bearer { tokens_file, realm } => {
+                        (AuthScheme::Bearer, tokens_file, realm)
+                    }
+                };
+                if !credentials_file.is_file() {
+                    return Err(invalid(format!(
+                        "credentials file {} for '{}' not found",
+                        credentials_file.display(),
+                        prefix
+                    )));
+                }
+                let realm = realm.unwrap_or_else(|| "Serava".to_string());
+                if realm.contains(['"', '\\']) || realm.chars().any(|c| c.is_control()) {
+                    return Err(invalid(format!(
+                        "realm for '{}' must not contain quotes, backslashes or control characters",
+                        prefix
+                    )));
+                }
+                auth_routes.push(AuthRoute {
+                    path_prefix: prefix,
+                    scheme,
+                    credentials_file,
+                    realm,
+                    forward_credentials: route.forward_credentials.unwrap_or(false),
+                });
+            }
+            sort_by_prefix(&mut auth_routes, |r| &r.path_prefix)
+                .map_err(|e| ValidationError::InvalidAuthRoute(server_id.clone(), e))?;
+
+            let invalid = |e: String| ValidationError::InvalidOriginCheck(server_id.clone(), e);
+            let (origin_allowed, origin_on_missing) = match &raw_srv.proxy.origin_check {
+                Some(c) => (
+                    parse_origins(&c.allowed_origins).map_err(invalid)?,
+                    c.on_missing.unwrap_or_default(),
+                ),
+                None => (Vec::new(), MissingOriginPolicy::default()),
+            };
+            let origin_check = raw_srv
+                .proxy
+                .origin_check
+                .as_ref()
+                .filter(|c| c.enabled.unwrap_or(true))
+                .map(|_| OriginRule {
+                    allowed: origin_allowed.clone(),
+                    on_missing: origin_on_missing,
+                });
+            let mut origin_routes: Vec<OriginRoute> = Vec::new();
+            for route in raw_srv.proxy.origin_route {
+                let prefix = route.path_prefix;
+                check_path_prefix(&prefix).map_err(invalid)?;
+                let rule = match route.enabled.unwrap_or(true) {
+                    true => Some(OriginRule {
+                        allowed: match &route.allowed_origins {
+                            Some(list) => parse_origins(list).map_err(invalid)?,
+                            None => origin_allowed.clone(),
+                        },
+                        on_missing: route.on_missing.unwrap_or(origin_on_missing),
+                    }),
+                    false => None,
+                };
+                origin_routes.push(OriginRoute {
+                    path_prefix: prefix,
+                    rule,
+                });
+            }
+            sort_by_prefix(&mut origin_routes, |r| &r.path_prefix).map_err(invalid)?;
+
+            let mut content_type_routes: Vec<ContentTypeRoute> = Vec::new();
+            for route in raw_srv.proxy.content_type_route {
+                let prefix = route.path_prefix;
+                let invalid =
+                    |e: String| ValidationError::InvalidContentTypeRoute(server_id.clone(), e);
+                check_path_prefix(&prefix).map_err(invalid)?;
+                if route.allowed_content_types.is_empty() {
+                    return Err(invalid(format!(
+                        "allowed_content_types for '{}' must not be empty",
+                        prefix
+                    )));
+                }
+                let allowed = route
+                    .allowed_content_types
+                    .iter()
+                    .map(|t| {
+                        parse_media_range(t).ok_or_else(|| {
+                            invalid(format!(
+                                "'{}' for '{}' is not a type/subtype media type",
+                                t, prefix
+                            ))
+                        })
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                content_type_routes.push(ContentTypeRoute {
+                    path_prefix: prefix,
+                    allowed,
+                    on_missing: route.on_missing.unwrap_or_default(),
+                });
+            }
+            sort_by_prefix(&mut content_type_routes, |r| &r.path_prefix)
+                .map_err(|e| ValidationError::InvalidContentTypeRoute(server_id.clone(), e))?;
+
+            let maintenance_windows = raw_srv
+                .proxy
+                .maintenance_window
+                .into_iter()
+                .map(|w| {
+                    parse_maintenance_window(w).map_err(|e| {
+                        ValidationError::InvalidMaintenanceWindow(server_id.clone(), e)
+                    })
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            let maintenance_bypass_ips = raw_srv
+                .proxy
+                .maintenance_bypass_ips
+                .iter()
+                .map(|c| parse_cidr(c))
+                .collect::<Result<Vec<_>, _>>()?;
+
+            let mut html_rewrite_routes: Vec<HtmlRewriteRoute> = Vec::new();
+            for route in raw_srv.proxy.html_rewrite {
+                let prefix = route.path_prefix;
+                let invalid = |e: String| ValidationError::InvalidHtmlRewrite(server_id.clone(), e);
+                check_path_prefix(&prefix).map_err(invalid)?;
+                if route.base_href.is_none()
+                    && route.inject.is_empty()
+                    && route.rewrite_links.is_empty()
+                {
+                    return Err(invalid(format!(
+                        "'{}' sets none of base_href, inject or rewrite_links",
+                        prefix
+                    )));
+                }
+                let inject = route
+                    .inject
+                    .into_iter()
+                    .map(|i| {
+                        let selector = i.selector.parse().map_err(|e| {
+                            invalid(format!("selector '{}' for '{}': {}", i.selector, prefix, e))
+                        })?;
+                        Ok(HtmlInject {
+                            selector,
+                            position: i.position.unwrap_or_default(),
+                            html: i.html,
+                        })
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                if let Some(link) = route.rewrite_links.iter().find(|l| l.from.is_empty()) {
+                    return Err(invalid(format!(
+                        "rewrite_links for '{}' has an empty from (to '{}')",
+                        prefix, link.to
+                    )));
+                }
+                let rewrite_links = route
+                    .rewrite_links
+                    .into_iter()
+                    .map(|l| LinkRewrite {
+                        from: l.from,
+                        to: l.to,
+                    })
+                    .collect();
+                html_rewrite_routes.push(HtmlRewriteRoute {
+                    path_prefix: prefix,
+                    base_href: route.base_href,
+                    inject,
+                    rewrite_links,
+                });
+            }
+            sort_by_prefix(&mut html_rewrite_routes, |r| &r.path_prefix)
+                .map_err(|e| ValidationError::InvalidHtmlRewrite(server_id.clone(), e))?;
+
+            let uses_fastcgi = backends
+                .iter()
+                .chain(match_rules.iter().flat_map(|r| r.backends.iter()))
+                .any(is_fastcgi);
+            let fastcgi = match raw_srv.proxy.fastcgi {
+                Some(raw) => {
+                    let invalid = |e: String| ValidationError::InvalidFastCgi(server_id.clone(), e);
+                    if !raw.document_root.is_absolute() {
+                        return Err(invalid(format!(
+                            "document_root '{}' must be an absolute path",
+                            raw.document_root.display()
+                        )));
+                    }
+                    let document_root = raw
+                        .document_root
+                        .to_str()
+                        .ok_or_else(|| invalid("document_root must be valid UTF-8".to_string()))?;
+                    let index = raw.index.unwrap_or_else(|| "index.php".to_string());
+                    if index.is_empty() || index.contains('/') {
+                        return Err(invalid(format!(
+                            "index '{}' must be a plain file name",
+                            index
+                        )));
+                    }
+                    Some(FastCgiConfig {
+                        document_root: document_root.trim_end_matches('/').to_string(),
+                        index,
+                    })
+                }
+                None if uses_fastcgi => {
+                    return Err(ValidationError::InvalidFastCgi(
+                        server_id.clone(),
+                        "fastcgi backends need [servers.proxy.fastcgi] with a document_root"
+                            .to_string(),
+                    ));
+                }
+                None => None,
+            };
+            let debug_headers = raw_srv.proxy.debug_headers.unwrap_or(false);
+
+            let block_rules = raw_srv
+                .block_rule
+                .into_iter()
+                .enumerate()
+                .map(|(i, r)| parse_block_rule(r, i, &server_id))
+                .collect::<Result<Vec<_>, _>>()?;
+            let mut names = std::collections::HashSet::new();
+            if let Some(dup) = block_rules.iter().find(|r| !names.insert(&r.name)) {
+                return Err(ValidationError::InvalidBlockRule(
+                    server_id.clone(),
+                    format!("name '{}' is used more than once", dup.name),
+                ));
+            }
+            let redirects = raw_srv
+                .redirects
+                .into_iter()
+                .enumerate()
+                .map(|(i, r)| parse_redirect(r, i, &server_id))
+                .collect::<Result<Vec<_>, _>>()?;
+            check_redirect_loops(&redirects)
+                .map_err(|e| ValidationError::InvalidRedirect(server_id.clone(), e))?;
+            let experiments = raw_srv
+                .experiments
+                .map(parse_experiments)
+                .transpose()
+                .map_err(|e| ValidationError::InvalidExperiments(server_id.clone(), e))?;
+
+            let tls_fingerprint = tls.as_ref().is_some_and(|t| t.fingerprint);
+            if !tls_fingerprint
+                && block_rules
+                    .iter()
+                    .any(|r| !r.ja3.is_empty() || !r.ja4.is_empty())
+            {
+                return Err(ValidationError::InvalidTlsFingerprint(
+                    server_id.clone(),
+                    "block_rule ja3/ja4 patterns need tls_fingerprint = true",
+                ));
+            }
+            let forward_tls_fingerprint = raw_srv.proxy.forward_tls_fingerprint.unwrap_or(false);
+            if forward_tls_fingerprint && !tls_fingerprint {
+                return Err(ValidationError::InvalidTlsFingerprint(
+                    server_id.clone(),
+                    "forward_tls_fingerprint needs tls_fingerprint = true",
+                ));
+            }
+
+            let cookie_rewrite = match raw_srv.proxy.cookie_rewrite {
+                Some(raw) => {
+                    let invalid =
+                        |e: String| ValidationError::InvalidCookieRewrite(server_id.clone(), e);
+                    let bad_value = |v: &str| {
+                        v.contains([';', ','])
+                            || v.chars().any(|c| c.is_whitespace() || c.is_control())
+                    };
+                    if let Some(domain) = &raw.domain
+                        && bad_value(domain)
+                    {
+                        return Err(invalid(format!("domain '{}' is not a valid host", domain)));
+                    }
+                    if let Some(path) = &raw.path
+                        && (!path.starts_with('/') || bad_value(path))
+                    {
+                        return Err(invalid(format!(
+                            "path '{}' must start with '/' and contain no ';', ',' or whitespace",
+                            path
+                        )));
+                    }
+                    let secure = raw.secure.unwrap_or(false);
+                    if raw.same_site == Some(SameSite::None) && !secure {
+                        return Err(invalid(
+                            "same_site = \"none\" requires secure = true (browsers drop it otherwise)"
+                                .to_string(),
+                        ));
+                    }
+                    Some(CookieRewrite {
+                        domain: raw.domain,
+                        path: raw.path,
+                        secure,
+                        same_site: raw.same_site,
+                    })
+                }
+                None => None,
+            };
+
+            let request_signing = match raw_srv.proxy.request_signing {
+                Some(raw) => {
+                    let secret = raw
+                        .secret
+                        .resolve(&format!("request_signing.secret in server '{}'", server_id))?;
+                    if secret.expose().is_empty() {
+                        return Err(ValidationError::InvalidRequestSigning(
+                            server_id.clone(),
+                            "secret must not be empty".to_string(),
+                        ));
+                    }
+                    let components = raw.components.unwrap_or_else(|| {
+                        vec![
+                            SignComponent::Method,
+                            SignComponent::Path,
+                            SignComponent::Timestamp,
+                        ]
+                    });
+                    if components.is_empty() {
+                        return Err(ValidationError::InvalidRequestSigning(
+                            server_id.clone(),
+                            "components must not be empty".to_string(),
+                        ));
+                    }
+                    Some(RequestSigning { secret, components })
+                }
+                None => None,
+            };
+
+            // A missing database isn't an error: lookups fail open until a reload finds it.
+            let has_country_db = raw_srv.proxy.geoip_db.is_some();
+            let geoip_dbs: Vec<PathBuf> = raw_srv
+                .proxy
+                .geoip_db
+                .into_iter()
+                .chain(raw_srv.proxy.geoip_asn_db)
+                .collect();
+            let deny_countries = match raw_srv.proxy.deny_countries.as_slice() {
+                [] => Vec::new(),
+                list => parse_countries(list)
+                    .map_err(|e| ValidationError::InvalidDenyCountries(server_id.clone(), e))?,
+            };
+            if !has_country_db
+                && (!deny_countries.is_empty()
+                    || match_rules
+                        .iter()
+                        .any(|r| matches!(r.source, MatchSource::Country)))
+            {
+                return Err(ValidationError::GeoIpDbRequired(server_id.clone()));
+            }
 
             out.push(ConfigEntry {
                 listen,
-                static_dir,
+                systemd_socket_name: raw_srv.systemd_socket_name,
+                reuse_port_acceptors,
+                max_connections: raw_srv.max_connections,
+                max_connections_action: raw_srv.max_connections_action.unwrap_or_default(),
+                max_connections_per_ip: raw_srv.max_connections_per_ip,
+                max_connections_per_ip_action: raw_srv
+                    .max_connections_per_ip_action
+                    .unwrap_or_default(),
+                max_requests_per_connection: raw_srv.max_requests_per_connection,
+                min_write_rate,
+                proxy_protocol,
+                max_request_head_bytes: raw_srv.max_request_head_bytes,
+                header_read_timeout: raw_srv.header_read_timeout_secs.map(Duration::from_secs),
+                static_dirs,
+                index_files: raw_srv.index_files,
+                static_extensions,
+                csp: raw_srv.csp,
+                csp_report_only: raw_srv.csp_report_only,
+                csp_override: raw_srv.csp_override.unwrap_or(false),
+                security_headers,
+                signed_urls,
+                block_rules,
+                redirects,
+                experiments,
                 backends,
                 tls,
+                admin_token,
+                admin_listen,
+                debug_panic_route: raw_srv.debug_panic_route.unwrap_or(false),
+                readiness_path,
+                favicon,
+                error_pages,
+                malformed_response_page,
+                maintenance_page,
+                robots_txt,
                 backend_timeout,
-                rate_limit_per_minute,
-                rate_limit_burst,
+                request_timeout: raw_srv
+                    .proxy
+                    .request_timeout_secs
+                    .filter(|&secs| secs > 0)
+                    .map(Duration::from_secs),
+                slow_request_threshold: raw_srv
+                    .proxy
+                    .slow_request_threshold_ms
+                    .filter(|&ms| ms > 0)
+                    .map(Duration::from_millis),
+                rate_limits,
+                rate_limit_on_missing_ip,
+                rate_limit_max_entries,
+                rate_limit_exempt_ips,
+                query_rewrites,
+                query_routes,
+                match_rules,
+                send_proxy_protocol,
+                allow_backend_pinning,
+                allow_absolute_form: raw_srv.proxy.allow_absolute_form.unwrap_or(false),
+                forward_headers_allowlist,
+                forward_tls_fingerprint,
+                forward_tls_info: raw_srv.proxy.forward_tls_info.unwrap_or(false),
+                backend_pinning_trusted_ips,
                 max_request_size_bytes,
+                max_uri_length,
                 cache_ttl_secs,
                 cache_max_size_bytes,
+                cache_max_entry_bytes,
+                cache_ttl_jitter,
+                cache_key_include_host: raw_srv.proxy.cache_key_include_host.unwrap_or(false),
+                path_decoding: raw_srv.proxy.path_decoding.unwrap_or_default(),
+                cache_bypass_headers,
+                cache_routes,
+                access,
+                access_routes,
+                trusted_proxies,
+                auth_routes,
+                origin_check,
+                origin_routes,
+                content_type_routes,
+                maintenance_windows,
+                maintenance_bypass_ips,
+                html_rewrite_routes,
+                fastcgi,
+                debug_headers,
+                trailing_slash: raw_srv.proxy.trailing_slash.unwrap_or_default(),
+                normalize_path: raw_srv.proxy.normalize_path.unwrap_or(false),
+                request_signing,
+                cookie_rewrite,
+                geoip_dbs,
+                deny_countries,
+                idempotency_window: raw_srv
+                    .proxy
+                    .idempotency_window_secs
+                    .filter(|&secs| secs > 0)
+                    .map(Duration::from_secs),
+                idempotency_max_body_bytes: raw_srv
+                    .proxy
+                    .idempotency_max_body_bytes
+                    .unwrap_or(1024 * 1024),
+                max_connections_per_backend: raw_srv.proxy.max_connections_per_backend,
+                backend_queue,
+                max_upload_buffer_bytes: raw_srv.proxy.max_upload_buffer_bytes,
+                max_decompressed_body_bytes,
             });
         }
 
-        Ok(out)
+        Ok(Config {
+            shutdown_grace: Duration::from_secs(shutdown_grace_secs),
+            shutdown_grace_max: self.shutdown_grace_max_secs.map(Duration::from_secs),
+            max_connections: self.max_connections,
+            bind_retry: BindRetry {
+                attempts: self.bind_retry_attempts.unwrap_or(0),
+                backoff: Duration::from_millis(self.bind_retry_backoff_ms.unwrap_or(500)),
+            },
+            allow_partial_startup: self.allow_partial_startup.unwrap_or(false),
+            server_restart: RestartPolicy {
+                limit: self.server_restart_limit.unwrap_or(5),
+                backoff: Duration::from_millis(self.server_restart_backoff_ms.unwrap_or(1000)),
+            },
+            startup_backend_check: self.startup_backend_check.unwrap_or_default(),
+            startup_backend_check_timeout: Duration::from_millis(
+                self.startup_backend_check_timeout_ms.unwrap_or(2000),
+            ),
+            drain_retry_after: Duration::from_secs(self.drain_retry_after_secs.unwrap_or(30)),
+            drain_serve_static: self.drain_serve_static.unwrap_or(true),
+            allow_trace: self.allow_trace.unwrap_or(false),
+            runtime,
+            limits,
+            auto_ban,
+            servers: out,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Validate a single server whose `[servers.proxy]` table has `proxy` added to it.
+    fn validate(proxy: &str) -> Result<Config, ValidationError> {
+        let toml = format!(
+            "[[servers]]\nlisten = \"127.0.0.1:8080\"\nstatic_dir = \"{}\"\n\n\
+             [servers.proxy]\nbackend = [\"http://127.0.0.1:4000\"]\n{}",
+            env!("CARGO_MANIFEST_DIR"),
+            proxy
+        );
+        toml::from_str::<RawConfig>(&toml).unwrap().validate()
+    }
+
+    fn server(proxy: &str) -> ConfigEntry {
+        validate(proxy).unwrap().servers.remove(0)
+    }
+
+    #[test]
+    fn cache_routes_are_validated_and_sorted() {
+        let srv = server(
+            "cache_ttl_secs = 60\n\
+             [[servers.proxy.cache_route]]\npath_prefix = \"/api\"\ncache = true\n\
+             [[servers.proxy.cache_route]]\npath_prefix = \"/api/private\"\ncache = false\n",
+        );
+        let routes: Vec<_> = srv
+            .cache_routes
+            .iter()
+            .map(|r| (r.path_prefix.as_str(), r.cache))
+            .collect();
+        assert_eq!(routes, [("/api/private", false), ("/api", true)]);
+
+        for bad in [
+            "[[servers.proxy.cache_route]]\npath_prefix = \"api\"\ncache = false\n",
+            "[[servers.proxy.cache_route]]\npath_prefix = \"/a?b\"\ncache = false\n",
+            "[[servers.proxy.cache_route]]\npath_prefix = \"/a\"\ncache = false\n\
+             [[servers.proxy.cache_route]]\npath_prefix = \"/a\"\ncache = true\n",
+            // Forcing a route into the cache needs a cache.
+            "[[servers.proxy.cache_route]]\npath_prefix = \"/a\"\ncache = true\n",
+        ] {
+            assert!(
+                matches!(validate(bad), Err(ValidationError::InvalidCacheRoute(..))),
+                "{}",
+                bad
+            );
+        }
+    }
+
+    #[test]
+    fn route_prefixes_are_sorted_and_unique() {
+        let srv = server(
+            "[[servers.proxy.access_route]]\npath_prefix = \"/a\"\n\
+             [[servers.proxy.access_route]]\npath_prefix = \"/a/b/c\"\n\
+             [[servers.proxy.access_route]]\npath_prefix = \"/a/b\"\n\
+             [[servers.proxy.access_route]]\npath_prefix = \"/z/b\"\n",
+        );
+        let prefixes: Vec<_> = srv.access_routes.iter().map(|r| &r.path_prefix).collect();
+        assert_eq!(prefixes, ["/a/b/c", "/a/b", "/z/b", "/a"]);
+
+        let twice = |block: &str, body: &str| {
+            format!(
+                "[[servers.proxy.{block}]]\npath_prefix = \"/a\"\n{body}\n\
+                 [[servers.proxy.{block}]]\npath_prefix = \"/b\"\n{body}\n\
+                 [[servers.proxy.{block}]]\npath_prefix = \"/a\"\n{body}\n"
+            )
+        };
+        assert!(matches!(
+            validate(&twice("access_route", "")),
+            Err(ValidationError::InvalidAccessRoute(..))
+        ));
+        assert!(matches!(
+            validate(&twice("origin_route", "")),
+            Err(ValidationError::InvalidOriginCheck(..))
+        ));
+        assert!(matches!(
+            validate(&twice("query_route", "query_remove = [\"x\"]")),
+            Err(ValidationError::InvalidQueryRewrite(..))
+        ));
+        assert!(matches!(
+            validate(&twice(
+                "content_type_route",
+                "allowed_content_types = [\"application/json\"]"
+            )),
+            Err(ValidationError::InvalidContentTypeRoute(..))
+        ));
+    }
+
+    #[test]
+    fn trace_is_not_forwarded_by_default() {
+        assert!(!validate("").unwrap().allow_trace);
+    }
+
+    #[test]
+    fn cache_bypass_headers_default_to_credentials() {
+        let srv = server("");
+        assert_eq!(
+            srv.cache_bypass_headers,
+            [header::AUTHORIZATION, header::COOKIE]
+        );
+        let srv = server("cache_bypass_headers = [\"X-Session\"]\n");
+        assert_eq!(srv.cache_bypass_headers, ["x-session"]);
+        assert!(validate("cache_bypass_headers = [\"bad header\"]\n").is_err());
     }
 }
@@ -0,0 +1,910 @@
+//! Connection accounting and `max_connections`/`max_connections_per_ip` enforcement on the
+//! accept path.
+//!
+//! [`ConnLimitAcceptor`] sits innermost in the axum-server acceptor chain (below TLS), so a
+//! permit is taken per TCP connection and released when the stream is dropped. The per-IP cap
+//! goes by the TCP peer address, since no request has been read yet.
+//!
+//! The same layer applies the per-connection policy once a connection is in:
+//! `max_requests_per_connection` and the `min_write_rate_bytes_per_sec` floor for slow readers.
+//!
+//! HTTP/3 connections don't pass through here, so they are neither limited nor counted.
+
+use axum::{
+    body::Body,
+    http::{Request, Response, StatusCode, Version, header},
+};
+use dashmap::DashMap;
+use futures::future::Either;
+use ipnet::IpNet;
+use serde::Serialize;
+use std::future::{Future, Ready, ready};
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::TcpStream;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::time::{Instant, Sleep};
+use tower_service::Service;
+
+use crate::config::{ConnectionLimitAction, MinWriteRate};
+use crate::transfers::hold_until_sent;
+
+// Minimum spacing between "connection limit reached" warnings for one server.
+const WARN_INTERVAL_SECS: u64 = 10;
+
+/// `max_connections_per_ip` of one server.
+#[derive(Debug, Clone)]
+pub struct PerIpLimit {
+    pub max: usize,
+    pub action: ConnectionLimitAction,
+    /// Addresses the cap doesn't apply to (`rate_limit_exempt_ips`).
+    pub exempt: Vec<IpNet>,
+}
+
+/// Limits applied to each connection once it is accepted.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ConnectionPolicy {
+    /// `max_requests_per_connection`.
+    pub max_requests: Option<u64>,
+    /// `min_write_rate_bytes_per_sec` and its window.
+    pub min_write_rate: Option<MinWriteRate>,
+}
+
+/// Live and peak connection counts for one server, plus its limits.
+#[derive(Debug)]
+pub struct ConnectionTracker {
+    listen: SocketAddr,
+    current: AtomicUsize,
+    peak: AtomicUsize,
+    accepted: AtomicU64,
+    requests: AtomicU64,
+    in_flight_requests: AtomicUsize,
+    bytes_in: AtomicU64,
+    bytes_out: AtomicU64,
+    rejected: AtomicU64,
+    rejected_per_ip: AtomicU64,
+    max: Option<usize>,
+    local: Option<Arc<Semaphore>>,
+    global: Option<Arc<Semaphore>>,
+    action: ConnectionLimitAction,
+    per_ip: Option<PerIpLimit>,
+    // Open connections per peer address; only kept while per_ip is set
+    by_ip: DashMap<IpAddr, usize>,
+    policy: ConnectionPolicy,
+    request_cap_closes: AtomicU64,
+    slow_write_aborts: AtomicU64,
+    proxy_protocol_errors: AtomicU64,
+    started: Instant,
+    // Seconds since `started` of the last warning, +1 (0 = never warned).
+    last_warn: AtomicU64,
+}
+
+/// Point-in-time view of a [`ConnectionTracker`].
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct ConnectionStats {
+    pub current: usize,
+    pub peak: usize,
+    /// Connections let in since startup.
+    pub accepted: u64,
+    /// Requests received on those connections since startup.
+    pub requests: u64,
+    /// Requests whose response hasn't been sent in full yet (streamed bodies included).
+    pub in_flight_requests: usize,
+    /// Bytes read from and written to clients, as on the wire (TLS included).
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    pub max: Option<usize>,
+    pub rejected: u64,
+    pub max_per_ip: Option<usize>,
+    /// Connections refused by `max_connections_per_ip` (not included in `rejected`).
+    pub rejected_per_ip: u64,
+    pub max_requests_per_connection: Option<u64>,
+    /// HTTP/1 connections closed after reaching `max_requests_per_connection`.
+    pub request_cap_closes: u64,
+    /// Connections aborted for reading responses slower than `min_write_rate_bytes_per_sec`.
+    pub slow_write_aborts: u64,
+    /// Connections closed for a missing, malformed or late PROXY protocol header.
+    pub proxy_protocol_errors: u64,
+}
+
+// Which cap refused a connection.
+enum Rejection {
+    Server,
+    PerIp(IpAddr),
+}
+
+impl ConnectionTracker {
+    pub fn new(
+        listen: SocketAddr,
+        max: Option<usize>,
+        global: Option<Arc<Semaphore>>,
+        action: ConnectionLimitAction,
+        per_ip: Option<PerIpLimit>,
+        policy: ConnectionPolicy,
+    ) -> Self {
+        Self {
+            listen,
+            current: AtomicUsize::new(0),
+            peak: AtomicUsize::new(0),
+            accepted: AtomicU64::new(0),
+            requests: AtomicU64::new(0),
+            in_flight_requests: AtomicUsize::new(0),
+            bytes_in: AtomicU64::new(0),
+            bytes_out: AtomicU64::new(0),
+            rejected: AtomicU64::new(0),
+            rejected_per_ip: AtomicU64::new(0),
+            max,
+            local: max.map(|n| Arc::new(Semaphore::new(n))),
+            global,
+            action,
+            per_ip,
+            by_ip: DashMap::new(),
+            policy,
+            request_cap_closes: AtomicU64::new(0),
+            slow_write_aborts: AtomicU64::new(0),
+            proxy_protocol_errors: AtomicU64::new(0),
+            started: Instant::now(),
+            last_warn: AtomicU64::new(0),
+        }
+    }
+
+    pub fn stats(&self) -> ConnectionStats {
+        ConnectionStats {
+            current: self.current.load(Ordering::Relaxed),
+            peak: self.peak.load(Ordering::Relaxed),
+            accepted: self.accepted.load(Ordering::Relaxed),
+            requests: self.requests.load(Ordering::Relaxed),
+            in_flight_requests: self.in_flight_requests.load(Ordering::Relaxed),
+            bytes_in: self.bytes_in.load(Ordering::Relaxed),
+            bytes_out: self.bytes_out.load(Ordering::Relaxed),
+            max: self.max,
+            rejected: self.rejected.load(Ordering::Relaxed),
+            max_per_ip: self.per_ip.as_ref().map(|l| l.max),
+            rejected_per_ip: self.rejected_per_ip.load(Ordering::Relaxed),
+            max_requests_per_connection: self.policy.max_requests,
+            request_cap_closes: self.request_cap_closes.load(Ordering::Relaxed),
+            slow_write_aborts: self.slow_write_aborts.load(Ordering::Relaxed),
+            proxy_protocol_errors: self.proxy_protocol_errors.load(Ordering::Relaxed),
+        }
+    }
+
+    pub fn record_proxy_protocol_error(&self) {
+        self.proxy_protocol_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    // Take the peer's slot first, then the process-wide permit, then the per-server one.
+    fn try_acquire(self: &Arc<Self>, ip: Option<IpAddr>) -> Result<ConnectionGuard, Rejection> {
+        let ip_slot = match (&self.per_ip, ip) {
+            (Some(limit), Some(ip)) if !limit.exempt.iter().any(|net| net.contains(&ip)) => {
+                let mut open = self.by_ip.entry(ip).or_insert(0);
+                if *open >= limit.max {
+                    return Err(Rejection::PerIp(ip));
+                }
+                *open += 1;
+                Some(IpSlot {
+                    tracker: self.clone(),
+                    ip,
+                })
+            }
+            _ => None,
+        };
+        let global = match &self.global {
+            Some(s) => Some(
+                s.clone()
+                    .try_acquire_owned()
+                    .map_err(|_| Rejection::Server)?,
+            ),
+            None => None,
+        };
+        let local = match &self.local {
+            Some(s) => Some(
+                s.clone()
+                    .try_acquire_owned()
+                    .map_err(|_| Rejection::Server)?,
+            ),
+            None => None,
+        };
+        let now = self.current.fetch_add(1, Ordering::Relaxed) + 1;
+        self.peak.fetch_max(now, Ordering::Relaxed);
+        self.accepted.fetch_add(1, Ordering::Relaxed);
+        Ok(ConnectionGuard {
+            tracker: self.clone(),
+            _permits: (global, local),
+            _ip_slot: ip_slot,
+        })
+    }
+
+    // The action for a refused connection, after counting it.
+    fn reject(&self, rejection: Rejection) -> ConnectionLimitAction {
+        match (rejection, &self.per_ip) {
+            (Rejection::PerIp(ip), Some(limit)) => {
+                self.rejected_per_ip.fetch_add(1, Ordering::Relaxed);
+                tracing::debug!(
+                    "refusing connection to {} from {}: {} already open",
+                    self.listen,
+                    ip,
+                    limit.max
+                );
+                limit.action
+            }
+            _ => {
+                self.record_rejection();
+                self.action
+            }
+        }
+    }
+
+    fn record_rejection(&self) {
+        self.rejected.fetch_add(1, Ordering::Relaxed);
+
+        let now = self.started.elapsed().as_secs() + 1;
+        let last = self.last_warn.load(Ordering::Relaxed);
+        if (last == 0 || now >= last + WARN_INTERVAL_SECS)
+            && self
+                .last_warn
+                .compare_exchange(last, now, Ordering::Relaxed, Ordering::Relaxed)
+                .is_ok()
+        {
+            tracing::warn!(
+                "connection limit reached on {} ({} open, {} rejected so far)",
+                self.listen,
+                self.current.load(Ordering::Relaxed),
+                self.rejected.load(Ordering::Relaxed)
+            );
+        }
+    }
+}
+
+// A connection counted against its peer address under max_connections_per_ip.
+#[derive(Debug)]
+struct IpSlot {
+    tracker: Arc<ConnectionTracker>,
+    ip: IpAddr,
+}
+
+impl Drop for IpSlot {
+    fn drop(&mut self) {
+        if let Some(mut open) = self.tracker.by_ip.get_mut(&self.ip) {
+            *open -= 1;
+        }
+        self.tracker.by_ip.remove_if(&self.ip, |_, open| *open == 0);
+    }
+}
+
+/// Held for the lifetime of an accepted connection.
+#[derive(Debug)]
+pub struct ConnectionGuard {
+    tracker: Arc<ConnectionTracker>,
+    _permits: (Option<OwnedSemaphorePermit>, Option<OwnedSemaphorePermit>),
+    _ip_slot: Option<IpSlot>,
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.tracker.current.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+// What a connection's stream and its service share.
+#[derive(Debug)]
+struct ConnShared {
+    tracker: Arc<ConnectionTracker>,
+    requests: AtomicU64,
+    // Set once the connection has answered with an event stream, which exempts it from
+    // min_write_rate for the rest of its life.
+    streaming: AtomicBool,
+}
+
+impl ConnShared {
+    // Count a request; true when its response should be the connection's last. HTTP/2 has
+    // no Connection header to close with, so it isn't capped.
+    fn count_request(&self, version: Version) -> bool {
+        let Some(max) = self.tracker.policy.max_requests else {
+            return false;
+        };
+        if version >= Version::HTTP_2 {
+            return false;
+        }
+        let n = self.requests.fetch_add(1, Ordering::Relaxed) + 1;
+        if n == max {
+            self.tracker
+                .request_cap_closes
+                .fetch_add(1, Ordering::Relaxed);
+        }
+        n >= max
+    }
+}
+
+// min_write_rate bookkeeping for one connection. Only time spent with a write blocked on a full
+// socket buffer counts, so a client that is merely slow to send its next request isn't
+// penalized. Once the blocked time adds up to a window, the bytes written over it must meet
+// the rate. Time is read from tokio's clock, the one its timer runs on.
+#[derive(Debug)]
+struct WriteRate {
+    conn: Arc<ConnShared>,
+    min: MinWriteRate,
+    peer: Option<IpAddr>,
+    // Start of the write currently blocked
+    blocked_since: Option<Instant>,
+    // Blocked time and bytes written in the current window, not counting blocked_since
+    blocked: Duration,
+    written: u64,
+    // Fires when the current blocked write fills the window
+    timer: Option<Pin<Box<Sleep>>>,
+}
+
+impl WriteRate {
+    fn new(conn: Arc<ConnShared>, min: MinWriteRate, peer: Option<IpAddr>) -> Self {
+        Self {
+            conn,
+            min,
+            peer,
+            blocked_since: None,
+            blocked: Duration::ZERO,
+            written: 0,
+            timer: None,
+        }
+    }
+
+    fn track(
+        &mut self,
+        cx: &mut Context<'_>,
+        stream: &TcpStream,
+        result: Poll<io::Result<usize>>,
+    ) -> Poll<io::Result<usize>> {
+        if self.conn.streaming.load(Ordering::Relaxed) {
+            return result;
+        }
+        let now = Instant::now();
+        match result {
+            Poll::Ready(Ok(n)) => {
+                if let Some(since) = self.blocked_since.take() {
+                    self.blocked += now - since;
+                }
+                self.written += n as u64;
+                self.timer = None;
+                Poll::Ready(Ok(n))
+            }
+            Poll::Pending => {
+                let since = *self.blocked_since.get_or_insert(now);
+                let blocked = self.blocked + (now - since);
+                if blocked >= self.min.window {
+                    let rate = self.written as f64 / blocked.as_secs_f64();
+                    if rate < self.min.bytes_per_sec as f64 {
+                        return Poll::Ready(Err(self.abort(stream, blocked)));
+                    }
+                    // Fast enough over this window; start the next one.
+                    self.blocked = Duration::ZERO;
+                    self.written = 0;
+                    self.blocked_since = Some(now);
+                    self.timer = None;
+                }
+                let remaining = self.min.window - self.blocked;
+                let timer = self
+                    .timer
+                    .get_or_insert_with(|| Box::pin(tokio::time::sleep(remaining)));
+                if timer.as_mut().poll(cx).is_ready() {
+                    // The window is full; come back to judge it.
+                    self.timer = None;
+                    cx.waker().wake_by_ref();
+                }
+                Poll::Pending
+            }
+            other => other,
+        }
+    }
+
+    fn abort(&self, stream: &TcpStream, blocked: Duration) -> io::Error {
+        // A plain close would still deliver what sits in the socket buffer, at the client's
+        // pace; reset the connection instead.
+        let _ = socket2::SockRef::from(stream).set_linger(Some(Duration::ZERO));
+        let tracker = &self.conn.tracker;
+        tracker.slow_write_aborts.fetch_add(1, Ordering::Relaxed);
+        tracing::debug!(
+            "aborting connection to {} from {:?}: {} bytes accepted in {:?} spent waiting to write",
+            tracker.listen,
+            self.peer,
+            self.written,
+            blocked
+        );
+        io::Error::new(
+            io::ErrorKind::TimedOut,
+            "client is reading the response too slowly",
+        )
+    }
+}
+
+/// Stream wrapper that releases its connection slot when dropped, counts the bytes passing
+/// through and enforces `min_write_rate_bytes_per_sec`.
+#[derive(Debug)]
+pub struct TrackedStream<S> {
+    inner: S,
+    tracker: Arc<ConnectionTracker>,
+    _guard: Option<ConnectionGuard>,
+    rate: Option<WriteRate>,
+}
+
+impl<S> TrackedStream<S> {
+    fn count_written(&self, result: &Poll<io::Result<usize>>) {
+        if let Poll::Ready(Ok(n)) = result {
+            self.tracker
+                .bytes_out
+                .fetch_add(*n as u64, Ordering::Relaxed);
+        }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for TrackedStream<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let before = buf.filled().len();
+        let result = Pin::new(&mut this.inner).poll_read(cx, buf);
+        let read = buf.filled().len() - before;
+        this.tracker
+            .bytes_in
+            .fetch_add(read as u64, Ordering::Relaxed);
+        result
+    }
+}
+
+impl AsyncWrite for TrackedStream<TcpStream> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let result = Pin::new(&mut this.inner).poll_write(cx, buf);
+        this.count_written(&result);
+        match &mut this.rate {
+            Some(rate) => rate.track(cx, &this.inner, result),
+            None => result,
+        }
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let result = Pin::new(&mut this.inner).poll_write_vectored(cx, bufs);
+        this.count_written(&result);
+        match &mut this.rate {
+            Some(rate) => rate.track(cx, &this.inner, result),
+            None => result,
+        }
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.inner.is_write_vectored()
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Response extension asking the connection layer to close the connection instead of sending
+/// the response. HTTP/1 connections are closed outright; on HTTP/2 only the stream is reset.
+#[derive(Debug, Clone, Copy)]
+pub struct CloseConnection;
+
+// Failing the request is what makes hyper close the connection without a response.
+fn close_if_asked<E: Into<BoxError>>(
+    result: Result<Response<Body>, E>,
+) -> Result<Response<Body>, BoxError> {
+    match result {
+        Ok(resp) if resp.extensions().get::<CloseConnection>().is_some() => {
+            Err("connection closed without a response".into())
+        }
+        Ok(resp) => Ok(resp),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn is_event_stream(resp: &Response<Body>) -> bool {
+    resp.headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| {
+            v.get(..17)
+                .is_some_and(|t| t.eq_ignore_ascii_case("text/event-stream"))
+        })
+}
+
+// Counts a request as in flight until it is dropped, which `hold_until_sent` delays until the
+// response body has been sent.
+struct InFlightRequest(Arc<ConnShared>);
+
+impl Drop for InFlightRequest {
+    fn drop(&mut self) {
+        self.0
+            .tracker
+            .in_flight_requests
+            .fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Response future of [`LimitedService`].
+pub struct ConnFuture<F> {
+    inner: Pin<Box<F>>,
+    conn: Option<Arc<ConnShared>>,
+    // Whether this response reaches max_requests_per_connection
+    last: bool,
+    // Moved into the response body once the head is ready
+    in_flight: Option<InFlightRequest>,
+}
+
+impl<F, E> Future for ConnFuture<F>
+where
+    F: Future<Output = Result<Response<Body>, E>>,
+    E: Into<BoxError>,
+{
+    type Output = Result<Response<Body>, BoxError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        let mut resp = close_if_asked(std::task::ready!(this.inner.as_mut().poll(cx)))?;
+        if let Some(conn) = &this.conn
+            && is_event_stream(&resp)
+        {
+            conn.streaming.store(true, Ordering::Relaxed);
+        }
+        if this.last {
+            resp.headers_mut().insert(
+                header::CONNECTION,
+                header::HeaderValue::from_static("close"),
+            );
+        }
+        if let Some(in_flight) = this.in_flight.take() {
+            resp = hold_until_sent(resp, in_flight);
+        }
+        Poll::Ready(Ok(resp))
+    }
+}
+
+/// Per-connection service: passes through, or answers 503/429 and closes on over-limit
+/// connections. Responses marked [`CloseConnection`] are turned into a closed connection here,
+/// and the response reaching `max_requests_per_connection` gets `Connection: close`.
+#[derive(Debug, Clone)]
+pub struct LimitedService<S> {
+    inner: S,
+    reject: Option<StatusCode>,
+    conn: Option<Arc<ConnShared>>,
+}
+
+impl<S, B> Service<Request<B>> for LimitedService<S>
+where
+    S: Service<Request<B>, Response = Response<Body>>,
+    S::Error: Into<BoxError>,
+{
+    type Response = Response<Body>;
+    type Error = BoxError;
+    type Future = Either<ConnFuture<S::Future>, Ready<Result<Response<Body>, BoxError>>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        if self.reject.is_some() {
+            Poll::Ready(Ok(()))
+        } else {
+            self.inner.poll_ready(cx).map_err(Into::into)
+        }
+    }
+
+    fn call(&mut self, req: Request<B>) -> Self::Future {
+        let Some(status) = self.reject else {
+            let last = self
+                .conn
+                .as_ref()
+                .is_some_and(|c| c.count_request(req.version()));
+            let in_flight = self.conn.clone().map(|conn| {
+                conn.tracker.requests.fetch_add(1, Ordering::Relaxed);
+                conn.tracker
+                    .in_flight_requests
+                    .fetch_add(1, Ordering::Relaxed);
+                InFlightRequest(conn)
+            });
+            return Either::Left(ConnFuture {
+                inner: Box::pin(self.inner.call(req)),
+                conn: self.conn.clone(),
+                last,
+                in_flight,
+            });
+        };
+        let mut resp = Response::new(Body::from("too many connections"));
+        *resp.status_mut() = status;
+        resp.headers_mut().insert(
+            header::CONNECTION,
+            header::HeaderValue::from_static("close"),
+        );
+        Either::Right(ready(Ok(resp)))
+    }
+}
+
+/// axum-server acceptor enforcing the connection limits of one server.
+#[derive(Debug, Clone)]
+pub struct ConnLimitAcceptor {
+    tracker: Arc<ConnectionTracker>,
+}
+
+impl ConnLimitAcceptor {
+    pub fn new(tracker: Arc<ConnectionTracker>) -> Self {
+        Self { tracker }
+    }
+
+    pub fn tracker(&self) -> &Arc<ConnectionTracker> {
+        &self.tracker
+    }
+
+    /// Accept `stream` on behalf of the client at `ip`, which may differ from the TCP peer when
+    /// a PROXY protocol header named it.
+    pub fn accept_from<S>(
+        &self,
+        stream: TcpStream,
+        service: S,
+        ip: Option<IpAddr>,
+    ) -> io::Result<(TrackedStream<TcpStream>, LimitedService<S>)> {
+        let ip = ip.map(|ip| ip.to_canonical());
+        let rejection = match self.tracker.try_acquire(ip) {
+            Ok(guard) => {
+                let conn = Arc::new(ConnShared {
+                    tracker: self.tracker.clone(),
+                    requests: AtomicU64::new(0),
+                    streaming: AtomicBool::new(false),
+                });
+                let rate = self
+                    .tracker
+                    .policy
+                    .min_write_rate
+                    .map(|min| WriteRate::new(conn.clone(), min, ip));
+                return Ok((
+                    TrackedStream {
+                        inner: stream,
+                        tracker: self.tracker.clone(),
+                        _guard: Some(guard),
+                        rate,
+                    },
+                    LimitedService {
+                        inner: service,
+                        reject: None,
+                        conn: Some(conn),
+                    },
+                ));
+            }
+            Err(rejection) => rejection,
+        };
+
+        let status = match self.tracker.reject(rejection) {
+            ConnectionLimitAction::Close => {
+                return Err(io::Error::other("connection limit reached"));
+            }
+            ConnectionLimitAction::ServiceUnavailable => StatusCode::SERVICE_UNAVAILABLE,
+            ConnectionLimitAction::TooManyRequests => StatusCode::TOO_MANY_REQUESTS,
+        };
+        Ok((
+            TrackedStream {
+                inner: stream,
+                tracker: self.tracker.clone(),
+                _guard: None,
+                rate: None,
+            },
+            LimitedService {
+                inner: service,
+                reject: Some(status),
+                conn: None,
+            },
+        ))
+    }
+}
+
+impl<S> axum_server::accept::Accept<TcpStream, S> for ConnLimitAcceptor {
+    type Stream = TrackedStream<TcpStream>;
+    type Service = LimitedService<S>;
+    type Future = Ready<io::Result<(Self::Stream, Self::Service)>>;
+
+    fn accept(&self, stream: TcpStream, service: S) -> Self::Future {
+        let ip = stream.peer_addr().ok().map(|a| a.ip());
+        ready(self.accept_from(stream, service, ip))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::Router;
+    use bytes::Bytes;
+    use futures::channel::mpsc;
+
+    fn tracker(policy: ConnectionPolicy) -> Arc<ConnectionTracker> {
+        Arc::new(ConnectionTracker::new(
+            "127.0.0.1:0".parse().unwrap(),
+            None,
+            None,
+            ConnectionLimitAction::default(),
+            None,
+            policy,
+        ))
+    }
+
+    fn connection(tracker: &Arc<ConnectionTracker>, app: Router) -> LimitedService<Router> {
+        let conn = Arc::new(ConnShared {
+            tracker: tracker.clone(),
+            requests: AtomicU64::new(0),
+            streaming: AtomicBool::new(false),
+        });
+        LimitedService {
+            inner: app,
+            reject: None,
+            conn: Some(conn),
+        }
+    }
+
+    // A connection's service in front of a handler streaming whatever is sent on the channel.
+    fn service() -> (
+        Arc<ConnectionTracker>,
+        LimitedService<Router>,
+        mpsc::UnboundedSender<io::Result<Bytes>>,
+    ) {
+        let tracker = tracker(ConnectionPolicy::default());
+        let (tx, rx) = mpsc::unbounded();
+        let rx = Arc::new(std::sync::Mutex::new(Some(rx)));
+        let app = Router::new().fallback(move || {
+            let rx = rx.lock().unwrap().take().unwrap();
+            async move { Body::from_stream(rx) }
+        });
+        let service = connection(&tracker, app);
+        (tracker, service, tx)
+    }
+
+    // An accepted loopback connection, plus the client end, which never reads.
+    async fn accepted(
+        tracker: &Arc<ConnectionTracker>,
+        app: Router,
+    ) -> (TrackedStream<TcpStream>, LimitedService<Router>, TcpStream) {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (server, peer) = listener.accept().await.unwrap();
+        let (stream, service) = ConnLimitAcceptor::new(tracker.clone())
+            .accept_from(server, app, Some(peer.ip()))
+            .unwrap();
+        (stream, service, client)
+    }
+
+    // Write until the client's socket buffers are full and a write stays blocked.
+    async fn write_to_unread_client(stream: &mut TrackedStream<TcpStream>) -> io::Result<()> {
+        use tokio::io::AsyncWriteExt;
+        let chunk = vec![0; 64 * 1024];
+        loop {
+            stream.write_all(&chunk).await?;
+        }
+    }
+
+    fn slow_write_policy() -> ConnectionPolicy {
+        ConnectionPolicy {
+            max_requests: None,
+            // Far more than the socket buffers take in before the first blocked write.
+            min_write_rate: Some(MinWriteRate {
+                bytes_per_sec: 1_000_000,
+                window: Duration::from_secs(1000),
+            }),
+        }
+    }
+
+    fn request() -> Request<Body> {
+        Request::builder().uri("/").body(Body::empty()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn request_is_in_flight_until_its_body_is_sent() {
+        let (tracker, mut service, tx) = service();
+        let resp = service.call(request()).await.unwrap();
+        // The head is ready but the body is still streaming.
+        assert_eq!(tracker.stats().requests, 1);
+        assert_eq!(tracker.stats().in_flight_requests, 1);
+
+        tx.unbounded_send(Ok(Bytes::from_static(b"data"))).unwrap();
+        drop(tx);
+        let body = axum::body::to_bytes(resp.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(&body[..], b"data");
+        assert_eq!(tracker.stats().in_flight_requests, 0);
+    }
+
+    #[tokio::test]
+    async fn abandoned_body_is_no_longer_in_flight() {
+        let (tracker, mut service, _tx) = service();
+        let resp = service.call(request()).await.unwrap();
+        assert_eq!(tracker.stats().in_flight_requests, 1);
+        drop(resp);
+        assert_eq!(tracker.stats().in_flight_requests, 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn slow_reader_is_aborted_and_counted() {
+        let tracker = tracker(slow_write_policy());
+        let (mut stream, _service, _client) = accepted(&tracker, Router::new()).await;
+
+        let err = tokio::time::timeout(
+            Duration::from_secs(5000),
+            write_to_unread_client(&mut stream),
+        )
+        .await
+        .expect("slow reader was not aborted")
+        .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        assert_eq!(tracker.stats().slow_write_aborts, 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn event_stream_is_exempt_from_min_write_rate() {
+        let tracker = tracker(slow_write_policy());
+        let app = Router::new()
+            .fallback(|| async { ([(header::CONTENT_TYPE, "text/event-stream")], "data: 1\n\n") });
+        let (mut stream, mut service, _client) = accepted(&tracker, app).await;
+        service.call(request()).await.unwrap();
+
+        let blocked = tokio::time::timeout(
+            Duration::from_secs(5000),
+            write_to_unread_client(&mut stream),
+        )
+        .await;
+        assert!(blocked.is_err(), "event stream write failed: {blocked:?}");
+        assert_eq!(tracker.stats().slow_write_aborts, 0);
+    }
+
+    #[tokio::test]
+    async fn last_request_of_a_connection_gets_connection_close() {
+        let tracker = tracker(ConnectionPolicy {
+            max_requests: Some(3),
+            min_write_rate: None,
+        });
+        let mut service = connection(&tracker, Router::new().fallback(|| async { "ok" }));
+
+        for n in 1..=3 {
+            let resp = service.call(request()).await.unwrap();
+            let close = resp.headers().get(header::CONNECTION);
+            if n < 3 {
+                assert_eq!(close, None, "request {n}");
+            } else {
+                assert_eq!(close.unwrap(), "close");
+            }
+        }
+        assert_eq!(tracker.stats().request_cap_closes, 1);
+    }
+
+    #[tokio::test]
+    async fn http2_requests_are_not_capped() {
+        let tracker = tracker(ConnectionPolicy {
+            max_requests: Some(1),
+            min_write_rate: None,
+        });
+        let mut service = connection(&tracker, Router::new().fallback(|| async { "ok" }));
+
+        for _ in 0..3 {
+            let mut req = request();
+            *req.version_mut() = Version::HTTP_2;
+            let resp = service.call(req).await.unwrap();
+            assert_eq!(resp.headers().get(header::CONNECTION), None);
+        }
+        assert_eq!(tracker.stats().request_cap_closes, 0);
+    }
+}
@@ -0,0 +1,124 @@
+//! `content_type_route`: only let listed request body types through to the backend.
+
+use axum::{
+    body::{Body, HttpBody},
+    http::{Method, Request, StatusCode, header},
+};
+
+use crate::config::{ContentTypeRoute, MissingContentTypePolicy};
+use crate::path::longest_prefix;
+use crate::proxy::AppState;
+
+fn carries_body(req: &Request<Body>) -> bool {
+    matches!(
+        *req.method(),
+        Method::POST | Method::PUT | Method::PATCH | Method::DELETE
+    ) && !req.body().is_end_stream()
+        && req.body().size_hint().exact() != Some(0)
+}
+
+impl ContentTypeRoute {
+    // `value` is a whole Content-Type header; only its media type counts, so
+    // `Application/JSON ; charset=utf-8` matches `application/json`.
+    fn permits(&self, value: &str) -> bool {
+        let media_type = value
+            .split(';')
+            .next()
+            .unwrap_or("")
+            .trim()
+            .to_ascii_lowercase();
+        let Some((kind, _)) = media_type.split_once('/') else {
+            return false;
+        };
+        self.allowed
+            .iter()
+            .any(|a| *a == media_type || a.strip_suffix("/*").is_some_and(|k| k == kind))
+    }
+}
+
+/// Apply the `content_type_route` with the longest prefix of `path` (the request's
+/// [`route_path`](crate::path::route_path)) to a POST, PUT, PATCH or DELETE request that has a
+/// body. Types not on the list get 415, as do bodies without a Content-Type unless
+/// the route's `on_missing` is `allow`. Paths without a route aren't checked.
+pub fn check_content_type(
+    state: &AppState,
+    req: &Request<Body>,
+    path: &str,
+) -> Result<(), StatusCode> {
+    let Some(route) = longest_prefix(&state.content_type_routes, path, |r| &r.path_prefix) else {
+        return Ok(());
+    };
+    if !carries_body(req) {
+        return Ok(());
+    }
+
+    let value = req.headers().get(header::CONTENT_TYPE);
+    let permitted = match value {
+        None => route.on_missing == MissingContentTypePolicy::Allow,
+        Some(v) => v.to_str().is_ok_and(|v| route.permits(v)),
+    };
+    if permitted {
+        return Ok(());
+    }
+    tracing::warn!(
+        "refusing {} {} with Content-Type {:?}",
+        req.method(),
+        path,
+        value
+    );
+    Err(StatusCode::UNSUPPORTED_MEDIA_TYPE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn route(allowed: &[&str]) -> ContentTypeRoute {
+        ContentTypeRoute {
+            path_prefix: "/api".to_string(),
+            allowed: allowed.iter().map(|a| a.to_string()).collect(),
+            on_missing: MissingContentTypePolicy::Deny,
+        }
+    }
+
+    #[test]
+    fn permits_matches_the_media_type_only() {
+        let route = route(&["application/json", "multipart/*"]);
+        for value in [
+            "application/json",
+            "application/json ;charset=utf-8",
+            "Application/JSON; charset=\"utf-8\"",
+            "  application/json  ",
+            "multipart/form-data; boundary=x",
+            "MULTIPART/mixed",
+        ] {
+            assert!(route.permits(value), "{}", value);
+        }
+        for value in [
+            "application/jsonx",
+            "application/json-patch+json",
+            "text/plain; x=application/json",
+            "application",
+            "multipart",
+            "",
+            "application/xml, application/json",
+        ] {
+            assert!(!route.permits(value), "{}", value);
+        }
+    }
+
+    #[test]
+    fn only_requests_with_bodies_are_checked() {
+        let req = |method: Method, body: &'static str| {
+            Request::builder()
+                .method(method)
+                .uri("/api")
+                .body(Body::from(body))
+                .unwrap()
+        };
+        assert!(carries_body(&req(Method::POST, "{}")));
+        assert!(carries_body(&req(Method::DELETE, "{}")));
+        assert!(!carries_body(&req(Method::POST, "")));
+        assert!(!carries_body(&req(Method::GET, "{}")));
+    }
+}
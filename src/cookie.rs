@@ -0,0 +1,68 @@
+use crate::config::{CookieRewrite, SameSite};
+
+impl SameSite {
+    fn as_str(self) -> &'static str {
+        match self {
+            SameSite::Strict => "Strict",
+            SameSite::Lax => "Lax",
+            SameSite::None => "None",
+        }
+    }
+}
+
+/// Apply `cookie_rewrite` to one `Set-Cookie` value.
+///
+/// The `name=value` pair and every attribute not being rewritten are kept as sent, in order.
+/// A rewritten attribute stays where the backend put it; `Path`, `SameSite` and `Secure` are
+/// appended when they are forced but missing. `Domain` is never added, since a cookie without
+/// one already belongs to the public host.
+pub fn rewrite_set_cookie(value: &str, rewrite: &CookieRewrite) -> String {
+    let mut parts = value.split(';');
+    let mut out = vec![parts.next().unwrap_or_default().trim().to_string()];
+    let (mut has_path, mut has_same_site, mut has_secure) = (false, false, false);
+
+    for attr in parts {
+        let attr = attr.trim();
+        if attr.is_empty() {
+            continue;
+        }
+        let key = attr.split_once('=').map_or(attr, |(k, _)| k).trim();
+        if key.eq_ignore_ascii_case("domain") {
+            match rewrite.domain.as_deref() {
+                Some("") => continue,
+                Some(domain) => out.push(format!("Domain={}", domain)),
+                None => out.push(attr.to_string()),
+            }
+        } else if key.eq_ignore_ascii_case("path") {
+            has_path = true;
+            match &rewrite.path {
+                Some(path) => out.push(format!("Path={}", path)),
+                None => out.push(attr.to_string()),
+            }
+        } else if key.eq_ignore_ascii_case("samesite") {
+            has_same_site = true;
+            match rewrite.same_site {
+                Some(same_site) => out.push(format!("SameSite={}", same_site.as_str())),
+                None => out.push(attr.to_string()),
+            }
+        } else {
+            has_secure |= key.eq_ignore_ascii_case("secure");
+            out.push(attr.to_string());
+        }
+    }
+
+    if let Some(path) = &rewrite.path
+        && !has_path
+    {
+        out.push(format!("Path={}", path));
+    }
+    if let Some(same_site) = rewrite.same_site
+        && !has_same_site
+    {
+        out.push(format!("SameSite={}", same_site.as_str()));
+    }
+    if rewrite.secure && !has_secure {
+        out.push("Secure".to_string());
+    }
+    out.join("; ")
+}
@@ -0,0 +1,53 @@
+use axum::http::{HeaderName, HeaderValue, Response, header};
+
+use crate::config::ConfigEntry;
+
+/// Content-Security-Policy headers added to HTML documents from the static mount.
+///
+/// Only `text/html` responses get the headers; scripts, stylesheets and images are left alone.
+#[derive(Debug, Clone)]
+pub struct StaticCsp {
+    enforce: Option<HeaderValue>,
+    report_only: Option<HeaderValue>,
+    override_existing: bool,
+}
+
+impl StaticCsp {
+    /// `None` when the server configures neither `csp` nor `csp_report_only`.
+    pub fn from_config(cfg: &ConfigEntry) -> Option<Self> {
+        // Values were checked to be valid header values during validation.
+        let value = |v: &Option<String>| v.as_deref().and_then(|v| HeaderValue::from_str(v).ok());
+        let csp = Self {
+            enforce: value(&cfg.csp),
+            report_only: value(&cfg.csp_report_only),
+            override_existing: cfg.csp_override,
+        };
+        (csp.enforce.is_some() || csp.report_only.is_some()).then_some(csp)
+    }
+
+    pub fn apply<B>(&self, resp: &mut Response<B>) {
+        let is_html = resp
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.split(';').next())
+            .is_some_and(|mime| mime.trim().eq_ignore_ascii_case("text/html"));
+        if !is_html {
+            return;
+        }
+        self.set(resp, header::CONTENT_SECURITY_POLICY, self.enforce.as_ref());
+        self.set(
+            resp,
+            header::CONTENT_SECURITY_POLICY_REPORT_ONLY,
+            self.report_only.as_ref(),
+        );
+    }
+
+    fn set<B>(&self, resp: &mut Response<B>, name: HeaderName, value: Option<&HeaderValue>) {
+        if let Some(value) = value
+            && (self.override_existing || !resp.headers().contains_key(&name))
+        {
+            resp.headers_mut().insert(name, value.clone());
+        }
+    }
+}
@@ -0,0 +1,86 @@
+//! End-to-end request deadline covering both the upstream wait and the response body.
+
+use axum::{
+    body::{Body, BodyDataStream},
+    http::{Method, Response, StatusCode, Uri},
+};
+use bytes::Bytes;
+use futures::Stream;
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::time::{Instant, Sleep, sleep_until, timeout_at};
+
+/// Run `handler` with a total budget of `limit`.
+///
+/// Running out before the response headers are ready gives a `504`. Running out while the
+/// body is still being sent fails the body stream, which aborts the client connection.
+pub async fn enforce<F>(
+    limit: Duration,
+    method: Method,
+    uri: Uri,
+    handler: F,
+) -> Result<Response<Body>, StatusCode>
+where
+    F: Future<Output = Result<Response<Body>, StatusCode>>,
+{
+    let deadline = Instant::now() + limit;
+    match timeout_at(deadline, handler).await {
+        Ok(Ok(resp)) => {
+            let (parts, body) = resp.into_parts();
+            let body = Body::from_stream(DeadlineBody {
+                inner: body.into_data_stream(),
+                sleep: Box::pin(sleep_until(deadline)),
+                limit,
+                method,
+                uri,
+            });
+            Ok(Response::from_parts(parts, body))
+        }
+        Ok(Err(status)) => Err(status),
+        Err(_) => {
+            tracing::warn!(
+                phase = "waiting for response",
+                "request {} {} exceeded request timeout of {:?}",
+                method,
+                uri,
+                limit
+            );
+            Err(StatusCode::GATEWAY_TIMEOUT)
+        }
+    }
+}
+
+struct DeadlineBody {
+    inner: BodyDataStream,
+    sleep: Pin<Box<Sleep>>,
+    limit: Duration,
+    method: Method,
+    uri: Uri,
+}
+
+impl Stream for DeadlineBody {
+    type Item = io::Result<Bytes>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if let Poll::Ready(item) = Pin::new(&mut self.inner).poll_next(cx) {
+            return Poll::Ready(item.map(|r| r.map_err(io::Error::other)));
+        }
+        if self.sleep.as_mut().poll(cx).is_ready() {
+            tracing::warn!(
+                phase = "streaming response body",
+                "request {} {} exceeded request timeout of {:?}, aborting",
+                self.method,
+                self.uri,
+                self.limit
+            );
+            return Poll::Ready(Some(Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "request timeout",
+            ))));
+        }
+        Poll::Pending
+    }
+}
@@ -0,0 +1,107 @@
+//! `decompress_request_body`: inflate compressed uploads for backends that can't.
+//!
+//! The body is read whole, so its inflated size is known up front and sent as
+//! `Content-Length`. Inflating stops one byte past `max_decompressed_body_bytes`, which keeps
+//! a small compressed body from expanding without bound.
+
+use axum::{
+    body::{Body, to_bytes},
+    http::{HeaderValue, Request, StatusCode, header},
+};
+use bytes::Bytes;
+use flate2::read::{MultiGzDecoder, ZlibDecoder};
+use http_body_util::LengthLimitError;
+use std::error::Error;
+use std::io::Read;
+
+#[derive(Debug, Clone, Copy)]
+enum Coding {
+    Gzip,
+    // HTTP's "deflate" is the zlib format (RFC 9110, 8.4.1.2).
+    Deflate,
+}
+
+// The codings applied to the body, in the order they were applied. `None` when there are none
+// or one of them isn't supported.
+fn codings(req: &Request<Body>) -> Option<Vec<Coding>> {
+    let mut out = Vec::new();
+    for value in req.headers().get_all(header::CONTENT_ENCODING) {
+        for coding in value.to_str().ok()?.split(',') {
+            let coding = coding.trim();
+            if coding.eq_ignore_ascii_case("gzip") || coding.eq_ignore_ascii_case("x-gzip") {
+                out.push(Coding::Gzip);
+            } else if coding.eq_ignore_ascii_case("deflate") {
+                out.push(Coding::Deflate);
+            } else if !coding.is_empty() && !coding.eq_ignore_ascii_case("identity") {
+                tracing::debug!("not decompressing request body in '{}'", coding);
+                return None;
+            }
+        }
+    }
+    (!out.is_empty()).then_some(out)
+}
+
+fn is_too_large(e: &axum::Error) -> bool {
+    let mut source: Option<&(dyn Error + 'static)> = Some(e);
+    while let Some(err) = source {
+        if err.is::<LengthLimitError>() {
+            return true;
+        }
+        source = err.source();
+    }
+    false
+}
+
+fn inflate(mut data: Bytes, codings: &[Coding], max_bytes: usize) -> Result<Bytes, StatusCode> {
+    for coding in codings.iter().rev() {
+        let reader: Box<dyn Read> = match coding {
+            Coding::Gzip => Box::new(MultiGzDecoder::new(&data[..])),
+            Coding::Deflate => Box::new(ZlibDecoder::new(&data[..])),
+        };
+        let mut out = Vec::new();
+        if let Err(e) = reader.take(max_bytes as u64 + 1).read_to_end(&mut out) {
+            tracing::debug!("rejecting malformed {:?} request body: {}", coding, e);
+            return Err(StatusCode::BAD_REQUEST);
+        }
+        if out.len() > max_bytes {
+            tracing::warn!(
+                "rejecting request body inflating to more than {} bytes",
+                max_bytes
+            );
+            return Err(StatusCode::PAYLOAD_TOO_LARGE);
+        }
+        data = Bytes::from(out);
+    }
+    Ok(data)
+}
+
+/// Replace a gzip- or deflate-encoded request body with its inflated form, dropping
+/// `Content-Encoding` and setting `Content-Length` to match.
+///
+/// Bodies without a `Content-Encoding`, or with a coding other than these, are left alone.
+/// A body that doesn't inflate is refused with 400, and one inflating past `max_bytes` with 413.
+pub async fn request_body(req: &mut Request<Body>, max_bytes: usize) -> Result<(), StatusCode> {
+    let Some(codings) = codings(req) else {
+        return Ok(());
+    };
+
+    // The compressed body is already held to max_request_size_bytes by the server's body limit.
+    let body = std::mem::take(req.body_mut());
+    let compressed = to_bytes(body, usize::MAX).await.map_err(|e| {
+        if is_too_large(&e) {
+            StatusCode::PAYLOAD_TOO_LARGE
+        } else {
+            tracing::debug!("failed to read compressed request body: {}", e);
+            StatusCode::BAD_REQUEST
+        }
+    })?;
+    let body = tokio::task::spawn_blocking(move || inflate(compressed, &codings, max_bytes))
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)??;
+
+    let headers = req.headers_mut();
+    headers.remove(header::CONTENT_ENCODING);
+    headers.insert(header::CONTENT_LENGTH, HeaderValue::from(body.len()));
+    *req.body_mut() = Body::from(body);
+    Ok(())
+}
@@ -0,0 +1,95 @@
+use axum::{
+    extract::Request,
+    http::{StatusCode, header},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use serde::Serialize;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::Duration;
+
+/// Operator-initiated drain: readiness fails and new proxied requests get a 503 while work
+/// already in flight finishes. Process-wide, and independent of signal-initiated shutdown.
+#[derive(Debug)]
+pub struct Drain {
+    draining: AtomicBool,
+    in_flight: AtomicUsize,
+    handle: axum_server::Handle,
+    /// `Retry-After` sent with the 503.
+    pub retry_after: Duration,
+    /// Keep serving `/static` while draining.
+    pub serve_static: bool,
+}
+
+/// Body of `GET /admin/drain`.
+#[derive(Debug, Serialize)]
+pub struct DrainStatus {
+    pub draining: bool,
+    /// Proxied requests whose response hasn't been sent in full yet (streamed bodies included).
+    pub in_flight_requests: usize,
+    /// Open client connections on every server, including the one asking.
+    pub connections: usize,
+}
+
+/// Counts one proxied request as in flight until dropped.
+#[derive(Debug)]
+pub struct InFlight(Arc<Drain>);
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.in_flight.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl Drain {
+    pub fn new(handle: axum_server::Handle, retry_after: Duration, serve_static: bool) -> Self {
+        Self {
+            draining: AtomicBool::new(false),
+            in_flight: AtomicUsize::new(0),
+            handle,
+            retry_after,
+            serve_static,
+        }
+    }
+
+    pub fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::SeqCst)
+    }
+
+    /// Start or stop draining; returns the previous state.
+    pub fn set(&self, draining: bool) -> bool {
+        self.draining.swap(draining, Ordering::SeqCst)
+    }
+
+    pub fn track(self: &Arc<Self>) -> InFlight {
+        self.in_flight.fetch_add(1, Ordering::Relaxed);
+        InFlight(self.clone())
+    }
+
+    /// The 503 sent instead of serving a request while draining.
+    pub fn unavailable(&self) -> Response {
+        (
+            StatusCode::SERVICE_UNAVAILABLE,
+            [(header::RETRY_AFTER, self.retry_after.as_secs())],
+            "draining",
+        )
+            .into_response()
+    }
+
+    pub fn status(&self) -> DrainStatus {
+        DrainStatus {
+            draining: self.is_draining(),
+            in_flight_requests: self.in_flight.load(Ordering::Relaxed),
+            connections: self.handle.connection_count(),
+        }
+    }
+}
+
+/// Middleware refusing requests while draining, for routes that stop with the proxy.
+pub async fn refuse_while_draining(drain: Arc<Drain>, req: Request, next: Next) -> Response {
+    if drain.is_draining() {
+        return drain.unavailable();
+    }
+    next.run(req).await
+}
@@ -0,0 +1,200 @@
+//! TLS 1.3 early data (0-RTT) handling, per RFC 8470.
+//!
+//! rustls keeps 0-RTT bytes apart from the regular plaintext, so [`EarlyDataAcceptor`] reads
+//! them out once the handshake is done and replays them ahead of the rest of the stream. The
+//! first request of such a connection is marked with [`EarlyData`]; clients put a single
+//! request in early data, and whatever follows it was sent after the handshake.
+
+use axum::{
+    body::Body,
+    http::{HeaderName, HeaderValue, Method, Request, StatusCode},
+};
+use futures::future::BoxFuture;
+use std::io::{self, Read};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio_rustls::server::TlsStream;
+use tower_service::Service;
+
+use crate::config::EarlyDataPolicy;
+
+/// Marks a request received, at least in part, as TLS early data.
+pub const EARLY_DATA_HEADER: HeaderName = HeaderName::from_static("early-data");
+
+/// Request extension set on requests that arrived in TLS early data.
+#[derive(Debug, Clone, Copy)]
+pub struct EarlyData;
+
+/// Apply `policy` to a request: early requests with a method that isn't idempotent are
+/// refused with `425 Too Early` under `reject`, and every early request that goes on carries
+/// `Early-Data: 1` for the backend.
+///
+/// A request that already has `Early-Data: 1` was marked by a TLS terminator in front of us
+/// and counts as early too; any other value is dropped.
+pub fn check(policy: EarlyDataPolicy, req: &mut Request<Body>) -> Result<(), StatusCode> {
+    let early = req.extensions().get::<EarlyData>().is_some()
+        || req
+            .headers()
+            .get(&EARLY_DATA_HEADER)
+            .is_some_and(|v| v.as_bytes() == b"1");
+    req.headers_mut().remove(&EARLY_DATA_HEADER);
+    if !early {
+        return Ok(());
+    }
+
+    if policy == EarlyDataPolicy::Reject && !is_idempotent(req.method()) {
+        tracing::debug!(
+            "refusing {} {} sent as early data",
+            req.method(),
+            req.uri().path()
+        );
+        return Err(StatusCode::TOO_EARLY);
+    }
+    req.headers_mut()
+        .insert(EARLY_DATA_HEADER, HeaderValue::from_static("1"));
+    Ok(())
+}
+
+// RFC 9110, 9.2.2.
+fn is_idempotent(method: &Method) -> bool {
+    matches!(
+        *method,
+        Method::GET | Method::HEAD | Method::OPTIONS | Method::TRACE | Method::PUT | Method::DELETE
+    )
+}
+
+/// TLS stream that yields the connection's early data before anything read after the
+/// handshake.
+pub struct EarlyDataStream<S> {
+    early: Vec<u8>,
+    pos: usize,
+    inner: S,
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for EarlyDataStream<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if this.pos < this.early.len() {
+            let n = buf.remaining().min(this.early.len() - this.pos);
+            buf.put_slice(&this.early[this.pos..this.pos + n]);
+            this.pos += n;
+            if this.pos == this.early.len() {
+                this.early = Vec::new();
+                this.pos = 0;
+            }
+            return Poll::Ready(Ok(()));
+        }
+        Pin::new(&mut this.inner).poll_read(cx, buf)
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for EarlyDataStream<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().inner).poll_write(cx, buf)
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().inner).poll_write_vectored(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.inner.is_write_vectored()
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+/// Per-connection service marking the first request when the connection carried early data.
+#[derive(Debug, Clone)]
+pub struct EarlyDataService<S> {
+    inner: S,
+    // Shared by the clones hyper makes per request, so only one request gets marked.
+    pending: Arc<AtomicBool>,
+}
+
+impl<S, B> Service<Request<B>> for EarlyDataService<S>
+where
+    S: Service<Request<B>>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: Request<B>) -> Self::Future {
+        if self.pending.swap(false, Ordering::Relaxed) {
+            req.extensions_mut().insert(EarlyData);
+        }
+        self.inner.call(req)
+    }
+}
+
+/// axum-server acceptor wrapping the rustls acceptor `inner` to pick up early data.
+#[derive(Debug, Clone)]
+pub struct EarlyDataAcceptor<A> {
+    inner: A,
+}
+
+impl<A> EarlyDataAcceptor<A> {
+    pub fn new(inner: A) -> Self {
+        Self { inner }
+    }
+}
+
+impl<A, I, S, T> axum_server::accept::Accept<I, S> for EarlyDataAcceptor<A>
+where
+    A: axum_server::accept::Accept<I, S, Stream = TlsStream<T>>,
+    A::Future: Send + 'static,
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    type Stream = EarlyDataStream<TlsStream<T>>;
+    type Service = EarlyDataService<A::Service>;
+    type Future = BoxFuture<'static, io::Result<(Self::Stream, Self::Service)>>;
+
+    fn accept(&self, stream: I, service: S) -> Self::Future {
+        let accept = self.inner.accept(stream, service);
+        Box::pin(async move {
+            let (mut inner, service) = accept.await?;
+            let mut early = Vec::new();
+            if let Some(mut reader) = inner.get_mut().1.early_data() {
+                reader.read_to_end(&mut early)?;
+            }
+            let service = EarlyDataService {
+                inner: service,
+                pending: Arc::new(AtomicBool::new(!early.is_empty())),
+            };
+            Ok((
+                EarlyDataStream {
+                    early,
+                    pos: 0,
+                    inner,
+                },
+                service,
+            ))
+        })
+    }
+}
@@ -0,0 +1,217 @@
+//! `[servers.error_pages]`: HTML pages for errors the proxy raises itself.
+//!
+//! Templates are parsed once at startup. `{{status}}`, `{{path}}`, `{{request_id}}` and
+//! `{{timestamp}}` are filled in per response, HTML-escaped; any other `{{...}}` is left as
+//! written. Substituted values are never scanned again, so a client can't smuggle in markup or
+//! placeholders of its own.
+
+use axum::{
+    body::Body,
+    http::{HeaderValue, Request, Response, StatusCode, header},
+};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// Request header carrying the ID shown by `{{request_id}}`, as set by an upstream edge proxy
+/// or the client.
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+// Longest request ID shown; anything longer is cut.
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+#[derive(Debug, Clone, Copy)]
+enum Var {
+    Status,
+    Path,
+    RequestId,
+    Timestamp,
+}
+
+#[derive(Debug)]
+enum Part {
+    Text(String),
+    Var(Var),
+}
+
+#[derive(Debug)]
+struct Template {
+    parts: Vec<Part>,
+}
+
+impl Template {
+    fn parse(source: &str) -> Self {
+        let mut parts = Vec::new();
+        let mut text = String::new();
+        let mut rest = source;
+        while let Some(start) = rest.find("{{") {
+            let Some(len) = rest[start + 2..].find("}}") else {
+                break;
+            };
+            let var = match rest[start + 2..start + 2 + len].trim() {
+                "status" => Some(Var::Status),
+                "path" => Some(Var::Path),
+                "request_id" => Some(Var::RequestId),
+                "timestamp" => Some(Var::Timestamp),
+                _ => None,
+            };
+            let end = start + 2 + len + 2;
+            match var {
+                Some(var) => {
+                    text.push_str(&rest[..start]);
+                    parts.push(Part::Text(std::mem::take(&mut text)));
+                    parts.push(Part::Var(var));
+                }
+                None => text.push_str(&rest[..end]),
+            }
+            rest = &rest[end..];
+        }
+        text.push_str(rest);
+        parts.push(Part::Text(text));
+        parts.retain(|p| !matches!(p, Part::Text(t) if t.is_empty()));
+        Self { parts }
+    }
+
+    fn render(&self, status: StatusCode, context: &ErrorContext) -> String {
+        let mut out = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Text(text) => out.push_str(text),
+                Part::Var(Var::Status) => out.push_str(status.as_str()),
+                Part::Var(Var::Path) => push_escaped(&mut out, &context.path),
+                Part::Var(Var::RequestId) => {
+                    push_escaped(&mut out, context.request_id.as_deref().unwrap_or(""))
+                }
+                Part::Var(Var::Timestamp) => {
+                    out.push_str(&httpdate::fmt_http_date(SystemTime::now()))
+                }
+            }
+        }
+        out
+    }
+}
+
+fn push_escaped(out: &mut String, value: &str) {
+    for c in value.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            c => out.push(c),
+        }
+    }
+}
+
+/// What a page may show about the request, captured before the proxy consumes it.
+#[derive(Debug)]
+pub struct ErrorContext {
+    path: String,
+    request_id: Option<String>,
+}
+
+impl ErrorContext {
+    pub fn of(req: &Request<Body>) -> Self {
+        let request_id = req
+            .headers()
+            .get(REQUEST_ID_HEADER)
+            .and_then(|v| v.to_str().ok())
+            .map(|id| id.chars().take(MAX_REQUEST_ID_LEN).collect());
+        Self {
+            path: req.uri().path().to_string(),
+            request_id,
+        }
+    }
+}
+
+/// A server's error page templates by status.
+#[derive(Debug, Default)]
+pub struct ErrorPages {
+    pages: HashMap<u16, Template>,
+    // `upstream_protocol_error`, used instead of the 502 page when it is set
+    malformed_response: Option<Template>,
+    // `maintenance`, used instead of the 503 page during maintenance windows
+    maintenance: Option<Template>,
+}
+
+fn read_template(path: &Path, name: &dyn std::fmt::Display) -> Result<Template, String> {
+    std::fs::read_to_string(path)
+        .map(|source| Template::parse(&source))
+        .map_err(|e| {
+            format!(
+                "failed to read error page for {} '{}': {}",
+                name,
+                path.display(),
+                e
+            )
+        })
+}
+
+impl ErrorPages {
+    /// Read and parse every configured page.
+    pub fn load(
+        pages: &[(u16, PathBuf)],
+        malformed_response: Option<&Path>,
+        maintenance: Option<&Path>,
+    ) -> Result<Self, String> {
+        let pages = pages
+            .iter()
+            .map(|(status, path)| Ok((*status, read_template(path, status)?)))
+            .collect::<Result<_, String>>()?;
+        let malformed_response = malformed_response
+            .map(|path| read_template(path, &"upstream_protocol_error"))
+            .transpose()?;
+        let maintenance = maintenance
+            .map(|path| read_template(path, &"maintenance"))
+            .transpose()?;
+        Ok(Self {
+            pages,
+            malformed_response,
+            maintenance,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pages.is_empty() && self.malformed_response.is_none() && self.maintenance.is_none()
+    }
+
+    /// The page for `status`, or `None` when there's no template for it.
+    pub fn render(&self, status: StatusCode, context: &ErrorContext) -> Option<Response<Body>> {
+        let page = self.pages.get(&status.as_u16())?;
+        Some(page_response(page, status, context))
+    }
+
+    /// The 502 for a malformed backend response: the `upstream_protocol_error` page, else the
+    /// 502 page.
+    pub fn render_malformed_response(&self, context: &ErrorContext) -> Option<Response<Body>> {
+        match &self.malformed_response {
+            Some(page) => Some(page_response(page, StatusCode::BAD_GATEWAY, context)),
+            None => self.render(StatusCode::BAD_GATEWAY, context),
+        }
+    }
+
+    /// The 503 of a maintenance window: the `maintenance` page, else the 503 page.
+    pub fn render_maintenance(&self, context: &ErrorContext) -> Option<Response<Body>> {
+        match &self.maintenance {
+            Some(page) => Some(page_response(
+                page,
+                StatusCode::SERVICE_UNAVAILABLE,
+                context,
+            )),
+            None => self.render(StatusCode::SERVICE_UNAVAILABLE, context),
+        }
+    }
+}
+
+fn page_response(page: &Template, status: StatusCode, context: &ErrorContext) -> Response<Body> {
+    let mut resp = Response::new(Body::from(page.render(status, context)));
+    *resp.status_mut() = status;
+    let headers = resp.headers_mut();
+    headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("text/html; charset=utf-8"),
+    );
+    headers.insert(header::CACHE_CONTROL, HeaderValue::from_static("no-store"));
+    resp
+}
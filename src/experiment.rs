@@ -0,0 +1,185 @@
+//! `[servers.experiments]`: put each visitor in an A/B bucket, remember it in a cookie and tell
+//! the backend through a request header.
+//!
+//! The cookie holds a random visitor id; the bucket is a hash of it, so the same visitor lands
+//! in the same bucket on every server sharing the config. Changing the percentages moves some
+//! visitors between buckets.
+
+use axum::{
+    body::Body,
+    http::{HeaderName, HeaderValue, Request, Response, header},
+};
+use ring::digest;
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::Serialize;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::config::ExperimentConfig;
+use crate::match_rules::cookie_values;
+use crate::signing::to_hex;
+
+/// Request extension with the bucket a request was put in, for the cache key.
+#[derive(Debug, Clone)]
+pub struct Bucket(pub String);
+
+/// New visitors per bucket and requests treated as bots, reported by `/admin/stats`.
+#[derive(Debug, Clone, Serialize)]
+pub struct ExperimentStats {
+    pub name: String,
+    pub assigned: Vec<BucketStats>,
+    pub bots: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BucketStats {
+    pub bucket: String,
+    pub new_visitors: u64,
+}
+
+/// What `assign` decided for a request.
+#[derive(Debug)]
+pub struct Assignment {
+    /// Set-Cookie for a visitor seen for the first time.
+    set_cookie: Option<HeaderValue>,
+}
+
+impl Assignment {
+    /// Hand a new visitor its cookie.
+    pub fn apply(self, resp: &mut Response<Body>) {
+        if let Some(cookie) = self.set_cookie {
+            resp.headers_mut().append(header::SET_COOKIE, cookie);
+        }
+    }
+}
+
+/// A server's experiment and its counters.
+#[derive(Debug)]
+pub struct Experiment {
+    config: ExperimentConfig,
+    // Cookie attributes after the value; Secure on TLS listeners.
+    cookie_attributes: String,
+    rng: SystemRandom,
+    new_visitors: Vec<AtomicU64>,
+    bots: AtomicU64,
+}
+
+fn is_visitor_id(id: &str) -> bool {
+    id.len() == 32 && id.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+}
+
+impl Experiment {
+    pub fn new(config: ExperimentConfig, tls: bool) -> Self {
+        let cookie_attributes = format!(
+            "; Path=/; Max-Age={}; HttpOnly; SameSite=Lax{}",
+            config.cookie_max_age.as_secs(),
+            if tls { "; Secure" } else { "" }
+        );
+        Self {
+            new_visitors: config.buckets.iter().map(|_| AtomicU64::new(0)).collect(),
+            config,
+            cookie_attributes,
+            rng: SystemRandom::new(),
+            bots: AtomicU64::new(0),
+        }
+    }
+
+    // The bucket for `visitor`: a hash of the experiment name and the id, spread over 100
+    // slots which the buckets take in order by their percentages.
+    fn bucket_for(&self, visitor: &str) -> usize {
+        let input = format!("{}:{}", self.config.name, visitor);
+        let hash = digest::digest(&digest::SHA256, input.as_bytes());
+        let mut first = [0u8; 8];
+        first.copy_from_slice(&hash.as_ref()[..8]);
+        let slot = (u64::from_be_bytes(first) % 100) as u32;
+        let mut upper = 0;
+        for (i, (_, percent)) in self.config.buckets.iter().enumerate() {
+            upper += percent;
+            if slot < upper {
+                return i;
+            }
+        }
+        self.config.control
+    }
+
+    fn is_bot(&self, req: &Request<Body>) -> bool {
+        let Some(ua) = req
+            .headers()
+            .get(header::USER_AGENT)
+            .and_then(|v| v.to_str().ok())
+        else {
+            return false;
+        };
+        let ua = ua.to_ascii_lowercase();
+        self.config
+            .bot_user_agents
+            .iter()
+            .any(|bot| ua.contains(bot.as_str()))
+    }
+
+    fn new_visitor_id(&self) -> Option<String> {
+        let mut bytes = [0u8; 16];
+        self.rng.fill(&mut bytes).ok()?;
+        Some(to_hex(&bytes))
+    }
+
+    /// Put `req` in a bucket and tag it with the bucket header, replacing any the client sent.
+    /// Bots always get the control bucket and no cookie.
+    pub fn assign(&self, req: &mut Request<Body>) -> Assignment {
+        let mut set_cookie = None;
+        let bucket = if self.is_bot(req) {
+            self.bots.fetch_add(1, Ordering::Relaxed);
+            self.config.control
+        } else if let Some(id) = cookie_values(req, &self.config.cookie).find(|v| is_visitor_id(v))
+        {
+            self.bucket_for(id)
+        } else {
+            match self.new_visitor_id() {
+                Some(id) => {
+                    let bucket = self.bucket_for(&id);
+                    self.new_visitors[bucket].fetch_add(1, Ordering::Relaxed);
+                    set_cookie = HeaderValue::from_str(&format!(
+                        "{}={}{}",
+                        self.config.cookie, id, self.cookie_attributes
+                    ))
+                    .ok();
+                    bucket
+                }
+                None => {
+                    tracing::warn!("could not generate a visitor id, using the control bucket");
+                    self.config.control
+                }
+            }
+        };
+
+        let name = &self.config.buckets[bucket].0;
+        let headers = req.headers_mut();
+        headers.remove(&self.config.header);
+        if let Ok(value) = HeaderValue::from_str(name) {
+            headers.insert(self.config.header.clone(), value);
+        }
+        req.extensions_mut().insert(Bucket(name.clone()));
+        Assignment { set_cookie }
+    }
+
+    /// The request header carrying the bucket to the backend.
+    pub fn header(&self) -> &HeaderName {
+        &self.config.header
+    }
+
+    pub fn stats(&self) -> ExperimentStats {
+        ExperimentStats {
+            name: self.config.name.clone(),
+            assigned: self
+                .config
+                .buckets
+                .iter()
+                .zip(&self.new_visitors)
+                .map(|((bucket, _), n)| BucketStats {
+                    bucket: bucket.clone(),
+                    new_visitors: n.load(Ordering::Relaxed),
+                })
+                .collect(),
+            bots: self.bots.load(Ordering::Relaxed),
+        }
+    }
+}
@@ -0,0 +1,766 @@
+//! FastCGI backends (`fastcgi://host:port`, `fastcgi+unix:///path/to/socket`), e.g. php-fpm.
+//!
+//! Each request is a single FastCGI responder request on a connection of its own (no
+//! multiplexing). Connections are kept open afterwards and reused for the same backend. The
+//! reply is turned into a `reqwest::Response`, so caching, header handling, timeouts and error
+//! pages treat it like any HTTP backend's.
+
+use axum::http::{HeaderMap, HeaderName, HeaderValue, StatusCode, Version, header};
+use bytes::{Buf, Bytes, BytesMut};
+use futures::{StreamExt, TryStreamExt, stream};
+use http_body_util::BodyExt;
+use percent_encoding::percent_decode_str;
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+use url::{Host, Url};
+
+use crate::config::FastCgiConfig;
+use crate::proxy_protocol::BoxError;
+
+const VERSION_1: u8 = 1;
+const BEGIN_REQUEST: u8 = 1;
+const END_REQUEST: u8 = 3;
+const PARAMS: u8 = 4;
+const STDIN: u8 = 5;
+const STDOUT: u8 = 6;
+const STDERR: u8 = 7;
+const RESPONDER: u16 = 1;
+const KEEP_CONN: u8 = 1;
+const REQUEST_COMPLETE: u8 = 0;
+// Only one request per connection, so every request has the same id.
+const REQUEST_ID: u16 = 1;
+const MAX_CONTENT: usize = 65535;
+// Idle connections kept per backend.
+const MAX_IDLE: usize = 16;
+// Largest CGI response header block accepted.
+const MAX_HEAD: usize = 64 * 1024;
+
+enum Conn {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(tokio::net::UnixStream),
+}
+
+impl Conn {
+    async fn open(backend: &Url) -> io::Result<Self> {
+        if backend.scheme() == "fastcgi+unix" {
+            #[cfg(unix)]
+            {
+                let path = percent_decode_str(backend.path()).decode_utf8_lossy();
+                return Ok(Conn::Unix(
+                    tokio::net::UnixStream::connect(path.as_ref()).await?,
+                ));
+            }
+            #[cfg(not(unix))]
+            return Err(io::Error::other(
+                "fastcgi+unix backends need a unix platform",
+            ));
+        }
+        let port = backend
+            .port()
+            .ok_or_else(|| io::Error::other("backend URL has no port"))?;
+        let stream = match backend.host() {
+            Some(Host::Domain(host)) => TcpStream::connect((host, port)).await?,
+            Some(Host::Ipv4(ip)) => TcpStream::connect((ip, port)).await?,
+            Some(Host::Ipv6(ip)) => TcpStream::connect((ip, port)).await?,
+            None => return Err(io::Error::other("backend URL has no host")),
+        };
+        stream.set_nodelay(true)?;
+        Ok(Conn::Tcp(stream))
+    }
+
+    // An idle connection is only worth reusing if the backend hasn't closed it (nothing to
+    // read yet, not even EOF).
+    fn is_open(&self) -> bool {
+        let mut probe = [0u8; 1];
+        let result = match self {
+            Conn::Tcp(s) => s.try_read(&mut probe),
+            #[cfg(unix)]
+            Conn::Unix(s) => s.try_read(&mut probe),
+        };
+        matches!(result, Err(e) if e.kind() == io::ErrorKind::WouldBlock)
+    }
+}
+
+impl AsyncRead for Conn {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Conn::Tcp(s) => Pin::new(s).poll_read(cx, buf),
+            #[cfg(unix)]
+            Conn::Unix(s) => Pin::new(s).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for Conn {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Conn::Tcp(s) => Pin::new(s).poll_write(cx, buf),
+            #[cfg(unix)]
+            Conn::Unix(s) => Pin::new(s).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Conn::Tcp(s) => Pin::new(s).poll_flush(cx),
+            #[cfg(unix)]
+            Conn::Unix(s) => Pin::new(s).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Conn::Tcp(s) => Pin::new(s).poll_shutdown(cx),
+            #[cfg(unix)]
+            Conn::Unix(s) => Pin::new(s).poll_shutdown(cx),
+        }
+    }
+}
+
+/// The client and listener a request came through, for `REMOTE_ADDR`, `SERVER_PORT` etc.
+#[derive(Debug, Clone)]
+pub struct RequestOrigin {
+    pub client: Option<SocketAddr>,
+    pub local: SocketAddr,
+    pub https: bool,
+    /// The client's Host header (or HTTP/2 authority).
+    pub host: Option<String>,
+}
+
+/// A server's `[servers.proxy.fastcgi]` settings and its idle backend connections.
+pub struct FastCgi {
+    config: FastCgiConfig,
+    idle: Mutex<HashMap<Url, Vec<Conn>>>,
+    // Stands in for the backend in the request handed over by the proxy: the HTTP client
+    // won't build one for a fastcgi+unix:// URL, and scripts are named by the path alone.
+    request_base: Url,
+}
+
+impl std::fmt::Debug for FastCgi {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FastCgi")
+            .field("config", &self.config)
+            .finish()
+    }
+}
+
+/// Whether `path` (percent-encoded, below the backend's base path) is safe to resolve against
+/// the document root: no `.`/`..` segments or NUL bytes once decoded.
+pub fn is_safe_path(path: &str) -> bool {
+    let decoded = percent_decode_str(path).decode_utf8_lossy();
+    !decoded.contains('\0')
+        && !decoded
+            .replace('\\', "/")
+            .split('/')
+            .any(|seg| seg == "." || seg == "..")
+}
+
+fn record(kind: u8, content: &[u8]) -> Vec<u8> {
+    let padding = (8 - content.len() % 8) % 8;
+    let mut out = Vec::with_capacity(8 + content.len() + padding);
+    out.push(VERSION_1);
+    out.push(kind);
+    out.extend_from_slice(&REQUEST_ID.to_be_bytes());
+    out.extend_from_slice(&(content.len() as u16).to_be_bytes());
+    out.push(padding as u8);
+    out.push(0);
+    out.extend_from_slice(content);
+    out.resize(out.len() + padding, 0);
+    out
+}
+
+// `data` as `kind` records; the empty record closing the stream is written separately.
+async fn write_stream(conn: &mut Conn, kind: u8, data: &[u8]) -> io::Result<()> {
+    for chunk in data.chunks(MAX_CONTENT) {
+        conn.write_all(&record(kind, chunk)).await?;
+    }
+    Ok(())
+}
+
+fn encode_length(out: &mut Vec<u8>, len: usize) {
+    if len < 128 {
+        out.push(len as u8);
+    } else {
+        out.extend_from_slice(&((len as u32) | 0x8000_0000).to_be_bytes());
+    }
+}
+
+fn encode_params(params: &BTreeMap<String, String>) -> Vec<u8> {
+    let mut out = Vec::new();
+    for (name, value) in params {
+        encode_length(&mut out, name.len());
+        encode_length(&mut out, value.len());
+        out.extend_from_slice(name.as_bytes());
+        out.extend_from_slice(value.as_bytes());
+    }
+    out
+}
+
+// Reads the records of one reply.
+struct Reader {
+    conn: Conn,
+    buf: BytesMut,
+}
+
+impl Reader {
+    async fn next(&mut self) -> io::Result<(u8, Bytes)> {
+        loop {
+            if self.buf.len() >= 8 {
+                let len = u16::from_be_bytes([self.buf[4], self.buf[5]]) as usize;
+                let total = 8 + len + self.buf[6] as usize;
+                if self.buf.len() >= total {
+                    let kind = self.buf[1];
+                    let mut rec = self.buf.split_to(total);
+                    rec.advance(8);
+                    rec.truncate(len);
+                    return Ok((kind, rec.freeze()));
+                }
+            }
+            if self.conn.read_buf(&mut self.buf).await? == 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "FastCGI backend closed the connection mid-reply",
+                ));
+            }
+        }
+    }
+}
+
+fn log_stderr(backend: &Url, data: &[u8]) {
+    for line in String::from_utf8_lossy(data).lines() {
+        let line = line.trim();
+        if !line.is_empty() {
+            tracing::warn!("FastCGI {}: {}", backend, line);
+        }
+    }
+}
+
+// The status and headers of a CGI header block (without its blank line).
+fn parse_head(head: &[u8]) -> Result<(StatusCode, HeaderMap), BoxError> {
+    let head = std::str::from_utf8(head).map_err(|_| "FastCGI response headers are not UTF-8")?;
+    let mut status = None;
+    let mut headers = HeaderMap::new();
+    for line in head.lines() {
+        let (name, value) = line
+            .split_once(':')
+            .ok_or_else(|| format!("malformed FastCGI response header '{}'", line))?;
+        let value = value.trim();
+        if name.eq_ignore_ascii_case("status") {
+            let code = value.split(' ').next().unwrap_or("");
+            status = Some(
+                StatusCode::from_bytes(code.as_bytes())
+                    .map_err(|_| format!("bad FastCGI Status '{}'", value))?,
+            );
+            continue;
+        }
+        headers.append(
+            HeaderName::from_bytes(name.trim().as_bytes())?,
+            HeaderValue::from_str(value)?,
+        );
+    }
+    // A CGI script redirects by sending only a Location (RFC 3875, 6.2.3).
+    let status = status.unwrap_or(if headers.contains_key(header::LOCATION) {
+        StatusCode::FOUND
+    } else {
+        StatusCode::OK
+    });
+    Ok((status, headers))
+}
+
+// Where the header block ends, and where the body starts after its blank line.
+fn head_end(data: &[u8]) -> Option<(usize, usize)> {
+    let crlf = data
+        .windows(4)
+        .position(|w| w == b"\r\n\r\n")
+        .map(|i| (i, i + 4));
+    let lf = data
+        .windows(2)
+        .position(|w| w == b"\n\n")
+        .map(|i| (i, i + 2));
+    match (crlf, lf) {
+        (Some(a), Some(b)) => Some(if a.0 <= b.0 { a } else { b }),
+        (a, b) => a.or(b),
+    }
+}
+
+impl FastCgi {
+    pub fn new(config: FastCgiConfig) -> Self {
+        Self {
+            config,
+            idle: Mutex::new(HashMap::new()),
+            request_base: Url::parse("http://fastcgi.invalid/").expect("valid URL"),
+        }
+    }
+
+    /// The base to resolve request paths against in place of a FastCGI backend's URL.
+    pub fn request_base(&self) -> &Url {
+        &self.request_base
+    }
+
+    async fn connect(&self, backend: &Url) -> io::Result<Conn> {
+        loop {
+            let conn = self
+                .idle
+                .lock()
+                .unwrap_or_else(|p| p.into_inner())
+                .get_mut(backend)
+                .and_then(Vec::pop);
+            match conn {
+                Some(conn) if conn.is_open() => return Ok(conn),
+                Some(_) => continue,
+                None => return Conn::open(backend).await,
+            }
+        }
+    }
+
+    fn release(&self, backend: &Url, conn: Conn) {
+        let mut idle = self.idle.lock().unwrap_or_else(|p| p.into_inner());
+        let conns = idle.entry(backend.clone()).or_default();
+        if conns.len() < MAX_IDLE {
+            conns.push(conn);
+        }
+    }
+
+    // The script to run for `path` and the PATH_INFO after it. A path going on past a
+    // `.php` segment names that script, the rest being PATH_INFO.
+    fn script(&self, path: &str) -> (String, String) {
+        let path = percent_decode_str(path).decode_utf8_lossy();
+        if let Some(i) = path.find(".php/") {
+            let (script, info) = path.split_at(i + 4);
+            return (script.to_string(), info.to_string());
+        }
+        if path.ends_with('/') {
+            return (format!("{}{}", path, self.config.index), String::new());
+        }
+        (path.into_owned(), String::new())
+    }
+
+    fn params(
+        &self,
+        request: &reqwest::Request,
+        path: &str,
+        origin: &RequestOrigin,
+        content_length: Option<usize>,
+    ) -> BTreeMap<String, String> {
+        let url = request.url();
+        let (script_name, path_info) = self.script(path);
+        let root = &self.config.document_root;
+        let query = url.query().unwrap_or("");
+        let mut params = BTreeMap::new();
+        let mut set = |name: &str, value: String| {
+            params.insert(name.to_string(), value);
+        };
+        set("GATEWAY_INTERFACE", "CGI/1.1".into());
+        set("SERVER_SOFTWARE", "serava".into());
+        set(
+            "SERVER_PROTOCOL",
+            match request.version() {
+                Version::HTTP_10 => "HTTP/1.0",
+                Version::HTTP_2 => "HTTP/2.0",
+                _ => "HTTP/1.1",
+            }
+            .into(),
+        );
+        set("REQUEST_METHOD", request.method().as_str().into());
+        set(
+            "REQUEST_URI",
+            if query.is_empty() {
+                path.to_string()
+            } else {
+                format!("{}?{}", path, query)
+            },
+        );
+        set("QUERY_STRING", query.into());
+        set("DOCUMENT_ROOT", root.clone());
+        set("DOCUMENT_URI", script_name.clone());
+        set("SCRIPT_FILENAME", format!("{}{}", root, script_name));
+        set("SCRIPT_NAME", script_name);
+        if !path_info.is_empty() {
+            set("PATH_TRANSLATED", format!("{}{}", root, path_info));
+            set("PATH_INFO", path_info);
+        }
+        // PHP built with cgi.force_redirect refuses to run without it.
+        set("REDIRECT_STATUS", "200".into());
+        if let Some(client) = origin.client {
+            set("REMOTE_ADDR", client.ip().to_string());
+            set("REMOTE_PORT", client.port().to_string());
+        }
+        set("SERVER_ADDR", origin.local.ip().to_string());
+        set("SERVER_PORT", origin.local.port().to_string());
+        if origin.https {
+            set("HTTPS", "on".into());
+        }
+        if let Some(len) = content_length {
+            set("CONTENT_LENGTH", len.to_string());
+        }
+
+        if let Some(host) = &origin.host {
+            let name = match host.rsplit_once(':') {
+                Some((name, port)) if port.bytes().all(|b| b.is_ascii_digit()) => name,
+                _ => host,
+            };
+            set("SERVER_NAME", name.to_string());
+            set("HTTP_HOST", host.clone());
+        }
+        for (name, value) in request.headers() {
+            let Ok(value) = value.to_str() else {
+                continue;
+            };
+            let key = match name.as_str() {
+                "content-type" => "CONTENT_TYPE".to_string(),
+                "content-length" => continue,
+                // HTTP_PROXY would be taken for a proxy setting by many libraries ("httpoxy").
+                "proxy" => continue,
+                other => format!("HTTP_{}", other.to_ascii_uppercase().replace('-', "_")),
+            };
+            let separator = if name == header::COOKIE { "; " } else { ", " };
+            params
+                .entry(key)
+                .and_modify(|v: &mut String| {
+                    v.push_str(separator);
+                    v.push_str(value);
+                })
+                .or_insert_with(|| value.to_string());
+        }
+        params
+    }
+
+    /// Run `request` on FastCGI `backend`. The response comes back once its headers have been
+    /// read; its body streams on from the connection. `stderr` output is logged as warnings.
+    pub async fn send(
+        self: &Arc<Self>,
+        backend: &Url,
+        mut request: reqwest::Request,
+        origin: RequestOrigin,
+    ) -> Result<reqwest::Response, BoxError> {
+        let path = request.url().path().to_string();
+
+        // The body is streamed when its length is known up front; otherwise it is read first,
+        // as the script needs CONTENT_LENGTH to read it.
+        let declared = request
+            .headers()
+            .get(header::CONTENT_LENGTH)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse::<usize>().ok());
+        let mut body = match request.body_mut().take() {
+            Some(body) => body.into_data_stream().map_err(io::Error::other).boxed(),
+            None => stream::empty().boxed(),
+        };
+        let buffered = match declared {
+            Some(_) => None,
+            None => {
+                let mut data = Vec::new();
+                while let Some(chunk) = body.next().await {
+                    data.extend_from_slice(&chunk?);
+                }
+                Some(data)
+            }
+        };
+        let content_length = declared.or(buffered.as_ref().map(Vec::len).filter(|n| *n > 0));
+        let params = self.params(&request, &path, &origin, content_length);
+
+        let mut conn = self.connect(backend).await?;
+        let mut begin = RESPONDER.to_be_bytes().to_vec();
+        begin.extend_from_slice(&[KEEP_CONN, 0, 0, 0, 0, 0]);
+        conn.write_all(&record(BEGIN_REQUEST, &begin)).await?;
+        write_stream(&mut conn, PARAMS, &encode_params(&params)).await?;
+        conn.write_all(&record(PARAMS, &[])).await?;
+        match buffered {
+            Some(data) => write_stream(&mut conn, STDIN, &data).await?,
+            None => {
+                while let Some(chunk) = body.next().await {
+                    write_stream(&mut conn, STDIN, &chunk?).await?;
+                }
+            }
+        }
+        conn.write_all(&record(STDIN, &[])).await?;
+        conn.flush().await?;
+
+        let mut reader = Reader {
+            conn,
+            buf: BytesMut::new(),
+        };
+        let mut head = Vec::new();
+        let (head_len, body_start) = loop {
+            match reader.next().await? {
+                (STDOUT, data) => {
+                    head.extend_from_slice(&data);
+                    if let Some(end) = head_end(&head) {
+                        break end;
+                    }
+                    if head.len() > MAX_HEAD {
+                        return Err("FastCGI response headers too large".into());
+                    }
+                }
+                (STDERR, data) => log_stderr(backend, &data),
+                (END_REQUEST, _) => return Err("FastCGI reply ended before its headers".into()),
+                _ => {}
+            }
+        };
+        let (status, headers) = parse_head(&head[..head_len])?;
+        let first = Bytes::copy_from_slice(&head[body_start..]);
+
+        // The rest of the body is read by a task of its own, so the connection can go back to
+        // the pool once the reply is complete even if nobody reads the last of it.
+        let (tx, rx) = mpsc::channel::<io::Result<Bytes>>(8);
+        let backend = backend.clone();
+        let pool = self.clone();
+        tokio::spawn(async move {
+            if !first.is_empty() && tx.send(Ok(first)).await.is_err() {
+                return;
+            }
+            loop {
+                match reader.next().await {
+                    Ok((STDOUT, data)) if !data.is_empty() => {
+                        if tx.send(Ok(data)).await.is_err() {
+                            // The client went away; the connection is dropped mid-reply.
+                            return;
+                        }
+                    }
+                    Ok((STDERR, data)) => log_stderr(&backend, &data),
+                    Ok((END_REQUEST, data)) => {
+                        if data.get(4) == Some(&REQUEST_COMPLETE) && reader.buf.is_empty() {
+                            pool.release(&backend, reader.conn);
+                        }
+                        return;
+                    }
+                    Ok(_) => {}
+                    Err(e) => {
+                        let _ = tx.send(Err(e)).await;
+                        return;
+                    }
+                }
+            }
+        });
+
+        let body = stream::unfold(rx, |mut rx| async move { rx.recv().await.map(|c| (c, rx)) });
+        let mut response = axum::http::Response::new(reqwest::Body::wrap_stream(body));
+        *response.status_mut() = status;
+        *response.headers_mut() = headers;
+        Ok(reqwest::Response::from(response))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::Method;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tokio::net::TcpListener;
+
+    fn fastcgi() -> Arc<FastCgi> {
+        Arc::new(FastCgi::new(FastCgiConfig {
+            document_root: "/srv/www".into(),
+            index: "index.php".into(),
+        }))
+    }
+
+    #[test]
+    fn records_are_padded_to_eight_bytes() {
+        let rec = record(STDOUT, b"hello");
+        assert_eq!(rec[..8], [VERSION_1, STDOUT, 0, 1, 0, 5, 3, 0]);
+        assert_eq!(&rec[8..13], b"hello");
+        assert_eq!(rec.len(), 16);
+        assert_eq!(record(STDIN, &[]), [VERSION_1, STDIN, 0, 1, 0, 0, 0, 0]);
+        assert_eq!(record(STDIN, &[7; 8]).len(), 16);
+    }
+
+    #[test]
+    fn long_param_lengths_take_four_bytes() {
+        let params = BTreeMap::from([
+            ("A".to_string(), "x".repeat(127)),
+            ("B".to_string(), "y".repeat(200)),
+        ]);
+        let out = encode_params(&params);
+        assert_eq!(out[..2], [1, 127]);
+        let b = 2 + 1 + 127;
+        assert_eq!(out[b..b + 5], [1, 0x80, 0, 0, 200]);
+        assert_eq!(out.len(), b + 5 + 1 + 200);
+    }
+
+    #[test]
+    fn head_ends_at_the_first_blank_line() {
+        assert_eq!(head_end(b"A: 1\r\nB: 2\r\n\r\nbody"), Some((10, 14)));
+        assert_eq!(head_end(b"A: 1\nB: 2\n\nbody"), Some((9, 11)));
+        // CRLF header lines ended by a bare LF blank line, and the other way round.
+        assert_eq!(head_end(b"A: 1\r\nB: 2\n\nbody"), Some((10, 12)));
+        assert_eq!(head_end(b"A: 1\n\r\n\r\nbody"), Some((5, 9)));
+        // The body may contain blank lines of its own.
+        assert_eq!(head_end(b"A: 1\n\nx\r\n\r\n"), Some((4, 6)));
+        assert_eq!(head_end(b"A: 1\r\n\r\nx\n\n"), Some((4, 8)));
+        assert_eq!(head_end(b"A: 1\r\nB: 2\r\n"), None);
+    }
+
+    #[test]
+    fn head_status_and_headers() {
+        let (status, headers) =
+            parse_head(b"Status: 404 Not Found\r\nContent-Type: text/html\r\nX-A: 1\nX-A: 2")
+                .unwrap();
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(headers[header::CONTENT_TYPE], "text/html");
+        assert_eq!(headers.get_all("x-a").iter().count(), 2);
+        assert!(!headers.contains_key("status"));
+
+        let (status, headers) = parse_head(b"Location: /elsewhere").unwrap();
+        assert_eq!(status, StatusCode::FOUND);
+        assert_eq!(headers[header::LOCATION], "/elsewhere");
+
+        assert_eq!(
+            parse_head(b"Content-Type: text/plain").unwrap().0,
+            StatusCode::OK
+        );
+        assert!(parse_head(b"Content-Type text/plain").is_err());
+        assert!(parse_head(b"Status: abc").is_err());
+        assert!(parse_head(b"Bad Name: 1").is_err());
+    }
+
+    #[test]
+    fn script_and_path_info() {
+        let fastcgi = fastcgi();
+        let split = |path| fastcgi.script(path);
+        assert_eq!(
+            split("/app/index.php/a/b"),
+            ("/app/index.php".into(), "/a/b".into())
+        );
+        assert_eq!(split("/app/"), ("/app/index.php".into(), String::new()));
+        assert_eq!(
+            split("/my%20page.php"),
+            ("/my page.php".into(), String::new())
+        );
+        assert_eq!(split("/x.phpx/y"), ("/x.phpx/y".into(), String::new()));
+    }
+
+    #[test]
+    fn unsafe_paths() {
+        assert!(is_safe_path("/index.php"));
+        assert!(is_safe_path("/a/..b/c.php"));
+        assert!(!is_safe_path("/a/../c.php"));
+        assert!(!is_safe_path("/a/%2e%2e/c.php"));
+        assert!(!is_safe_path("/a/%2E%2e/c.php"));
+        assert!(!is_safe_path("/a/%2e/c.php"));
+        assert!(!is_safe_path("/a%5c..%5cc.php"));
+        assert!(!is_safe_path("/c.php%00.txt"));
+    }
+
+    fn decode_params(mut data: &[u8]) -> HashMap<String, String> {
+        fn length(data: &mut &[u8]) -> usize {
+            if data[0] < 128 {
+                let len = data[0] as usize;
+                *data = &data[1..];
+                len
+            } else {
+                let len = u32::from_be_bytes([data[0], data[1], data[2], data[3]]) & 0x7fff_ffff;
+                *data = &data[4..];
+                len as usize
+            }
+        }
+        let mut params = HashMap::new();
+        while !data.is_empty() {
+            let name_len = length(&mut data);
+            let value_len = length(&mut data);
+            let name = String::from_utf8(data[..name_len].to_vec()).unwrap();
+            let value = String::from_utf8(data[name_len..name_len + value_len].to_vec()).unwrap();
+            params.insert(name, value);
+            data = &data[name_len + value_len..];
+        }
+        params
+    }
+
+    // A responder answering every request on a connection with its script, PATH_INFO, client
+    // address and body, until the connection is closed.
+    async fn mock_responder(listener: TcpListener, accepts: Arc<AtomicUsize>) {
+        loop {
+            let (mut conn, _) = listener.accept().await.unwrap();
+            accepts.fetch_add(1, Ordering::Relaxed);
+            tokio::spawn(async move {
+                loop {
+                    let mut params = Vec::new();
+                    let mut stdin = Vec::new();
+                    loop {
+                        let mut head = [0u8; 8];
+                        if conn.read_exact(&mut head).await.is_err() {
+                            return;
+                        }
+                        let len = u16::from_be_bytes([head[4], head[5]]) as usize;
+                        let mut content = vec![0; len + head[6] as usize];
+                        conn.read_exact(&mut content).await.unwrap();
+                        content.truncate(len);
+                        match head[1] {
+                            PARAMS => params.extend_from_slice(&content),
+                            STDIN if len == 0 => break,
+                            STDIN => stdin.extend_from_slice(&content),
+                            _ => {}
+                        }
+                    }
+                    let params = decode_params(&params);
+                    let out = format!(
+                        "Status: 201 Created\r\nContent-Type: text/plain\r\n\r\n{} {} {} {}",
+                        params["SCRIPT_NAME"],
+                        params["PATH_INFO"],
+                        params["REMOTE_ADDR"],
+                        String::from_utf8_lossy(&stdin)
+                    );
+                    let mut reply = record(STDERR, b"a warning\n");
+                    reply.extend(record(STDOUT, out.as_bytes()));
+                    reply.extend(record(STDOUT, &[]));
+                    reply.extend(record(
+                        END_REQUEST,
+                        &[0, 0, 0, 0, REQUEST_COMPLETE, 0, 0, 0],
+                    ));
+                    conn.write_all(&reply).await.unwrap();
+                }
+            });
+        }
+    }
+
+    #[tokio::test]
+    async fn round_trip_reuses_the_connection() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let backend = Url::parse(&format!("fastcgi://{}", listener.local_addr().unwrap())).unwrap();
+        let accepts = Arc::new(AtomicUsize::new(0));
+        tokio::spawn(mock_responder(listener, accepts.clone()));
+        let fastcgi = fastcgi();
+        let origin = RequestOrigin {
+            client: Some("192.0.2.7:5000".parse().unwrap()),
+            local: "127.0.0.1:8080".parse().unwrap(),
+            https: false,
+            host: Some("example.test".into()),
+        };
+
+        for body in ["first", "second"] {
+            let url = fastcgi
+                .request_base()
+                .join("/app/index.php/a/b?x=1")
+                .unwrap();
+            let mut req = reqwest::Request::new(Method::POST, url);
+            req.headers_mut()
+                .insert(header::CONTENT_LENGTH, body.len().into());
+            *req.body_mut() = Some(body.into());
+            let resp = fastcgi.send(&backend, req, origin.clone()).await.unwrap();
+            assert_eq!(resp.status(), StatusCode::CREATED);
+            assert_eq!(resp.headers()[header::CONTENT_TYPE], "text/plain");
+            assert_eq!(
+                resp.text().await.unwrap(),
+                format!("/app/index.php /a/b 192.0.2.7 {body}")
+            );
+        }
+        // The connection went back to the pool after END_REQUEST and served the second request.
+        assert_eq!(accepts.load(Ordering::Relaxed), 1);
+    }
+}
@@ -0,0 +1,452 @@
+//! TLS client fingerprints (JA3 and JA4) for `tls_fingerprint`.
+//!
+//! rustls doesn't expose the ClientHello as sent (extension order, point formats), so
+//! [`FingerprintAcceptor`] sits below the TLS acceptor and copies what the handshake reads until
+//! the whole ClientHello has been seen. After that the stream is passed through untouched. The
+//! fingerprint is attached to every request on the connection as a [`TlsFingerprint`]
+//! extension.
+
+use axum::http::{HeaderName, Request};
+use futures::future::BoxFuture;
+use ring::digest;
+use std::fmt::Write;
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::{Arc, OnceLock};
+use std::task::{Context, Poll, ready};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::TcpStream;
+use tower_service::Service;
+
+pub const JA3_HEADER: HeaderName = HeaderName::from_static("x-tls-ja3");
+pub const JA4_HEADER: HeaderName = HeaderName::from_static("x-tls-ja4");
+
+// A ClientHello fits in one 16 KiB record in practice; give up on anything much larger.
+const MAX_CLIENT_HELLO_BYTES: usize = 64 * 1024;
+
+const EXT_SERVER_NAME: u16 = 0x0000;
+const EXT_SUPPORTED_GROUPS: u16 = 0x000a;
+const EXT_EC_POINT_FORMATS: u16 = 0x000b;
+const EXT_SIGNATURE_ALGORITHMS: u16 = 0x000d;
+const EXT_ALPN: u16 = 0x0010;
+const EXT_SUPPORTED_VERSIONS: u16 = 0x002b;
+
+/// Request extension with the fingerprints of the connection's ClientHello.
+#[derive(Debug, Clone)]
+pub struct TlsFingerprint {
+    /// MD5 of the JA3 string, in hex.
+    pub ja3: Arc<str>,
+    pub ja4: Arc<str>,
+}
+
+impl TlsFingerprint {
+    /// The fingerprint of the connection `req` arrived on, if it was computed.
+    pub fn of<B>(req: &Request<B>) -> Option<&TlsFingerprint> {
+        req.extensions().get::<TlsFingerprint>()
+    }
+}
+
+// The ClientHello fields both fingerprints are made of, in the order the client sent them.
+#[derive(Debug, Default)]
+struct ClientHello {
+    version: u16,
+    ciphers: Vec<u16>,
+    extensions: Vec<u16>,
+    groups: Vec<u16>,
+    point_formats: Vec<u8>,
+    signature_algorithms: Vec<u16>,
+    supported_versions: Vec<u16>,
+    alpn: Option<Vec<u8>>,
+    sni: bool,
+}
+
+// GREASE values (RFC 8701) are random per connection and left out of both fingerprints.
+fn is_grease(v: u16) -> bool {
+    v & 0x0f0f == 0x0a0a && v >> 8 == v & 0xff
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Option<&'a [u8]> {
+        if self.0.len() < n {
+            return None;
+        }
+        let (head, rest) = self.0.split_at(n);
+        self.0 = rest;
+        Some(head)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.take(1).map(|b| b[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        self.take(2).map(|b| u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    fn vec8(&mut self) -> Option<&'a [u8]> {
+        let len = self.u8()? as usize;
+        self.take(len)
+    }
+
+    fn vec16(&mut self) -> Option<&'a [u8]> {
+        let len = self.u16()? as usize;
+        self.take(len)
+    }
+
+    fn u16s(data: &[u8]) -> Vec<u16> {
+        data.chunks_exact(2)
+            .map(|c| u16::from_be_bytes([c[0], c[1]]))
+            .collect()
+    }
+}
+
+impl ClientHello {
+    // `body` is the handshake message without its 4-byte header.
+    fn parse(body: &[u8]) -> Option<Self> {
+        let mut r = Reader(body);
+        let mut hello = ClientHello {
+            version: r.u16()?,
+            ..Default::default()
+        };
+        r.take(32)?; // random
+        r.vec8()?; // legacy_session_id
+        hello.ciphers = Reader::u16s(r.vec16()?);
+        r.vec8()?; // legacy_compression_methods
+
+        let mut exts = Reader(r.vec16().unwrap_or_default());
+        while !exts.0.is_empty() {
+            let ty = exts.u16()?;
+            let mut data = Reader(exts.vec16()?);
+            hello.extensions.push(ty);
+            match ty {
+                EXT_SERVER_NAME => hello.sni = true,
+                EXT_SUPPORTED_GROUPS => hello.groups = Reader::u16s(data.vec16()?),
+                EXT_EC_POINT_FORMATS => hello.point_formats = data.vec8()?.to_vec(),
+                EXT_SIGNATURE_ALGORITHMS => {
+                    hello.signature_algorithms = Reader::u16s(data.vec16()?)
+                }
+                EXT_ALPN => hello.alpn = Reader(data.vec16()?).vec8().map(<[u8]>::to_vec),
+                EXT_SUPPORTED_VERSIONS => hello.supported_versions = Reader::u16s(data.vec8()?),
+                _ => {}
+            }
+        }
+        Some(hello)
+    }
+
+    // SSLVersion,Ciphers,Extensions,EllipticCurves,EllipticCurvePointFormats
+    fn ja3(&self) -> String {
+        let list = |values: &[u16]| {
+            values
+                .iter()
+                .filter(|v| !is_grease(**v))
+                .map(u16::to_string)
+                .collect::<Vec<_>>()
+                .join("-")
+        };
+        let point_formats = self
+            .point_formats
+            .iter()
+            .map(u8::to_string)
+            .collect::<Vec<_>>()
+            .join("-");
+        format!(
+            "{},{},{},{},{}",
+            self.version,
+            list(&self.ciphers),
+            list(&self.extensions),
+            list(&self.groups),
+            point_formats
+        )
+    }
+
+    fn ja4(&self) -> String {
+        let version = self
+            .supported_versions
+            .iter()
+            .copied()
+            .filter(|v| !is_grease(*v))
+            .max()
+            .unwrap_or(self.version);
+        let version = match version {
+            0x0304 => "13",
+            0x0303 => "12",
+            0x0302 => "11",
+            0x0301 => "10",
+            0x0300 => "s3",
+            _ => "00",
+        };
+        let ciphers: Vec<u16> = self
+            .ciphers
+            .iter()
+            .copied()
+            .filter(|v| !is_grease(*v))
+            .collect();
+        let extensions: Vec<u16> = self
+            .extensions
+            .iter()
+            .copied()
+            .filter(|v| !is_grease(*v))
+            .collect();
+        let alpn = match self.alpn.as_deref() {
+            Some([first, .., last])
+                if first.is_ascii_alphanumeric() && last.is_ascii_alphanumeric() =>
+            {
+                format!("{}{}", *first as char, *last as char)
+            }
+            Some([only]) if only.is_ascii_alphanumeric() => format!("{0}{0}", *only as char),
+            Some(value) if !value.is_empty() => {
+                let hex = hex(value);
+                format!("{}{}", &hex[..1], &hex[hex.len() - 1..])
+            }
+            _ => "00".to_string(),
+        };
+
+        let mut sorted_ciphers = ciphers.clone();
+        sorted_ciphers.sort_unstable();
+        let mut sorted_extensions: Vec<u16> = extensions
+            .iter()
+            .copied()
+            .filter(|e| *e != EXT_SERVER_NAME && *e != EXT_ALPN)
+            .collect();
+        sorted_extensions.sort_unstable();
+        let mut ext_input = hex_list(&sorted_extensions);
+        if !self.signature_algorithms.is_empty() {
+            ext_input.push('_');
+            ext_input.push_str(&hex_list(&self.signature_algorithms));
+        }
+
+        format!(
+            "t{}{}{:02}{:02}{}_{}_{}",
+            version,
+            if self.sni { 'd' } else { 'i' },
+            ciphers.len().min(99),
+            extensions.len().min(99),
+            alpn,
+            truncated_sha256(&hex_list(&sorted_ciphers), sorted_ciphers.is_empty()),
+            truncated_sha256(&ext_input, sorted_extensions.is_empty()),
+        )
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .fold(String::with_capacity(bytes.len() * 2), |mut out, b| {
+            let _ = write!(out, "{:02x}", b);
+            out
+        })
+}
+
+fn hex_list(values: &[u16]) -> String {
+    values
+        .iter()
+        .map(|v| format!("{:04x}", v))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+fn truncated_sha256(input: &str, empty: bool) -> String {
+    if empty {
+        return "0".repeat(12);
+    }
+    let mut out = hex(digest::digest(&digest::SHA256, input.as_bytes()).as_ref());
+    out.truncate(12);
+    out
+}
+
+// The ClientHello body once `data` (the start of the connection) holds all of it.
+// Err when the bytes aren't a TLS handshake starting with a ClientHello.
+fn client_hello_body(data: &[u8]) -> Result<Option<Vec<u8>>, ()> {
+    let mut handshake = Vec::new();
+    let mut records = Reader(data);
+    while records.0.len() >= 5 {
+        let header = records.take(5).ok_or(())?;
+        if header[0] != 22 {
+            return Err(());
+        }
+        let len = u16::from_be_bytes([header[3], header[4]]) as usize;
+        let Some(fragment) = records.take(len) else {
+            return Ok(None);
+        };
+        handshake.extend_from_slice(fragment);
+        if handshake.len() >= 4 {
+            if handshake[0] != 1 {
+                return Err(());
+            }
+            let msg_len = u32::from_be_bytes([0, handshake[1], handshake[2], handshake[3]]);
+            if handshake.len() >= 4 + msg_len as usize {
+                handshake.truncate(4 + msg_len as usize);
+                return Ok(Some(handshake.split_off(4)));
+            }
+        }
+    }
+    Ok(None)
+}
+
+struct Capture {
+    seen: Vec<u8>,
+    peer: Option<SocketAddr>,
+    slot: Arc<OnceLock<TlsFingerprint>>,
+}
+
+impl Capture {
+    // Returns true once capturing is over, successfully or not.
+    fn feed(&mut self, data: &[u8]) -> bool {
+        if data.is_empty() {
+            return true;
+        }
+        self.seen.extend_from_slice(data);
+        match client_hello_body(&self.seen) {
+            Ok(Some(body)) => {
+                match ClientHello::parse(&body) {
+                    Some(hello) => {
+                        let ja3_string = hello.ja3();
+                        let fingerprint = TlsFingerprint {
+                            ja3: format!("{:x}", md5::compute(&ja3_string)).into(),
+                            ja4: hello.ja4().into(),
+                        };
+                        tracing::debug!(
+                            "TLS client {:?}: ja3 {} ({}), ja4 {}",
+                            self.peer,
+                            fingerprint.ja3,
+                            ja3_string,
+                            fingerprint.ja4
+                        );
+                        let _ = self.slot.set(fingerprint);
+                    }
+                    None => tracing::debug!("malformed ClientHello from {:?}", self.peer),
+                }
+                true
+            }
+            Ok(None) => self.seen.len() > MAX_CLIENT_HELLO_BYTES,
+            Err(()) => true,
+        }
+    }
+}
+
+/// Stream copying the start of the connection until the ClientHello is complete.
+pub struct FingerprintStream<S> {
+    inner: S,
+    capture: Option<Box<Capture>>,
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for FingerprintStream<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let before = buf.filled().len();
+        ready!(Pin::new(&mut this.inner).poll_read(cx, buf))?;
+        if let Some(capture) = &mut this.capture
+            && capture.feed(&buf.filled()[before..])
+        {
+            this.capture = None;
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for FingerprintStream<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().inner).poll_write(cx, buf)
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().inner).poll_write_vectored(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.inner.is_write_vectored()
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+/// Per-connection service attaching the connection's [`TlsFingerprint`] to each request.
+#[derive(Debug, Clone)]
+pub struct FingerprintService<S> {
+    inner: S,
+    // Filled in by the stream during the handshake, before any request is read.
+    slot: Option<Arc<OnceLock<TlsFingerprint>>>,
+}
+
+impl<S, B> Service<Request<B>> for FingerprintService<S>
+where
+    S: Service<Request<B>>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: Request<B>) -> Self::Future {
+        if let Some(fingerprint) = self.slot.as_ref().and_then(|s| s.get()) {
+            req.extensions_mut().insert(fingerprint.clone());
+        }
+        self.inner.call(req)
+    }
+}
+
+/// axum-server acceptor below the rustls acceptor, fingerprinting clients when `enabled`.
+#[derive(Debug, Clone)]
+pub struct FingerprintAcceptor<A> {
+    inner: A,
+    enabled: bool,
+}
+
+impl<A> FingerprintAcceptor<A> {
+    pub fn new(inner: A, enabled: bool) -> Self {
+        Self { inner, enabled }
+    }
+}
+
+impl<A, S> axum_server::accept::Accept<TcpStream, S> for FingerprintAcceptor<A>
+where
+    A: axum_server::accept::Accept<TcpStream, S>,
+    A::Future: Send + 'static,
+{
+    type Stream = FingerprintStream<A::Stream>;
+    type Service = FingerprintService<A::Service>;
+    type Future = BoxFuture<'static, io::Result<(Self::Stream, Self::Service)>>;
+
+    fn accept(&self, stream: TcpStream, service: S) -> Self::Future {
+        let capture = self.enabled.then(|| {
+            Box::new(Capture {
+                seen: Vec::new(),
+                peer: stream.peer_addr().ok(),
+                slot: Arc::new(OnceLock::new()),
+            })
+        });
+        let accept = self.inner.accept(stream, service);
+        Box::pin(async move {
+            let (inner, service) = accept.await?;
+            let service = FingerprintService {
+                inner: service,
+                slot: capture.as_ref().map(|c| c.slot.clone()),
+            };
+            Ok((FingerprintStream { inner, capture }, service))
+        })
+    }
+}
@@ -0,0 +1,203 @@
+//! Rejection of requests whose body framing different HTTP parsers could disagree on.
+//!
+//! hyper's HTTP/1 parser handles part of this before a request reaches the proxy; the rest is
+//! checked by [`check`] on the parsed headers. Behaviour for the classic smuggling cases:
+//!
+//! | Request framing                                      | Result | Decided by |
+//! |------------------------------------------------------|--------|------------|
+//! | `Content-Length` then `Transfer-Encoding: chunked` (CL.TE) | 400 | [`check`] |
+//! | `Transfer-Encoding: chunked` then `Content-Length` (TE.CL) | read as chunked | hyper drops the later CL |
+//! | two `Content-Length` headers, different values       | 400    | hyper      |
+//! | two `Content-Length` headers, same value             | read with that length | hyper keeps one |
+//! | `Content-Length: 6, 5`                               | 400    | hyper      |
+//! | `Content-Length: 5, 5`                               | 400    | [`check`]  |
+//! | `Transfer-Encoding: gzip` (chunked not final)        | 400    | hyper      |
+//! | `Transfer-Encoding: gzip, chunked`                   | 400    | [`check`]  |
+//! | `Transfer-Encoding: chunked` twice                   | 400    | [`check`]  |
+//! | `Transfer-Encoding: xchunked` / `identity`           | 400    | hyper      |
+//! | `Transfer-Encoding : chunked` (space before colon)   | 400    | hyper      |
+//! | obs-folded header (`X-A: a` + `\r\n b`)              | 400    | hyper (httparse) |
+//! | `Transfer-Encoding` on HTTP/1.0                      | 400    | hyper      |
+//!
+//! Only rejections made by [`check`] are counted; hyper answers its own 400s before any
+//! handler runs. Request bodies are always re-framed on the way to the backend.
+
+use axum::http::{HeaderMap, header};
+use std::fmt;
+
+/// Why a request's framing headers were refused.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FramingError {
+    /// Both `Content-Length` and `Transfer-Encoding` are present.
+    LengthAndEncoding,
+    /// `Content-Length` is repeated or not a plain decimal number.
+    InvalidLength,
+    /// `Transfer-Encoding` is anything other than a single `chunked`.
+    UnsupportedEncoding,
+}
+
+impl fmt::Display for FramingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            FramingError::LengthAndEncoding => "both Content-Length and Transfer-Encoding present",
+            FramingError::InvalidLength => "repeated or malformed Content-Length",
+            FramingError::UnsupportedEncoding => "Transfer-Encoding other than a single chunked",
+        })
+    }
+}
+
+/// Check that a request's body framing is unambiguous.
+///
+/// Other transfer codings are refused even when `chunked` is final, since they would be lost
+/// when the body is re-framed for the backend.
+pub fn check(headers: &HeaderMap) -> Result<(), FramingError> {
+    let lengths: Vec<_> = headers.get_all(header::CONTENT_LENGTH).iter().collect();
+    let codings: Vec<String> = headers
+        .get_all(header::TRANSFER_ENCODING)
+        .iter()
+        .flat_map(|v| {
+            String::from_utf8_lossy(v.as_bytes())
+                .split(',')
+                .map(|c| c.trim().to_ascii_lowercase())
+                .collect::<Vec<_>>()
+        })
+        .collect();
+
+    if !lengths.is_empty() && !codings.is_empty() {
+        return Err(FramingError::LengthAndEncoding);
+    }
+    match lengths.as_slice() {
+        [] => {}
+        [len] if !len.is_empty() && len.as_bytes().iter().all(u8::is_ascii_digit) => {}
+        _ => return Err(FramingError::InvalidLength),
+    }
+    if !codings.is_empty() && codings != ["chunked"] {
+        return Err(FramingError::UnsupportedEncoding);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use axum::http::{Request, Response, StatusCode};
+    use hyper::body::Incoming;
+    use hyper::service::service_fn;
+    use hyper_util::rt::TokioIo;
+    use std::convert::Infallible;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    // Feed a raw request to hyper's HTTP/1 server, checking framing the way the proxy does, and
+    // return the response status.
+    async fn status_for(head: &str) -> u16 {
+        let (mut client, server) = tokio::io::duplex(4096);
+        let service = service_fn(|req: Request<Incoming>| async move {
+            let status = match check(req.headers()) {
+                Ok(()) => StatusCode::OK,
+                Err(_) => StatusCode::BAD_REQUEST,
+            };
+            let mut resp = Response::new(Body::empty());
+            *resp.status_mut() = status;
+            Ok::<_, Infallible>(resp)
+        });
+        let conn = tokio::spawn(
+            hyper::server::conn::http1::Builder::new()
+                .serve_connection(TokioIo::new(server), service),
+        );
+        let raw = format!("{}Connection: close\r\n\r\n0\r\n\r\n", head);
+        client.write_all(raw.as_bytes()).await.unwrap();
+        let mut out = Vec::new();
+        client.read_to_end(&mut out).await.unwrap();
+        let _ = conn.await;
+        let out = String::from_utf8_lossy(&out);
+        out.split(' ')
+            .nth(1)
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(0)
+    }
+
+    #[tokio::test]
+    async fn smuggling_table() {
+        let post = "POST / HTTP/1.1\r\nHost: a\r\n";
+        let cases = [
+            ("Content-Length: 5\r\nTransfer-Encoding: chunked\r\n", 400),
+            ("Transfer-Encoding: chunked\r\nContent-Length: 5\r\n", 200),
+            ("Content-Length: 5\r\nContent-Length: 6\r\n", 400),
+            ("Content-Length: 5\r\nContent-Length: 5\r\n", 200),
+            ("Content-Length: 6, 5\r\n", 400),
+            ("Content-Length: 5, 5\r\n", 400),
+            ("Transfer-Encoding: gzip\r\n", 400),
+            ("Transfer-Encoding: chunked, gzip\r\n", 400),
+            ("Transfer-Encoding: gzip, chunked\r\n", 400),
+            (
+                "Transfer-Encoding: chunked\r\nTransfer-Encoding: chunked\r\n",
+                400,
+            ),
+            ("Transfer-Encoding: xchunked\r\n", 400),
+            ("Transfer-Encoding: identity\r\n", 400),
+            ("Transfer-Encoding : chunked\r\n", 400),
+            ("X-A: a\r\n b\r\nTransfer-Encoding: chunked\r\n", 400),
+            ("Transfer-Encoding: chunked\r\n", 200),
+        ];
+        for (headers, expected) in cases {
+            let status = status_for(&format!("{}{}", post, headers)).await;
+            assert_eq!(status, expected, "{:?}", headers);
+        }
+        let http10 = "POST / HTTP/1.0\r\nHost: a\r\nTransfer-Encoding: chunked\r\n";
+        assert_eq!(status_for(http10).await, 400);
+    }
+
+    #[test]
+    fn check_reasons() {
+        let headers = |pairs: &[(header::HeaderName, &str)]| {
+            let mut map = HeaderMap::new();
+            for (name, value) in pairs {
+                map.append(name, value.parse().unwrap());
+            }
+            map
+        };
+        let cases = [
+            (
+                headers(&[
+                    (header::CONTENT_LENGTH, "5"),
+                    (header::TRANSFER_ENCODING, "chunked"),
+                ]),
+                Err(FramingError::LengthAndEncoding),
+            ),
+            (
+                headers(&[(header::CONTENT_LENGTH, "5"), (header::CONTENT_LENGTH, "5")]),
+                Err(FramingError::InvalidLength),
+            ),
+            (
+                headers(&[(header::CONTENT_LENGTH, "5, 5")]),
+                Err(FramingError::InvalidLength),
+            ),
+            (
+                headers(&[(header::CONTENT_LENGTH, "+5")]),
+                Err(FramingError::InvalidLength),
+            ),
+            (
+                headers(&[(header::TRANSFER_ENCODING, "gzip, chunked")]),
+                Err(FramingError::UnsupportedEncoding),
+            ),
+            (
+                headers(&[(header::TRANSFER_ENCODING, "chunked, gzip")]),
+                Err(FramingError::UnsupportedEncoding),
+            ),
+            (
+                headers(&[
+                    (header::TRANSFER_ENCODING, "chunked"),
+                    (header::TRANSFER_ENCODING, "chunked"),
+                ]),
+                Err(FramingError::UnsupportedEncoding),
+            ),
+            (headers(&[(header::TRANSFER_ENCODING, "Chunked")]), Ok(())),
+            (headers(&[(header::CONTENT_LENGTH, "0")]), Ok(())),
+            (headers(&[]), Ok(())),
+        ];
+        for (map, expected) in cases {
+            assert_eq!(check(&map), expected, "{:?}", map);
+        }
+    }
+}
@@ -0,0 +1,159 @@
+use maxminddb::{Mmap, Reader};
+use serde::Deserialize;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+
+pub const GEO_COUNTRY_HEADER: &str = "x-geo-country";
+pub const GEO_ASN_HEADER: &str = "x-geo-asn";
+
+// The subset of fields we read. Country/City databases fill `country`, ASN databases fill
+// `autonomous_system_number`; decoding is lenient so any of them can back either lookup.
+#[derive(Deserialize)]
+struct GeoRecord<'a> {
+    #[serde(borrow)]
+    country: Option<CountryRecord<'a>>,
+    autonomous_system_number: Option<u32>,
+}
+
+#[derive(Deserialize)]
+struct CountryRecord<'a> {
+    iso_code: Option<&'a str>,
+}
+
+/// Result of a lookup; either half may be missing. Also kept as a request extension, so each
+/// request is looked up once.
+#[derive(Debug, Clone, Default)]
+pub struct GeoInfo {
+    pub country: Option<String>,
+    pub asn: Option<u32>,
+}
+
+/// MaxMind-format databases, memory-mapped.
+///
+/// Loading fails open: a database that can't be opened is logged and left out, and lookups
+/// come back without what it would have given until a reload (SIGHUP) finds it.
+pub struct GeoIp {
+    paths: Vec<PathBuf>,
+    // One slot per path, in order; `None` while that database can't be opened
+    readers: RwLock<Arc<Slots>>,
+}
+
+type Slots = Vec<Option<Arc<Reader<Mmap>>>>;
+
+impl std::fmt::Debug for GeoIp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("GeoIp").field("paths", &self.paths).finish()
+    }
+}
+
+impl GeoIp {
+    /// Open every database in `paths`; country databases should come before ASN ones.
+    pub fn open(paths: Vec<PathBuf>) -> Self {
+        let readers = paths
+            .iter()
+            .map(|path| match Reader::open_mmap(path) {
+                Ok(reader) => Some(Arc::new(reader)),
+                Err(e) => {
+                    tracing::warn!(
+                        "GeoIP database {} unavailable, its lookups will be unknown: {}",
+                        path.display(),
+                        e
+                    );
+                    None
+                }
+            })
+            .collect();
+        Self {
+            paths,
+            readers: RwLock::new(Arc::new(readers)),
+        }
+    }
+
+    /// Map every database again, e.g. after an update replaced the files. A database that
+    /// fails to open keeps its previous mapping. Returns how many were reloaded.
+    ///
+    /// Updates must rename new files into place: the old mapping is read until its last
+    /// lookup finishes, and a file rewritten in place changes under it.
+    pub fn reload(&self) -> usize {
+        let current = self.readers();
+        let mut reloaded = 0;
+        let readers = self
+            .paths
+            .iter()
+            .zip(current.iter())
+            .map(|(path, old)| match Reader::open_mmap(path) {
+                Ok(reader) => {
+                    reloaded += 1;
+                    Some(Arc::new(reader))
+                }
+                Err(e) => {
+                    tracing::error!(
+                        "failed to reload GeoIP database {}, keeping the previous one: {}",
+                        path.display(),
+                        e
+                    );
+                    old.clone()
+                }
+            })
+            .collect();
+        *self.readers.write().unwrap_or_else(|p| p.into_inner()) = Arc::new(readers);
+        reloaded
+    }
+
+    fn readers(&self) -> Arc<Slots> {
+        self.readers
+            .read()
+            .unwrap_or_else(|p| p.into_inner())
+            .clone()
+    }
+
+    /// Look `ip` up in each database, keeping the first country and ASN found.
+    pub fn lookup(&self, ip: IpAddr) -> GeoInfo {
+        let mut info = GeoInfo::default();
+        for reader in self.readers().iter().flatten() {
+            // Addresses missing from a database are expected (private ranges etc.).
+            let Ok(record) = reader.lookup::<GeoRecord>(ip) else {
+                continue;
+            };
+            if info.country.is_none() {
+                info.country = record.country.and_then(|c| c.iso_code).map(str::to_string);
+            }
+            if info.asn.is_none() {
+                info.asn = record.autonomous_system_number;
+            }
+        }
+        info
+    }
+}
+
+/// Reload every GeoIP database on SIGHUP.
+#[cfg(unix)]
+pub async fn reload_on_sighup(dbs: Vec<Arc<GeoIp>>) {
+    use tokio::signal::unix::{SignalKind, signal};
+
+    let mut sighup = match signal(SignalKind::hangup()) {
+        Ok(s) => s,
+        Err(e) => {
+            tracing::warn!(
+                "failed to install SIGHUP handler, GeoIP databases won't be reloaded: {}",
+                e
+            );
+            return;
+        }
+    };
+    while sighup.recv().await.is_some() {
+        for db in &dbs {
+            let reloaded = db.reload();
+            tracing::info!(
+                "reloaded {} of {} GeoIP database(s) {:?}",
+                reloaded,
+                db.paths.len(),
+                db.paths
+            );
+        }
+    }
+}
+
+#[cfg(not(unix))]
+pub async fn reload_on_sighup(_dbs: Vec<Arc<GeoIp>>) {}
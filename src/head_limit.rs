@@ -0,0 +1,286 @@
+//! `max_request_head_bytes` and `header_read_timeout_secs` on the connection layer.
+//!
+//! hyper enforces both limits on every request, but it drops a connection whose head timed out
+//! without answering, and axum-server discards the connection errors that would tell the two
+//! cases apart. [`HeadLimitAcceptor`] therefore watches the first request head of each
+//! connection itself: it answers 431 or 408, counts the rejection and closes. Later requests
+//! on a kept-alive connection are held to the same limits by hyper alone, which closes the
+//! connection (answering 431 where it can) without those being counted.
+
+use futures::future::BoxFuture;
+use hyper_util::rt::{TokioExecutor, TokioTimer};
+use hyper_util::server::conn::auto::Builder;
+use serde::Serialize;
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::task::{Context, Poll, ready};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::time::Sleep;
+
+// hyper's default read buffer limit, which is what bounds a request head when none is set.
+const DEFAULT_MAX_HEAD_BYTES: usize = 8192 + 4096 * 100;
+
+const TOO_LARGE: &[u8] = b"HTTP/1.1 431 Request Header Fields Too Large\r\n\
+    connection: close\r\ncontent-length: 0\r\n\r\n";
+const TIMED_OUT: &[u8] =
+    b"HTTP/1.1 408 Request Timeout\r\nconnection: close\r\ncontent-length: 0\r\n\r\n";
+
+/// Request head limits of one server.
+#[derive(Debug, Clone, Copy)]
+pub struct HeadLimits {
+    max_bytes: Option<usize>,
+    timeout: Option<Duration>,
+}
+
+impl HeadLimits {
+    pub fn new(max_bytes: Option<usize>, timeout: Option<Duration>) -> Self {
+        Self { max_bytes, timeout }
+    }
+
+    fn is_set(&self) -> bool {
+        self.max_bytes.is_some() || self.timeout.is_some()
+    }
+
+    /// Apply the limits to hyper's connection builder; unset limits keep hyper's defaults.
+    pub fn configure(&self, builder: &mut Builder<TokioExecutor>) {
+        if let Some(max) = self.max_bytes {
+            builder.http1().max_buf_size(max);
+            builder
+                .http2()
+                .max_header_list_size(u32::try_from(max).unwrap_or(u32::MAX));
+        }
+        if let Some(timeout) = self.timeout {
+            builder
+                .http1()
+                .timer(TokioTimer::new())
+                .header_read_timeout(timeout);
+        }
+    }
+}
+
+/// Connections closed for an oversized or too slow first request head.
+#[derive(Debug, Default)]
+pub struct HeadRejections {
+    too_large: AtomicU64,
+    timed_out: AtomicU64,
+}
+
+/// Point-in-time view of [`HeadRejections`].
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct HeadRejectionStats {
+    /// Answered 431.
+    pub too_large: u64,
+    /// Answered 408.
+    pub timed_out: u64,
+}
+
+impl HeadRejections {
+    pub fn stats(&self) -> HeadRejectionStats {
+        HeadRejectionStats {
+            too_large: self.too_large.load(Ordering::Relaxed),
+            timed_out: self.timed_out.load(Ordering::Relaxed),
+        }
+    }
+}
+
+enum State {
+    // Reading the first request head; `newline` tracks how much of an empty line was just seen.
+    Head {
+        seen: usize,
+        newline: u8,
+        deadline: Option<Pin<Box<Sleep>>>,
+    },
+    // Sending a rejection; reads report EOF once it's out.
+    Reply {
+        response: &'static [u8],
+        written: usize,
+    },
+    Closed,
+    Passthrough,
+}
+
+/// Stream wrapper enforcing [`HeadLimits`] on the first request head.
+pub struct HeadLimitStream<S> {
+    inner: S,
+    state: State,
+    max_bytes: usize,
+    rejections: Arc<HeadRejections>,
+}
+
+// Advance the empty-line scan over `data`; returns the offset just past the end of the head.
+fn find_head_end(newline: &mut u8, data: &[u8]) -> Option<usize> {
+    for (i, &b) in data.iter().enumerate() {
+        *newline = match (*newline, b) {
+            (1 | 2, b'\n') => return Some(i + 1),
+            (1, b'\r') => 2,
+            (_, b'\n') => 1,
+            _ => 0,
+        };
+    }
+    None
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncRead for HeadLimitStream<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        loop {
+            match &mut this.state {
+                State::Passthrough => return Pin::new(&mut this.inner).poll_read(cx, buf),
+                State::Closed => return Poll::Ready(Ok(())),
+                State::Reply { response, written } => {
+                    while *written < response.len() {
+                        match ready!(
+                            Pin::new(&mut this.inner).poll_write(cx, &response[*written..])
+                        ) {
+                            Ok(0) | Err(_) => break,
+                            Ok(n) => *written += n,
+                        }
+                    }
+                    let _ = ready!(Pin::new(&mut this.inner).poll_flush(cx));
+                    this.state = State::Closed;
+                }
+                State::Head {
+                    seen,
+                    newline,
+                    deadline,
+                } => {
+                    let before = buf.filled().len();
+                    match Pin::new(&mut this.inner).poll_read(cx, buf) {
+                        Poll::Ready(Ok(())) => {
+                            let data = &buf.filled()[before..];
+                            let end = find_head_end(newline, data);
+                            let total = *seen + end.unwrap_or(data.len());
+                            let too_large = match end {
+                                Some(_) => total > this.max_bytes,
+                                None => total >= this.max_bytes,
+                            };
+                            if !too_large {
+                                // Whole head seen (or EOF): hand everything else to hyper as is.
+                                if end.is_some() || data.is_empty() {
+                                    this.state = State::Passthrough;
+                                } else {
+                                    *seen = total;
+                                }
+                                return Poll::Ready(Ok(()));
+                            }
+                            buf.set_filled(before);
+                            this.rejections.too_large.fetch_add(1, Ordering::Relaxed);
+                            tracing::debug!("request head over {} bytes, closing", this.max_bytes);
+                            this.state = State::Reply {
+                                response: TOO_LARGE,
+                                written: 0,
+                            };
+                        }
+                        Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                        Poll::Pending => {
+                            let expired = deadline
+                                .as_mut()
+                                .is_some_and(|d| d.as_mut().poll(cx).is_ready());
+                            if !expired {
+                                return Poll::Pending;
+                            }
+                            this.rejections.timed_out.fetch_add(1, Ordering::Relaxed);
+                            tracing::debug!("request head not received in time, closing");
+                            this.state = State::Reply {
+                                response: TIMED_OUT,
+                                written: 0,
+                            };
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for HeadLimitStream<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().inner).poll_write(cx, buf)
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().inner).poll_write_vectored(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.inner.is_write_vectored()
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+/// axum-server acceptor applying [`HeadLimits`] on top of `inner` (outermost, so above TLS).
+#[derive(Debug, Clone)]
+pub struct HeadLimitAcceptor<A> {
+    inner: A,
+    limits: HeadLimits,
+    rejections: Arc<HeadRejections>,
+}
+
+impl<A> HeadLimitAcceptor<A> {
+    pub fn new(inner: A, limits: HeadLimits, rejections: Arc<HeadRejections>) -> Self {
+        Self {
+            inner,
+            limits,
+            rejections,
+        }
+    }
+}
+
+impl<A, I, S> axum_server::accept::Accept<I, S> for HeadLimitAcceptor<A>
+where
+    A: axum_server::accept::Accept<I, S>,
+    A::Future: Send + 'static,
+    A::Stream: AsyncRead + AsyncWrite + Unpin,
+{
+    type Stream = HeadLimitStream<A::Stream>;
+    type Service = A::Service;
+    type Future = BoxFuture<'static, io::Result<(Self::Stream, Self::Service)>>;
+
+    fn accept(&self, stream: I, service: S) -> Self::Future {
+        let accept = self.inner.accept(stream, service);
+        let (limits, rejections) = (self.limits, self.rejections.clone());
+        Box::pin(async move {
+            let (inner, service) = accept.await?;
+            // The timeout runs from here, like hyper's for the requests that follow.
+            let state = if limits.is_set() {
+                State::Head {
+                    seen: 0,
+                    newline: 0,
+                    deadline: limits.timeout.map(|t| Box::pin(tokio::time::sleep(t))),
+                }
+            } else {
+                State::Passthrough
+            };
+            let stream = HeadLimitStream {
+                inner,
+                state,
+                max_bytes: limits.max_bytes.unwrap_or(DEFAULT_MAX_HEAD_BYTES),
+                rejections,
+            };
+            Ok((stream, service))
+        })
+    }
+}
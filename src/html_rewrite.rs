@@ -0,0 +1,309 @@
+//! `[[servers.proxy.html_rewrite]]`: edit HTML responses on their way to the client.
+//!
+//! Bodies are rewritten as they stream through [lol_html], so a page is never held whole
+//! (cached responses excepted, which are buffered anyway). gzip and deflate bodies are
+//! inflated first and sent on uncompressed; other codings are passed through untouched.
+
+use axum::http::{HeaderMap, HeaderName, HeaderValue, StatusCode, header};
+use bytes::Bytes;
+use flate2::write::{MultiGzDecoder, ZlibDecoder};
+use futures::{Stream, StreamExt, stream};
+use lol_html::OutputSink;
+use lol_html::html_content::ContentType;
+use lol_html::send::{Element, ElementContentHandlers, HtmlRewriter, Settings};
+use std::borrow::Cow;
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+
+use crate::config::{HtmlRewriteRoute, InjectPosition, LinkRewrite};
+
+#[derive(Debug, Clone, Copy)]
+enum Coding {
+    Gzip,
+    // HTTP's "deflate" is the zlib format (RFC 9110, 8.4.1.2).
+    Deflate,
+}
+
+/// The rewrite of one response: its route and the coding its body arrives in.
+#[derive(Debug)]
+pub struct Rewrite {
+    route: Arc<HtmlRewriteRoute>,
+    coding: Option<Coding>,
+}
+
+fn is_html(headers: &HeaderMap) -> bool {
+    headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.split(';').next())
+        .is_some_and(|t| t.trim().eq_ignore_ascii_case("text/html"))
+}
+
+impl Rewrite {
+    /// The rewrite for a response under `route`, or `None` when it isn't a full `text/html`
+    /// body in a coding that can be undone.
+    pub fn for_response(
+        route: Arc<HtmlRewriteRoute>,
+        status: StatusCode,
+        headers: &HeaderMap,
+    ) -> Option<Self> {
+        if status == StatusCode::PARTIAL_CONTENT
+            || status == StatusCode::NO_CONTENT
+            || status == StatusCode::NOT_MODIFIED
+            || !is_html(headers)
+        {
+            return None;
+        }
+        let mut codings = Vec::new();
+        for value in headers.get_all(header::CONTENT_ENCODING) {
+            for coding in value.to_str().ok()?.split(',') {
+                let coding = coding.trim();
+                if coding.eq_ignore_ascii_case("gzip") || coding.eq_ignore_ascii_case("x-gzip") {
+                    codings.push(Coding::Gzip);
+                } else if coding.eq_ignore_ascii_case("deflate") {
+                    codings.push(Coding::Deflate);
+                } else if !coding.is_empty() && !coding.eq_ignore_ascii_case("identity") {
+                    tracing::debug!("not rewriting HTML response in '{}'", coding);
+                    return None;
+                }
+            }
+        }
+        if codings.len() > 1 {
+            tracing::debug!("not rewriting HTML response with stacked content codings");
+            return None;
+        }
+        Some(Self {
+            route,
+            coding: codings.pop(),
+        })
+    }
+
+    /// The value to send for response header `name` along with the rewritten body, or `None`
+    /// to drop it: the length and coding no longer hold, and a strong ETag becomes weak.
+    pub fn header(&self, name: &HeaderName, value: &HeaderValue) -> Option<HeaderValue> {
+        if *name == header::CONTENT_LENGTH || *name == header::ACCEPT_RANGES {
+            return None;
+        }
+        if *name == header::CONTENT_ENCODING && self.coding.is_some() {
+            return None;
+        }
+        if *name == header::ETAG && !value.as_bytes().starts_with(b"W/") {
+            let mut weak = b"W/".to_vec();
+            weak.extend_from_slice(value.as_bytes());
+            return HeaderValue::from_bytes(&weak).ok();
+        }
+        Some(value.clone())
+    }
+
+    /// Rewrite a streamed body chunk by chunk.
+    pub fn stream<S>(self, body: S) -> impl Stream<Item = io::Result<Bytes>> + Send + 'static
+    where
+        S: Stream<Item = io::Result<Bytes>> + Send + 'static,
+    {
+        let pipeline = Pipeline::new(&self);
+        stream::unfold(Some((pipeline, Box::pin(body))), |state| async move {
+            let (mut pipeline, mut body) = state?;
+            loop {
+                match body.next().await {
+                    Some(Ok(chunk)) => match pipeline.push(&chunk) {
+                        Ok(out) if out.is_empty() => continue,
+                        Ok(out) => return Some((Ok(out), Some((pipeline, body)))),
+                        Err(e) => return Some((Err(e), None)),
+                    },
+                    Some(Err(e)) => return Some((Err(e), None)),
+                    None => return Some((pipeline.finish(), None)),
+                }
+            }
+        })
+    }
+
+    /// Rewrite a whole body at once.
+    pub fn bytes(self, body: &[u8]) -> io::Result<Bytes> {
+        let mut pipeline = Pipeline::new(&self);
+        let head = pipeline.push(body)?;
+        let tail = pipeline.finish()?;
+        Ok([head, tail].concat().into())
+    }
+}
+
+enum Decoder {
+    Gzip(MultiGzDecoder<Vec<u8>>),
+    Deflate(ZlibDecoder<Vec<u8>>),
+}
+
+impl Decoder {
+    fn write(&mut self, data: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            Decoder::Gzip(d) => {
+                d.write_all(data)?;
+                Ok(std::mem::take(d.get_mut()))
+            }
+            Decoder::Deflate(d) => {
+                d.write_all(data)?;
+                Ok(std::mem::take(d.get_mut()))
+            }
+        }
+    }
+
+    fn finish(self) -> io::Result<Vec<u8>> {
+        match self {
+            Decoder::Gzip(d) => d.finish(),
+            Decoder::Deflate(d) => d.finish(),
+        }
+    }
+}
+
+// Collects the rewriter's output until the pipeline drains it.
+struct Sink(Arc<Mutex<Vec<u8>>>);
+
+impl OutputSink for Sink {
+    fn handle_chunk(&mut self, chunk: &[u8]) {
+        self.0
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .extend_from_slice(chunk);
+    }
+}
+
+// The decoder and rewriter a body passes through. `out` is drained after each chunk.
+struct Pipeline {
+    decoder: Option<Decoder>,
+    rewriter: HtmlRewriter<'static, Sink>,
+    out: Arc<Mutex<Vec<u8>>>,
+}
+
+impl Pipeline {
+    fn new(rewrite: &Rewrite) -> Self {
+        let decoder = rewrite.coding.map(|c| match c {
+            Coding::Gzip => Decoder::Gzip(MultiGzDecoder::new(Vec::new())),
+            Coding::Deflate => Decoder::Deflate(ZlibDecoder::new(Vec::new())),
+        });
+        let out = Arc::new(Mutex::new(Vec::new()));
+        let rewriter = HtmlRewriter::new(
+            Settings {
+                element_content_handlers: handlers(&rewrite.route),
+                ..Settings::new_send()
+            },
+            Sink(out.clone()),
+        );
+        Self {
+            decoder,
+            rewriter,
+            out,
+        }
+    }
+
+    fn take(&self) -> Bytes {
+        std::mem::take(&mut *self.out.lock().unwrap_or_else(|e| e.into_inner())).into()
+    }
+
+    fn push(&mut self, chunk: &[u8]) -> io::Result<Bytes> {
+        match &mut self.decoder {
+            Some(decoder) => {
+                let decoded = decoder.write(chunk)?;
+                self.rewriter.write(&decoded)
+            }
+            None => self.rewriter.write(chunk),
+        }
+        .map_err(io::Error::other)?;
+        Ok(self.take())
+    }
+
+    fn finish(mut self) -> io::Result<Bytes> {
+        if let Some(decoder) = self.decoder.take() {
+            let rest = decoder.finish()?;
+            self.rewriter.write(&rest).map_err(io::Error::other)?;
+        }
+        let out = self.out.clone();
+        self.rewriter.end().map_err(io::Error::other)?;
+        let rest = std::mem::take(&mut *out.lock().unwrap_or_else(|e| e.into_inner()));
+        Ok(rest.into())
+    }
+}
+
+fn escape_attribute(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('"', "&quot;")
+        .replace('<', "&lt;")
+}
+
+// `value` with the first matching `from` prefix replaced. Protocol-relative `//host` links
+// are only touched by a `from` that is itself protocol-relative.
+fn rewrite_link(links: &[LinkRewrite], value: &str) -> Option<String> {
+    links.iter().find_map(|l| {
+        let rest = value.strip_prefix(&l.from)?;
+        if value.starts_with("//") && !l.from.starts_with("//") {
+            return None;
+        }
+        Some(format!("{}{}", l.to, rest))
+    })
+}
+
+type Handlers = Vec<(
+    Cow<'static, lol_html::Selector>,
+    ElementContentHandlers<'static>,
+)>;
+
+fn on(selector: &str) -> Cow<'static, lol_html::Selector> {
+    Cow::Owned(selector.parse().expect("built-in selector"))
+}
+
+fn handlers(route: &Arc<HtmlRewriteRoute>) -> Handlers {
+    let mut handlers: Handlers = Vec::new();
+
+    if let Some(href) = &route.base_href {
+        let base = format!("<base href=\"{}\">", escape_attribute(href));
+        handlers.push((
+            on("base"),
+            ElementContentHandlers::default().element(|el: &mut Element<'_, '_>| {
+                el.remove();
+                Ok(())
+            }),
+        ));
+        handlers.push((
+            on("head"),
+            ElementContentHandlers::default().element(move |el: &mut Element<'_, '_>| {
+                el.prepend(&base, ContentType::Html);
+                Ok(())
+            }),
+        ));
+    }
+
+    for (i, inject) in route.inject.iter().enumerate() {
+        let route = route.clone();
+        handlers.push((
+            Cow::Owned(inject.selector.clone()),
+            ElementContentHandlers::default().element(move |el: &mut Element<'_, '_>| {
+                let inject = &route.inject[i];
+                let html = inject.html.as_str();
+                match inject.position {
+                    InjectPosition::Before => el.before(html, ContentType::Html),
+                    InjectPosition::Prepend => el.prepend(html, ContentType::Html),
+                    InjectPosition::Append => el.append(html, ContentType::Html),
+                    InjectPosition::After => el.after(html, ContentType::Html),
+                    InjectPosition::Replace => el.replace(html, ContentType::Html),
+                }
+                Ok(())
+            }),
+        ));
+    }
+
+    if !route.rewrite_links.is_empty() {
+        let route = route.clone();
+        handlers.push((
+            on("[href], [src]"),
+            ElementContentHandlers::default().element(move |el: &mut Element<'_, '_>| {
+                for attr in ["href", "src"] {
+                    if let Some(value) = el.get_attribute(attr)
+                        && let Some(rewritten) = rewrite_link(&route.rewrite_links, &value)
+                    {
+                        el.set_attribute(attr, &rewritten)?;
+                    }
+                }
+                Ok(())
+            }),
+        ));
+    }
+    handlers
+}
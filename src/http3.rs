@@ -0,0 +1,202 @@
+use axum::{Router, http::HeaderValue};
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use crate::config::TlsConfig;
+
+/// Start the HTTP/3 listener for a TLS server, serving `app` on UDP `listen`.
+///
+/// Returns the `Alt-Svc` value to advertise on the TCP listener, or `None` when HTTP/3 isn't
+/// available; the server then keeps serving over TCP only. On shutdown new QUIC connections are
+/// refused and open ones get `grace` to finish.
+#[cfg(feature = "http3")]
+pub fn spawn(
+    listen: SocketAddr,
+    tls: &TlsConfig,
+    app: Router,
+    grace: Duration,
+) -> Option<HeaderValue> {
+    let endpoint = crate::tls::load_quic_config(tls)
+        .and_then(|config| quinn::Endpoint::server(config, listen));
+    match endpoint {
+        Ok(endpoint) => {
+            tracing::info!("listening for HTTP/3 on udp://{}", listen);
+            tokio::spawn(serve::endpoint(endpoint, app, grace));
+            HeaderValue::from_str(&format!("h3=\":{}\"; ma=86400", listen.port())).ok()
+        }
+        Err(e) => {
+            tracing::error!("HTTP/3 disabled for {}, serving TCP only: {}", listen, e);
+            None
+        }
+    }
+}
+
+#[cfg(not(feature = "http3"))]
+pub fn spawn(
+    listen: SocketAddr,
+    _tls: &TlsConfig,
+    _app: Router,
+    _grace: Duration,
+) -> Option<HeaderValue> {
+    tracing::warn!(
+        "http3 is set for {} but serava was built without the http3 feature; serving TCP only",
+        listen
+    );
+    None
+}
+
+#[cfg(feature = "http3")]
+mod serve {
+    use axum::{
+        Router,
+        body::Body,
+        extract::ConnectInfo,
+        http::{Request, Response, header},
+    };
+    use bytes::{Buf, Bytes};
+    use futures_util::StreamExt;
+    use h3::error::{Code, StreamError};
+    use h3::server::RequestStream;
+    use std::net::SocketAddr;
+    use std::time::Duration;
+    use tower_service::Service;
+
+    use crate::conn_limit::CloseConnection;
+    use crate::shutdown::ShutdownSignals;
+
+    // Connection-specific headers are malformed in HTTP/3 (RFC 9114, 4.2).
+    const CONNECTION_HEADERS: [header::HeaderName; 5] = [
+        header::CONNECTION,
+        header::TRANSFER_ENCODING,
+        header::UPGRADE,
+        header::HeaderName::from_static("keep-alive"),
+        header::HeaderName::from_static("proxy-connection"),
+    ];
+
+    pub async fn endpoint(endpoint: quinn::Endpoint, app: Router, grace: Duration) {
+        let mut signals = ShutdownSignals::new();
+        loop {
+            tokio::select! {
+                incoming = endpoint.accept() => {
+                    let Some(incoming) = incoming else {
+                        break;
+                    };
+                    tokio::spawn(connection(incoming, app.clone()));
+                }
+                _ = signals.recv() => break,
+            }
+        }
+
+        endpoint.set_server_config(None);
+        if tokio::time::timeout(grace, endpoint.wait_idle())
+            .await
+            .is_err()
+        {
+            tracing::warn!(
+                "closing {} HTTP/3 connection(s) still open after {:?}",
+                endpoint.open_connections(),
+                grace
+            );
+        }
+        // H3_NO_ERROR (0x100) always fits in a u32.
+        let code = quinn::VarInt::from_u32(Code::H3_NO_ERROR.value() as u32);
+        endpoint.close(code, b"shutting down");
+    }
+
+    async fn connection(incoming: quinn::Incoming, app: Router) {
+        let conn = match incoming.await {
+            Ok(conn) => conn,
+            Err(e) => {
+                tracing::debug!("QUIC handshake failed: {}", e);
+                return;
+            }
+        };
+        let remote = conn.remote_address();
+        let mut conn =
+            match h3::server::Connection::<_, Bytes>::new(h3_quinn::Connection::new(conn)).await {
+                Ok(conn) => conn,
+                Err(e) => {
+                    tracing::debug!("HTTP/3 setup with {} failed: {}", remote, e);
+                    return;
+                }
+            };
+
+        loop {
+            match conn.accept().await {
+                Ok(Some(resolver)) => {
+                    let app = app.clone();
+                    tokio::spawn(async move {
+                        let result = match resolver.resolve_request().await {
+                            Ok((req, stream)) => request(req, stream, remote, app).await,
+                            Err(e) => Err(e),
+                        };
+                        if let Err(e) = result
+                            && !e.is_h3_no_error()
+                        {
+                            tracing::debug!("HTTP/3 stream from {} failed: {}", remote, e);
+                        }
+                    });
+                }
+                Ok(None) => break,
+                Err(e) => {
+                    if !e.is_h3_no_error() {
+                        tracing::debug!("HTTP/3 connection from {} closed: {}", remote, e);
+                    }
+                    break;
+                }
+            }
+        }
+    }
+
+    async fn request(
+        req: Request<()>,
+        stream: RequestStream<h3_quinn::BidiStream<Bytes>, Bytes>,
+        remote: SocketAddr,
+        mut app: Router,
+    ) -> Result<(), StreamError> {
+        let (mut send, recv) = stream.split();
+        let body = futures_util::stream::unfold(Some(recv), |recv| async move {
+            let mut recv = recv?;
+            match recv.recv_data().await {
+                Ok(Some(mut chunk)) => {
+                    Some((Ok(chunk.copy_to_bytes(chunk.remaining())), Some(recv)))
+                }
+                Ok(None) => None,
+                Err(e) => Some((Err(e), None)),
+            }
+        });
+
+        let (parts, ()) = req.into_parts();
+        let mut req = Request::from_parts(parts, Body::from_stream(body));
+        // The proxy reads the client address from here, as on the TCP listener.
+        req.extensions_mut().insert(ConnectInfo(remote));
+
+        // A Router is always ready, so there's no need to poll_ready first.
+        let resp = match app.call(req).await {
+            Ok(resp) => resp,
+            Err(never) => match never {},
+        };
+        if resp.extensions().get::<CloseConnection>().is_some() {
+            send.stop_stream(Code::H3_REQUEST_REJECTED);
+            return Ok(());
+        }
+        let (mut parts, body) = resp.into_parts();
+        for name in &CONNECTION_HEADERS {
+            parts.headers.remove(name);
+        }
+        send.send_response(Response::from_parts(parts, ())).await?;
+
+        let mut body = body.into_data_stream();
+        while let Some(chunk) = body.next().await {
+            match chunk {
+                Ok(chunk) => send.send_data(chunk).await?,
+                Err(e) => {
+                    tracing::debug!("response body for {} failed: {}", remote, e);
+                    send.stop_stream(Code::H3_INTERNAL_ERROR);
+                    return Ok(());
+                }
+            }
+        }
+        send.finish().await
+    }
+}
@@ -0,0 +1,402 @@
+//! Replay of responses to requests carrying an `Idempotency-Key` header.
+//!
+//! The first request for a key is forwarded and its response buffered; later requests from the
+//! same caller with the same key, method and path within the window get that response back
+//! instead of reaching the backend again. Concurrent duplicates wait for the first one to finish.
+//!
+//! The caller is the authenticated user, else a hash of the Authorization header, else the
+//! client IP, so one client can't read another's response by reusing its key. Replays never
+//! carry `Set-Cookie`.
+
+use axum::{
+    body::Body,
+    http::{HeaderMap, Method, Request, Response, StatusCode, header},
+};
+use bytes::Bytes;
+use dashmap::DashMap;
+use futures::{StreamExt, stream};
+use ring::digest;
+use std::future::Future;
+use std::net::IpAddr;
+use std::pin::pin;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use tokio::sync::OnceCell;
+
+use crate::auth::{AUTHENTICATED_USER_HEADER, Authenticated};
+use crate::signing::to_hex;
+
+pub const IDEMPOTENCY_KEY_HEADER: &str = "idempotency-key";
+/// Set on responses served from the store rather than the backend.
+pub const IDEMPOTENCY_REPLAYED_HEADER: &str = "idempotency-replayed";
+
+// Sweep expired slots once every this many new keys.
+const SWEEP_EVERY: usize = 256;
+
+#[derive(Debug)]
+struct StoredResponse {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Bytes,
+}
+
+#[derive(Debug)]
+struct Slot {
+    created: Instant,
+    // `None` once initialized means the first response could not be stored.
+    response: OnceCell<Option<StoredResponse>>,
+}
+
+/// Per-server store of responses keyed by idempotency key, method and path.
+#[derive(Debug)]
+pub struct IdempotencyStore {
+    slots: DashMap<String, Arc<Slot>>,
+    window: Duration,
+    max_body_bytes: usize,
+    inserts: AtomicUsize,
+}
+
+impl IdempotencyStore {
+    pub fn new(window: Duration, max_body_bytes: usize) -> Self {
+        Self {
+            slots: DashMap::new(),
+            window,
+            max_body_bytes,
+            inserts: AtomicUsize::new(0),
+        }
+    }
+
+    /// Store key for `req` sent by `client` (resolved through trusted_proxies), or `None` if it
+    /// has no key or uses a safe method.
+    pub fn request_key(req: &Request<Body>, client: Option<IpAddr>) -> Option<String> {
+        if matches!(
+            *req.method(),
+            Method::GET | Method::HEAD | Method::OPTIONS | Method::TRACE
+        ) {
+            return None;
+        }
+        let key = req
+            .headers()
+            .get(IDEMPOTENCY_KEY_HEADER)?
+            .to_str()
+            .ok()?
+            .trim();
+        if key.is_empty() {
+            return None;
+        }
+        Some(format!(
+            "{} {} {} {}",
+            caller(req, client),
+            key,
+            req.method(),
+            req.uri().path()
+        ))
+    }
+
+    fn slot(&self, key: &str) -> Arc<Slot> {
+        let now = Instant::now();
+        if let Some(slot) = self.slots.get(key)
+            && now.duration_since(slot.created) < self.window
+        {
+            return slot.clone();
+        }
+
+        let slot = Arc::new(Slot {
+            created: now,
+            response: OnceCell::new(),
+        });
+        self.slots.insert(key.to_string(), slot.clone());
+
+        if self.inserts.fetch_add(1, Ordering::Relaxed) % SWEEP_EVERY == SWEEP_EVERY - 1 {
+            self.slots
+                .retain(|_, s| now.duration_since(s.created) < self.window);
+        }
+        slot
+    }
+
+    /// Serve `key` from the store, or run `forward` (at most once across concurrent callers)
+    /// and remember its response.
+    ///
+    /// Error statuses and 5xx responses are not stored, so the client can retry them; waiting
+    /// duplicates then forward their own request instead.
+    pub async fn handle<F>(&self, key: String, forward: F) -> Result<Response<Body>, StatusCode>
+    where
+        F: Future<Output = Result<Response<Body>, StatusCode>>,
+    {
+        let slot = self.slot(&key);
+        let mut forward = pin!(forward);
+
+        // The caller that runs `forward` gets its own response back through here.
+        let mut own: Option<Result<Response<Body>, StatusCode>> = None;
+        let stored = slot
+            .response
+            .get_or_init(|| async {
+                let (stored, resp) = self.capture(forward.as_mut().await).await;
+                own = Some(resp);
+                stored
+            })
+            .await;
+
+        if let Some(resp) = own {
+            if stored.is_none() {
+                self.slots.remove_if(&key, |_, s| Arc::ptr_eq(s, &slot));
+            }
+            return resp;
+        }
+
+        match stored {
+            Some(stored) => {
+                tracing::debug!("replaying stored response for idempotency key {}", key);
+                let mut resp = Response::new(Body::from(stored.body.clone()));
+                *resp.status_mut() = stored.status;
+                *resp.headers_mut() = stored.headers.clone();
+                resp.headers_mut().insert(
+                    IDEMPOTENCY_REPLAYED_HEADER,
+                    axum::http::HeaderValue::from_static("true"),
+                );
+                Ok(resp)
+            }
+            None => forward.await,
+        }
+    }
+
+    // Buffer a response so it can be stored, handing back what to send to the first client. A
+    // body that turns out larger than the cap, or fails part way, is passed on as it comes
+    // and not stored: the backend has already acted on the request either way.
+    async fn capture(
+        &self,
+        result: Result<Response<Body>, StatusCode>,
+    ) -> (Option<StoredResponse>, Result<Response<Body>, StatusCode>) {
+        let resp = match result {
+            Ok(resp) if !resp.status().is_server_error() => resp,
+            other => return (None, other),
+        };
+
+        let too_large = resp
+            .headers()
+            .get(axum::http::header::CONTENT_LENGTH)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse::<usize>().ok())
+            .is_some_and(|len| len > self.max_body_bytes);
+        if too_large {
+            tracing::debug!("not storing idempotent response: body exceeds the size cap");
+            return (None, Ok(resp));
+        }
+
+        let (parts, body) = resp.into_parts();
+        let mut rest = body.into_data_stream();
+        let mut chunks = Vec::new();
+        let mut len = 0;
+        let failed = loop {
+            match rest.next().await {
+                Some(Ok(chunk)) => {
+                    len += chunk.len();
+                    chunks.push(chunk);
+                    if len > self.max_body_bytes {
+                        tracing::debug!(
+                            "not storing idempotent response: body exceeds the size cap"
+                        );
+                        break None;
+                    }
+                }
+                Some(Err(e)) => {
+                    tracing::warn!("not storing idempotent response: body failed: {}", e);
+                    break Some(e);
+                }
+                None => break None,
+            }
+        };
+        if failed.is_some() || len > self.max_body_bytes {
+            let read = stream::iter(chunks.into_iter().map(Ok));
+            let body = match failed {
+                Some(e) => Body::from_stream(read.chain(stream::once(async { Err(e) }))),
+                None => Body::from_stream(read.chain(rest)),
+            };
+            return (None, Ok(Response::from_parts(parts, body)));
+        }
+
+        let body = Bytes::from(chunks.concat());
+        let mut headers = parts.headers.clone();
+        headers.remove(header::SET_COOKIE);
+        let stored = StoredResponse {
+            status: parts.status,
+            headers,
+            body: body.clone(),
+        };
+        (
+            Some(stored),
+            Ok(Response::from_parts(parts, Body::from(body))),
+        )
+    }
+}
+
+// Who sent `req`, for scoping its key: the authenticated user, a hash of its credentials, or
+// its address.
+fn caller(req: &Request<Body>, client: Option<IpAddr>) -> String {
+    if req.extensions().get::<Authenticated>().is_some()
+        && let Some(user) = req.headers().get(AUTHENTICATED_USER_HEADER)
+    {
+        return format!("user:{}", String::from_utf8_lossy(user.as_bytes()));
+    }
+    if let Some(auth) = req.headers().get(header::AUTHORIZATION) {
+        let hash = digest::digest(&digest::SHA256, auth.as_bytes());
+        return format!("auth:{}", to_hex(&hash.as_ref()[..16]));
+    }
+    client.map_or_else(|| "ip:-".to_string(), |ip| format!("ip:{}", ip))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+    use std::io;
+
+    fn store(max_body_bytes: usize) -> IdempotencyStore {
+        IdempotencyStore::new(Duration::from_secs(60), max_body_bytes)
+    }
+
+    // A backend call returning `status` and `body` with a session cookie, counted in `calls`.
+    async fn forward(
+        calls: &AtomicUsize,
+        status: StatusCode,
+        body: Body,
+    ) -> Result<Response<Body>, StatusCode> {
+        calls.fetch_add(1, Ordering::Relaxed);
+        tokio::task::yield_now().await;
+        let mut resp = Response::new(body);
+        *resp.status_mut() = status;
+        resp.headers_mut()
+            .insert(header::SET_COOKIE, HeaderValue::from_static("session=a"));
+        Ok(resp)
+    }
+
+    async fn body(resp: Response<Body>) -> Bytes {
+        axum::body::to_bytes(resp.into_body(), usize::MAX)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn replays_without_set_cookie() {
+        let (store, calls) = (store(1024), AtomicUsize::new(0));
+        let first = store
+            .handle(
+                "k".into(),
+                forward(&calls, StatusCode::CREATED, "done".into()),
+            )
+            .await
+            .unwrap();
+        assert!(first.headers().contains_key(header::SET_COOKIE));
+        assert_eq!(body(first).await, "done");
+
+        let replay = store
+            .handle(
+                "k".into(),
+                forward(&calls, StatusCode::CREATED, "again".into()),
+            )
+            .await
+            .unwrap();
+        assert_eq!(calls.load(Ordering::Relaxed), 1);
+        assert_eq!(replay.status(), StatusCode::CREATED);
+        assert_eq!(replay.headers()[IDEMPOTENCY_REPLAYED_HEADER], "true");
+        assert!(!replay.headers().contains_key(header::SET_COOKIE));
+        assert_eq!(body(replay).await, "done");
+    }
+
+    #[tokio::test]
+    async fn concurrent_duplicates_are_coalesced() {
+        let (store, calls) = (store(1024), AtomicUsize::new(0));
+        let (a, b) = tokio::join!(
+            store.handle("k".into(), forward(&calls, StatusCode::OK, "one".into())),
+            store.handle("k".into(), forward(&calls, StatusCode::OK, "two".into())),
+        );
+        assert_eq!(calls.load(Ordering::Relaxed), 1);
+        assert_eq!(body(a.unwrap()).await, body(b.unwrap()).await);
+    }
+
+    #[tokio::test]
+    async fn server_errors_are_not_stored() {
+        let (store, calls) = (store(1024), AtomicUsize::new(0));
+        let resp = store
+            .handle(
+                "k".into(),
+                forward(&calls, StatusCode::BAD_GATEWAY, "".into()),
+            )
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::BAD_GATEWAY);
+        let resp = store
+            .handle("k".into(), forward(&calls, StatusCode::OK, "ok".into()))
+            .await
+            .unwrap();
+        assert_eq!(calls.load(Ordering::Relaxed), 2);
+        assert!(!resp.headers().contains_key(IDEMPOTENCY_REPLAYED_HEADER));
+    }
+
+    #[tokio::test]
+    async fn oversized_chunked_response_is_passed_on_unstored() {
+        let (store, calls) = (store(8), AtomicUsize::new(0));
+        let chunked = || {
+            let chunks = ["0123", "4567", "89ab", "cdef"].map(Ok::<_, io::Error>);
+            Body::from_stream(stream::iter(chunks))
+        };
+        let resp = store
+            .handle("k".into(), forward(&calls, StatusCode::OK, chunked()))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(body(resp).await, "0123456789abcdef");
+
+        let resp = store
+            .handle("k".into(), forward(&calls, StatusCode::OK, chunked()))
+            .await
+            .unwrap();
+        assert_eq!(calls.load(Ordering::Relaxed), 2);
+        assert!(!resp.headers().contains_key(IDEMPOTENCY_REPLAYED_HEADER));
+    }
+
+    #[test]
+    fn keys_are_scoped_to_the_caller() {
+        let req = |method: Method, auth: Option<&'static str>| {
+            let mut req = Request::builder().method(method).uri("/orders");
+            req = req.header(IDEMPOTENCY_KEY_HEADER, " k1 ");
+            if let Some(auth) = auth {
+                req = req.header(header::AUTHORIZATION, auth);
+            }
+            req.body(Body::empty()).unwrap()
+        };
+        let ip = |s: &str| Some(s.parse().unwrap());
+        let key = |req: &Request<Body>, client| IdempotencyStore::request_key(req, client);
+
+        assert_eq!(key(&req(Method::GET, None), ip("10.0.0.1")), None);
+        let post = req(Method::POST, None);
+        assert_eq!(
+            key(&post, ip("10.0.0.1")).unwrap(),
+            "ip:10.0.0.1 k1 POST /orders"
+        );
+        assert_ne!(key(&post, ip("10.0.0.1")), key(&post, ip("10.0.0.2")));
+
+        // Credentials win over the address, and different credentials never share a key.
+        let alice = key(&req(Method::POST, Some("Bearer alice")), ip("10.0.0.1"));
+        let bob = key(&req(Method::POST, Some("Bearer bob")), ip("10.0.0.1"));
+        assert_eq!(
+            alice,
+            key(&req(Method::POST, Some("Bearer alice")), ip("10.0.0.2"))
+        );
+        assert_ne!(alice, bob);
+        assert!(alice.unwrap().starts_with("auth:"));
+
+        let mut authenticated = req(Method::PUT, None);
+        authenticated
+            .headers_mut()
+            .insert(AUTHENTICATED_USER_HEADER, HeaderValue::from_static("carol"));
+        authenticated
+            .extensions_mut()
+            .insert(Authenticated { credentials: None });
+        assert_eq!(
+            key(&authenticated, None).unwrap(),
+            "user:carol k1 PUT /orders"
+        );
+    }
+}
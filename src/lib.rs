@@ -0,0 +1,1087 @@
+//! Serava: a small reverse proxy and static file server built on axum.
+//!
+//! The binary is a thin wrapper around [`run`]; the modules are public so the config types,
+//! the proxy handler and its state can be reused and exercised from integration tests.
+
+use axum::{
+    Router,
+    handler::Handler,
+    http::{StatusCode, header},
+    response::Html,
+    routing::get,
+};
+use bytes::Bytes;
+use dashmap::DashMap;
+use futures::stream::{FuturesUnordered, StreamExt};
+use reqwest::Client;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{
+    Arc,
+    atomic::{AtomicBool, AtomicUsize, Ordering},
+};
+use std::time::Duration;
+use tower_http::{limit::RequestBodyLimitLayer, services::ServeDir};
+use tracing::info;
+
+pub mod access;
+mod admin;
+pub mod auth;
+pub mod backend_limit;
+pub mod backend_pool;
+pub mod backend_queue;
+pub mod backend_stats;
+pub mod ban;
+pub mod block;
+pub mod cache_warm;
+pub mod config;
+pub mod conn_limit;
+pub mod content_type;
+pub mod cookie;
+pub mod csp;
+pub mod deadline;
+pub mod decompress;
+pub mod drain;
+pub mod early_data;
+pub mod error_page;
+pub mod experiment;
+pub mod fastcgi;
+pub mod fingerprint;
+pub mod framing;
+pub mod geoip;
+pub mod head_limit;
+pub mod html_rewrite;
+mod http3;
+pub mod idempotency;
+pub mod listener;
+pub mod maintenance;
+pub mod match_rules;
+pub mod memory;
+pub mod origin;
+mod panic;
+pub mod path;
+pub mod pidfile;
+pub mod preflight;
+pub mod proxy;
+pub mod proxy_protocol;
+pub mod query;
+pub mod rate_limit;
+pub mod redirect;
+pub mod security_headers;
+mod shutdown;
+pub mod signed_url;
+pub mod signing;
+mod static_index;
+mod static_options;
+mod static_route;
+pub mod supervisor;
+mod systemd;
+pub mod target;
+mod tls;
+pub mod tls_info;
+pub mod transfers;
+mod upgrade;
+pub mod upload;
+pub mod upstream;
+
+pub use config::{Config, ConfigEntry, RawConfig, ValidationError};
+pub use proxy::{AppState, proxy_handler};
+pub use upgrade::is_upgrade_child;
+
+/// Serve every configured server until a shutdown signal is received.
+///
+/// Takes the validated config produced by [`RawConfig::validate`]. Installs the ring rustls
+/// crypto provider if no process-wide provider has been installed yet, and a panic hook that
+/// reports panics (with a backtrace) through `tracing`.
+pub async fn run(config: Config) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    // Ignore the error if the embedding application already installed a provider.
+    let _ = rustls::crypto::ring::default_provider().install_default();
+    panic::install_hook();
+
+    // Catch typo'd or down backends before serving traffic (no-op unless configured).
+    preflight::check_backends(&config).await?;
+
+    // shared HTTP client across servers
+    let client = Client::builder()
+        .connect_timeout(Duration::from_secs(5))
+        .timeout(Duration::from_secs(30))
+        .pool_max_idle_per_host(32)
+        .redirect(reqwest::redirect::Policy::none())
+        .build()?;
+
+    let global_handle = axum_server::Handle::new();
+
+    // Readiness flips to failing as soon as shutdown begins.
+    let ready = Arc::new(AtomicBool::new(true));
+    // Proxied bodies still streaming, reported (and waited on) during shutdown.
+    let transfers = Arc::new(transfers::Transfers::default());
+    tokio::spawn(shutdown::watch(
+        global_handle.clone(),
+        ready.clone(),
+        transfers.clone(),
+        config.shutdown_grace,
+        config.shutdown_grace_max,
+    ));
+
+    // Drain toggled via /admin/drain, shared by every server.
+    let drain = Arc::new(drain::Drain::new(
+        global_handle.clone(),
+        config.drain_retry_after,
+        config.drain_serve_static,
+    ));
+
+    let allow_trace = config.allow_trace;
+    if allow_trace {
+        info!("forwarding TRACE requests");
+    }
+
+    // Process-wide connection cap shared by every server.
+    let global_conn_limit = config.max_connections.map(|n| {
+        info!("global max_connections = {}", n);
+        Arc::new(tokio::sync::Semaphore::new(n))
+    });
+
+    // Sockets passed in by systemd socket activation or a binary upgrade, if any.
+    let mut activated = systemd::listen_fds();
+    activated.extend(upgrade::inherited_fds());
+    let mut handoff = upgrade::HandoffSockets::default();
+
+    // Spawn one axum server per config entry.
+    let mut server_tasks = Vec::with_capacity(config.servers.len());
+
+    // Servers that failed to bind under allow_partial_startup, surfaced in /admin/stats.
+    let bind_failures = Arc::new(std::sync::Mutex::new(Vec::new()));
+    // Restart state of every accept loop, also surfaced in /admin/stats.
+    let supervisor = Arc::new(supervisor::Supervisor::default());
+    // Every cache registers here so [limits] total_cache_bytes can be enforced across servers.
+    if let Some(total) = config.limits.total_cache_bytes {
+        info!("global cache memory budget = {} bytes", total);
+    }
+    let memory = Arc::new(memory::MemoryBudget::new(config.limits.total_cache_bytes));
+    // Failed logins per client IP across every server's auth_route blocks.
+    let auth_failures = Arc::new(auth::AuthFailures::default());
+    let origin_rejections = Arc::new(std::sync::atomic::AtomicU64::new(0));
+    // Clients banned by [auto_ban], on every server.
+    let auto_ban = config.auto_ban.map(|cfg| {
+        info!(
+            "auto_ban enabled: more than {} strike(s) in {:?} bans for {:?}",
+            cfg.max_strikes, cfg.window, cfg.ban
+        );
+        let auto_ban = Arc::new(ban::AutoBan::new(cfg));
+        tokio::spawn(ban::sweep(auto_ban.clone()));
+        auto_ban
+    });
+    // Credential stores and GeoIP databases reloaded together on SIGHUP.
+    let mut auth_stores = Vec::new();
+    let mut geoip_dbs = Vec::new();
+
+    for (idx, cfg) in config.servers.into_iter().enumerate() {
+        info!("preparing server on {}", cfg.listen);
+
+        // load per-server 404.html from the first static dir that has one (fall back to embedded)
+        let default_404 = include_str!("../static/404.html").to_string();
+        let not_found_html = Arc::new(
+            cfg.static_dirs
+                .iter()
+                .find_map(|dir| std::fs::read_to_string(dir.join("404.html")).ok())
+                .unwrap_or_else(|| {
+                    info!(
+                        "no 404.html in {:?}, falling back to embedded 404.html",
+                        cfg.static_dirs
+                    );
+                    default_404.clone()
+                }),
+        );
+
+        for (i, rule) in cfg.match_rules.iter().enumerate() {
+            info!(
+                "match rule[{}] for {}: {:?} {:?} -> {} backend(s)",
+                i,
+                cfg.listen,
+                rule.source,
+                rule.matcher,
+                rule.backends.len()
+            );
+        }
+
+        // Request paths are appended below a backend's path, so "/app" behaves like "/app/".
+        let routed = cfg.match_rules.iter().flat_map(|r| r.backends.iter());
+        for backend in cfg.backends.iter().chain(routed) {
+            if !config::is_fastcgi(backend) && !backend.path().ends_with('/') {
+                tracing::warn!(
+                    "backend {} has a base path without a trailing slash; requests are forwarded below {}/",
+                    backend,
+                    backend.path()
+                );
+            }
+        }
+
+        for rule in &cfg.rate_limits {
+            info!(
+                "rate limit for {}: key={:?}, per_minute={}, burst={}",
+                cfg.listen, rule.key, rule.per_minute, rule.burst
+            );
+        }
+        // Every listener below hands the router ConnectInfo, so a client IP is only missing
+        // when proxy_handler is mounted elsewhere without it; say up front what happens then.
+        if cfg
+            .rate_limits
+            .iter()
+            .any(|r| r.key == config::RateLimitKey::Ip)
+        {
+            info!(
+                "per-IP rate limits on {} apply rate_limit_on_missing_ip = {:?} to requests without a client IP",
+                cfg.listen, cfg.rate_limit_on_missing_ip
+            );
+        }
+
+        // per-server response cache (optional)
+        let response_cache = if let Some(ttl) = cfg.cache_ttl_secs {
+            if ttl == 0 {
+                tracing::info!("response caching disabled (ttl=0) for {}", cfg.listen);
+                None
+            } else {
+                tracing::info!(
+                    "response caching enabled for {}: ttl={}s, max_size_bytes={:?}",
+                    cfg.listen,
+                    ttl,
+                    cfg.cache_max_size_bytes
+                );
+                Some(Arc::new(DashMap::new()))
+            }
+        } else {
+            tracing::info!("response caching disabled for {}", cfg.listen);
+            None
+        };
+        let cache_current_size = Arc::new(AtomicUsize::new(0));
+        if let Some(cache) = &response_cache {
+            memory.register(
+                format!("response_cache {}", cfg.listen),
+                Arc::new(proxy::ResponseCacheUsage {
+                    cache: cache.clone(),
+                    size: cache_current_size.clone(),
+                }),
+            );
+        }
+
+        let geoip = if cfg.geoip_dbs.is_empty() {
+            None
+        } else {
+            let geoip = Arc::new(geoip::GeoIp::open(cfg.geoip_dbs.clone()));
+            info!("GeoIP enabled for {} using {:?}", cfg.listen, cfg.geoip_dbs);
+            geoip_dbs.push(geoip.clone());
+            Some(geoip)
+        };
+
+        let auth_routes = cfg
+            .auth_routes
+            .iter()
+            .map(|route| {
+                info!(
+                    "{:?} auth required for {} on {}",
+                    route.scheme, route.path_prefix, cfg.listen
+                );
+                auth::AuthStore::open(route.clone()).map(Arc::new)
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| format!("failed to load credentials for {}: {}", cfg.listen, e))?;
+        auth_stores.extend(auth_routes.iter().cloned());
+
+        let backend_limiter = cfg.max_connections_per_backend.map(|max| {
+            info!("max_connections_per_backend for {} = {}", cfg.listen, max);
+            let routed = cfg.match_rules.iter().flat_map(|r| r.backends.iter());
+            Arc::new(backend_limit::BackendLimiter::new(
+                cfg.backends.iter().chain(routed),
+                max as usize,
+            ))
+        });
+
+        let backend_queue =
+            cfg.backend_queue
+                .zip(backend_limiter.as_ref())
+                .map(|(limits, limiter)| {
+                    info!(
+                        "backend queue for {}: up to {} request(s) per pool, waiting at most {:?}",
+                        cfg.listen, limits.max_len, limits.max_wait
+                    );
+                    Arc::new(backend_queue::BackendQueue::new(
+                        limits,
+                        cfg.match_rules.len() + 1,
+                        limiter.released(),
+                    ))
+                });
+
+        let backend_stats = Arc::new(backend_stats::BackendStats::new(
+            cfg.backends
+                .iter()
+                .chain(cfg.match_rules.iter().flat_map(|r| r.backends.iter())),
+        ));
+
+        let idempotency = cfg.idempotency_window.map(|window| {
+            info!(
+                "Idempotency-Key replay enabled for {} (window {:?}, max body {} bytes)",
+                cfg.listen, window, cfg.idempotency_max_body_bytes
+            );
+            Arc::new(idempotency::IdempotencyStore::new(
+                window,
+                cfg.idempotency_max_body_bytes as usize,
+            ))
+        });
+
+        if let Some(max) = cfg.max_connections {
+            info!(
+                "max_connections for {} = {} (when saturated: {:?})",
+                cfg.listen, max, cfg.max_connections_action
+            );
+        }
+        if let Some(rate) = cfg.min_write_rate {
+            info!(
+                "min_write_rate for {} = {} bytes/s over {:?}",
+                cfg.listen, rate.bytes_per_sec, rate.window
+            );
+        }
+        let connections = Arc::new(conn_limit::ConnectionTracker::new(
+            cfg.listen,
+            cfg.max_connections,
+            global_conn_limit.clone(),
+            cfg.max_connections_action,
+            cfg.max_connections_per_ip
+                .map(|max| conn_limit::PerIpLimit {
+                    max,
+                    action: cfg.max_connections_per_ip_action,
+                    exempt: cfg.rate_limit_exempt_ips.clone(),
+                }),
+            conn_limit::ConnectionPolicy {
+                max_requests: cfg.max_requests_per_connection,
+                min_write_rate: cfg.min_write_rate,
+            },
+        ));
+        let head_limits =
+            head_limit::HeadLimits::new(cfg.max_request_head_bytes, cfg.header_read_timeout);
+        let head_rejections = Arc::new(head_limit::HeadRejections::default());
+        let block_rules = Arc::new(block::BlockRules::new(cfg.block_rules.clone()));
+
+        let error_pages = Arc::new(error_page::ErrorPages::load(
+            &cfg.error_pages,
+            cfg.malformed_response_page.as_deref(),
+            cfg.maintenance_page.as_deref(),
+        )?);
+        if !error_pages.is_empty() {
+            info!(
+                "custom error pages for {}: {:?}",
+                cfg.listen,
+                cfg.error_pages.iter().map(|(s, _)| s).collect::<Vec<_>>()
+            );
+        }
+
+        let maintenance = (!cfg.maintenance_windows.is_empty()).then(|| {
+            info!(
+                "{} maintenance window(s) for {}",
+                cfg.maintenance_windows.len(),
+                cfg.listen
+            );
+            Arc::new(maintenance::Maintenance::new(
+                cfg.maintenance_windows.clone(),
+                cfg.maintenance_bypass_ips.clone(),
+            ))
+        });
+
+        let experiment = cfg.experiments.as_ref().map(|e| {
+            info!(
+                "experiment '{}' for {}: buckets {:?}",
+                e.name, cfg.listen, e.buckets
+            );
+            Arc::new(experiment::Experiment::new(e.clone(), cfg.tls.is_some()))
+        });
+
+        // Build per-server AppState (client is cloned)
+        let state = AppState {
+            client: client.clone(),
+            backends: Arc::new(backend_pool::BackendPool::new(cfg.backends.clone())),
+            counter: Arc::new(AtomicUsize::new(0)),
+            backend_limiter,
+            backend_queue,
+            backend_timeout: cfg.backend_timeout,
+            request_timeout: cfg.request_timeout,
+            slow_request_threshold: cfg.slow_request_threshold,
+            early_data: cfg.tls.as_ref().and_then(|t| t.early_data),
+            max_upload_buffer_bytes: cfg.max_upload_buffer_bytes.map(|v| v as usize),
+            max_decompressed_body_bytes: cfg.max_decompressed_body_bytes.map(|v| v as usize),
+            max_uri_length: cfg.max_uri_length as usize,
+            upstream_errors: Arc::new(upstream::UpstreamErrorCounters::default()),
+            backend_stats,
+            rate_limiters: Arc::new(
+                cfg.rate_limits
+                    .iter()
+                    .cloned()
+                    .map(rate_limit::RateLimiter::new)
+                    .collect(),
+            ),
+            rate_limit_on_missing_ip: cfg.rate_limit_on_missing_ip,
+            rate_limit_max_entries: cfg.rate_limit_max_entries.map(|v| v as usize),
+            rate_limit_exempt_ips: Arc::new(cfg.rate_limit_exempt_ips.clone()),
+            cookie_rewrite: cfg.cookie_rewrite.clone().map(Arc::new),
+            query_rewrites: Arc::new(cfg.query_rewrites.clone()),
+            query_routes: Arc::new(cfg.query_routes.clone()),
+            trailing_slash: cfg.trailing_slash,
+            normalize_path: cfg.normalize_path,
+            match_routes: Arc::new(
+                cfg.match_rules
+                    .iter()
+                    .cloned()
+                    .map(match_rules::MatchRoute::new)
+                    .collect(),
+            ),
+            allow_absolute_form: cfg.allow_absolute_form,
+            forward_headers_allowlist: cfg.forward_headers_allowlist.clone().map(Arc::new),
+            forward_tls_fingerprint: cfg.forward_tls_fingerprint,
+            forward_tls_info: cfg.forward_tls_info,
+            send_proxy_protocol: Arc::new(cfg.send_proxy_protocol.clone()),
+            listen: cfg.listen,
+            tls: cfg.tls.is_some(),
+            allow_backend_pinning: cfg.allow_backend_pinning,
+            backend_pinning_trusted_ips: Arc::new(cfg.backend_pinning_trusted_ips.clone()),
+            response_cache,
+            cache_ttl_secs: cfg.cache_ttl_secs,
+            cache_max_size_bytes: cfg.cache_max_size_bytes.map(|v| v as usize),
+            cache_max_entry_bytes: cfg.cache_max_entry_bytes.map(|v| v as usize),
+            cache_ttl_jitter: cfg.cache_ttl_jitter,
+            cache_key_include_host: cfg.cache_key_include_host,
+            path_decoding: cfg.path_decoding,
+            cache_bypass_headers: Arc::new(cfg.cache_bypass_headers.clone()),
+            cache_routes: Arc::new(cfg.cache_routes.clone()),
+            access: Arc::new(cfg.access.clone()),
+            access_routes: Arc::new(cfg.access_routes.clone()),
+            trusted_proxies: Arc::new(cfg.trusted_proxies.clone()),
+            auth_routes: Arc::new(auth_routes),
+            auth_failures: auth_failures.clone(),
+            origin_check: cfg.origin_check.clone().map(Arc::new),
+            origin_routes: Arc::new(cfg.origin_routes.clone()),
+            content_type_routes: Arc::new(cfg.content_type_routes.clone()),
+            html_rewrite_routes: Arc::new(
+                cfg.html_rewrite_routes
+                    .iter()
+                    .cloned()
+                    .map(Arc::new)
+                    .collect(),
+            ),
+            origin_scheme: if cfg.tls.is_some() { "https" } else { "http" },
+            origin_rejections: origin_rejections.clone(),
+            cache_current_size,
+            memory: memory.clone(),
+            cache_warmer: Arc::new(cache_warm::CacheWarmer::default()),
+            debug_headers: cfg.debug_headers,
+            error_pages,
+            geoip,
+            deny_countries: Arc::new(cfg.deny_countries.clone()),
+            connections: connections.clone(),
+            head_rejections: head_rejections.clone(),
+            block_rules: block_rules.clone(),
+            auto_ban: auto_ban.clone(),
+            panics: Arc::new(std::sync::atomic::AtomicU64::new(0)),
+            framing_rejections: Arc::new(std::sync::atomic::AtomicU64::new(0)),
+            idempotency,
+            request_signer: cfg
+                .request_signing
+                .as_ref()
+                .map(|c| Arc::new(signing::RequestSigner::new(c))),
+            bind_failures: bind_failures.clone(),
+            supervisor: supervisor.clone(),
+            drain: drain.clone(),
+            allow_trace,
+            maintenance,
+            experiment,
+            fastcgi: cfg.fastcgi.clone().map(|c| {
+                info!(
+                    "FastCGI for {}: document_root {}, index {}",
+                    cfg.listen, c.document_root, c.index
+                );
+                Arc::new(fastcgi::FastCgi::new(c))
+            }),
+            transfers: transfers.clone(),
+            admin_token: cfg.admin_token.clone(),
+        };
+
+        // static service per server
+        let nf = not_found_html.clone();
+        // With index_files configured, directory defaults are resolved by static_index instead.
+        let default_index = cfg.index_files.is_none();
+        let static_service = static_dirs_service(
+            &cfg.static_dirs,
+            default_index,
+            Router::new().fallback(get(move || async move { Html((*nf).clone()) })),
+        );
+        let probe = static_dirs_service(
+            &cfg.static_dirs,
+            default_index,
+            Router::new().fallback(|| async { StatusCode::NOT_FOUND }),
+        );
+        let mut static_service =
+            Router::new()
+                .fallback_service(static_service)
+                .layer(axum::middleware::from_fn(move |req, next| {
+                    static_options::handle(probe.clone(), req, next)
+                }));
+        if let Some(names) = &cfg.index_files {
+            info!("directory index files for {}: {:?}", cfg.listen, names);
+            let index = static_index::IndexFiles::new(cfg.static_dirs.clone(), names.clone());
+            static_service = static_service.layer(axum::middleware::from_fn(move |req, next| {
+                static_index::handle(index.clone(), req, next)
+            }));
+        }
+        if let Some(signed) = &cfg.signed_urls {
+            info!(
+                "signed URLs required on {} for {:?}",
+                cfg.listen, signed.required_prefixes
+            );
+            let signed = Arc::new(signed_url::SignedUrls::new(signed));
+            static_service = static_service.layer(axum::middleware::from_fn(move |req, next| {
+                signed_url::handle(signed.clone(), req, next)
+            }));
+        }
+
+        let static_service = if drain.serve_static {
+            static_service
+        } else {
+            let drain = drain.clone();
+            static_service.layer(axum::middleware::from_fn(move |req, next| {
+                drain::refuse_while_draining(drain.clone(), req, next)
+            }))
+        };
+
+        let mut app = Router::new().nest_service("/static", static_service);
+        if let Some(csp) = csp::StaticCsp::from_config(&cfg) {
+            info!(
+                "Content-Security-Policy enabled for static HTML on {}",
+                cfg.listen
+            );
+            app = app.layer(axum::middleware::map_response(
+                move |mut resp: axum::response::Response| {
+                    let csp = csp.clone();
+                    async move {
+                        csp.apply(&mut resp);
+                        resp
+                    }
+                },
+            ));
+        }
+
+        // admin endpoints are only mounted when a token is configured, either on the public
+        // router or on their own admin_listen server
+        let admin_routes = cfg.admin_token.as_ref().map(|_| {
+            if cfg.debug_panic_route {
+                tracing::warn!("debug panic route enabled for {}", cfg.listen);
+            }
+            admin::router(cfg.debug_panic_route)
+        });
+        if let Some(routes) = &admin_routes
+            && cfg.admin_listen.is_none()
+        {
+            info!("admin endpoints enabled for {}", cfg.listen);
+            app = app.merge(routes.clone());
+        }
+
+        if let Some(path) = &cfg.readiness_path {
+            info!("readiness probe for {} at {}", cfg.listen, path);
+            let (ready, drain) = (ready.clone(), drain.clone());
+            app = app.route(
+                path,
+                get(move || async move {
+                    if !ready.load(Ordering::SeqCst) {
+                        (StatusCode::SERVICE_UNAVAILABLE, "shutting down")
+                    } else if drain.is_draining() {
+                        (StatusCode::SERVICE_UNAVAILABLE, "draining")
+                    } else {
+                        (StatusCode::OK, "ready")
+                    }
+                }),
+            );
+        }
+
+        if let Some(path) = &cfg.favicon {
+            let body = Bytes::from(
+                std::fs::read(path)
+                    .map_err(|e| format!("failed to read favicon '{}': {}", path.display(), e))?,
+            );
+            let content_type = favicon_content_type(path);
+            info!(
+                "serving /favicon.ico for {} from {}",
+                cfg.listen,
+                path.display()
+            );
+            app = app.route(
+                "/favicon.ico",
+                get(move || {
+                    let body = body.clone();
+                    async move { ([(header::CONTENT_TYPE, content_type)], body) }
+                }),
+            );
+        }
+
+        if let Some(robots) = &cfg.robots_txt {
+            let body = match robots {
+                config::RobotsTxt::Inline(text) => Bytes::from(text.clone()),
+                config::RobotsTxt::File(path) => Bytes::from(std::fs::read(path).map_err(|e| {
+                    format!("failed to read robots_txt '{}': {}", path.display(), e)
+                })?),
+            };
+            info!("serving /robots.txt for {}", cfg.listen);
+            app = app.route(
+                "/robots.txt",
+                get(move || {
+                    let body = body.clone();
+                    async move { ([(header::CONTENT_TYPE, "text/plain; charset=utf-8")], body) }
+                }),
+            );
+        }
+
+        let panics = state.panics.clone();
+        let admin_app = admin_routes.map(|routes| {
+            routes
+                .layer(panic::layer(panics.clone()))
+                .with_state(state.clone())
+        });
+        // Only what would reach the proxy is checked, so routes above keep their paths.
+        let mut app = if cfg.static_extensions.is_empty() {
+            app.fallback(proxy_handler)
+        } else {
+            info!(
+                "serving {:?} files from the static directories on {}",
+                cfg.static_extensions, cfg.listen
+            );
+            let files = static_dirs_service(
+                &cfg.static_dirs,
+                false,
+                Router::new().fallback(|| async { StatusCode::NOT_FOUND }),
+            );
+            let files = if drain.serve_static {
+                files
+            } else {
+                let drain = drain.clone();
+                files.layer(axum::middleware::from_fn(move |req, next| {
+                    drain::refuse_while_draining(drain.clone(), req, next)
+                }))
+            };
+            let routes = static_route::StaticExtensions::new(cfg.static_extensions.clone(), files);
+            app.fallback(
+                proxy_handler.layer(axum::middleware::from_fn(move |req, next| {
+                    static_route::handle(routes.clone(), req, next)
+                })),
+            )
+        };
+        // Inside the block rules, so refused requests aren't redirected.
+        let redirects = redirect::Redirects::new(cfg.redirects.clone());
+        if !redirects.is_empty() {
+            info!(
+                "{} redirect(s) enabled for {}",
+                cfg.redirects.len(),
+                cfg.listen
+            );
+            for (rule, earlier) in redirects.shadowed() {
+                tracing::warn!(
+                    "redirects[{}] in {} never fires: redirects[{}] already matches its path",
+                    rule,
+                    cfg.listen,
+                    earlier
+                );
+            }
+            let redirects = Arc::new(redirects);
+            app = app.layer(axum::middleware::from_fn(move |req, next| {
+                redirect::handle(redirects.clone(), req, next)
+            }));
+        }
+        if !block_rules.is_empty() {
+            info!(
+                "{} block rule(s) enabled for {}",
+                cfg.block_rules.len(),
+                cfg.listen
+            );
+            app = app.layer(axum::middleware::from_fn(move |req, next| {
+                block::handle(block_rules.clone(), req, next)
+            }));
+        }
+        if let Some(auto_ban) = &auto_ban {
+            let (auto_ban, trusted_proxies) =
+                (auto_ban.clone(), Arc::new(cfg.trusted_proxies.clone()));
+            app = app.layer(axum::middleware::from_fn(move |req, next| {
+                ban::handle(auto_ban.clone(), trusted_proxies.clone(), req, next)
+            }));
+        }
+        if let Some(headers) = security_headers::SecurityHeaders::from_config(&cfg) {
+            info!("Expect-CT/Report-To/NEL headers enabled for {}", cfg.listen);
+            if cfg
+                .security_headers
+                .as_ref()
+                .is_some_and(|sh| sh.nel.is_some() && sh.report_to.is_none())
+            {
+                tracing::warn!(
+                    "NEL set without Report-To for {}: browsers need a Report-To group to deliver reports",
+                    cfg.listen
+                );
+            }
+            app = app.layer(axum::middleware::map_response(
+                move |mut resp: axum::response::Response| {
+                    let headers = headers.clone();
+                    async move {
+                        headers.apply(&mut resp);
+                        resp
+                    }
+                },
+            ));
+        }
+        let app = app
+            .layer(RequestBodyLimitLayer::new(
+                cfg.max_request_size_bytes as usize,
+            ))
+            .layer(panic::layer(panics));
+        // Outermost, so everything that reads ConnectInfo sees the client.
+        let app = match &cfg.proxy_protocol {
+            Some(pp) => {
+                info!(
+                    "PROXY protocol {:?} from {:?} on {}",
+                    pp.version, pp.trusted, cfg.listen
+                );
+                app.layer(axum::middleware::from_fn(proxy_protocol::apply))
+            }
+            None => app,
+        }
+        .with_state(state);
+        let proxy_protocol = cfg.proxy_protocol.clone().map(Arc::new);
+
+        let handle_clone = global_handle.clone();
+        let listen_addr = cfg.listen;
+
+        // Prefer sockets handed over by systemd or a previous instance; otherwise bind ourselves.
+        let inherited = systemd::take_listeners(
+            &mut activated,
+            listen_addr,
+            cfg.systemd_socket_name.as_deref(),
+        );
+        let bound = match inherited.len() {
+            n if n > 0 => {
+                info!("using {} inherited socket(s) for {}", n, listen_addr);
+                if cfg.reuse_port_acceptors.is_some() && n == 1 {
+                    tracing::warn!(
+                        "reuse_port ignored for {}: serving on the inherited socket",
+                        listen_addr
+                    );
+                }
+                Ok(inherited)
+            }
+            _ if cfg.systemd_socket_name.is_some() => {
+                return Err(format!(
+                    "no systemd socket named '{}' for {}",
+                    cfg.systemd_socket_name.as_deref().unwrap_or_default(),
+                    listen_addr
+                )
+                .into());
+            }
+            _ => {
+                let acceptors = cfg.reuse_port_acceptors.map(|n| {
+                    if n == 0 {
+                        tokio::runtime::Handle::current().metrics().num_workers()
+                    } else {
+                        n
+                    }
+                });
+                listener::bind_with_retry(listen_addr, &config.bind_retry, || match acceptors {
+                    Some(n) => listener::bind_reuse_port(listen_addr, n),
+                    None => listener::bind(listen_addr).map(|l| vec![l]),
+                })
+                .await
+            }
+        };
+
+        let listeners = match bound {
+            Ok(listeners) => listeners,
+            Err(e) => {
+                let msg = format!("server[{}] {}: failed to bind: {}", idx, listen_addr, e);
+                if config.allow_partial_startup {
+                    tracing::error!("{}; continuing without it", msg);
+                    bind_failures
+                        .lock()
+                        .unwrap_or_else(|p| p.into_inner())
+                        .push(listener::BindFailure {
+                            listen: listen_addr,
+                            error: e.to_string(),
+                        });
+                    continue;
+                }
+                return Err(abort_startup(&global_handle, server_tasks, msg).await);
+            }
+        };
+        for l in &listeners {
+            handoff.register(l, cfg.systemd_socket_name.as_deref());
+        }
+        if cfg.reuse_port_acceptors.is_some() && listeners.len() > 1 {
+            info!(
+                "spawned {} SO_REUSEPORT acceptor(s) for {}",
+                listeners.len(),
+                listen_addr
+            );
+        }
+
+        // If TLS configured for this server, load it
+        if let Some(tls_files) = cfg.tls {
+            info!("TLS enabled for {}", listen_addr);
+            info!("loading cert: {}", tls_files.cert.display());
+            info!("loading key: {}", tls_files.key.display());
+            info!(
+                "TLS session_cache_size = {}, ticket_lifetime_secs = {:?}",
+                tls_files.session_cache_size, tls_files.ticket_lifetime_secs
+            );
+
+            let tls_config = tls::load_rustls_config(&tls_files)?;
+            let fingerprint = tls_files.fingerprint;
+            if fingerprint {
+                info!("JA3/JA4 client fingerprints enabled for {}", listen_addr);
+            }
+            let tls_info = cfg.forward_tls_info;
+
+            // Advertise HTTP/3 on the TCP listener only once the QUIC endpoint is up.
+            let alt_svc = if tls_files.http3 {
+                http3::spawn(listen_addr, &tls_files, app.clone(), config.shutdown_grace)
+            } else {
+                None
+            };
+            let app = match alt_svc {
+                Some(alt_svc) => app.layer(axum::middleware::map_response(
+                    move |mut resp: axum::response::Response| {
+                        let alt_svc = alt_svc.clone();
+                        async move {
+                            resp.headers_mut()
+                                .entry(axum::http::header::ALT_SVC)
+                                .or_insert(alt_svc);
+                            resp
+                        }
+                    },
+                )),
+                None => app,
+            };
+
+            // spawn one supervised server task per listening socket
+            for listener in listeners {
+                let (app, handle) = (app.clone(), handle_clone.clone());
+                let (tls_config, connections) = (tls_config.clone(), connections.clone());
+                let proxy_protocol = proxy_protocol.clone();
+                let head_rejections = head_rejections.clone();
+                let (supervisor, policy) = (supervisor.clone(), config.server_restart.clone());
+                server_tasks.push(tokio::spawn(async move {
+                    info!("listening securely on https://{}", listen_addr);
+                    supervisor
+                        .supervise(listener, &policy, move |listener| {
+                            let tcp = proxy_protocol::ProxyProtocolAcceptor::new(
+                                conn_limit::ConnLimitAcceptor::new(connections.clone()),
+                                proxy_protocol.clone(),
+                            );
+                            let acceptor =
+                                early_data::EarlyDataAcceptor::new(tls_info::TlsInfoAcceptor::new(
+                                    axum_server::tls_rustls::RustlsAcceptor::new(
+                                        tls_config.clone(),
+                                    )
+                                    .acceptor(
+                                        fingerprint::FingerprintAcceptor::new(tcp, fingerprint),
+                                    ),
+                                    tls_info,
+                                ));
+                            // Head limits apply to the decrypted stream, so they go above TLS.
+                            let mut server = axum_server::from_tcp(listener).acceptor(
+                                head_limit::HeadLimitAcceptor::new(
+                                    acceptor,
+                                    head_limits,
+                                    head_rejections.clone(),
+                                ),
+                            );
+                            head_limits.configure(server.http_builder());
+                            server.handle(handle.clone()).serve(
+                                app.clone()
+                                    .into_make_service_with_connect_info::<SocketAddr>(),
+                            )
+                        })
+                        .await
+                }));
+            }
+        } else {
+            tracing::info!("TLS disabled for {} (no cert/key)", listen_addr);
+            for listener in listeners {
+                let (app, handle) = (app.clone(), handle_clone.clone());
+                let (connections, head_rejections) = (connections.clone(), head_rejections.clone());
+                let proxy_protocol = proxy_protocol.clone();
+                let (supervisor, policy) = (supervisor.clone(), config.server_restart.clone());
+                server_tasks.push(tokio::spawn(async move {
+                    info!("listening on http://{}", listen_addr);
+                    supervisor
+                        .supervise(listener, &policy, move |listener| {
+                            let tcp = proxy_protocol::ProxyProtocolAcceptor::new(
+                                conn_limit::ConnLimitAcceptor::new(connections.clone()),
+                                proxy_protocol.clone(),
+                            );
+                            let mut server = axum_server::from_tcp(listener).acceptor(
+                                head_limit::HeadLimitAcceptor::new(
+                                    tcp,
+                                    head_limits,
+                                    head_rejections.clone(),
+                                ),
+                            );
+                            head_limits.configure(server.http_builder());
+                            server.handle(handle.clone()).serve(
+                                app.clone()
+                                    .into_make_service_with_connect_info::<SocketAddr>(),
+                            )
+                        })
+                        .await
+                }));
+            }
+        }
+
+        if let (Some(admin_addr), Some(admin_app)) = (cfg.admin_listen, admin_app) {
+            let bound = match systemd::take_listeners(&mut activated, admin_addr, None).pop() {
+                Some(l) => Ok(l),
+                None => {
+                    listener::bind_with_retry(admin_addr, &config.bind_retry, || {
+                        listener::bind(admin_addr)
+                    })
+                    .await
+                }
+            };
+            match bound {
+                Ok(listener) => {
+                    handoff.register(&listener, None);
+                    let handle = global_handle.clone();
+                    let (supervisor, policy) = (supervisor.clone(), config.server_restart.clone());
+                    server_tasks.push(tokio::spawn(async move {
+                        info!(
+                            "admin endpoints for {} listening on http://{}",
+                            listen_addr, admin_addr
+                        );
+                        supervisor
+                            .supervise(listener, &policy, move |listener| {
+                                axum_server::from_tcp(listener)
+                                    .handle(handle.clone())
+                                    .serve(
+                                        admin_app
+                                            .clone()
+                                            .into_make_service_with_connect_info::<SocketAddr>(),
+                                    )
+                            })
+                            .await
+                    }));
+                }
+                Err(e) => {
+                    let msg = format!(
+                        "server[{}] {}: failed to bind admin_listen {}: {}",
+                        idx, listen_addr, admin_addr, e
+                    );
+                    if !config.allow_partial_startup {
+                        return Err(abort_startup(&global_handle, server_tasks, msg).await);
+                    }
+                    tracing::error!("{}; continuing without it", msg);
+                    bind_failures
+                        .lock()
+                        .unwrap_or_else(|p| p.into_inner())
+                        .push(listener::BindFailure {
+                            listen: admin_addr,
+                            error: e.to_string(),
+                        });
+                }
+            }
+        }
+    }
+
+    if server_tasks.is_empty() {
+        return Err("no server could be started".into());
+    }
+
+    for s in &activated {
+        tracing::warn!(
+            "systemd socket {} (name {:?}) matches no configured server",
+            s.local_addr,
+            s.name
+        );
+    }
+
+    // Every listener is bound; tell systemd (or the instance we replace) we're up and keep the
+    // watchdog fed.
+    systemd::notify("READY=1");
+    upgrade::notify_parent();
+    tokio::spawn(upgrade::watch(
+        handoff,
+        global_handle.clone(),
+        config.shutdown_grace,
+    ));
+    if !auth_stores.is_empty() {
+        tokio::spawn(auth::reload_on_sighup(auth_stores));
+    }
+    if !geoip_dbs.is_empty() {
+        tokio::spawn(geoip::reload_on_sighup(geoip_dbs));
+    }
+    if let Some(interval) = systemd::watchdog_interval() {
+        info!("systemd watchdog enabled, pinging every {:?}", interval);
+        tokio::spawn(systemd::watchdog(interval));
+    }
+
+    // Wait for all server tasks; one that exhausted its restarts takes the process down.
+    let mut server_tasks: FuturesUnordered<_> = server_tasks.into_iter().collect();
+    let mut failure = None;
+    while let Some(result) = server_tasks.next().await {
+        let err = match result {
+            Ok(Ok(())) => continue,
+            Ok(Err(e)) => e,
+            Err(e) => format!("server supervisor failed: {}", e),
+        };
+        if failure.is_none() {
+            tracing::error!("{}; shutting down", err);
+            global_handle.shutdown();
+            failure = Some(err);
+        }
+    }
+
+    match failure {
+        Some(err) => Err(err.into()),
+        None => Ok(()),
+    }
+}
+
+// Stop the servers started so far so a fatal bind failure doesn't leave the process running
+// half-configured, and turn the message into the error returned from `run`.
+async fn abort_startup(
+    handle: &axum_server::Handle,
+    server_tasks: Vec<tokio::task::JoinHandle<Result<(), String>>>,
+    msg: String,
+) -> Box<dyn std::error::Error + Send + Sync> {
+    tracing::error!("{}", msg);
+    handle.shutdown();
+    for t in server_tasks {
+        let _ = t.await;
+    }
+    msg.into()
+}
+
+// `dirs` searched in order, each through its own ServeDir so path traversal is checked per
+// directory; a file missing from all of them goes to `not_found`.
+fn static_dirs_service(dirs: &[PathBuf], default_index: bool, not_found: Router) -> Router {
+    dirs.iter().rev().fold(not_found, |next, dir| {
+        Router::new().fallback_service(
+            ServeDir::new(dir)
+                .append_index_html_on_directories(default_index)
+                .fallback(next),
+        )
+    })
+}
+
+// Pick the favicon Content-Type from the file extension, defaulting to the classic ICO type.
+fn favicon_content_type(path: &Path) -> &'static str {
+    match path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_ascii_lowercase())
+        .as_deref()
+    {
+        Some("png") => "image/png",
+        Some("svg") => "image/svg+xml",
+        Some("gif") => "image/gif",
+        Some("jpg" | "jpeg") => "image/jpeg",
+        Some("webp") => "image/webp",
+        _ => "image/x-icon",
+    }
+}
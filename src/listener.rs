@@ -0,0 +1,92 @@
+use serde::Serialize;
+use socket2::{Domain, Protocol, Socket, Type};
+use std::io;
+use std::net::{SocketAddr, TcpListener};
+
+use crate::config::BindRetry;
+
+const LISTEN_BACKLOG: i32 = 1024;
+
+fn new_socket(addr: SocketAddr) -> io::Result<Socket> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    #[cfg(unix)]
+    socket.set_reuse_address(true)?;
+    Ok(socket)
+}
+
+fn listen(socket: Socket, addr: SocketAddr) -> io::Result<TcpListener> {
+    socket.bind(&addr.into())?;
+    socket.listen(LISTEN_BACKLOG)?;
+    Ok(socket.into())
+}
+
+/// Bind a single listening socket.
+pub fn bind(addr: SocketAddr) -> io::Result<TcpListener> {
+    listen(new_socket(addr)?, addr)
+}
+
+/// Bind `count` sockets to the same address with `SO_REUSEPORT`, letting the kernel spread
+/// incoming connections across them.
+#[cfg(unix)]
+pub fn bind_reuse_port(addr: SocketAddr, count: usize) -> io::Result<Vec<TcpListener>> {
+    (0..count)
+        .map(|_| {
+            let socket = new_socket(addr)?;
+            socket.set_reuse_port(true)?;
+            listen(socket, addr)
+        })
+        .collect()
+}
+
+#[cfg(not(unix))]
+pub fn bind_reuse_port(_addr: SocketAddr, _count: usize) -> io::Result<Vec<TcpListener>> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "SO_REUSEPORT is not supported on this platform",
+    ))
+}
+
+// Errors worth retrying: the port is still held (e.g. TIME_WAIT, a previous instance winding
+// down) or the address isn't configured on an interface yet.
+fn is_transient(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::AddrInUse | io::ErrorKind::AddrNotAvailable
+    )
+}
+
+/// Run `bind`, retrying transient failures with exponential backoff per `retry`.
+pub async fn bind_with_retry<T>(
+    addr: SocketAddr,
+    retry: &BindRetry,
+    mut bind: impl FnMut() -> io::Result<T>,
+) -> io::Result<T> {
+    let mut delay = retry.backoff;
+    let mut attempt = 0;
+    loop {
+        match bind() {
+            Ok(v) => return Ok(v),
+            Err(e) if attempt < retry.attempts && is_transient(&e) => {
+                attempt += 1;
+                tracing::warn!(
+                    "bind {} failed: {}; retrying in {:?} ({}/{})",
+                    addr,
+                    e,
+                    delay,
+                    attempt,
+                    retry.attempts
+                );
+                tokio::time::sleep(delay).await;
+                delay = delay.saturating_mul(2);
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// A server that could not be started, reported by the stats endpoint.
+#[derive(Debug, Clone, Serialize)]
+pub struct BindFailure {
+    pub listen: SocketAddr,
+    pub error: String,
+}
@@ -1,174 +1,208 @@
-use axum::{Router, response::Html, routing::get};
-use axum_server::tls_rustls::RustlsConfig;
-use dashmap::DashMap;
-use reqwest::Client;
-use std::sync::{Arc, atomic::AtomicUsize};
-use std::time::Duration;
-use tower_http::{limit::RequestBodyLimitLayer, services::ServeDir};
 use tracing::info;
 
-mod config;
-mod proxy;
+use serava::config::{self, RuntimeFlavor};
+use serava::pidfile::PidFile;
+use serava::signed_url::UrlSigner;
+use serava::signing;
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     rustls::crypto::ring::default_provider()
         .install_default()
         .expect("Failed to install rustls crypto provider");
 
     tracing_subscriber::fmt().init();
 
-    let config_path = std::env::args()
-        .nth(1)
-        .unwrap_or_else(|| "config.toml".to_string());
+    if std::env::args().nth(1).as_deref() == Some("sign-url") {
+        return sign_url(std::env::args().skip(2));
+    }
+
+    let mut config_path = "config.toml".to_string();
+    let mut worker_threads = env_usize("SERAVA_WORKER_THREADS")?;
+    let mut max_blocking_threads = env_usize("SERAVA_MAX_BLOCKING_THREADS")?;
+    let mut pid_file: Option<String> = None;
+    let mut log_file: Option<String> = None;
+    let mut daemonize = false;
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--worker-threads" => worker_threads = Some(flag_usize(&arg, args.next())?),
+            "--max-blocking-threads" => max_blocking_threads = Some(flag_usize(&arg, args.next())?),
+            "--pid-file" => pid_file = Some(flag_value(&arg, args.next())?),
+            "--log-file" => log_file = Some(flag_value(&arg, args.next())?),
+            "--daemonize" => daemonize = true,
+            _ if arg.starts_with("--") => return Err(format!("unknown option '{}'", arg).into()),
+            _ => config_path = arg,
+        }
+    }
 
     let toml_str = std::fs::read_to_string(&config_path)
         .map_err(|e| format!("failed to read config file '{}': {}", config_path, e))?;
-    let raw: config::RawConfig = toml::from_str(&toml_str)
+    let mut raw: config::RawConfig = toml::from_str(&toml_str)
         .map_err(|e| format!("failed to parse TOML '{}': {}", config_path, e))?;
-    let server_cfgs = raw
+
+    // CLI flags win over environment variables, which win over the config file.
+    if worker_threads.is_some() {
+        raw.runtime.worker_threads = worker_threads;
+    }
+    if max_blocking_threads.is_some() {
+        raw.runtime.max_blocking_threads = max_blocking_threads;
+    }
+
+    let config = raw
         .validate()
         .map_err(|e| format!("config validation error: {}", e))?;
 
-    info!("loaded config: {} server(s)", server_cfgs.len());
-    for (i, s) in server_cfgs.iter().enumerate() {
+    info!("loaded config: {} server(s)", config.servers.len());
+    for (i, s) in config.servers.iter().enumerate() {
         info!("server[{}] listen = {}", i, s.listen);
-        info!("server[{}] static_dir = {}", i, s.static_dir.display());
+        for dir in &s.static_dirs {
+            info!("server[{}] static_dir = {}", i, dir.display());
+        }
         for (j, b) in s.backends.iter().enumerate() {
             info!("server[{}] backend[{}] = {}", i, j, b);
         }
     }
 
-    // shared HTTP client across servers
-    let client = Client::builder()
-        .connect_timeout(Duration::from_secs(5))
-        .timeout(Duration::from_secs(30))
-        .pool_max_idle_per_host(32)
-        .redirect(reqwest::redirect::Policy::none())
-        .build()?;
+    // A binary upgrade starts us while the previous instance (which holds the pid file) is
+    // still running, and from an already detached process.
+    let upgrading = serava::is_upgrade_child();
 
-    let global_handle = axum_server::Handle::new();
+    // Report a live instance on the terminal before detaching from it.
+    if let Some(path) = &pid_file
+        && !upgrading
+    {
+        PidFile::check(path.as_ref()).map_err(|e| format!("pid file '{}': {}", path, e))?;
+    }
 
-    let shutdown_handle = global_handle.clone();
-    tokio::spawn(async move {
-        if let Err(e) = tokio::signal::ctrl_c().await {
-            tracing::warn!("failed to install CTRL+C handler: {}", e);
+    if daemonize {
+        #[cfg(unix)]
+        if !upgrading {
+            serava::pidfile::daemonize(log_file.as_deref().map(std::path::Path::new))
+                .map_err(|e| format!("failed to daemonize: {}", e))?;
         }
-        info!("shutdown signal received");
-        // Wait 10 seconds for requests to finish
-        shutdown_handle.graceful_shutdown(Some(Duration::from_secs(10)));
-    });
-
-    // Spawn one axum server per config entry.
-    let mut server_tasks = Vec::with_capacity(server_cfgs.len());
-
-    for cfg in server_cfgs.into_iter() {
-        info!("preparing server on {}", cfg.listen);
-
-        // load per-server 404.html (fall back to embedded)
-        let default_404 = include_str!("../static/404.html").to_string();
-        let not_found_html = Arc::new(
-            std::fs::read_to_string(cfg.static_dir.join("404.html")).unwrap_or_else(|e| {
-                info!(
-                    "failed to load {}/404.html: {}, falling back to embedded 404.html",
-                    cfg.static_dir.display(),
-                    e
-                );
-                default_404.clone()
-            }),
-        );
-
-        // per-server response cache (optional)
-        let response_cache = if let Some(ttl) = cfg.cache_ttl_secs {
-            if ttl == 0 {
-                tracing::info!("response caching disabled (ttl=0) for {}", cfg.listen);
-                None
+        #[cfg(not(unix))]
+        return Err("--daemonize is only supported on unix".into());
+    } else if log_file.is_some() {
+        return Err("--log-file requires --daemonize".into());
+    }
+
+    // Removed again when main returns.
+    let _pid_file = match &pid_file {
+        Some(path) => {
+            let created = if upgrading {
+                PidFile::take_over(path.as_ref())
             } else {
-                tracing::info!(
-                    "response caching enabled for {}: ttl={}s, max_size_bytes={:?}",
-                    cfg.listen,
-                    ttl,
-                    cfg.cache_max_size_bytes
-                );
-                Some(Arc::new(DashMap::new()))
-            }
-        } else {
-            tracing::info!("response caching disabled for {}", cfg.listen);
-            None
-        };
-
-        // Build per-server AppState (client is cloned)
-        let state = proxy::AppState {
-            client: client.clone(),
-            backends: cfg.backends.clone(),
-            counter: Arc::new(AtomicUsize::new(0)),
-            backend_timeout: cfg.backend_timeout,
-            rate_limit_map: Arc::new(DashMap::new()),
-            rate_limit_per_minute: cfg.rate_limit_per_minute.map(|v| v as f64),
-            rate_limit_burst: cfg
-                .rate_limit_burst
-                .map(|v| v as f64)
-                .or(cfg.rate_limit_per_minute.map(|p| p as f64)),
-            response_cache,
-            cache_ttl_secs: cfg.cache_ttl_secs,
-            cache_max_size_bytes: cfg.cache_max_size_bytes.map(|v| v as usize),
-            cache_current_size: Arc::new(AtomicUsize::new(0)),
-        };
-
-        // static service per server
-        let nf = not_found_html.clone();
-        let static_service = ServeDir::new(&cfg.static_dir)
-            .fallback(get(move || async move { Html((*nf).clone()) }));
-
-        let app = Router::new()
-            .nest_service("/static", static_service)
-            .fallback(proxy::proxy_handler)
-            .layer(RequestBodyLimitLayer::new(
-                cfg.max_request_size_bytes as usize,
-            ))
-            .with_state(state);
-
-        let handle_clone = global_handle.clone();
-        let listen_addr = cfg.listen;
-
-        // If TLS configured for this server, load it
-        if let Some(tls_files) = cfg.tls {
-            info!("TLS enabled for {}", listen_addr);
-            info!("loading cert: {}", tls_files.cert.display());
-            info!("loading key: {}", tls_files.key.display());
-
-            let tls_config = RustlsConfig::from_pem_file(tls_files.cert, tls_files.key).await?;
-
-            // spawn the server task
-            server_tasks.push(tokio::spawn(async move {
-                info!("listening securely on https://{}", listen_addr);
-                if let Err(e) = axum_server::bind_rustls(listen_addr, tls_config)
-                    .handle(handle_clone)
-                    .serve(app.into_make_service_with_connect_info::<std::net::SocketAddr>())
-                    .await
-                {
-                    tracing::error!("server {} failed: {}", listen_addr, e);
-                }
-            }));
-        } else {
-            tracing::info!("TLS disabled for {} (no cert/key)", listen_addr);
-            server_tasks.push(tokio::spawn(async move {
-                info!("listening on http://{}", listen_addr);
-                if let Err(e) = axum_server::bind(listen_addr)
-                    .handle(handle_clone)
-                    .serve(app.into_make_service_with_connect_info::<std::net::SocketAddr>())
-                    .await
-                {
-                    tracing::error!("server {} failed: {}", listen_addr, e);
-                }
-            }));
+                PidFile::create(path.as_ref())
+            };
+            Some(created.map_err(|e| format!("pid file '{}': {}", path, e))?)
         }
+        None => None,
+    };
+
+    let rt_cfg = &config.runtime;
+    let mut builder = match rt_cfg.flavor {
+        RuntimeFlavor::MultiThread => tokio::runtime::Builder::new_multi_thread(),
+        RuntimeFlavor::CurrentThread => tokio::runtime::Builder::new_current_thread(),
+    };
+    builder.enable_all();
+    if let Some(n) = rt_cfg.worker_threads {
+        builder.worker_threads(n);
+    }
+    if let Some(n) = rt_cfg.max_blocking_threads {
+        builder.max_blocking_threads(n);
     }
+    if let Some(name) = &rt_cfg.thread_name {
+        builder.thread_name(name);
+    }
+    let runtime = builder.build()?;
+
+    info!(
+        "runtime: flavor={:?}, worker_threads={}, max_blocking_threads={}, thread_name={}",
+        rt_cfg.flavor,
+        runtime.metrics().num_workers(),
+        rt_cfg
+            .max_blocking_threads
+            .map_or_else(|| "default".to_string(), |n| n.to_string()),
+        rt_cfg.thread_name.as_deref().unwrap_or("default"),
+    );
+
+    runtime.block_on(serava::run(config))
+}
 
-    // Wait for all spawned server tasks to complete
-    for t in server_tasks {
-        let _ = t.await;
+/// `serava sign-url <path> [--ttl <secs> | --expires <unix>] [--server <index>] [config.toml]`
+///
+/// Prints a signed link for `path` using the `signed_urls` secret of the given server (default:
+/// the first one with `signed_urls`).
+fn sign_url(
+    mut args: impl Iterator<Item = String>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let mut config_path = "config.toml".to_string();
+    let mut path: Option<String> = None;
+    let mut ttl: u64 = 3600;
+    let mut expires: Option<u64> = None;
+    let mut server: Option<usize> = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--ttl" => ttl = flag_usize(&arg, args.next())? as u64,
+            "--expires" => expires = Some(flag_usize(&arg, args.next())? as u64),
+            "--server" => server = Some(flag_usize(&arg, args.next())?),
+            _ if arg.starts_with("--") => return Err(format!("unknown option '{}'", arg).into()),
+            _ if path.is_none() => path = Some(arg),
+            _ => config_path = arg,
+        }
+    }
+    let path = path.ok_or("usage: serava sign-url <path> [--ttl <secs> | --expires <unix>] [--server <index>] [config.toml]")?;
+    if !path.starts_with('/') {
+        return Err(format!("path '{}' must start with '/'", path).into());
     }
 
+    let toml_str = std::fs::read_to_string(&config_path)
+        .map_err(|e| format!("failed to read config file '{}': {}", config_path, e))?;
+    let raw: config::RawConfig = toml::from_str(&toml_str)
+        .map_err(|e| format!("failed to parse TOML '{}': {}", config_path, e))?;
+    let config = raw
+        .validate()
+        .map_err(|e| format!("config validation error: {}", e))?;
+    let signed = match server {
+        Some(i) => config
+            .servers
+            .get(i)
+            .ok_or_else(|| format!("no server[{}] in '{}'", i, config_path))?
+            .signed_urls
+            .as_ref()
+            .ok_or_else(|| format!("server[{}] has no signed_urls", i))?,
+        None => config
+            .servers
+            .iter()
+            .find_map(|s| s.signed_urls.as_ref())
+            .ok_or_else(|| format!("no server in '{}' has signed_urls", config_path))?,
+    };
+
+    let expires = expires.unwrap_or_else(|| signing::unix_now() + ttl);
+    let signer = UrlSigner::new(signed.secret.expose().as_bytes());
+    println!("{}", signer.sign(&path, expires));
     Ok(())
 }
+
+fn env_usize(name: &str) -> Result<Option<usize>, String> {
+    match std::env::var(name) {
+        Ok(v) => v
+            .trim()
+            .parse()
+            .map(Some)
+            .map_err(|e| format!("invalid {}='{}': {}", name, v, e)),
+        Err(_) => Ok(None),
+    }
+}
+
+fn flag_value(flag: &str, value: Option<String>) -> Result<String, String> {
+    value.ok_or_else(|| format!("{} requires a value", flag))
+}
+
+fn flag_usize(flag: &str, value: Option<String>) -> Result<usize, String> {
+    let value = flag_value(flag, value)?;
+    value
+        .parse()
+        .map_err(|e| format!("invalid {} '{}': {}", flag, value, e))
+}
@@ -0,0 +1,106 @@
+//! `[[servers.proxy.maintenance_window]]`: scheduled times during which the proxy answers 503
+//! instead of forwarding, without anyone having to flip a switch.
+
+use axum::{
+    body::Body,
+    http::{Request, Response, StatusCode, header},
+    response::IntoResponse,
+};
+use ipnet::IpNet;
+use jiff::Timestamp;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::access;
+use crate::config::MaintenanceWindow;
+
+/// Response extension marking the 503 of a maintenance window, for the `maintenance` page.
+#[derive(Debug, Clone, Copy)]
+pub struct InMaintenance;
+
+impl MaintenanceWindow {
+    /// The end of this window if `now` falls inside it.
+    fn active_until(&self, now: Timestamp) -> Option<Timestamp> {
+        let (start, end, days, timezone) = match self {
+            MaintenanceWindow::Once { start, end } => {
+                return (*start <= now && now < *end).then_some(*end);
+            }
+            MaintenanceWindow::Recurring {
+                start,
+                end,
+                days,
+                timezone,
+            } => (*start, *end, days, timezone),
+        };
+        // A window running past midnight may have started yesterday.
+        let today = now.to_zoned(timezone.clone()).date();
+        [today.yesterday().ok(), Some(today)]
+            .into_iter()
+            .flatten()
+            .filter(|day| days.is_empty() || days.contains(&day.weekday()))
+            .find_map(|day| {
+                let last_day = if end > start {
+                    day
+                } else {
+                    day.tomorrow().ok()?
+                };
+                let from = day.to_datetime(start).to_zoned(timezone.clone()).ok()?;
+                let until = last_day.to_datetime(end).to_zoned(timezone.clone()).ok()?;
+                (from.timestamp() <= now && now < until.timestamp()).then(|| until.timestamp())
+            })
+    }
+}
+
+/// A server's maintenance windows and the clients allowed through them.
+#[derive(Debug)]
+pub struct Maintenance {
+    windows: Vec<MaintenanceWindow>,
+    bypass_ips: Vec<IpNet>,
+    // Whether the last request fell in a window, to log when one starts and ends
+    active: AtomicBool,
+}
+
+impl Maintenance {
+    pub fn new(windows: Vec<MaintenanceWindow>, bypass_ips: Vec<IpNet>) -> Self {
+        Self {
+            windows,
+            bypass_ips,
+            active: AtomicBool::new(false),
+        }
+    }
+
+    /// The latest end of the windows `now` falls in, if any.
+    pub fn active_until(&self, now: Timestamp) -> Option<Timestamp> {
+        self.windows
+            .iter()
+            .filter_map(|w| w.active_until(now))
+            .max()
+    }
+
+    /// The 503 for `req` while a window is in force, unless the client (resolved through
+    /// `trusted_proxies`) is on the bypass list. Retry-After points at the end of the window.
+    pub fn check(&self, req: &Request<Body>, trusted_proxies: &[IpNet]) -> Option<Response<Body>> {
+        let now = Timestamp::now();
+        let until = self.active_until(now);
+        if self.active.swap(until.is_some(), Ordering::Relaxed) != until.is_some() {
+            match until {
+                Some(until) => tracing::warn!("maintenance window in force until {}", until),
+                None => tracing::info!("maintenance window over, proxying again"),
+            }
+        }
+        let until = until?;
+
+        let ip = access::client_ip(req, trusted_proxies);
+        if ip.is_some_and(|ip| self.bypass_ips.iter().any(|net| net.contains(&ip))) {
+            return None;
+        }
+        let retry_after = until.duration_since(now).as_secs_f64().ceil().max(1.0) as u64;
+        let mut resp = (
+            StatusCode::SERVICE_UNAVAILABLE,
+            [(header::RETRY_AFTER, retry_after)],
+            "down for maintenance",
+        )
+            .into_response();
+        resp.extensions_mut().insert(InMaintenance);
+        Some(resp)
+    }
+}
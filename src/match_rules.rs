@@ -0,0 +1,69 @@
+use axum::{body::Body, http::Request};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::config::{MatchRule, MatchSource};
+use crate::geoip::GeoInfo;
+
+/// A match rule plus the round-robin counter for its backend group.
+pub struct MatchRoute {
+    pub rule: MatchRule,
+    pub counter: AtomicUsize,
+}
+
+impl MatchRoute {
+    pub fn new(rule: MatchRule) -> Self {
+        Self {
+            rule,
+            counter: AtomicUsize::new(0),
+        }
+    }
+
+    /// Round-robin position for the next request; callers index `rule.backends` modulo its length.
+    pub fn next_index(&self) -> usize {
+        self.counter.fetch_add(1, Ordering::Relaxed)
+    }
+
+    fn matches(&self, req: &Request<Body>) -> bool {
+        match &self.rule.source {
+            MatchSource::Header(name) => req
+                .headers()
+                .get_all(name.as_str())
+                .iter()
+                .filter_map(|v| v.to_str().ok())
+                .any(|v| self.rule.matcher.is_match(v.trim())),
+            MatchSource::Cookie(name) => {
+                cookie_values(req, name).any(|v| self.rule.matcher.is_match(v))
+            }
+            // A client whose country is unknown matches no country rule.
+            MatchSource::Country => req
+                .extensions()
+                .get::<GeoInfo>()
+                .and_then(|g| g.country.as_deref())
+                .is_some_and(|c| self.rule.matcher.is_match(c)),
+        }
+    }
+}
+
+/// Values of every cookie called `name` across all `Cookie` headers.
+pub(crate) fn cookie_values<'a>(
+    req: &'a Request<Body>,
+    name: &'a str,
+) -> impl Iterator<Item = &'a str> {
+    req.headers()
+        .get_all("cookie")
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(';'))
+        .filter_map(move |pair| {
+            let (k, v) = pair.trim().split_once('=')?;
+            (k.trim() == name).then(|| v.trim().trim_matches('"'))
+        })
+}
+
+/// Find the first rule matching the request, with its index.
+pub fn find_route<'a>(
+    routes: &'a [MatchRoute],
+    req: &Request<Body>,
+) -> Option<(usize, &'a MatchRoute)> {
+    routes.iter().enumerate().find(|(_, r)| r.matches(req))
+}
@@ -0,0 +1,123 @@
+//! Process-wide memory budget shared by every cache (`[limits] total_cache_bytes`).
+//!
+//! Each cache registers as a [`MemoryConsumer`] and calls [`MemoryBudget::enforce`] after it
+//! grows. When the total is over budget, every consumer is asked to shed a share of the excess
+//! proportional to its size, so the biggest caches give up the most. Per-cache limits are
+//! enforced by the caches themselves and still apply within the global budget.
+
+use serde::Serialize;
+use std::sync::{Arc, RwLock};
+
+/// Something holding memory that can be released on demand.
+pub trait MemoryConsumer: Send + Sync {
+    /// Bytes currently held.
+    fn used_bytes(&self) -> usize;
+    /// Release roughly `bytes` (oldest or least useful entries first) and return how much was
+    /// actually freed.
+    fn shed(&self, bytes: usize) -> usize;
+}
+
+struct Component {
+    name: String,
+    consumer: Arc<dyn MemoryConsumer>,
+}
+
+/// Registry of every cache in the process and the optional global limit over them.
+#[derive(Default)]
+pub struct MemoryBudget {
+    limit: Option<usize>,
+    components: RwLock<Vec<Component>>,
+}
+
+/// Usage of one registered consumer, reported by the stats endpoint.
+#[derive(Debug, Clone, Serialize)]
+pub struct ComponentUsage {
+    pub name: String,
+    pub bytes: usize,
+}
+
+/// Global and per-consumer usage, reported by the stats endpoint.
+#[derive(Debug, Clone, Serialize)]
+pub struct MemoryStats {
+    pub limit_bytes: Option<usize>,
+    pub total_bytes: usize,
+    pub components: Vec<ComponentUsage>,
+}
+
+impl MemoryBudget {
+    pub fn new(limit: Option<usize>) -> Self {
+        Self {
+            limit,
+            components: RwLock::new(Vec::new()),
+        }
+    }
+
+    pub fn register(&self, name: impl Into<String>, consumer: Arc<dyn MemoryConsumer>) {
+        self.components
+            .write()
+            .unwrap_or_else(|p| p.into_inner())
+            .push(Component {
+                name: name.into(),
+                consumer,
+            });
+    }
+
+    pub fn stats(&self) -> MemoryStats {
+        let components: Vec<ComponentUsage> = self
+            .components
+            .read()
+            .unwrap_or_else(|p| p.into_inner())
+            .iter()
+            .map(|c| ComponentUsage {
+                name: c.name.clone(),
+                bytes: c.consumer.used_bytes(),
+            })
+            .collect();
+        MemoryStats {
+            limit_bytes: self.limit,
+            total_bytes: components.iter().map(|c| c.bytes).sum(),
+            components,
+        }
+    }
+
+    /// Bring the total back under the limit, if one is set and it has been exceeded.
+    pub fn enforce(&self) {
+        let Some(limit) = self.limit else {
+            return;
+        };
+        let mut usage: Vec<(Arc<dyn MemoryConsumer>, usize)> = self
+            .components
+            .read()
+            .unwrap_or_else(|p| p.into_inner())
+            .iter()
+            .map(|c| (c.consumer.clone(), c.consumer.used_bytes()))
+            .collect();
+        let total: usize = usage.iter().map(|(_, used)| used).sum();
+        if total <= limit {
+            return;
+        }
+        let excess = total - limit;
+        usage.sort_by_key(|(_, used)| std::cmp::Reverse(*used));
+
+        // Proportional shares first, rounded up so they cover the excess between them.
+        let mut freed = 0;
+        for (consumer, used) in &usage {
+            let share = (excess as u128 * *used as u128).div_ceil(total as u128) as usize;
+            if share > 0 {
+                freed += consumer.shed(share);
+            }
+        }
+        // Whatever a consumer couldn't give up comes from the biggest ones that still can.
+        for (consumer, _) in &usage {
+            if freed >= excess {
+                break;
+            }
+            freed += consumer.shed(excess - freed);
+        }
+        tracing::debug!(
+            "memory budget exceeded by {} bytes, freed {} bytes",
+            excess,
+            freed
+        );
+    }
+}
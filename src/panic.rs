@@ -0,0 +1,58 @@
+use axum::{
+    body::Body,
+    http::{Response, StatusCode, header},
+};
+use std::any::Any;
+use std::backtrace::Backtrace;
+use std::sync::Arc;
+use std::sync::Once;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tower_http::catch_panic::CatchPanicLayer;
+
+static HOOK: Once = Once::new();
+
+/// Route panic reports through tracing, with a backtrace, instead of the default stderr hook.
+///
+/// The hook replaces the default one for the whole process, so every panic is reported exactly
+/// once whether or not [`layer`] turns it into a 500 afterwards.
+pub fn install_hook() {
+    HOOK.call_once(|| {
+        std::panic::set_hook(Box::new(|info| {
+            let location = info
+                .location()
+                .map(|l| format!("{}:{}", l.file(), l.line()))
+                .unwrap_or_else(|| "<unknown>".to_string());
+            tracing::error!(
+                location = %location,
+                "panic: {}\n{}",
+                payload_message(info.payload()),
+                Backtrace::force_capture()
+            );
+        }));
+    });
+}
+
+fn payload_message(payload: &(dyn Any + Send)) -> &str {
+    payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("<non-string panic payload>")
+}
+
+/// Layer converting a panicking request into a 500 and counting it in `panics`.
+pub fn layer(
+    panics: Arc<AtomicU64>,
+) -> CatchPanicLayer<impl Fn(Box<dyn Any + Send + 'static>) -> Response<Body> + Clone> {
+    CatchPanicLayer::custom(move |_err: Box<dyn Any + Send + 'static>| {
+        // The panic hook has already logged the message and backtrace.
+        panics.fetch_add(1, Ordering::Relaxed);
+        let mut resp = Response::new(Body::from("Internal Server Error"));
+        *resp.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+        resp.headers_mut().insert(
+            header::CONTENT_TYPE,
+            header::HeaderValue::from_static("text/plain; charset=utf-8"),
+        );
+        resp
+    })
+}
@@ -0,0 +1,331 @@
+use percent_encoding::percent_decode_str;
+use std::borrow::Cow;
+use url::Url;
+
+use crate::config::TrailingSlash;
+
+/// Rewrite `path` according to the trailing-slash policy, returning `None` when it is already
+/// in the desired form.
+///
+/// The root path is never touched. `Append` leaves paths whose last segment looks like a file
+/// (contains a `.`) alone; `Redirect` canonicalizes like `Strip`.
+pub fn normalize_trailing_slash(path: &str, policy: TrailingSlash) -> Option<String> {
+    if path == "/" {
+        return None;
+    }
+    match policy {
+        TrailingSlash::Preserve => None,
+        TrailingSlash::Strip | TrailingSlash::Redirect => {
+            let trimmed = path.trim_end_matches('/');
+            if trimmed.len() == path.len() {
+                None
+            } else if trimmed.is_empty() {
+                Some("/".to_string())
+            } else {
+                Some(trimmed.to_string())
+            }
+        }
+        TrailingSlash::Append => {
+            let last = path.rsplit('/').next().unwrap_or("");
+            if path.ends_with('/') || last.contains('.') {
+                None
+            } else {
+                Some(format!("{}/", path))
+            }
+        }
+    }
+}
+
+/// Whether `path` is `prefix` or lies below it, comparing whole segments (`/api` matches
+/// `/api/x` but not `/apix`).
+pub fn has_path_prefix(path: &str, prefix: &str) -> bool {
+    match path.strip_prefix(prefix) {
+        Some(rest) => rest.is_empty() || prefix.ends_with('/') || rest.starts_with('/'),
+        None => false,
+    }
+}
+
+/// The route in `routes` covering `path`, found by its `prefix`. `routes` must be sorted longest
+/// prefix first, as config validation leaves every per-path route list, so the first match is
+/// the most specific.
+pub fn longest_prefix<'a, T>(
+    routes: &'a [T],
+    path: &str,
+    prefix: impl Fn(&T) -> &str,
+) -> Option<&'a T> {
+    routes.iter().find(|r| has_path_prefix(path, prefix(r)))
+}
+
+/// Collapse runs of slashes and resolve `.`/`..` segments (`/api//users/./1` gives
+/// `/api/users/1`), for the optional `normalize_path` setting.
+///
+/// Returns `None` if `path` is not absolute or would climb above the root. A trailing slash is
+/// kept, and encoded slashes (`%2F`) are left alone.
+pub fn normalize_path(path: &str) -> Option<String> {
+    let path = path.replace('\\', "/");
+    let mut collapsed = String::with_capacity(path.len());
+    for c in path.chars() {
+        if !(c == '/' && collapsed.ends_with('/')) {
+            collapsed.push(c);
+        }
+    }
+    resolve_dot_segments(&collapsed)
+}
+
+/// The path that per-route rules (`auth_route`, `access_route`, ...) are matched against:
+/// `path` with unreserved escapes decoded, slashes collapsed and dot segments resolved, so
+/// `/./admin`, `/x/../admin` and `/%61dmin` all give `/admin`, as the backend will read them.
+///
+/// Returns `None` if `path` would climb above the root.
+pub fn route_path(path: &str) -> Option<String> {
+    normalize_path(&normalize_percent_encoding(path))
+}
+
+/// `path` with escapes of unreserved characters decoded (`%7E` gives `~`) and the remaining
+/// escapes uppercased (`%2f` gives `%2F`), per RFC 3986, 6.2.2. Spellings of the same path then
+/// compare equal, while escapes that change its meaning (`%2F`, `%3F`, `%25`, ...) stay.
+pub fn normalize_percent_encoding(path: &str) -> Cow<'_, str> {
+    if !path.contains('%') {
+        return Cow::Borrowed(path);
+    }
+    let bytes = path.as_bytes();
+    let mut out = String::with_capacity(path.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|h| std::str::from_utf8(h).ok());
+        match hex.and_then(|h| u8::from_str_radix(h, 16).ok()) {
+            Some(b) if bytes[i] == b'%' => {
+                if b.is_ascii_alphanumeric() || matches!(b, b'-' | b'.' | b'_' | b'~') {
+                    out.push(b as char);
+                } else {
+                    out.push_str(&format!("%{:02X}", b));
+                }
+                i += 3;
+            }
+            _ => {
+                let end = bytes[i + 1..]
+                    .iter()
+                    .position(|b| *b == b'%')
+                    .map_or(bytes.len(), |n| i + 1 + n);
+                out.push_str(&path[i..end]);
+                i = end;
+            }
+        }
+    }
+    Cow::Owned(out)
+}
+
+/// `path` percent-decoded for reading in logs. Control characters are shown escaped again, so
+/// a request can't forge log lines; invalid UTF-8 shows as U+FFFD.
+pub fn decode_for_log(path: &str) -> Cow<'_, str> {
+    let decoded = percent_decode_str(path).decode_utf8_lossy();
+    if !decoded.chars().any(char::is_control) {
+        return decoded;
+    }
+    let mut out = String::with_capacity(decoded.len());
+    for c in decoded.chars() {
+        if c.is_control() {
+            let mut buf = [0; 4];
+            for b in c.encode_utf8(&mut buf).bytes() {
+                out.push_str(&format!("%{:02X}", b));
+            }
+        } else {
+            out.push(c);
+        }
+    }
+    Cow::Owned(out)
+}
+
+/// Upstream URL for a request: `path`, with dot segments resolved, appended to the backend's
+/// own path (`http://internal/app/` + `/foo` gives `http://internal/app/foo`); `query` is
+/// passed through untouched.
+///
+/// Returns `None` if `path` is not absolute or a `..` segment would climb above it, so a
+/// request can never reach outside the backend's base path. Encoded slashes stay encoded.
+pub fn upstream_url(backend: &Url, path: &str, query: Option<&str>) -> Option<Url> {
+    let path = resolve_dot_segments(path)?;
+    let base = backend.path().trim_end_matches('/');
+    let mut url = backend.clone();
+    url.set_path(&format!("{}{}", base, path));
+    url.set_query(query);
+    Some(url)
+}
+
+// RFC 3986 dot-segment removal that refuses to go above the root. Backslashes count as
+// separators because the URL parser treats them that way for http(s).
+fn resolve_dot_segments(path: &str) -> Option<String> {
+    let path = path.replace('\\', "/");
+    let segments: Vec<&str> = path.strip_prefix('/')?.split('/').collect();
+    let last = segments.len() - 1;
+
+    let mut out: Vec<&str> = Vec::with_capacity(segments.len());
+    for (i, seg) in segments.iter().enumerate() {
+        match seg.to_ascii_lowercase().replace("%2e", ".").as_str() {
+            "." => {}
+            ".." => {
+                out.pop()?;
+            }
+            _ => {
+                out.push(seg);
+                continue;
+            }
+        }
+        // "/a/." and "/a/b/.." name a directory; keep the trailing slash.
+        if i == last {
+            out.push("");
+        }
+    }
+    Some(format!("/{}", out.join("/")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn route_path_sees_what_the_backend_receives() {
+        let backend = Url::parse("http://backend/").unwrap();
+        for (raw, resolved) in [
+            ("/./internal/x", "/internal/x"),
+            ("/foo/../ops/x", "/ops/x"),
+            ("/%69nternal/x", "/internal/x"),
+            ("/%2e/internal/x", "/internal/x"),
+            ("/a/%2E%2e/internal", "/internal"),
+            ("//internal/x", "/internal/x"),
+            ("/internal\\x", "/internal/x"),
+        ] {
+            let route = route_path(raw).unwrap();
+            assert_eq!(route, resolved, "{}", raw);
+            assert!(has_path_prefix(&route, "/internal") || has_path_prefix(&route, "/ops"));
+            // The backend would decode the escapes of the upstream path the same way.
+            let upstream = upstream_url(&backend, raw, None).unwrap();
+            let upstream = normalize_path(&normalize_percent_encoding(upstream.path())).unwrap();
+            assert_eq!(upstream, route, "{}", raw);
+        }
+    }
+
+    #[test]
+    fn longest_prefix_takes_the_first_whole_segment_match() {
+        let routes = ["/api/v1/", "/api/v1", "/api", "/"];
+        let find = |path| longest_prefix(&routes, path, |r| r).copied();
+        assert_eq!(find("/api/v1/users"), Some("/api/v1/"));
+        assert_eq!(find("/api/v1"), Some("/api/v1"));
+        assert_eq!(find("/api/v10"), Some("/api"));
+        assert_eq!(find("/apix"), Some("/"));
+
+        let routes = ["/api"];
+        assert_eq!(longest_prefix(&routes, "/other", |r| r), None);
+    }
+
+    #[test]
+    fn route_path_keeps_meaningful_escapes() {
+        assert_eq!(route_path("/api%2Fx").unwrap(), "/api%2Fx");
+        assert_eq!(route_path("/a%3fb").unwrap(), "/a%3Fb");
+    }
+
+    #[test]
+    fn route_path_refuses_to_climb_above_root() {
+        assert_eq!(route_path("/../internal"), None);
+        assert_eq!(route_path("/a/%2e%2e/%2E%2E/internal"), None);
+    }
+
+    #[test]
+    fn dot_segments_resolve_within_the_path() {
+        for (raw, resolved) in [
+            ("/a/./b", "/a/b"),
+            ("/a/b/..", "/a/"),
+            ("/a/../b", "/b"),
+            ("/a/%2e/b", "/a/b"),
+            ("/a/b/%2E%2e/c", "/a/c"),
+            ("/a/.hidden/..x", "/a/.hidden/..x"),
+            ("/a/.", "/a/"),
+        ] {
+            assert_eq!(
+                resolve_dot_segments(raw).as_deref(),
+                Some(resolved),
+                "{}",
+                raw
+            );
+        }
+    }
+
+    #[test]
+    fn dot_segments_never_climb_above_root() {
+        for raw in [
+            "/..",
+            "/../etc/passwd",
+            "/a/../../b",
+            "/%2e%2e/x",
+            "/a/%2E%2e/%2e./x",
+        ] {
+            assert_eq!(resolve_dot_segments(raw), None, "{}", raw);
+        }
+        assert_eq!(resolve_dot_segments("relative"), None);
+    }
+
+    #[test]
+    fn upstream_url_keeps_the_backend_base_path() {
+        let with_base = Url::parse("http://internal/app/").unwrap();
+        let without_slash = Url::parse("http://internal/app").unwrap();
+        let bare = Url::parse("http://internal").unwrap();
+        for backend in [&with_base, &without_slash] {
+            let url = upstream_url(backend, "/foo", Some("a=1&b=%2F")).unwrap();
+            assert_eq!(url.as_str(), "http://internal/app/foo?a=1&b=%2F");
+        }
+        let url = upstream_url(&bare, "/foo/", None).unwrap();
+        assert_eq!(url.as_str(), "http://internal/foo/");
+    }
+
+    #[test]
+    fn upstream_url_resists_path_tricks() {
+        let backend = Url::parse("http://internal/app/").unwrap();
+        let url = |path| upstream_url(&backend, path, None).map(|u| u.to_string());
+        // Encoded slashes stay encoded rather than becoming separators.
+        assert_eq!(url("/a%2Fb").as_deref(), Some("http://internal/app/a%2Fb"));
+        assert_eq!(url("/x/../y").as_deref(), Some("http://internal/app/y"));
+        // An authority-looking path stays a path on the backend.
+        assert_eq!(
+            url("//evil.com/x").as_deref(),
+            Some("http://internal/app//evil.com/x")
+        );
+        assert_eq!(url("/../admin"), None);
+        assert_eq!(url("/%2e%2e/admin"), None);
+        assert_eq!(url("/..\\admin"), None);
+    }
+
+    #[test]
+    fn normalize_path_collapses_slashes_and_dot_segments() {
+        for (raw, normalized) in [
+            ("/api//users///1", "/api/users/1"),
+            ("//api/./users/../v2/", "/api/v2/"),
+            ("/api/%2F/x", "/api/%2F/x"),
+            ("/", "/"),
+        ] {
+            assert_eq!(normalize_path(raw).as_deref(), Some(normalized), "{}", raw);
+        }
+        assert_eq!(normalize_path("/a//../../b"), None);
+    }
+
+    #[test]
+    fn percent_encoding_is_normalized() {
+        for (raw, normalized) in [
+            ("/plain", "/plain"),
+            ("/%7Euser/%61%62c", "/~user/abc"),
+            ("/a%2fb%3f", "/a%2Fb%3F"),
+            ("/caf%c3%a9", "/caf%C3%A9"),
+            ("/100%25", "/100%25"),
+            ("/bad%zz%4", "/bad%zz%4"),
+        ] {
+            assert_eq!(normalize_percent_encoding(raw), normalized, "{}", raw);
+        }
+    }
+
+    #[test]
+    fn logged_paths_are_decoded_but_not_forged() {
+        assert_eq!(decode_for_log("/a%20b/caf%C3%A9"), "/a b/café");
+        assert_eq!(decode_for_log("/x%0D%0Afake"), "/x%0D%0Afake");
+        assert_eq!(decode_for_log("/%FF"), "/\u{FFFD}");
+    }
+}
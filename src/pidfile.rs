@@ -0,0 +1,155 @@
+//! PID file handling and optional daemonization for non-systemd init setups.
+
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// An exclusively created PID file, removed again when dropped.
+#[derive(Debug)]
+pub struct PidFile {
+    path: PathBuf,
+}
+
+/// Why a PID file could not be created.
+#[derive(Debug)]
+pub enum PidFileError {
+    /// Another live process holds the file.
+    Running(u32),
+    Io(io::Error),
+}
+
+impl std::fmt::Display for PidFileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PidFileError::Running(pid) => write!(f, "another instance is running (pid {})", pid),
+            PidFileError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for PidFileError {}
+
+impl From<io::Error> for PidFileError {
+    fn from(e: io::Error) -> Self {
+        PidFileError::Io(e)
+    }
+}
+
+impl PidFile {
+    /// Fail if `path` names a process that is still alive. A stale file is left in place.
+    pub fn check(path: &Path) -> Result<(), PidFileError> {
+        match read_pid(path)? {
+            Some(pid) if is_alive(pid) => Err(PidFileError::Running(pid)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Create `path` holding the current PID, replacing a stale file left by a dead process.
+    pub fn create(path: &Path) -> Result<Self, PidFileError> {
+        if let Some(pid) = read_pid(path)? {
+            if is_alive(pid) {
+                return Err(PidFileError::Running(pid));
+            }
+            tracing::warn!(
+                "removing stale pid file {} (pid {} is not running)",
+                path.display(),
+                pid
+            );
+            fs::remove_file(path)?;
+        }
+
+        let mut file = OpenOptions::new().write(true).create_new(true).open(path)?;
+        writeln!(file, "{}", std::process::id())?;
+        Ok(Self {
+            path: path.to_path_buf(),
+        })
+    }
+
+    /// Overwrite `path` with the current PID, even while the previous owner is still running.
+    ///
+    /// Used by the successor in a binary upgrade, which replaces the instance holding the file.
+    pub fn take_over(path: &Path) -> Result<Self, PidFileError> {
+        fs::write(path, format!("{}\n", std::process::id()))?;
+        Ok(Self {
+            path: path.to_path_buf(),
+        })
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        // After a binary upgrade the file belongs to the successor; leave it alone.
+        if matches!(read_pid(&self.path), Ok(Some(pid)) if pid != std::process::id()) {
+            return;
+        }
+        if let Err(e) = fs::remove_file(&self.path) {
+            tracing::warn!("failed to remove pid file {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+// `Ok(None)` when the file doesn't exist; unparsable contents count as stale.
+fn read_pid(path: &Path) -> io::Result<Option<u32>> {
+    match fs::read_to_string(path) {
+        Ok(s) => Ok(Some(s.trim().parse().unwrap_or(0))),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(unix)]
+fn is_alive(pid: u32) -> bool {
+    if pid == 0 || pid > i32::MAX as u32 {
+        return false;
+    }
+    // Signal 0 only checks for existence; EPERM means it exists under another user.
+    let rc = unsafe { libc::kill(pid as libc::pid_t, 0) };
+    rc == 0 || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+#[cfg(not(unix))]
+fn is_alive(_pid: u32) -> bool {
+    false
+}
+
+/// Detach from the terminal with the classic double fork, then point stdin at `/dev/null` and
+/// stdout/stderr at `log_file` (or `/dev/null`).
+///
+/// Must be called before any threads are started, i.e. before the tokio runtime is built.
+#[cfg(unix)]
+pub fn daemonize(log_file: Option<&Path>) -> io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    // Open the log sink first so errors still reach the terminal.
+    let out = match log_file {
+        Some(p) => OpenOptions::new().create(true).append(true).open(p)?,
+        None => OpenOptions::new().write(true).open("/dev/null")?,
+    };
+    let null = OpenOptions::new().read(true).open("/dev/null")?;
+
+    unsafe {
+        match libc::fork() {
+            -1 => return Err(io::Error::last_os_error()),
+            0 => {}
+            _ => libc::_exit(0),
+        }
+        if libc::setsid() == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        // Second fork: the daemon is no longer a session leader and can't reacquire a tty.
+        match libc::fork() {
+            -1 => return Err(io::Error::last_os_error()),
+            0 => {}
+            _ => libc::_exit(0),
+        }
+        // The working directory is kept so relative static_dir/cert paths still resolve.
+        libc::umask(0o027);
+        if libc::dup2(null.as_raw_fd(), 0) == -1
+            || libc::dup2(out.as_raw_fd(), 1) == -1
+            || libc::dup2(out.as_raw_fd(), 2) == -1
+        {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
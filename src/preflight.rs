@@ -0,0 +1,126 @@
+//! Optional startup check that every configured backend accepts TCP connections.
+
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio::time::timeout;
+use url::{Host, Url};
+
+use crate::config::{BackendCheckMode, Config};
+
+/// Why a backend failed the startup check.
+#[derive(Debug)]
+pub enum BackendCheckError {
+    /// The host name did not resolve.
+    Dns(String),
+    /// Every resolved address refused the connection.
+    Refused(SocketAddr),
+    Timeout,
+    Connect(io::Error),
+    /// The URL has no host or port to connect to.
+    NoAddress,
+}
+
+impl fmt::Display for BackendCheckError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BackendCheckError::Dns(e) => write!(f, "DNS resolution failed: {}", e),
+            BackendCheckError::Refused(addr) => write!(f, "connection refused by {}", addr),
+            BackendCheckError::Timeout => write!(f, "connect timed out"),
+            BackendCheckError::Connect(e) => write!(f, "connect failed: {}", e),
+            BackendCheckError::NoAddress => write!(f, "URL has no host or port"),
+        }
+    }
+}
+
+/// Run the configured check over the backends of every server, concurrently.
+///
+/// Returns an error listing the unreachable backends only in `fail` mode.
+pub async fn check_backends(config: &Config) -> Result<(), String> {
+    if config.startup_backend_check == BackendCheckMode::Off {
+        return Ok(());
+    }
+
+    let mut backends: Vec<&Url> = config
+        .servers
+        .iter()
+        .flat_map(|s| {
+            s.backends
+                .iter()
+                .chain(s.match_rules.iter().flat_map(|r| r.backends.iter()))
+        })
+        .collect();
+    backends.sort();
+    backends.dedup();
+    // Only TCP backends are probed.
+    backends.retain(|b| {
+        let unix = b.scheme() == "fastcgi+unix";
+        if unix {
+            tracing::info!("startup check: skipping unix socket backend {}", b);
+        }
+        !unix
+    });
+
+    let to = config.startup_backend_check_timeout;
+    let results = futures::future::join_all(
+        backends
+            .iter()
+            .map(|b| async move { (*b, probe(b, to).await) }),
+    )
+    .await;
+
+    let mut unreachable = Vec::new();
+    for (backend, result) in results {
+        match result {
+            Ok(addr) => tracing::info!("startup check: backend {} reachable at {}", backend, addr),
+            Err(e) => {
+                tracing::warn!("startup check: backend {} unreachable: {}", backend, e);
+                unreachable.push(backend.as_str());
+            }
+        }
+    }
+
+    if unreachable.is_empty() || config.startup_backend_check == BackendCheckMode::Warn {
+        return Ok(());
+    }
+    Err(format!(
+        "startup backend check failed for {} backend(s): {}",
+        unreachable.len(),
+        unreachable.join(", ")
+    ))
+}
+
+// Connect to the first address of `backend` that accepts, within `to` overall.
+async fn probe(backend: &Url, to: Duration) -> Result<SocketAddr, BackendCheckError> {
+    let port = backend
+        .port_or_known_default()
+        .ok_or(BackendCheckError::NoAddress)?;
+    let addrs: Vec<SocketAddr> = match backend.host().ok_or(BackendCheckError::NoAddress)? {
+        Host::Ipv4(ip) => vec![SocketAddr::from((ip, port))],
+        Host::Ipv6(ip) => vec![SocketAddr::from((ip, port))],
+        Host::Domain(name) => match timeout(to, tokio::net::lookup_host((name, port))).await {
+            Ok(Ok(addrs)) => addrs.collect(),
+            Ok(Err(e)) => return Err(BackendCheckError::Dns(e.to_string())),
+            Err(_) => return Err(BackendCheckError::Dns("lookup timed out".to_string())),
+        },
+    };
+
+    let connect = async {
+        let mut last = BackendCheckError::Dns("no addresses".to_string());
+        for addr in addrs {
+            match TcpStream::connect(addr).await {
+                Ok(_) => return Ok(addr),
+                Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => {
+                    last = BackendCheckError::Refused(addr);
+                }
+                Err(e) => last = BackendCheckError::Connect(e),
+            }
+        }
+        Err(last)
+    };
+    timeout(to, connect)
+        .await
+        .unwrap_or(Err(BackendCheckError::Timeout))
+}
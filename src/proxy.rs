@@ -3,15 +3,18 @@ use axum::{
     extract::State,
     http::{
         Method, Request, Response, StatusCode,
-        header::{HeaderName, HeaderValue},
+        header::{self, HeaderName, HeaderValue},
     },
+    response::IntoResponse,
 };
-use futures::TryStreamExt;
+use futures::{TryFutureExt, TryStreamExt, future::Either};
 use reqwest::{Body as ReqwestBody, Client};
+use ring::rand::{SecureRandom, SystemRandom};
+use std::borrow::Cow;
 use std::io;
 use std::sync::{
-    Arc,
-    atomic::{AtomicUsize, Ordering},
+    Arc, Mutex, OnceLock,
+    atomic::{AtomicU64, AtomicUsize, Ordering},
 };
 use std::time::Duration;
 use tokio::time::timeout;
@@ -19,9 +22,61 @@ use url::Url;
 
 use bytes::Bytes;
 use dashmap::DashMap;
-use std::net::IpAddr;
+use ipnet::IpNet;
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
 use std::time::Instant;
 
+use crate::access::{self, check_access, check_country};
+use crate::auth::{AUTHENTICATED_USER_HEADER, AuthFailures, AuthStore, Authenticated, check_auth};
+use crate::backend_limit::{BackendLimiter, BackendPermit};
+use crate::backend_pool::BackendPool;
+use crate::backend_queue::BackendQueue;
+use crate::backend_stats::BackendStats;
+use crate::ban::AutoBan;
+use crate::block::BlockRules;
+use crate::cache_warm::CacheWarmer;
+use crate::config::{
+    AccessRoute, AccessRule, CacheRoute, ContentTypeRoute, CookieRewrite, EarlyDataPolicy,
+    HtmlRewriteRoute, MissingIpPolicy, OriginRoute, OriginRule, PathDecoding, ProxyProtocolVersion,
+    QueryRewrite, QueryRoute, Secret, TrailingSlash, is_fastcgi,
+};
+use crate::conn_limit::ConnectionTracker;
+use crate::content_type::check_content_type;
+use crate::cookie::rewrite_set_cookie;
+use crate::deadline;
+use crate::decompress;
+use crate::drain::Drain;
+use crate::early_data::{self, EARLY_DATA_HEADER};
+use crate::error_page::{ErrorContext, ErrorPages};
+use crate::experiment::{Bucket, Experiment};
+use crate::fastcgi::{self, FastCgi, RequestOrigin};
+use crate::fingerprint::{JA3_HEADER, JA4_HEADER, TlsFingerprint};
+use crate::framing;
+use crate::geoip::{GEO_ASN_HEADER, GEO_COUNTRY_HEADER, GeoInfo, GeoIp};
+use crate::head_limit::HeadRejections;
+use crate::html_rewrite::Rewrite;
+use crate::idempotency::IdempotencyStore;
+use crate::listener::BindFailure;
+use crate::maintenance::{InMaintenance, Maintenance};
+use crate::match_rules::{self, MatchRoute};
+use crate::memory::{MemoryBudget, MemoryConsumer};
+use crate::origin::check_origin;
+use crate::path::{
+    decode_for_log, longest_prefix, normalize_path, normalize_percent_encoding,
+    normalize_trailing_slash, route_path, upstream_url,
+};
+use crate::proxy_protocol::{self, BoxError};
+use crate::query::rewrite_query;
+use crate::rate_limit::{RateLimiter, check_rate_limit};
+use crate::signing::{RequestSigner, SIGNATURE_HEADER, SIGNATURE_TIMESTAMP_HEADER, unix_now};
+use crate::supervisor::Supervisor;
+use crate::target;
+use crate::tls_info::{TLS_CIPHER_HEADER, TLS_VERSION_HEADER, TlsInfo};
+use crate::transfers::{Transfers, hold_until_sent};
+use crate::upload;
+use crate::upstream::{self, UpstreamErrorCounters, UpstreamErrorKind};
+
 /// Cached response entry (stored in the in-memory cache)
 #[derive(Clone)]
 pub struct CacheEntry {
@@ -32,26 +87,217 @@ pub struct CacheEntry {
     pub size: usize,
 }
 
+impl CacheEntry {
+    /// Look up a stored response header by name (case-insensitive), if it is valid UTF-8.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .and_then(|(_, v)| std::str::from_utf8(v).ok())
+    }
+}
+
+/// A server's response cache as seen by the process-wide memory budget.
+pub struct ResponseCacheUsage {
+    pub cache: Arc<DashMap<String, CacheEntry>>,
+    pub size: Arc<AtomicUsize>,
+}
+
+impl MemoryConsumer for ResponseCacheUsage {
+    fn used_bytes(&self) -> usize {
+        self.size.load(Ordering::Relaxed)
+    }
+
+    fn shed(&self, bytes: usize) -> usize {
+        evict_cache_entries(&self.cache, &self.size, bytes)
+    }
+}
+
+/// Remove entries closest to expiry until at least `bytes` have been freed; returns the bytes freed.
+fn evict_cache_entries(
+    cache: &DashMap<String, CacheEntry>,
+    size: &AtomicUsize,
+    bytes: usize,
+) -> usize {
+    let mut items: Vec<(String, Instant)> = cache
+        .iter()
+        .map(|r| (r.key().clone(), r.value().expires_at))
+        .collect();
+    items.sort_by_key(|t| t.1);
+    let mut freed = 0;
+    for (k, _exp) in items {
+        if freed >= bytes {
+            break;
+        }
+        if let Some((_, removed)) = cache.remove(&k) {
+            freed += removed.size;
+            size.fetch_sub(removed.size, Ordering::Relaxed);
+        }
+    }
+    freed
+}
+
 /// Application shared state.
 #[derive(Clone)]
 pub struct AppState {
     pub client: Client,
-    pub backends: Vec<Url>,
+    // Default backends; /admin/backends can add and remove them at runtime
+    pub backends: Arc<BackendPool>,
     pub counter: Arc<AtomicUsize>,
+    // Per-backend in-flight caps from max_connections_per_backend (None = unlimited)
+    pub backend_limiter: Option<Arc<BackendLimiter>>,
+    // Requests waiting for a backend slot when all are at the cap (None = refused at once)
+    pub backend_queue: Option<Arc<BackendQueue>>,
     pub backend_timeout: Duration,
+    // Total request deadline incl. the response body (None = unlimited; routes may override)
+    pub request_timeout: Option<Duration>,
+    // Warn about proxied requests slower than this (None = off)
+    pub slow_request_threshold: Option<Duration>,
+    // Handling of requests sent as TLS early data (None = 0-RTT disabled)
+    pub early_data: Option<EarlyDataPolicy>,
+    // Largest piece of a request body handed upstream at once (None = as read from the client)
+    pub max_upload_buffer_bytes: Option<usize>,
+    // Inflate gzip/deflate request bodies up to this size before forwarding (None = off)
+    pub max_decompressed_body_bytes: Option<usize>,
+    // Longest path and query accepted; longer request targets get 414
+    pub max_uri_length: usize,
+    // Upstream failures by category
+    pub upstream_errors: Arc<UpstreamErrorCounters>,
+    // Requests, errors and in-flight counts per backend, for /admin/backends
+    pub backend_stats: Arc<BackendStats>,
+
+    // In-process rate limiters; a request must pass every rule.
+    pub rate_limiters: Arc<Vec<RateLimiter>>,
+    pub rate_limit_on_missing_ip: MissingIpPolicy,
+    // Maximum distinct buckets per rate-limit rule (None = unbounded)
+    pub rate_limit_max_entries: Option<usize>,
+    // Clients never rate limited (resolved through trusted_proxies)
+    pub rate_limit_exempt_ips: Arc<Vec<IpNet>>,
+
+    // Domain/Path/Secure/SameSite rewrite for upstream Set-Cookie headers
+    pub cookie_rewrite: Option<Arc<CookieRewrite>>,
+    // Query-string rewrites applied to the upstream URL, in order
+    pub query_rewrites: Arc<Vec<QueryRewrite>>,
+    // Per-path-prefix query rewrites, longest prefix first
+    pub query_routes: Arc<Vec<QueryRoute>>,
+    // Trailing-slash normalization of the upstream path
+    pub trailing_slash: TrailingSlash,
+    // Collapse `//` and resolve dot segments in the request path before routing
+    pub normalize_path: bool,
+
+    // Header/cookie match rules routing to alternate backend groups (first match wins)
+    pub match_routes: Arc<Vec<MatchRoute>>,
+
+    // Strict mode: only these request headers are forwarded (None = all but hop-by-hop/sensitive)
+    pub forward_headers_allowlist: Option<Arc<Vec<HeaderName>>>,
+    // Send the client's TLS fingerprints upstream in X-TLS-JA3/X-TLS-JA4
+    pub forward_tls_fingerprint: bool,
+    // Send the TLS version and cipher suite upstream in X-TLS-Version/X-TLS-Cipher
+    pub forward_tls_info: bool,
+    // Backends that get a PROXY protocol header on a fresh connection per request
+    pub send_proxy_protocol: Arc<HashMap<Url, ProxyProtocolVersion>>,
+    // This server's listen address, the destination in outgoing PROXY headers
+    pub listen: SocketAddr,
+    // Whether this server terminates TLS, for HTTPS=on to FastCGI backends
+    pub tls: bool,
+    // Accept `GET http://host/path` targets whose authority matches Host (default: 400)
+    pub allow_absolute_form: bool,
 
-    // Per-IP in-memory token buckets (tokens, last_seen)
-    // This is used as an in-process rate limiter.
-    pub rate_limit_map: Arc<DashMap<IpAddr, (f64, Instant)>>,
-    pub rate_limit_per_minute: Option<f64>,
-    pub rate_limit_burst: Option<f64>,
+    // Backend pinning via X-Serava-Backend (only honored from trusted peers)
+    pub allow_backend_pinning: bool,
+    pub backend_pinning_trusted_ips: Arc<Vec<IpNet>>,
 
     // Response cache using DashMap for simple concurrent in-memory caching
     pub response_cache: Option<Arc<DashMap<String, CacheEntry>>>,
     pub cache_ttl_secs: Option<u64>,
     pub cache_max_size_bytes: Option<usize>,
+    // Largest single response that may be cached (derived from cache_max_entry_fraction)
+    pub cache_max_entry_bytes: Option<usize>,
+    // Spread of each cached entry's TTL, as a fraction either way (None = exact TTLs)
+    pub cache_ttl_jitter: Option<f64>,
+    // Key cached responses by Host as well as method, path and query
+    pub cache_key_include_host: bool,
+    // Path form in cache keys and the slow request log (never the upstream URL)
+    pub path_decoding: PathDecoding,
+    // Request headers whose presence skips the cache, lookup and store
+    pub cache_bypass_headers: Arc<Vec<HeaderName>>,
+    // Per-path-prefix cache overrides, longest prefix first
+    pub cache_routes: Arc<Vec<CacheRoute>>,
+
+    // Server-wide client IP allow/deny lists
+    pub access: Arc<AccessRule>,
+    // Per-path-prefix replacements for `access`, longest prefix first
+    pub access_routes: Arc<Vec<AccessRoute>>,
+    // Peers whose X-Forwarded-For is believed for access checks
+    pub trusted_proxies: Arc<Vec<IpNet>>,
+    // Basic/Bearer authentication per path prefix, longest prefix first
+    pub auth_routes: Arc<Vec<Arc<AuthStore>>>,
+    // Failed authentication attempts per client IP (shared by all servers)
+    pub auth_failures: Arc<AuthFailures>,
+    // Origin/Referer check for state-changing requests (None = off)
+    pub origin_check: Option<Arc<OriginRule>>,
+    // Per-path-prefix replacements for `origin_check`, longest prefix first
+    pub origin_routes: Arc<Vec<OriginRoute>>,
+    // Scheme this server is reached on, for same-origin checks
+    pub origin_scheme: &'static str,
+    // Requests refused by the origin check (shared by all servers)
+    pub origin_rejections: Arc<AtomicU64>,
+    // Request Content-Type allowlists per path prefix, longest prefix first
+    pub content_type_routes: Arc<Vec<ContentTypeRoute>>,
+    // HTML response rewriting per path prefix, longest prefix first
+    pub html_rewrite_routes: Arc<Vec<Arc<HtmlRewriteRoute>>>,
+    // Progress of the latest /admin/cache/warm job
+    pub cache_warmer: Arc<CacheWarmer>,
     // Current approximate cache size (sum of stored body sizes). Used for eviction.
     pub cache_current_size: Arc<AtomicUsize>,
+    // Global budget over every cache in the process (shared by all servers)
+    pub memory: Arc<MemoryBudget>,
+    // Emit X-Serava-Cache / X-Cache-TTL diagnostic response headers
+    pub debug_headers: bool,
+    // Templated pages for errors raised by the proxy, by status
+    pub error_pages: Arc<ErrorPages>,
+
+    // Country/ASN lookup for X-Geo-* request headers (None = disabled)
+    pub geoip: Option<Arc<GeoIp>>,
+    // Countries refused with 403 (uppercase ISO codes)
+    pub deny_countries: Arc<Vec<String>>,
+
+    // Open/peak client connections and the max_connections cap
+    pub connections: Arc<ConnectionTracker>,
+    // Connections closed for an oversized or slow first request head
+    pub head_rejections: Arc<HeadRejections>,
+    // block_rule patterns and their match counts
+    pub block_rules: Arc<BlockRules>,
+    // Strikes and active bans from [auto_ban] (None = disabled; shared by all servers)
+    pub auto_ban: Option<Arc<AutoBan>>,
+    // Requests that panicked and were answered with a 500
+    pub panics: Arc<AtomicU64>,
+    // Requests refused for ambiguous body framing (possible request smuggling)
+    pub framing_rejections: Arc<AtomicU64>,
+
+    // Stored responses replayed for repeated Idempotency-Key requests (None = disabled)
+    pub idempotency: Option<Arc<IdempotencyStore>>,
+    // HMAC signer adding X-Signature headers to forwarded requests (None = disabled)
+    pub request_signer: Option<Arc<RequestSigner>>,
+    // Servers that failed to bind under allow_partial_startup (shared by all servers)
+    pub bind_failures: Arc<Mutex<Vec<BindFailure>>>,
+    // Operator drain state and in-flight request count (shared by all servers)
+    pub drain: Arc<Drain>,
+    // Whether TRACE is forwarded rather than refused with 405 (global)
+    pub allow_trace: bool,
+    // Script mapping and idle connections for fastcgi:// backends (None = none configured)
+    pub fastcgi: Option<Arc<FastCgi>>,
+    // Scheduled maintenance windows answering 503 (None = none configured)
+    pub maintenance: Option<Arc<Maintenance>>,
+    // A/B bucket assignment (None = no experiment configured)
+    pub experiment: Option<Arc<Experiment>>,
+    // Restart counts and last failures of every accept loop (shared by all servers)
+    pub supervisor: Arc<Supervisor>,
+    // Response bodies still streaming to clients, watched by graceful shutdown (shared by all servers)
+    pub transfers: Arc<Transfers>,
+
+    // Bearer token for the admin endpoints (None = admin routes disabled)
+    pub admin_token: Option<Secret<String>>,
 }
 
 // Use a static array for fast checking without allocating strings
@@ -67,15 +313,40 @@ const HOP_BY_HOP_HEADERS: &[&str] = &[
     "host",
 ];
 
+// Internal request header used to pin a request to a backend; never forwarded upstream.
+const BACKEND_PIN_HEADER: &str = "x-serava-backend";
+
+// Diagnostic response headers emitted when `debug_headers` is enabled.
+const CACHE_STATUS_HEADER: &str = "x-serava-cache";
+const CACHE_TTL_HEADER: &str = "x-cache-ttl";
+
 fn is_hop_by_hop(name: &str) -> bool {
     HOP_BY_HOP_HEADERS
         .iter()
         .any(|h| h.eq_ignore_ascii_case(name))
 }
 
+// Request headers the proxy sets itself (after removing any client-sent copy), which strict
+// mode forwards whatever the allowlist says. Every feature adding a request header for the
+// backend must list it here.
+static PROXY_SET_HEADERS: [HeaderName; 8] = [
+    HeaderName::from_static(GEO_COUNTRY_HEADER),
+    HeaderName::from_static(GEO_ASN_HEADER),
+    HeaderName::from_static(AUTHENTICATED_USER_HEADER),
+    EARLY_DATA_HEADER,
+    JA3_HEADER,
+    JA4_HEADER,
+    TLS_VERSION_HEADER,
+    TLS_CIPHER_HEADER,
+];
+
+// `bucket_header` is the experiment's bucket header, which is configurable and so can't be
+// in PROXY_SET_HEADERS.
 fn sanitize_and_forward_headers(
     req_builder: reqwest::RequestBuilder,
     headers: &axum::http::HeaderMap,
+    allowlist: Option<&[HeaderName]>,
+    bucket_header: Option<&HeaderName>,
 ) -> reqwest::RequestBuilder {
     let mut rb = req_builder;
 
@@ -88,6 +359,23 @@ fn sanitize_and_forward_headers(
             continue;
         }
 
+        // Strict mode: only allowlisted headers (plus the ones we add ourselves) go upstream
+        if let Some(allowed) = allowlist
+            && !allowed.contains(name)
+            && !PROXY_SET_HEADERS.contains(name)
+            && bucket_header != Some(name)
+        {
+            tracing::debug!(
+                "dropping header not in forward_headers_allowlist: {}",
+                name_str
+            );
+            continue;
+        }
+
+        if name_str.eq_ignore_ascii_case(BACKEND_PIN_HEADER) {
+            continue;
+        }
+
         // Validate header name length
         if name_str.is_empty() || name_str.len() > 256 {
             tracing::warn!("dropping header with invalid name length: {}", name_str);
@@ -155,84 +443,283 @@ fn sanitize_and_forward_headers(
     rb
 }
 
-fn check_rate_limit(state: &AppState, req: &Request<Body>) -> Result<(), StatusCode> {
-    // Check if rate limiting is disabled.
-    if state.rate_limit_per_minute.is_none() {
-        return Ok(());
+/// Address of the directly connected peer (ignores X-Forwarded-For, so it can't be spoofed).
+pub fn peer_ip(req: &Request<Body>) -> Option<IpAddr> {
+    req.extensions()
+        .get::<axum::extract::connect_info::ConnectInfo<std::net::SocketAddr>>()
+        .map(|ci| ci.0.ip())
+        .or_else(|| {
+            req.extensions()
+                .get::<std::net::SocketAddr>()
+                .map(|sock| sock.ip())
+        })
+}
+
+/// Resolve an `X-Serava-Backend` hint (backend index or URL) if pinning is allowed for this peer.
+fn pinned_backend<'a>(
+    state: &AppState,
+    backends: &'a [Url],
+    req: &Request<Body>,
+) -> Option<&'a Url> {
+    if !state.allow_backend_pinning {
+        return None;
     }
+    let hint = req.headers().get(BACKEND_PIN_HEADER)?.to_str().ok()?.trim();
 
-    let mut client_ip_opt: Option<IpAddr> = None;
+    let trusted = peer_ip(req).is_some_and(|ip| {
+        state
+            .backend_pinning_trusted_ips
+            .iter()
+            .any(|net| net.contains(&ip))
+    });
+    if !trusted {
+        tracing::warn!("ignoring backend pinning hint from untrusted client");
+        return None;
+    }
 
-    // 1) X-Forwarded-For header (take the first IP)
-    if let Some(xff) = req.headers().get("x-forwarded-for") {
-        if let Ok(s) = std::str::from_utf8(xff.as_bytes()) {
-            if let Some(first) = s.split(',').next() {
-                if let Ok(ip) = first.trim().parse::<IpAddr>() {
-                    client_ip_opt = Some(ip);
-                }
-            }
-        }
+    let pinned = match hint.parse::<usize>() {
+        Ok(idx) => backends.get(idx),
+        Err(_) => Url::parse(hint)
+            .ok()
+            .and_then(|u| backends.iter().find(|b| **b == u)),
+    };
+    match pinned {
+        Some(b) => tracing::debug!("request pinned to backend {}", b),
+        None => tracing::warn!("ignoring unknown backend pinning hint '{}'", hint),
     }
+    pinned
+}
 
-    // 2) axum ConnectInfo (if present)
-    if client_ip_opt.is_none() {
-        if let Some(ci) = req
-            .extensions()
-            .get::<axum::extract::connect_info::ConnectInfo<std::net::SocketAddr>>()
-        {
-            client_ip_opt = Some(ci.0.ip());
-        }
+/// Reserve a slot on `backend`. `Some(None)` means no per-backend cap is configured.
+fn acquire_backend(state: &AppState, backend: &Url) -> Option<Option<BackendPermit>> {
+    match &state.backend_limiter {
+        Some(limiter) => limiter.try_acquire(backend).map(Some),
+        None => Some(None),
     }
+}
 
-    // 3) fallback to raw SocketAddr in extensions
-    if client_ip_opt.is_none() {
-        if let Some(sock) = req.extensions().get::<std::net::SocketAddr>() {
-            client_ip_opt = Some(sock.ip());
+/// The cache key of a request: its method, the lowercased `host` when given, and its normalized
+/// origin-form path and query, e.g. `GET /a?b=1` or `GET example.com/a?b=1`. Responses routed
+/// by a match rule get the rule's index appended, so they never mix with the default backends'.
+///
+/// Methods are case-sensitive (RFC 9110): `get` is an extension method, not GET, and keeps
+/// its own key.
+fn cache_key(
+    method: &Method,
+    host: Option<&str>,
+    origin: &str,
+    route: Option<usize>,
+    bucket: Option<&str>,
+) -> String {
+    let mut key = format!("{} ", method.as_str());
+    if let Some(host) = host {
+        key.push_str(&host.to_ascii_lowercase());
+    }
+    key.push_str(origin);
+    if let Some(idx) = route {
+        key.push_str(&format!(" match={}", idx));
+    }
+    if let Some(bucket) = bucket {
+        key.push_str(&format!(" bucket={}", bucket));
+    }
+    key
+}
+
+fn backend_full() -> StatusCode {
+    tracing::warn!("all candidate backends are at max_connections_per_backend");
+    StatusCode::SERVICE_UNAVAILABLE
+}
+
+pub async fn proxy_handler(
+    State(state): State<AppState>,
+    req: Request<Body>,
+) -> Result<Response<Body>, StatusCode> {
+    if state.error_pages.is_empty() {
+        return timed(state, req).await;
+    }
+    let pages = state.error_pages.clone();
+    let context = ErrorContext::of(&req);
+    match timed(state, req).await {
+        Err(status) => pages.render(status, &context).ok_or(status),
+        Ok(resp)
+            if resp
+                .extensions()
+                .get::<upstream::MalformedResponse>()
+                .is_some() =>
+        {
+            Ok(pages.render_malformed_response(&context).unwrap_or(resp))
+        }
+        Ok(resp) if resp.extensions().get::<InMaintenance>().is_some() => {
+            Ok(match pages.render_maintenance(&context) {
+                Some(mut page) => {
+                    if let Some(retry_after) = resp.headers().get(header::RETRY_AFTER) {
+                        page.headers_mut()
+                            .insert(header::RETRY_AFTER, retry_after.clone());
+                    }
+                    page
+                }
+                None => resp,
+            })
         }
+        result => result,
     }
+}
+
+// Runs the request, logging it when it takes longer than `slow_request_threshold`.
+async fn timed(state: AppState, mut req: Request<Body>) -> Result<Response<Body>, StatusCode> {
+    let Some(threshold) = state.slow_request_threshold else {
+        return handle(state, req, None).await;
+    };
 
-    let ip = match client_ip_opt {
-        Some(ip) => ip,
-        None => return Ok(()), // Can't attribute an IP; allow the request
+    // Captured up front: the request is consumed and its path may be rewritten on the way.
+    let started = Instant::now();
+    let method = req.method().clone();
+    let path = match state.path_decoding {
+        PathDecoding::Raw => req.uri().path().to_string(),
+        PathDecoding::Decoded => decode_for_log(req.uri().path()).into_owned(),
     };
+    let ip = access::client_ip(&req, &state.trusted_proxies);
+    let ja4 = TlsFingerprint::of(&req).map(|f| format!(" [ja4 {}]", f.ja4));
+    let country = state.geoip.as_ref().map(|geoip| {
+        let geo = ip.map(|ip| geoip.lookup(ip)).unwrap_or_default();
+        let country = format!(" [country {}]", geo.country.as_deref().unwrap_or("unknown"));
+        req.extensions_mut().insert(geo);
+        country
+    });
+    let backend = OnceLock::new();
+    let result = handle(state, req, Some(&backend)).await;
 
-    let now = Instant::now();
+    let elapsed = started.elapsed();
+    if elapsed >= threshold {
+        let status = match &result {
+            Ok(resp) => resp.status(),
+            Err(status) => *status,
+        };
+        tracing::warn!(
+            "slow request: {} {} from {}{}{} via {} -> {} in {:?}",
+            method,
+            path,
+            ip.map_or_else(|| "-".to_string(), |ip| ip.to_string()),
+            country.unwrap_or_default(),
+            ja4.unwrap_or_default(),
+            backend.get().map_or("-", Url::as_str),
+            status.as_u16(),
+            elapsed
+        );
+    }
+    result
+}
 
-    let per_min = state.rate_limit_per_minute.unwrap();
-    let rate_per_sec = per_min / 60.0;
-    let burst = state.rate_limit_burst.unwrap_or(per_min);
+// `backend_used` receives the backend the request was sent to, if it got that far.
+async fn handle(
+    state: AppState,
+    req: Request<Body>,
+    backend_used: Option<&OnceLock<Url>>,
+) -> Result<Response<Body>, StatusCode> {
+    // Not a transient upstream failure: there is nothing to send to. The 503 goes through the
+    // 503 error page like any other.
+    if state.backends.snapshot().is_empty() {
+        tracing::error!("no backends configured, refusing {}", req.uri().path());
+        return Err(StatusCode::SERVICE_UNAVAILABLE);
+    }
 
-    // Update or insert token bucket for this IP
-    // Initialize new entries with 0 tokens to avoid allowing a large initial burst.
-    let mut allowed = false;
-    {
-        // When inserting a fresh bucket, start with 0.0 tokens and last-seen = now.
-        // Existing entries will be topped up based on elapsed time below.
-        let mut entry = state.rate_limit_map.entry(ip).or_insert((0.0, now));
-        let elapsed = now.duration_since(entry.1).as_secs_f64();
-        entry.0 = (entry.0 + elapsed * rate_per_sec).min(burst);
-        entry.1 = now;
-        if entry.0 >= 1.0 {
-            entry.0 -= 1.0;
-            allowed = true;
+    // TRACE would reflect the request (cookies and auth headers included) back to the client,
+    // and CONNECT asks for a tunnel, which a reverse proxy doesn't offer (its authority-form
+    // target couldn't be forwarded anyway).
+    let refused = match *req.method() {
+        Method::TRACE => !state.allow_trace,
+        Method::CONNECT => true,
+        _ => false,
+    };
+    if refused {
+        tracing::warn!("refusing {} {}", req.method(), req.uri());
+        let mut allow = String::from("GET, HEAD, POST, PUT, PATCH, DELETE, OPTIONS");
+        if state.allow_trace {
+            allow.push_str(", TRACE");
         }
+        return Ok((StatusCode::METHOD_NOT_ALLOWED, [(header::ALLOW, allow)]).into_response());
     }
 
-    if allowed {
-        Ok(())
-    } else {
-        tracing::debug!("rate limit exceeded for {}", ip);
-        Err(StatusCode::TOO_MANY_REQUESTS)
+    if state.drain.is_draining() {
+        return Ok(state.drain.unavailable());
     }
+    if let Some(maintenance) = &state.maintenance
+        && let Some(resp) = maintenance.check(&req, &state.trusted_proxies)
+    {
+        return Ok(resp);
+    }
+    // Counted by /admin/drain until the response body has been sent, not just its head.
+    let in_flight = state.drain.track();
+    let result = serve(state, req, backend_used).await;
+    result.map(|resp| hold_until_sent(resp, in_flight))
 }
 
-pub async fn proxy_handler(
-    State(state): State<AppState>,
-    req: Request<Body>,
+// The rest of `handle`, for requests that are let in.
+async fn serve(
+    state: AppState,
+    mut req: Request<Body>,
+    backend_used: Option<&OnceLock<Url>>,
 ) -> Result<Response<Body>, StatusCode> {
-    // Relaxed ordering is fine and fastest here.
-    if state.backends.is_empty() {
-        return Err(StatusCode::BAD_GATEWAY);
+    // Checked first, as everything after (rewrites, the cache key) copies the target around.
+    let target_len = req.uri().path_and_query().map_or(0, |pq| pq.as_str().len());
+    if target_len > state.max_uri_length {
+        tracing::warn!(
+            "rejecting {}-byte request target (max_uri_length {})",
+            target_len,
+            state.max_uri_length
+        );
+        return Err(StatusCode::URI_TOO_LONG);
+    }
+    if let Err(e) = target::to_origin_form(&mut req, state.allow_absolute_form) {
+        tracing::warn!("rejecting request: {}", e);
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    if let Err(e) = framing::check(req.headers()) {
+        state.framing_rejections.fetch_add(1, Ordering::Relaxed);
+        tracing::warn!("rejecting request with ambiguous framing: {}", e);
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    if state.normalize_path {
+        let Some(path) = normalize_path(req.uri().path()) else {
+            tracing::warn!("rejecting request path that climbs above the root");
+            return Err(StatusCode::BAD_REQUEST);
+        };
+        if path != req.uri().path() {
+            let target = match req.uri().query() {
+                Some(q) => format!("{}?{}", path, q),
+                None => path,
+            };
+            *req.uri_mut() = target.parse().map_err(|_| StatusCode::BAD_REQUEST)?;
+        }
+    }
+
+    // Route prefixes are matched against the path as the backend will resolve it, so dot
+    // segments and escaped letters can't slip a request past them.
+    let Some(route_path) = route_path(req.uri().path()) else {
+        tracing::warn!("rejecting request path that climbs above the root");
+        return Err(StatusCode::BAD_REQUEST);
+    };
+
+    // Looked up once per request, from the client address resolved through trusted_proxies.
+    if let Some(geoip) = &state.geoip
+        && req.extensions().get::<GeoInfo>().is_none()
+    {
+        let geo = access::client_ip(&req, &state.trusted_proxies)
+            .map(|ip| geoip.lookup(ip))
+            .unwrap_or_default();
+        req.extensions_mut().insert(geo);
+    }
+
+    check_access(&state, &req, &route_path)?;
+    check_country(&state, &req)?;
+    check_origin(&state, &req, &route_path)?;
+    check_content_type(&state, &req, &route_path)?;
+    if let Err(challenge) = check_auth(&state, &mut req, &route_path).await {
+        return Ok(challenge);
+    }
+
+    if let Some(policy) = state.early_data {
+        early_data::check(policy, &mut req)?;
     }
 
     if let Err(status) = check_rate_limit(&state, &req) {
@@ -240,69 +727,383 @@ pub async fn proxy_handler(
         return Err(status);
     }
 
-    // Build a simple cache key using method + absolute URI (includes query)
-    let cache_key = format!("{} {}", req.method(), req.uri().to_string());
+    // Before match rules, so they can route buckets on the bucket header.
+    let assignment = state.experiment.as_ref().map(|e| e.assign(&mut req));
+
+    let limit = match_rules::find_route(&state.match_routes, &req)
+        .and_then(|(_, route)| route.rule.request_timeout)
+        .or(state.request_timeout)
+        .filter(|t| !t.is_zero());
+    let (method, uri) = (req.method().clone(), req.uri().clone());
+
+    let response = async {
+        if let Some(max) = state.max_decompressed_body_bytes {
+            decompress::request_body(&mut req, max).await?;
+        }
+        if let Some(store) = state.idempotency.clone()
+            && let Some(key) =
+                IdempotencyStore::request_key(&req, access::client_ip(&req, &state.trusted_proxies))
+        {
+            return store
+                .handle(key, forward(state, req, &route_path, backend_used))
+                .await;
+        }
+        forward(state, req, &route_path, backend_used).await
+    };
+
+    let result = match limit {
+        Some(limit) => deadline::enforce(limit, method, uri, response).await,
+        None => response.await,
+    };
+    match assignment {
+        Some(assignment) => result.map(|mut resp| {
+            assignment.apply(&mut resp);
+            resp
+        }),
+        None => result,
+    }
+}
+
+// `ttl` seconds scaled by a random factor in [1 - jitter, 1 + jitter], so entries stored
+// together don't all expire (and go back to the backend) in the same instant.
+fn jittered_ttl(ttl: u64, jitter: Option<f64>) -> Duration {
+    let ttl = Duration::from_secs(ttl);
+    let Some(jitter) = jitter else {
+        return ttl;
+    };
+    let mut bytes = [0u8; 4];
+    if SystemRandom::new().fill(&mut bytes).is_err() {
+        return ttl;
+    }
+    let unit = u32::from_le_bytes(bytes) as f64 / u32::MAX as f64;
+    ttl.mul_f64(1.0 + jitter * (2.0 * unit - 1.0))
+}
+
+// `route_path` is the request's resolved path, as the checks in `handle` saw it.
+async fn forward(
+    state: AppState,
+    mut req: Request<Body>,
+    route_path: &str,
+    backend_used: Option<&OnceLock<Url>>,
+) -> Result<Response<Body>, StatusCode> {
+    let normalized_path = normalize_trailing_slash(req.uri().path(), state.trailing_slash);
+    if state.trailing_slash == TrailingSlash::Redirect
+        && let Some(canonical) = &normalized_path
+        // A leading "//" would make the Location protocol-relative; just forward those.
+        && !canonical.starts_with("//")
+    {
+        let location = match req.uri().query() {
+            Some(q) => format!("{}?{}", canonical, q),
+            None => canonical.clone(),
+        };
+        return Response::builder()
+            .status(StatusCode::PERMANENT_REDIRECT)
+            .header(axum::http::header::LOCATION, location)
+            .body(Body::empty())
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR);
+    }
+    let req_path = normalized_path.as_deref().unwrap_or(req.uri().path());
+
+    // Match rules are resolved before the cache so routed responses are cached separately.
+    let matched_route = match_rules::find_route(&state.match_routes, &req);
+
+    // Rewritten before the cache key is built, so parameters dropped for the backend don't
+    // split the cache either.
+    let query_route = longest_prefix(&state.query_routes, req_path, |r| &r.path_prefix);
+    let query = if state.query_rewrites.is_empty() && query_route.is_none() {
+        req.uri().query().map(str::to_string)
+    } else {
+        let route_rewrites = query_route.into_iter().flat_map(|r| &r.rewrites);
+        rewrite_query(
+            req.uri().query(),
+            state.query_rewrites.iter().chain(route_rewrites),
+        )
+    };
+    // Build the cache key from the method and the normalized origin-form path plus query
+    let key_path = match state.path_decoding {
+        PathDecoding::Raw => Cow::Borrowed(req_path),
+        PathDecoding::Decoded => normalize_percent_encoding(req_path),
+    };
+    let origin = match &query {
+        Some(q) => format!("{}?{}", key_path, q),
+        None => key_path.into_owned(),
+    };
+    let host = match state.cache_key_include_host {
+        true => req
+            .headers()
+            .get(header::HOST)
+            .and_then(|h| h.to_str().ok()),
+        false => None,
+    };
+    let bucket = req.extensions().get::<Bucket>().map(|b| b.0.as_str());
+    let cache_key = cache_key(
+        req.method(),
+        host,
+        &origin,
+        matched_route.map(|(idx, _)| idx),
+        bucket,
+    );
+
+    // Responses to authenticated requests may be per-user, so they are never cached. The same
+    // goes for requests carrying credentials the proxy doesn't check (cache_bypass_headers).
+    let authenticated = req.extensions().get::<Authenticated>().cloned();
+    let bypass_header = state
+        .cache_bypass_headers
+        .iter()
+        .find(|name| req.headers().contains_key(*name));
+    let cache_override = match (&authenticated, bypass_header) {
+        (Some(_), _) => Some(false),
+        (None, Some(name)) => {
+            tracing::debug!("request carries {}, bypassing the cache", name);
+            Some(false)
+        }
+        (None, None) => {
+            longest_prefix(&state.cache_routes, route_path, |r| &r.path_prefix).map(|r| r.cache)
+        }
+    };
+    let html_route =
+        longest_prefix(&state.html_rewrite_routes, req_path, |r| &r.path_prefix).cloned();
 
     // If a response cache is configured (DashMap), check it first.
-    if let Some(cache) = &state.response_cache {
-        if let Some(entry_ref) = cache.get(&cache_key) {
-            // If cached and still fresh, serve it immediately.
-            if Instant::now() < entry_ref.expires_at {
-                let mut response_builder = Response::builder().status(entry_ref.status);
-                for (name, val) in &entry_ref.headers {
-                    if let Ok(hn) = HeaderName::from_bytes(name.as_bytes()) {
-                        if let Ok(hv) = HeaderValue::from_bytes(val) {
-                            response_builder = response_builder.header(hn, hv);
-                        }
-                    }
+    if let Some(cache) = &state.response_cache
+        && cache_override != Some(false)
+        && let Some(entry_ref) = cache.get(&cache_key)
+    {
+        // If cached and still fresh, serve it immediately.
+        let now = Instant::now();
+        if now < entry_ref.expires_at {
+            let mut response_builder = Response::builder().status(entry_ref.status);
+            for (name, val) in &entry_ref.headers {
+                if let Ok(hn) = HeaderName::from_bytes(name.as_bytes())
+                    && let Ok(hv) = HeaderValue::from_bytes(val)
+                {
+                    response_builder = response_builder.header(hn, hv);
                 }
-                let resp = response_builder
-                    .body(Body::from(entry_ref.body.clone()))
-                    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-                return Ok(resp);
-            } else {
-                // expired -> remove it
-                cache.remove(&cache_key);
             }
+            if state.debug_headers {
+                let ttl_remaining = entry_ref.expires_at.duration_since(now).as_secs();
+                response_builder = response_builder
+                    .header(CACHE_STATUS_HEADER, "HIT")
+                    .header(CACHE_TTL_HEADER, ttl_remaining);
+            }
+            let resp = response_builder
+                .body(Body::from(entry_ref.body.clone()))
+                .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+            return Ok(resp);
+        } else {
+            // expired -> remove it (drop the read guard first to avoid deadlocking the shard)
+            drop(entry_ref);
+            if let Some((_, removed)) = cache.remove(&cache_key) {
+                state
+                    .cache_current_size
+                    .fetch_sub(removed.size, Ordering::Relaxed);
+            }
+        }
+    }
+
+    let backends = state.backends.snapshot();
+    let (backend, permit) = if let Some(pinned) = pinned_backend(&state, &backends, &req) {
+        // A pinned request goes to that backend or nowhere.
+        (
+            pinned,
+            acquire_backend(&state, pinned).ok_or_else(backend_full)?,
+        )
+    } else {
+        let (pool, start, queue_pool) = match matched_route {
+            Some((idx, route)) => (&route.rule.backends, route.next_index(), idx + 1),
+            None => (&*backends, state.counter.fetch_add(1, Ordering::Relaxed), 0),
+        };
+        // Round-robin from `start`, skipping backends that are at their connection cap.
+        let select = || {
+            (0..pool.len())
+                .map(|i| &pool[(start + i) % pool.len()])
+                .find_map(|b| acquire_backend(&state, b).map(|p| (b, p)))
+        };
+        match &state.backend_queue {
+            Some(queue) => match queue.acquire(queue_pool, select).await {
+                Some(selected) => selected,
+                None => return Ok(queue.shed()),
+            },
+            None => select().ok_or_else(backend_full)?,
         }
+    };
+    if let Some(slot) = backend_used {
+        let _ = slot.set(backend.clone());
+    }
+
+    let fastcgi = state.fastcgi.as_ref().filter(|_| is_fastcgi(backend));
+    let base = fastcgi.map_or(backend, |f| f.request_base());
+    let url = upstream_url(base, req_path, query.as_deref()).ok_or_else(|| {
+        tracing::warn!("rejecting request path that escapes the backend base path");
+        StatusCode::BAD_REQUEST
+    })?;
+    // FastCGI scripts are named by the path, so encoded dot segments must not reach it either.
+    if fastcgi.is_some() && !fastcgi::is_safe_path(url.path()) {
+        tracing::warn!("rejecting FastCGI request path with dot segments");
+        return Err(StatusCode::BAD_REQUEST);
     }
 
-    let idx = state.counter.fetch_add(1, Ordering::Relaxed) % state.backends.len();
-    let backend = &state.backends[idx];
+    let is_get = req.method() == Method::GET;
+    let is_head = req.method() == Method::HEAD;
 
-    let path = req
-        .uri()
-        .path_and_query()
-        .map(|p| p.as_str())
-        .unwrap_or("/");
-    let url = backend
-        .join(path)
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    // Signature over the URL actually sent upstream, computed before `url` is consumed.
+    let signature = state.request_signer.as_ref().map(|signer| {
+        let ts = unix_now();
+        let sig = signer.sign(req.method().as_str(), url.path(), url.query(), ts);
+        (sig, ts)
+    });
 
-    let is_get = req.method() == &Method::GET;
+    // PROXY headers and FastCGI get the client's address, with its port when the address is
+    // the peer's own rather than one taken from X-Forwarded-For.
+    let peer = req
+        .extensions()
+        .get::<axum::extract::connect_info::ConnectInfo<std::net::SocketAddr>>()
+        .map(|c| c.0);
+    let client = access::client_ip(&req, &state.trusted_proxies).map(|ip| match peer {
+        Some(peer) if peer.ip() == ip => peer,
+        _ => SocketAddr::new(ip, 0),
+    });
+    let proxy_header = state
+        .send_proxy_protocol
+        .get(backend)
+        .map(|&version| (version, client));
+    let request_origin = fastcgi.map(|_| RequestOrigin {
+        client,
+        local: state.listen,
+        https: state.tls,
+        // Host is a hop-by-hop header to the HTTP client, so it isn't in the built request.
+        host: req
+            .headers()
+            .get(header::HOST)
+            .and_then(|h| h.to_str().ok())
+            .map(str::to_string)
+            .or_else(|| req.uri().authority().map(|a| a.to_string())),
+    });
 
     let method = req.method().clone();
     let mut req_builder = state.client.request(method, url);
 
+    // Geo headers are only trusted from our own lookup, never from the client.
+    if state.geoip.is_some() {
+        let geo = req
+            .extensions()
+            .get::<GeoInfo>()
+            .cloned()
+            .unwrap_or_default();
+        let headers = req.headers_mut();
+        headers.remove(GEO_COUNTRY_HEADER);
+        headers.remove(GEO_ASN_HEADER);
+        if let Some(country) = geo.country
+            && let Ok(hv) = HeaderValue::from_str(&country)
+        {
+            headers.insert(GEO_COUNTRY_HEADER, hv);
+        }
+        if let Some(asn) = geo.asn {
+            headers.insert(GEO_ASN_HEADER, HeaderValue::from(asn));
+        }
+    }
+
+    // Like the geo headers, fingerprints are only trusted from our own handshake.
+    if state.forward_tls_fingerprint {
+        let fingerprint = TlsFingerprint::of(&req).cloned();
+        let headers = req.headers_mut();
+        headers.remove(JA3_HEADER);
+        headers.remove(JA4_HEADER);
+        if let Some(fingerprint) = fingerprint {
+            for (name, value) in [(JA3_HEADER, fingerprint.ja3), (JA4_HEADER, fingerprint.ja4)] {
+                if let Ok(hv) = HeaderValue::from_str(&value) {
+                    headers.insert(name, hv);
+                }
+            }
+        }
+    }
+
+    if state.forward_tls_info {
+        let info = TlsInfo::of(&req).cloned();
+        let headers = req.headers_mut();
+        headers.remove(TLS_VERSION_HEADER);
+        headers.remove(TLS_CIPHER_HEADER);
+        if let Some(info) = info {
+            headers.insert(TLS_VERSION_HEADER, HeaderValue::from_static(info.version));
+            if let Ok(hv) = HeaderValue::from_str(&info.cipher) {
+                headers.insert(TLS_CIPHER_HEADER, hv);
+            }
+        }
+    }
+
+    if let Some((sig, ts)) = signature {
+        // Never pass through a client-supplied signature alongside ours.
+        req.headers_mut().remove(SIGNATURE_HEADER);
+        req.headers_mut().remove(SIGNATURE_TIMESTAMP_HEADER);
+        req_builder = req_builder
+            .header(SIGNATURE_HEADER, sig)
+            .header(SIGNATURE_TIMESTAMP_HEADER, ts);
+    }
+
     // Sanitize and forward headers from the incoming request
-    req_builder = sanitize_and_forward_headers(req_builder, req.headers());
+    req_builder = sanitize_and_forward_headers(
+        req_builder,
+        req.headers(),
+        state
+            .forward_headers_allowlist
+            .as_deref()
+            .map(Vec::as_slice),
+        state.experiment.as_ref().map(|e| e.header()),
+    );
+    if let Some(credentials) = authenticated.and_then(|a| a.credentials) {
+        req_builder = req_builder.header(axum::http::header::AUTHORIZATION, credentials);
+    }
 
     // Convert Axum Body to Reqwest Body.
-    let client_body = req.into_body();
-    let stream = client_body
-        .into_data_stream()
-        .map_err(|e| io::Error::new(io::ErrorKind::Other, e));
-    req_builder = req_builder.body(ReqwestBody::wrap_stream(stream));
+    let client_body = req.into_body().into_data_stream();
+    req_builder = match state.max_upload_buffer_bytes {
+        Some(max) => req_builder.body(ReqwestBody::wrap_stream(upload::bounded(client_body, max))),
+        None => req_builder.body(ReqwestBody::wrap_stream(
+            client_body.map_err(io::Error::other),
+        )),
+    };
 
     // Send request to backend with a configured timeout. Map errors appropriately.
-    let send_future = req_builder.send();
+    let tracked = state.backend_stats.start(backend);
+    let send_future = match (fastcgi.zip(request_origin), proxy_header) {
+        (Some((fastcgi, origin)), _) => Either::Right(Either::Left(async move {
+            let request = req_builder.build()?;
+            fastcgi.send(backend, request, origin).await
+        })),
+        (None, None) => Either::Left(req_builder.send().map_err(BoxError::from)),
+        (None, Some((version, client))) => Either::Right(Either::Right(async move {
+            let request = req_builder.build()?;
+            proxy_protocol::send(request, version, client, state.listen).await
+        })),
+    };
     let resp = match timeout(state.backend_timeout, send_future).await {
         Ok(Ok(r)) => r,
         Ok(Err(e)) => {
-            tracing::error!("Upstream error: {}", e);
-            return Err(StatusCode::BAD_GATEWAY);
+            if let Some(t) = &tracked {
+                t.failed();
+            }
+            let kind = upstream::classify_any(&*e);
+            state.upstream_errors.record(kind);
+            tracing::error!(
+                category = kind.as_str(),
+                "upstream request to {} failed ({}): {}",
+                backend,
+                kind.description(),
+                upstream::error_chain(&*e)
+            );
+            if kind == UpstreamErrorKind::MalformedResponse {
+                let mut resp = Response::new(Body::empty());
+                *resp.status_mut() = kind.status();
+                resp.extensions_mut().insert(upstream::MalformedResponse);
+                return Ok(resp);
+            }
+            return Err(kind.status());
         }
         Err(_) => {
+            if let Some(t) = &tracked {
+                t.failed();
+            }
+            state.upstream_errors.record(UpstreamErrorKind::Timeout);
             tracing::warn!(
                 "upstream request timed out after {:?}",
                 state.backend_timeout
@@ -311,46 +1112,82 @@ pub async fn proxy_handler(
         }
     };
 
+    if resp.status().is_server_error()
+        && let Some(t) = &tracked
+    {
+        t.failed();
+    }
+
     let mut response_builder = Response::builder().status(resp.status());
+    if state.debug_headers && state.response_cache.is_some() {
+        response_builder = response_builder.header(CACHE_STATUS_HEADER, "MISS");
+    }
+
+    // HTML rewriting changes the body, so its length, coding and ETag are adjusted below.
+    let rewrite = html_route
+        .filter(|_| !is_head)
+        .and_then(|route| Rewrite::for_response(route, resp.status(), resp.headers()));
 
     let mut resp_headers: Vec<(String, Vec<u8>)> = Vec::new();
     for (name, value) in resp.headers() {
-        if !is_hop_by_hop(name.as_str()) {
-            response_builder = response_builder.header(name, value);
-            resp_headers.push((name.to_string(), value.as_bytes().to_vec()));
+        if is_hop_by_hop(name.as_str()) {
+            continue;
         }
+        let rewritten = match &state.cookie_rewrite {
+            Some(rewrite) if *name == axum::http::header::SET_COOKIE => value
+                .to_str()
+                .ok()
+                .and_then(|v| HeaderValue::from_str(&rewrite_set_cookie(v, rewrite)).ok()),
+            _ => None,
+        };
+        let value = rewritten.as_ref().unwrap_or(value);
+        let adjusted = match &rewrite {
+            Some(rewrite) => match rewrite.header(name, value) {
+                Some(v) => Some(v),
+                None => continue,
+            },
+            None => None,
+        };
+        let value = adjusted.as_ref().unwrap_or(value);
+        response_builder = response_builder.header(name, value);
+        resp_headers.push((name.to_string(), value.as_bytes().to_vec()));
     }
 
-    // Helper: robustly parse Cache-Control header bytes and return (s_maxage, max_age, no_store_or_no_cache)
-    fn parse_cache_control_bytes(hv: &[u8]) -> (Option<u64>, Option<u64>, bool) {
+    // Helper: robustly parse Cache-Control header bytes and return (s_maxage, max_age, no_store, no_cache)
+    fn parse_cache_control_bytes(hv: &[u8]) -> (Option<u64>, Option<u64>, bool, bool) {
         if let Ok(s) = std::str::from_utf8(hv) {
             let mut s_maxage: Option<u64> = None;
             let mut maxage: Option<u64> = None;
-            let mut no_store_or_no_cache = false;
+            let mut no_store = false;
+            let mut no_cache = false;
             for part in s.split(',') {
                 let p = part.trim();
                 // accept quoted values and spaces: split on '=' only once
-                if p.eq_ignore_ascii_case("no-store") || p.eq_ignore_ascii_case("no-cache") {
-                    no_store_or_no_cache = true;
+                if p.eq_ignore_ascii_case("no-store") {
+                    no_store = true;
+                    continue;
+                }
+                if p.eq_ignore_ascii_case("no-cache") {
+                    no_cache = true;
                     continue;
                 }
-                if let Some(rest) = p.splitn(2, '=').nth(1) {
-                    let k = p.splitn(2, '=').next().unwrap_or("").trim();
+                if let Some((k, rest)) = p.split_once('=') {
+                    let k = k.trim();
                     let v = rest.trim().trim_matches('"');
                     if k.eq_ignore_ascii_case("s-maxage") {
                         if let Ok(n) = v.parse::<u64>() {
                             s_maxage = Some(n);
                         }
-                    } else if k.eq_ignore_ascii_case("max-age") {
-                        if let Ok(n) = v.parse::<u64>() {
-                            maxage = Some(n);
-                        }
+                    } else if k.eq_ignore_ascii_case("max-age")
+                        && let Ok(n) = v.parse::<u64>()
+                    {
+                        maxage = Some(n);
                     }
                 }
             }
-            return (s_maxage, maxage, no_store_or_no_cache);
+            return (s_maxage, maxage, no_store, no_cache);
         }
-        (None, None, false)
+        (None, None, false, false)
     }
 
     // Resolve TTL and cacheability from headers and config.
@@ -361,8 +1198,9 @@ pub async fn proxy_handler(
         .iter()
         .find(|(n, _)| n.eq_ignore_ascii_case("cache-control"))
     {
-        let (s_max, max_a, no_store) = parse_cache_control_bytes(hv);
-        backend_forbids_cache = no_store;
+        let (s_max, max_a, no_store, no_cache) = parse_cache_control_bytes(hv);
+        // A route forced to cache overrides no-cache, but never no-store.
+        backend_forbids_cache = no_store || (no_cache && cache_override != Some(true));
         ttl_seconds = s_max.or(max_a);
     }
     if ttl_seconds.is_none() {
@@ -370,11 +1208,20 @@ pub async fn proxy_handler(
     }
 
     // Only consider caching for GET requests, successful 200 responses, cache enabled, and not forbidden.
+    // Responses declaring a body larger than the per-entry cap are streamed instead of
+    // buffered, so one huge response can't evict everything else from the cache.
+    let fits_entry_cap = match (state.cache_max_entry_bytes, resp.content_length()) {
+        (Some(max), Some(len)) => len <= max as u64,
+        _ => true,
+    };
+
     let should_cache = is_get
         && resp.status().as_u16() == 200
         && !backend_forbids_cache
         && ttl_seconds.is_some()
-        && state.response_cache.is_some();
+        && state.response_cache.is_some()
+        && cache_override != Some(false)
+        && fits_entry_cap;
 
     if should_cache {
         // We need to buffer the body for caching
@@ -385,56 +1232,624 @@ pub async fn proxy_handler(
                 return Err(StatusCode::BAD_GATEWAY);
             }
         };
+        // The cache stores the rewritten page, so hits don't rewrite again.
+        let bytes = match rewrite {
+            Some(rewrite) => rewrite.bytes(&bytes).map_err(|e| {
+                tracing::error!("error rewriting HTML from {}: {}", backend, e);
+                StatusCode::BAD_GATEWAY
+            })?,
+            None => bytes,
+        };
 
         // Build response to return to client
         let response = response_builder
             .body(Body::from(bytes.clone()))
             .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
 
-        // Insert into cache
-        if let (Some(cache), Some(ttl)) = (state.response_cache.as_ref(), ttl_seconds) {
+        // Insert into cache (unless the body turned out to exceed the per-entry cap)
+        let too_large = state
+            .cache_max_entry_bytes
+            .is_some_and(|max| bytes.len() > max);
+        if too_large {
+            tracing::debug!(
+                "not caching {}: {} bytes exceeds the per-entry cap",
+                cache_key,
+                bytes.len()
+            );
+        } else if let (Some(cache), Some(ttl)) = (state.response_cache.as_ref(), ttl_seconds) {
             let size = bytes.len();
-            let expires_at = Instant::now() + Duration::from_secs(ttl);
+            let expires_at = Instant::now() + jittered_ttl(ttl, state.cache_ttl_jitter);
             let entry = CacheEntry {
                 status: response.status().as_u16(),
                 headers: resp_headers.clone(),
-                body: Bytes::from(bytes.clone()),
+                body: bytes.clone(),
                 expires_at,
                 size,
             };
-            cache.insert(cache_key.clone(), entry);
+            // A replaced entry no longer counts towards the cache size.
+            if let Some(old) = cache.insert(cache_key.clone(), entry) {
+                state
+                    .cache_current_size
+                    .fetch_sub(old.size, Ordering::Relaxed);
+            }
             state.cache_current_size.fetch_add(size, Ordering::Relaxed);
 
-            // Evict if cache exceeds configured max size (best-effort).
+            // Evict if cache exceeds configured max size (best-effort), oldest expirations first.
             if let Some(max_bytes) = state.cache_max_size_bytes {
-                // Collect items and evict oldest expirations first.
-                let mut items: Vec<(String, Instant, usize)> = cache
-                    .iter()
-                    .map(|r| (r.key().clone(), r.value().expires_at, r.value().size))
-                    .collect();
-                items.sort_by_key(|t| t.1);
-                let mut cur_total = state.cache_current_size.load(Ordering::Relaxed);
-                for (k, _exp, _sz) in items {
-                    if cur_total as u64 <= max_bytes as u64 {
-                        break;
-                    }
-                    if let Some(removed) = cache.remove(&k) {
-                        cur_total = cur_total.saturating_sub(removed.1.size);
-                        state
-                            .cache_current_size
-                            .fetch_sub(removed.1.size, Ordering::Relaxed);
-                    }
+                let cur_total = state.cache_current_size.load(Ordering::Relaxed);
+                if cur_total > max_bytes {
+                    evict_cache_entries(cache, &state.cache_current_size, cur_total - max_bytes);
                 }
             }
+            // Then keep the sum of all caches within [limits] total_cache_bytes.
+            state.memory.enforce();
         }
-        return Ok(response);
+        Ok(response)
     } else {
+        // The backend slot stays taken, and the transfer counted, until the body has been
+        // streamed to the client (or the stream fails or is dropped).
+        let transfer = state.transfers.start();
         let upstream_stream = resp
             .bytes_stream()
-            .map_err(|e| io::Error::new(io::ErrorKind::Other, e));
+            .map_ok(move |chunk| {
+                let _guards = (&permit, &transfer, &tracked);
+                chunk
+            })
+            .map_err(io::Error::other);
+        let body = match rewrite {
+            Some(rewrite) => Body::from_stream(rewrite.stream(upstream_stream)),
+            None => Body::from_stream(upstream_stream),
+        };
         let streamed = response_builder
-            .body(Body::from_stream(upstream_stream))
+            .body(body)
             .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-        return Ok(streamed);
+        Ok(streamed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{ExperimentConfig, MissingContentTypePolicy};
+
+    // A backend answering every request with its method and target, counting the requests.
+    async fn echo_backend() -> (Url, Arc<AtomicUsize>) {
+        let hits = Arc::new(AtomicUsize::new(0));
+        let counter = hits.clone();
+        let app = axum::Router::new().fallback(move |req: Request<Body>| {
+            let counter = counter.clone();
+            async move {
+                counter.fetch_add(1, Ordering::Relaxed);
+                format!("{} {}", req.method(), req.uri())
+            }
+        });
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        (Url::parse(&format!("http://{}/", addr)).unwrap(), hits)
+    }
+
+    // A backend answering with the request headers it received, one `name: value` per line.
+    async fn headers_backend() -> Url {
+        let app = axum::Router::new().fallback(|req: Request<Body>| async move {
+            req.headers()
+                .iter()
+                .map(|(name, value)| format!("{}: {}\n", name, value.to_str().unwrap_or("?")))
+                .collect::<String>()
+        });
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        Url::parse(&format!("http://{}/", addr)).unwrap()
+    }
+
+    // A server proxying to `backend` with a 60 second response cache and nothing else enabled.
+    fn state(backend: Url) -> AppState {
+        let listen = "127.0.0.1:0".parse().unwrap();
+        AppState {
+            client: Client::new(),
+            backends: Arc::new(BackendPool::new(vec![backend.clone()])),
+            counter: Arc::new(AtomicUsize::new(0)),
+            backend_limiter: None,
+            backend_queue: None,
+            backend_timeout: Duration::from_secs(5),
+            request_timeout: None,
+            slow_request_threshold: None,
+            early_data: None,
+            max_upload_buffer_bytes: None,
+            max_decompressed_body_bytes: None,
+            max_uri_length: 8192,
+            upstream_errors: Arc::new(UpstreamErrorCounters::default()),
+            backend_stats: Arc::new(BackendStats::new([&backend])),
+            rate_limiters: Arc::new(Vec::new()),
+            rate_limit_on_missing_ip: MissingIpPolicy::default(),
+            rate_limit_max_entries: None,
+            rate_limit_exempt_ips: Arc::new(Vec::new()),
+            cookie_rewrite: None,
+            query_rewrites: Arc::new(Vec::new()),
+            query_routes: Arc::new(Vec::new()),
+            trailing_slash: TrailingSlash::Preserve,
+            normalize_path: false,
+            match_routes: Arc::new(Vec::new()),
+            forward_headers_allowlist: None,
+            forward_tls_fingerprint: false,
+            forward_tls_info: false,
+            send_proxy_protocol: Arc::new(HashMap::new()),
+            listen,
+            tls: false,
+            allow_absolute_form: false,
+            allow_backend_pinning: false,
+            backend_pinning_trusted_ips: Arc::new(Vec::new()),
+            response_cache: Some(Arc::new(DashMap::new())),
+            cache_ttl_secs: Some(60),
+            cache_max_size_bytes: None,
+            cache_max_entry_bytes: None,
+            cache_ttl_jitter: None,
+            cache_key_include_host: false,
+            path_decoding: PathDecoding::Raw,
+            cache_bypass_headers: Arc::new(vec![header::AUTHORIZATION, header::COOKIE]),
+            cache_routes: Arc::new(Vec::new()),
+            access: Arc::new(AccessRule::default()),
+            access_routes: Arc::new(Vec::new()),
+            trusted_proxies: Arc::new(Vec::new()),
+            auth_routes: Arc::new(Vec::new()),
+            auth_failures: Arc::new(AuthFailures::default()),
+            origin_check: None,
+            origin_routes: Arc::new(Vec::new()),
+            origin_scheme: "http",
+            origin_rejections: Arc::new(AtomicU64::new(0)),
+            content_type_routes: Arc::new(Vec::new()),
+            html_rewrite_routes: Arc::new(Vec::new()),
+            cache_warmer: Arc::new(CacheWarmer::default()),
+            cache_current_size: Arc::new(AtomicUsize::new(0)),
+            memory: Arc::new(MemoryBudget::new(None)),
+            debug_headers: false,
+            error_pages: Arc::new(ErrorPages::default()),
+            geoip: None,
+            deny_countries: Arc::new(Vec::new()),
+            connections: Arc::new(ConnectionTracker::new(
+                listen,
+                None,
+                None,
+                Default::default(),
+                None,
+                Default::default(),
+            )),
+            head_rejections: Arc::new(HeadRejections::default()),
+            block_rules: Arc::new(BlockRules::new(Vec::new())),
+            auto_ban: None,
+            panics: Arc::new(AtomicU64::new(0)),
+            framing_rejections: Arc::new(AtomicU64::new(0)),
+            idempotency: None,
+            request_signer: None,
+            bind_failures: Arc::new(Mutex::new(Vec::new())),
+            drain: Arc::new(Drain::new(
+                axum_server::Handle::new(),
+                Duration::from_secs(5),
+                false,
+            )),
+            allow_trace: false,
+            fastcgi: None,
+            maintenance: None,
+            experiment: None,
+            supervisor: Arc::new(Supervisor::default()),
+            transfers: Arc::new(Transfers::default()),
+            admin_token: None,
+        }
+    }
+
+    fn request(method: Method, target: &str) -> Request<Body> {
+        Request::builder()
+            .method(method)
+            .uri(target)
+            .header(header::HOST, "proxy.test")
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    // Status and body of the proxy's response to `req`.
+    async fn send(state: &AppState, req: Request<Body>) -> (StatusCode, String) {
+        let resp = match proxy_handler(State(state.clone()), req).await {
+            Ok(resp) => resp,
+            Err(status) => return (status, String::new()),
+        };
+        let status = resp.status();
+        let body = axum::body::to_bytes(resp.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, String::from_utf8_lossy(&body).into_owned())
+    }
+
+    fn cache_keys(state: &AppState) -> Vec<String> {
+        let cache = state.response_cache.as_ref().unwrap();
+        let mut keys: Vec<_> = cache.iter().map(|e| e.key().clone()).collect();
+        keys.sort();
+        keys
+    }
+
+    #[tokio::test]
+    async fn trailing_slash_modes() {
+        let (backend, _) = echo_backend().await;
+        let mut state = state(backend);
+        state.response_cache = None;
+        for (policy, target, forwarded) in [
+            (TrailingSlash::Preserve, "/a/", "GET /a/"),
+            (TrailingSlash::Preserve, "/a", "GET /a"),
+            (TrailingSlash::Strip, "/a/?q=1", "GET /a?q=1"),
+            (TrailingSlash::Strip, "/", "GET /"),
+            (TrailingSlash::Append, "/a", "GET /a/"),
+            (TrailingSlash::Append, "/a/app.js", "GET /a/app.js"),
+            (TrailingSlash::Redirect, "/a", "GET /a"),
+        ] {
+            state.trailing_slash = policy;
+            let (status, body) = send(&state, request(Method::GET, target)).await;
+            assert_eq!(
+                (status, body.as_str()),
+                (StatusCode::OK, forwarded),
+                "{:?}",
+                policy
+            );
+        }
+
+        state.trailing_slash = TrailingSlash::Redirect;
+        let resp = proxy_handler(State(state), request(Method::GET, "/a/?q=1"))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::PERMANENT_REDIRECT);
+        assert_eq!(resp.headers()[header::LOCATION], "/a?q=1");
+    }
+
+    #[tokio::test]
+    async fn cache_route_excludes_a_prefix() {
+        let (backend, hits) = echo_backend().await;
+        let mut state = state(backend);
+        state.cache_routes = Arc::new(vec![
+            CacheRoute {
+                path_prefix: "/api/private".to_string(),
+                cache: false,
+            },
+            CacheRoute {
+                path_prefix: "/api".to_string(),
+                cache: true,
+            },
+        ]);
+
+        for _ in 0..2 {
+            send(&state, request(Method::GET, "/api/private/me")).await;
+        }
+        assert_eq!(hits.load(Ordering::Relaxed), 2);
+        assert!(cache_keys(&state).is_empty());
+
+        // Whole segments only: /api/privateer is under /api, not /api/private.
+        for _ in 0..2 {
+            send(&state, request(Method::GET, "/api/privateer")).await;
+        }
+        assert_eq!(hits.load(Ordering::Relaxed), 3);
+        assert_eq!(cache_keys(&state), ["GET /api/privateer"]);
+    }
+
+    #[tokio::test]
+    async fn normalize_path_is_optional() {
+        let (backend, _) = echo_backend().await;
+        let mut state = state(backend);
+        state.response_cache = None;
+        let target = "/api//users/./1?q=a//b";
+
+        let (_, body) = send(&state, request(Method::GET, target)).await;
+        assert_eq!(body, "GET /api//users/1?q=a//b");
+
+        state.normalize_path = true;
+        let (_, body) = send(&state, request(Method::GET, target)).await;
+        assert_eq!(body, "GET /api/users/1?q=a//b");
+        let (status, _) = send(&state, request(Method::GET, "/a//../../etc")).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn over_length_uri_gets_414() {
+        let (backend, hits) = echo_backend().await;
+        let mut state = state(backend);
+        state.max_uri_length = 64;
+
+        let long = format!("/{}", "a".repeat(64));
+        let (status, _) = send(&state, request(Method::GET, &long)).await;
+        assert_eq!(status, StatusCode::URI_TOO_LONG);
+        // Refused before the backend or the cache saw it.
+        assert_eq!(hits.load(Ordering::Relaxed), 0);
+        assert!(cache_keys(&state).is_empty());
+
+        // The query counts towards the limit too.
+        let (status, _) = send(&state, request(Method::GET, &format!("/a?{}", long))).await;
+        assert_eq!(status, StatusCode::URI_TOO_LONG);
+
+        let fits = format!("/{}", "a".repeat(63));
+        let (status, _) = send(&state, request(Method::GET, &fits)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(cache_keys(&state), [format!("GET {}", fits)]);
+    }
+
+    #[tokio::test]
+    async fn content_type_route_refuses_unlisted_bodies() {
+        let (backend, hits) = echo_backend().await;
+        let mut state = state(backend);
+        let mut route = ContentTypeRoute {
+            path_prefix: "/api".to_string(),
+            allowed: vec!["application/json".to_string()],
+            on_missing: MissingContentTypePolicy::Deny,
+        };
+        state.content_type_routes = Arc::new(vec![route.clone()]);
+        let post = |path: &str, content_type: Option<&str>| {
+            let mut req = Request::builder().method(Method::POST).uri(path);
+            if let Some(ct) = content_type {
+                req = req.header(header::CONTENT_TYPE, ct);
+            }
+            req.body(Body::from("{}")).unwrap()
+        };
+
+        for (req, expected) in [
+            (
+                post("/api/x", Some("application/json ;charset=utf-8")),
+                StatusCode::OK,
+            ),
+            (
+                post("/api/x", Some("text/xml")),
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            ),
+            (post("/api/x", None), StatusCode::UNSUPPORTED_MEDIA_TYPE),
+            // Dot segments can't move a request out from under the route.
+            (
+                post("/x/../api/y", Some("text/xml")),
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            ),
+            (post("/other", Some("text/xml")), StatusCode::OK),
+        ] {
+            let uri = req.uri().clone();
+            assert_eq!(send(&state, req).await.0, expected, "{}", uri);
+        }
+        assert_eq!(hits.load(Ordering::Relaxed), 2);
+
+        route.on_missing = MissingContentTypePolicy::Allow;
+        state.content_type_routes = Arc::new(vec![route]);
+        assert_eq!(send(&state, post("/api/x", None)).await.0, StatusCode::OK);
+    }
+
+    #[test]
+    fn cache_key_format() {
+        let get = Method::GET;
+        assert_eq!(cache_key(&get, None, "/a?b=1", None, None), "GET /a?b=1");
+        assert_eq!(
+            cache_key(&get, Some("Example.COM"), "/a", None, None),
+            "GET example.com/a"
+        );
+        assert_eq!(
+            cache_key(&get, None, "/a", Some(2), Some("b")),
+            "GET /a match=2 bucket=b"
+        );
+        let lower = Method::from_bytes(b"get").unwrap();
+        assert_eq!(cache_key(&lower, None, "/a", None, None), "get /a");
+    }
+
+    #[tokio::test]
+    async fn origin_and_absolute_form_share_a_cache_key() {
+        let (backend, hits) = echo_backend().await;
+        let mut state = state(backend);
+        state.allow_absolute_form = true;
+
+        send(&state, request(Method::GET, "/a?b=1")).await;
+        send(&state, request(Method::GET, "http://proxy.test/a?b=1")).await;
+        send(&state, request(Method::GET, "http://PROXY.test:80/a?b=1")).await;
+        assert_eq!(cache_keys(&state), ["GET /a?b=1"]);
+        assert_eq!(hits.load(Ordering::Relaxed), 1);
+
+        state.cache_key_include_host = true;
+        send(&state, request(Method::GET, "http://proxy.test/a?b=1")).await;
+        assert_eq!(cache_keys(&state), ["GET /a?b=1", "GET proxy.test/a?b=1"]);
+    }
+
+    #[tokio::test]
+    async fn trace_and_connect_are_refused_by_default() {
+        let (backend, hits) = echo_backend().await;
+        let mut state = state(backend);
+        state.response_cache = None;
+
+        // A real CONNECT carries an authority-form target.
+        for (method, target) in [(Method::TRACE, "/a"), (Method::CONNECT, "backend.test:443")] {
+            let resp = proxy_handler(State(state.clone()), request(method.clone(), target))
+                .await
+                .unwrap();
+            assert_eq!(resp.status(), StatusCode::METHOD_NOT_ALLOWED, "{}", method);
+            let allow = resp.headers()[header::ALLOW].to_str().unwrap();
+            assert!(!allow.contains(method.as_str()), "{}", allow);
+        }
+        assert_eq!(hits.load(Ordering::Relaxed), 0);
+
+        state.allow_trace = true;
+        let (status, body) = send(&state, request(Method::TRACE, "/a")).await;
+        assert_eq!((status, body.as_str()), (StatusCode::OK, "TRACE /a"));
+        let resp = proxy_handler(State(state), request(Method::CONNECT, "backend.test:443"))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::METHOD_NOT_ALLOWED);
+        assert!(
+            resp.headers()[header::ALLOW]
+                .to_str()
+                .unwrap()
+                .ends_with(", TRACE")
+        );
+    }
+
+    #[tokio::test]
+    async fn path_decoding_applies_to_cache_keys_only() {
+        let (backend, hits) = echo_backend().await;
+        let mut state = state(backend);
+        let target = "/%7Eme/a%2fb%20c";
+
+        let (_, body) = send(&state, request(Method::GET, target)).await;
+        assert_eq!(body, format!("GET {}", target));
+        assert_eq!(cache_keys(&state), [format!("GET {}", target)]);
+
+        state.response_cache = Some(Arc::new(DashMap::new()));
+        state.path_decoding = PathDecoding::Decoded;
+        let (_, body) = send(&state, request(Method::GET, target)).await;
+        assert_eq!(body, format!("GET {}", target));
+        assert_eq!(cache_keys(&state), ["GET /~me/a%2Fb%20c"]);
+        // Another spelling of the same path is a hit.
+        send(&state, request(Method::GET, "/~me/a%2Fb%20c")).await;
+        assert_eq!(hits.load(Ordering::Relaxed), 2);
+    }
+
+    #[tokio::test]
+    async fn credentialed_requests_bypass_the_cache() {
+        let (backend, hits) = echo_backend().await;
+        let state = state(backend);
+        let with = |name: HeaderName, path: &str| {
+            let mut req = request(Method::GET, path);
+            req.headers_mut()
+                .insert(name, HeaderValue::from_static("x"));
+            req
+        };
+
+        // Not stored: the backend sees every credentialed request and the cache stays empty.
+        for _ in 0..2 {
+            send(&state, with(header::AUTHORIZATION, "/me")).await;
+            send(&state, with(header::COOKIE, "/me")).await;
+        }
+        assert_eq!(hits.load(Ordering::Relaxed), 4);
+        assert!(cache_keys(&state).is_empty());
+
+        // Not served from the cache either, even when an anonymous response is stored.
+        send(&state, request(Method::GET, "/me")).await;
+        assert_eq!(cache_keys(&state), ["GET /me"]);
+        send(&state, with(header::AUTHORIZATION, "/me")).await;
+        send(&state, with(header::COOKIE, "/me")).await;
+        assert_eq!(hits.load(Ordering::Relaxed), 7);
+        send(&state, request(Method::GET, "/me")).await;
+        assert_eq!(hits.load(Ordering::Relaxed), 7);
+    }
+
+    #[tokio::test]
+    async fn cache_bypass_headers_are_configurable() {
+        let (backend, hits) = echo_backend().await;
+        let mut state = state(backend);
+        state.cache_bypass_headers = Arc::new(vec![HeaderName::from_static("x-session")]);
+
+        for _ in 0..2 {
+            let mut req = request(Method::GET, "/a");
+            req.headers_mut()
+                .insert(header::COOKIE, HeaderValue::from_static("theme=dark"));
+            send(&state, req).await;
+        }
+        assert_eq!(hits.load(Ordering::Relaxed), 1);
+
+        let mut req = request(Method::GET, "/a");
+        req.headers_mut()
+            .insert("x-session", HeaderValue::from_static("1"));
+        send(&state, req).await;
+        assert_eq!(hits.load(Ordering::Relaxed), 2);
+    }
+
+    #[tokio::test]
+    async fn bucket_header_survives_strict_mode() {
+        let mut state = state(headers_backend().await);
+        state.response_cache = None;
+        state.forward_headers_allowlist = Some(Arc::new(vec![header::ACCEPT]));
+        state.experiment = Some(Arc::new(Experiment::new(
+            ExperimentConfig {
+                name: "checkout".to_string(),
+                cookie: "exp".to_string(),
+                cookie_max_age: Duration::from_secs(60),
+                header: HeaderName::from_static("x-experiment-bucket"),
+                control: 0,
+                bot_user_agents: Vec::new(),
+                buckets: vec![("b".to_string(), 100)],
+            },
+            false,
+        )));
+
+        let mut req = request(Method::GET, "/");
+        req.headers_mut()
+            .insert("x-experiment-bucket", HeaderValue::from_static("forged"));
+        let (_, body) = send(&state, req).await;
+        assert!(body.contains("x-experiment-bucket: b\n"), "{}", body);
+        assert!(!body.contains("forged"), "{}", body);
+    }
+
+    #[tokio::test]
+    async fn strict_mode_forwards_only_allowlisted_headers() {
+        let mut state = state(headers_backend().await);
+        state.response_cache = None;
+        state.forward_headers_allowlist = Some(Arc::new(vec![
+            header::ACCEPT,
+            HeaderName::from_static("x-request-id"),
+        ]));
+        state.forward_tls_info = true;
+
+        let mut req = request(Method::GET, "/");
+        for (name, value) in [
+            ("accept", "text/html"),
+            ("X-Request-Id", "abc"),
+            ("x-internal-debug", "1"),
+            ("user-agent", "curl"),
+            ("x-tls-version", "forged"),
+        ] {
+            req.headers_mut()
+                .insert(name, HeaderValue::from_static(value));
+        }
+        req.extensions_mut().insert(TlsInfo {
+            version: "TLSv1.3",
+            cipher: "TLS_AES_128_GCM_SHA256".into(),
+        });
+        let (_, body) = send(&state, req).await;
+
+        for expected in [
+            "accept: text/html\n",
+            "x-request-id: abc\n",
+            "x-tls-version: TLSv1.3\n",
+            "x-tls-cipher: TLS_AES_128_GCM_SHA256\n",
+        ] {
+            assert!(
+                body.contains(expected),
+                "missing {:?} in {}",
+                expected,
+                body
+            );
+        }
+        for dropped in ["x-internal-debug", "user-agent: curl", "forged"] {
+            assert!(
+                !body.contains(dropped),
+                "{:?} forwarded in {}",
+                dropped,
+                body
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn request_is_in_flight_until_its_body_is_sent() {
+        let (tx, rx) = futures::channel::mpsc::unbounded::<io::Result<Bytes>>();
+        let rx = Arc::new(Mutex::new(Some(rx)));
+        let app = axum::Router::new().fallback(move || {
+            let rx = rx.lock().unwrap().take().unwrap();
+            async move { Body::from_stream(rx) }
+        });
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        let mut state = state(Url::parse(&format!("http://{}/", addr)).unwrap());
+        state.response_cache = None;
+
+        let resp = proxy_handler(State(state.clone()), request(Method::GET, "/download"))
+            .await
+            .unwrap();
+        // The head has been handed back but the body is still streaming.
+        assert_eq!(state.drain.status().in_flight_requests, 1);
+
+        tx.unbounded_send(Ok(Bytes::from_static(b"data"))).unwrap();
+        drop(tx);
+        let body = axum::body::to_bytes(resp.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(&body[..], b"data");
+        assert_eq!(state.drain.status().in_flight_requests, 0);
     }
 }
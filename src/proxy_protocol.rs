@@ -0,0 +1,634 @@
+//! PROXY protocol v1/v2 on TCP listeners behind a load balancer that prepends it.
+//!
+//! [`ProxyProtocolAcceptor`] reads the header before anything else touches the connection, so
+//! TLS and HTTP start right after it. The client address it carries counts for the connection
+//! limits and rides along on each request as [`ProxiedClient`]; [`apply`] then puts it in place
+//! of `ConnectInfo`, which rate limiting, logging and the forwarded headers go by.
+//!
+//! Only peers in `proxy_protocol_trusted` are expected to send a header. Everyone else is served
+//! as a plain connection under its own address, so a header from them is just a bad request.
+//!
+//! In the other direction, [`send`] talks to `send_proxy_protocol` backends, which reqwest can't
+//! do: each request gets a fresh connection opening with a header for its client.
+
+use axum::{
+    extract::{ConnectInfo, Request},
+    http::{HeaderValue, header},
+    middleware::Next,
+    response::Response,
+};
+use futures::future::BoxFuture;
+use hyper_util::rt::TokioIo;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tower_service::Service;
+use url::{Host, Position};
+
+use crate::config::{ProxyProtocolConfig, ProxyProtocolVersion};
+use crate::conn_limit::{ConnLimitAcceptor, LimitedService, TrackedStream};
+
+const V2_SIGNATURE: &[u8; 12] = b"\r\n\r\n\0\r\nQUIT\n";
+
+// Longest v1 header, CRLF included.
+const V1_MAX_LEN: usize = 107;
+
+// Time a trusted peer gets to send its header.
+const HEADER_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Request extension with the client address from the connection's PROXY header.
+#[derive(Debug, Clone, Copy)]
+pub struct ProxiedClient(pub SocketAddr);
+
+fn malformed(what: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("malformed PROXY header: {}", what),
+    )
+}
+
+// The source address of a v1 line without its CRLF; `Ok(None)` for `PROXY UNKNOWN`.
+fn parse_v1(line: &str) -> io::Result<Option<SocketAddr>> {
+    let mut parts = line.split(' ');
+    if parts.next() != Some("PROXY") {
+        return Err(malformed("not a v1 header"));
+    }
+    let ip = match parts.next() {
+        Some("UNKNOWN") => return Ok(None),
+        Some("TCP4") => parts
+            .next()
+            .and_then(|a| a.parse::<Ipv4Addr>().ok())
+            .map(IpAddr::V4),
+        Some("TCP6") => parts
+            .next()
+            .and_then(|a| a.parse::<Ipv6Addr>().ok())
+            .map(IpAddr::V6),
+        _ => return Err(malformed("unknown protocol")),
+    };
+    let ip = ip.ok_or_else(|| malformed("bad source address"))?;
+    let _destination = parts.next();
+    let port = parts
+        .next()
+        .and_then(|p| p.parse::<u16>().ok())
+        .ok_or_else(|| malformed("bad source port"))?;
+    // Destination port, and nothing after it.
+    if parts.next().is_none() || parts.next().is_some() {
+        return Err(malformed("wrong number of fields"));
+    }
+    Ok(Some(SocketAddr::new(ip, port)))
+}
+
+// Read exactly the v1 line, leaving whatever follows it in the socket.
+async fn read_v1(stream: &mut TcpStream) -> io::Result<Option<SocketAddr>> {
+    let mut line = Vec::with_capacity(V1_MAX_LEN);
+    let mut buf = [0u8; V1_MAX_LEN];
+    loop {
+        let n = stream.peek(&mut buf[..V1_MAX_LEN - line.len()]).await?;
+        if n == 0 {
+            return Err(malformed("connection closed"));
+        }
+        let take = buf[..n].iter().position(|&b| b == b'\n').map(|i| i + 1);
+        let len = take.unwrap_or(n);
+        stream.read_exact(&mut buf[..len]).await?;
+        line.extend_from_slice(&buf[..len]);
+        if !b"PROXY ".starts_with(&line[..line.len().min(6)]) {
+            return Err(malformed("not a v1 header"));
+        }
+        if take.is_some() {
+            break;
+        }
+        if line.len() >= V1_MAX_LEN {
+            return Err(malformed("line too long"));
+        }
+    }
+    let line = line
+        .strip_suffix(b"\r\n")
+        .and_then(|l| std::str::from_utf8(l).ok())
+        .ok_or_else(|| malformed("bad line ending"))?;
+    parse_v1(line)
+}
+
+// Read the v2 header and its addresses; `Ok(None)` for LOCAL connections (the balancer's own
+// health checks) and address families without an IP.
+async fn read_v2(stream: &mut TcpStream) -> io::Result<Option<SocketAddr>> {
+    let mut head = [0u8; 16];
+    stream.read_exact(&mut head).await?;
+    if &head[..12] != V2_SIGNATURE || head[12] >> 4 != 2 {
+        return Err(malformed("not a v2 header"));
+    }
+    let mut body = vec![0u8; u16::from_be_bytes([head[14], head[15]]) as usize];
+    stream.read_exact(&mut body).await?;
+    match head[12] & 0x0f {
+        0 => return Ok(None),
+        1 => {}
+        _ => return Err(malformed("unknown command")),
+    }
+    let addr = match head[13] >> 4 {
+        1 if body.len() >= 12 => {
+            let ip: [u8; 4] = body[..4].try_into().unwrap_or_default();
+            SocketAddr::new(ip.into(), u16::from_be_bytes([body[8], body[9]]))
+        }
+        2 if body.len() >= 36 => {
+            let ip: [u8; 16] = body[..16].try_into().unwrap_or_default();
+            SocketAddr::new(ip.into(), u16::from_be_bytes([body[32], body[33]]))
+        }
+        1 | 2 => return Err(malformed("address block too short")),
+        _ => return Ok(None),
+    };
+    Ok(Some(addr))
+}
+
+/// Per-connection service attaching the [`ProxiedClient`] to each request.
+#[derive(Debug, Clone)]
+pub struct ProxyProtocolService<S> {
+    inner: S,
+    client: Option<SocketAddr>,
+}
+
+impl<S, B> Service<axum::http::Request<B>> for ProxyProtocolService<S>
+where
+    S: Service<axum::http::Request<B>>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: axum::http::Request<B>) -> Self::Future {
+        if let Some(client) = self.client {
+            req.extensions_mut().insert(ProxiedClient(client));
+        }
+        self.inner.call(req)
+    }
+}
+
+/// axum-server acceptor reading PROXY headers from trusted peers ahead of the connection
+/// limits, which then go by the client address. Without a config it passes connections
+/// straight through.
+#[derive(Debug, Clone)]
+pub struct ProxyProtocolAcceptor {
+    inner: ConnLimitAcceptor,
+    config: Option<Arc<ProxyProtocolConfig>>,
+}
+
+impl ProxyProtocolAcceptor {
+    pub fn new(inner: ConnLimitAcceptor, config: Option<Arc<ProxyProtocolConfig>>) -> Self {
+        Self { inner, config }
+    }
+}
+
+impl<S> axum_server::accept::Accept<TcpStream, S> for ProxyProtocolAcceptor
+where
+    S: Send + 'static,
+{
+    type Stream = TrackedStream<TcpStream>;
+    type Service = ProxyProtocolService<LimitedService<S>>;
+    type Future = BoxFuture<'static, io::Result<(Self::Stream, Self::Service)>>;
+
+    fn accept(&self, mut stream: TcpStream, service: S) -> Self::Future {
+        let (inner, config) = (self.inner.clone(), self.config.clone());
+        Box::pin(async move {
+            let peer = stream.peer_addr().ok();
+            let version = match (&config, peer) {
+                (Some(config), Some(peer))
+                    if config
+                        .trusted
+                        .iter()
+                        .any(|net| net.contains(&peer.ip().to_canonical())) =>
+                {
+                    Some(config.version)
+                }
+                _ => None,
+            };
+            let client = match version {
+                Some(version) => {
+                    let read = async {
+                        match version {
+                            ProxyProtocolVersion::V1 => read_v1(&mut stream).await,
+                            ProxyProtocolVersion::V2 => read_v2(&mut stream).await,
+                        }
+                    };
+                    let result = tokio::time::timeout(HEADER_TIMEOUT, read)
+                        .await
+                        .unwrap_or_else(|_| {
+                            Err(io::Error::new(
+                                io::ErrorKind::TimedOut,
+                                "no PROXY header in time",
+                            ))
+                        });
+                    match result {
+                        Ok(client) => client,
+                        Err(e) => {
+                            inner.tracker().record_proxy_protocol_error();
+                            tracing::debug!("closing connection from {:?}: {}", peer, e);
+                            return Err(e);
+                        }
+                    }
+                }
+                None => None,
+            };
+            let ip = client.or(peer).map(|a| a.ip());
+            let (stream, service) = inner.accept_from(stream, service, ip)?;
+            Ok((
+                stream,
+                ProxyProtocolService {
+                    inner: service,
+                    client,
+                },
+            ))
+        })
+    }
+}
+
+/// Router middleware putting the [`ProxiedClient`] address in place of `ConnectInfo`.
+pub async fn apply(mut req: Request, next: Next) -> Response {
+    if let Some(&ProxiedClient(client)) = req.extensions().get::<ProxiedClient>() {
+        req.extensions_mut().insert(ConnectInfo(client));
+    }
+    next.run(req).await
+}
+
+/// The header for a connection from `client` to `local`. An unknown client is sent as
+/// `PROXY UNKNOWN` (v1) or a LOCAL command (v2). Mixed address families are both sent as IPv6.
+pub fn encode(
+    version: ProxyProtocolVersion,
+    client: Option<SocketAddr>,
+    local: SocketAddr,
+) -> Vec<u8> {
+    let addrs = client.map(|client| {
+        let ips = match (client.ip(), local.ip()) {
+            (IpAddr::V4(src), IpAddr::V4(dst)) => (IpAddr::V4(src), IpAddr::V4(dst)),
+            (src, dst) => (IpAddr::V6(to_v6(src)), IpAddr::V6(to_v6(dst))),
+        };
+        (ips, client.port(), local.port())
+    });
+    match (version, addrs) {
+        (ProxyProtocolVersion::V1, Some(((src, dst), sport, dport))) => format!(
+            "PROXY {} {} {} {} {}\r\n",
+            if src.is_ipv4() { "TCP4" } else { "TCP6" },
+            src,
+            dst,
+            sport,
+            dport
+        )
+        .into_bytes(),
+        (ProxyProtocolVersion::V1, None) => b"PROXY UNKNOWN\r\n".to_vec(),
+        (ProxyProtocolVersion::V2, addrs) => {
+            let mut out = V2_SIGNATURE.to_vec();
+            let Some(((src, dst), sport, dport)) = addrs else {
+                out.extend_from_slice(&[0x20, 0x00, 0, 0]);
+                return out;
+            };
+            let mut body = Vec::with_capacity(36);
+            let family = match (src, dst) {
+                (IpAddr::V4(src), IpAddr::V4(dst)) => {
+                    body.extend_from_slice(&src.octets());
+                    body.extend_from_slice(&dst.octets());
+                    0x11
+                }
+                (src, dst) => {
+                    body.extend_from_slice(&to_v6(src).octets());
+                    body.extend_from_slice(&to_v6(dst).octets());
+                    0x21
+                }
+            };
+            body.extend_from_slice(&sport.to_be_bytes());
+            body.extend_from_slice(&dport.to_be_bytes());
+            out.extend_from_slice(&[0x21, family]);
+            out.extend_from_slice(&(body.len() as u16).to_be_bytes());
+            out.extend_from_slice(&body);
+            out
+        }
+    }
+}
+
+fn to_v6(ip: IpAddr) -> Ipv6Addr {
+    match ip {
+        IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+        IpAddr::V6(ip) => ip,
+    }
+}
+
+pub type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Send `request` to its (http://) backend on a new connection opening with the PROXY header
+/// for `client` → `local`. The connection closes after this one exchange: the header names one
+/// client, so it can't be pooled for others.
+pub async fn send(
+    request: reqwest::Request,
+    version: ProxyProtocolVersion,
+    client: Option<SocketAddr>,
+    local: SocketAddr,
+) -> Result<reqwest::Response, BoxError> {
+    let url = request.url().clone();
+    let port = url
+        .port_or_known_default()
+        .ok_or("backend URL has no port")?;
+    let mut stream = match url.host() {
+        Some(Host::Domain(host)) => TcpStream::connect((host, port)).await?,
+        Some(Host::Ipv4(ip)) => TcpStream::connect((ip, port)).await?,
+        Some(Host::Ipv6(ip)) => TcpStream::connect((ip, port)).await?,
+        None => return Err("backend URL has no host".into()),
+    };
+    stream.set_nodelay(true)?;
+    stream.write_all(&encode(version, client, local)).await?;
+
+    let (mut sender, conn) = hyper::client::conn::http1::handshake(TokioIo::new(stream)).await?;
+    tokio::spawn(async move {
+        if let Err(e) = conn.await {
+            tracing::debug!("PROXY protocol backend connection failed: {}", e);
+        }
+    });
+
+    // A client connection sends origin-form and Host; hyper leaves both to us here.
+    let mut request = axum::http::Request::try_from(request)?;
+    *request.uri_mut() = url[Position::BeforePath..Position::AfterQuery].parse()?;
+    if !request.headers().contains_key(header::HOST) {
+        let host = &url[Position::BeforeHost..Position::AfterPort];
+        request
+            .headers_mut()
+            .insert(header::HOST, HeaderValue::from_str(host)?);
+    }
+    let response = sender.send_request(request).await?;
+    Ok(reqwest::Response::from(response.map(reqwest::Body::wrap)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ConnectionLimitAction;
+    use crate::conn_limit::{ConnectionPolicy, ConnectionTracker};
+    use axum::Router;
+    use axum_server::accept::Accept;
+    use tokio::net::TcpListener;
+
+    // The server end of a loopback connection whose client has sent `bytes`, and the client end.
+    async fn received(bytes: &[u8]) -> (TcpStream, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        client.write_all(bytes).await.unwrap();
+        let (server, _) = listener.accept().await.unwrap();
+        (server, client)
+    }
+
+    async fn rest(stream: &mut TcpStream, len: usize) -> Vec<u8> {
+        let mut buf = vec![0; len];
+        stream.read_exact(&mut buf).await.unwrap();
+        buf
+    }
+
+    fn v2(command: u8, family: u8, body: &[u8]) -> Vec<u8> {
+        let mut out = V2_SIGNATURE.to_vec();
+        out.extend_from_slice(&[0x20 | command, family]);
+        out.extend_from_slice(&(body.len() as u16).to_be_bytes());
+        out.extend_from_slice(body);
+        out
+    }
+
+    #[test]
+    fn v1_lines() {
+        assert_eq!(
+            parse_v1("PROXY TCP4 192.0.2.1 198.51.100.1 56324 443").unwrap(),
+            Some("192.0.2.1:56324".parse().unwrap())
+        );
+        assert_eq!(
+            parse_v1("PROXY TCP6 2001:db8::1 2001:db8::2 56324 443").unwrap(),
+            Some("[2001:db8::1]:56324".parse().unwrap())
+        );
+        assert_eq!(parse_v1("PROXY UNKNOWN").unwrap(), None);
+        assert_eq!(
+            parse_v1("PROXY UNKNOWN 192.0.2.1 198.51.100.1 1 2").unwrap(),
+            None
+        );
+
+        for bad in [
+            "PROXY TCP4 192.0.2.1 198.51.100.1 56324",
+            "PROXY TCP4 192.0.2.1 198.51.100.1 56324 443 extra",
+            "PROXY TCP4 192.0.2.1",
+            "PROXY TCP4 2001:db8::1 198.51.100.1 56324 443",
+            "PROXY TCP4 192.0.2.1 198.51.100.1 65536 443",
+            "PROXY UDP4 192.0.2.1 198.51.100.1 56324 443",
+            "GET / HTTP/1.1",
+        ] {
+            let err = parse_v1(bad).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData, "{bad}");
+        }
+    }
+
+    #[tokio::test]
+    async fn v1_leaves_what_follows_in_the_socket() {
+        let (mut stream, _client) =
+            received(b"PROXY TCP4 192.0.2.1 198.51.100.1 56324 443\r\nGET / HTTP/1.1\r\n").await;
+        assert_eq!(
+            read_v1(&mut stream).await.unwrap(),
+            Some("192.0.2.1:56324".parse().unwrap())
+        );
+        assert_eq!(rest(&mut stream, 16).await, b"GET / HTTP/1.1\r\n");
+    }
+
+    #[tokio::test]
+    async fn v1_refuses_overlong_and_foreign_lines() {
+        let long = format!("PROXY TCP4 {}\r\n", "1".repeat(V1_MAX_LEN));
+        let (mut stream, _client) = received(long.as_bytes()).await;
+        assert!(read_v1(&mut stream).await.is_err());
+
+        let (mut stream, _client) = received(b"GET / HTTP/1.1\r\n\r\n").await;
+        assert!(read_v1(&mut stream).await.is_err());
+
+        let (mut stream, _client) = received(b"PROXY UNKNOWN\n").await;
+        assert!(read_v1(&mut stream).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn v2_headers() {
+        let mut tcp4 = vec![192, 0, 2, 1, 198, 51, 100, 1];
+        tcp4.extend_from_slice(&56324u16.to_be_bytes());
+        tcp4.extend_from_slice(&443u16.to_be_bytes());
+        let mut header = v2(1, 0x11, &tcp4);
+        header.extend_from_slice(b"GET");
+        let (mut stream, _client) = received(&header).await;
+        assert_eq!(
+            read_v2(&mut stream).await.unwrap(),
+            Some("192.0.2.1:56324".parse().unwrap())
+        );
+        assert_eq!(rest(&mut stream, 3).await, b"GET");
+
+        let src: Ipv6Addr = "2001:db8::1".parse().unwrap();
+        let dst: Ipv6Addr = "2001:db8::2".parse().unwrap();
+        let mut tcp6 = [src.octets(), dst.octets()].concat();
+        tcp6.extend_from_slice(&56324u16.to_be_bytes());
+        tcp6.extend_from_slice(&443u16.to_be_bytes());
+        // TLVs after the addresses are skipped along with them.
+        tcp6.extend_from_slice(&[0x04, 0, 1, 0]);
+        let (mut stream, _client) = received(&v2(1, 0x21, &tcp6)).await;
+        assert_eq!(
+            read_v2(&mut stream).await.unwrap(),
+            Some("[2001:db8::1]:56324".parse().unwrap())
+        );
+
+        // LOCAL, as sent by the balancer's own health checks, with its body skipped.
+        let mut header = v2(0, 0x11, &tcp4);
+        header.extend_from_slice(b"GET");
+        let (mut stream, _client) = received(&header).await;
+        assert_eq!(read_v2(&mut stream).await.unwrap(), None);
+        assert_eq!(rest(&mut stream, 3).await, b"GET");
+
+        // A unix socket address carries no IP.
+        let (mut stream, _client) = received(&v2(1, 0x31, &[0; 216])).await;
+        assert_eq!(read_v2(&mut stream).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn v2_refuses_bad_headers() {
+        // An IPv4 address block cut short.
+        let (mut stream, _client) = received(&v2(1, 0x11, &[192, 0, 2, 1, 198, 51])).await;
+        assert!(read_v2(&mut stream).await.is_err());
+
+        let (mut stream, _client) = received(&v2(1, 0x21, &[0; 12])).await;
+        assert!(read_v2(&mut stream).await.is_err());
+
+        let (mut stream, _client) = received(&v2(2, 0x11, &[0; 12])).await;
+        assert!(read_v2(&mut stream).await.is_err());
+
+        // Fewer bytes than the declared length, then the client goes away.
+        let mut header = v2(1, 0x11, &[0; 12]);
+        header.truncate(20);
+        let (mut stream, client) = received(&header).await;
+        drop(client);
+        assert!(read_v2(&mut stream).await.is_err());
+
+        let (mut stream, _client) = received(b"GET / HTTP/1.1\r\nHost: a\r\n\r\n").await;
+        assert!(read_v2(&mut stream).await.is_err());
+    }
+
+    // Accept a connection whose client sent `bytes` first, with `trusted` peers expected to
+    // open with a v1 header. Returns what the client then sent, and the client address the
+    // service saw.
+    async fn accept(trusted: &str, bytes: &[u8]) -> io::Result<(Vec<u8>, String)> {
+        let tracker = Arc::new(ConnectionTracker::new(
+            "127.0.0.1:0".parse().unwrap(),
+            None,
+            None,
+            ConnectionLimitAction::default(),
+            None,
+            ConnectionPolicy::default(),
+        ));
+        let acceptor = ProxyProtocolAcceptor::new(
+            ConnLimitAcceptor::new(tracker),
+            Some(Arc::new(ProxyProtocolConfig {
+                version: ProxyProtocolVersion::V1,
+                trusted: vec![trusted.parse().unwrap()],
+            })),
+        );
+        let app = Router::new().fallback(|req: Request| async move {
+            match req.extensions().get::<ProxiedClient>() {
+                Some(ProxiedClient(client)) => client.to_string(),
+                None => "-".to_string(),
+            }
+        });
+        let (stream, _client) = received(bytes).await;
+        let (mut stream, mut service) = acceptor.accept(stream, app).await?;
+        let mut sent = vec![0; 3];
+        stream.read_exact(&mut sent).await?;
+        let resp = service
+            .call(axum::http::Request::new(axum::body::Body::empty()))
+            .await
+            .unwrap();
+        let body = axum::body::to_bytes(resp.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        Ok((sent, String::from_utf8(body.to_vec()).unwrap()))
+    }
+
+    #[tokio::test]
+    async fn only_trusted_peers_send_a_header() {
+        let header = b"PROXY TCP4 192.0.2.1 198.51.100.1 56324 443\r\nGET";
+        assert_eq!(
+            accept("127.0.0.0/8", header).await.unwrap(),
+            (b"GET".to_vec(), "192.0.2.1:56324".to_string())
+        );
+        assert!(accept("127.0.0.0/8", b"GET").await.is_err());
+
+        // An untrusted peer is served as a plain connection, header or not.
+        assert_eq!(
+            accept("10.0.0.0/8", b"GET").await.unwrap(),
+            (b"GET".to_vec(), "-".to_string())
+        );
+        assert_eq!(
+            accept("10.0.0.0/8", header).await.unwrap(),
+            (b"PRO".to_vec(), "-".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn encoded_headers_read_back() {
+        let addr = |a: &str| -> SocketAddr { a.parse().unwrap() };
+        let mapped = |a: &str| {
+            let a = addr(a);
+            SocketAddr::new(IpAddr::V6(to_v6(a.ip())), a.port())
+        };
+        let cases = [
+            (
+                Some(addr("192.0.2.1:56324")),
+                addr("198.51.100.1:443"),
+                Some(addr("192.0.2.1:56324")),
+            ),
+            (
+                Some(addr("[2001:db8::1]:56324")),
+                addr("[2001:db8::2]:443"),
+                Some(addr("[2001:db8::1]:56324")),
+            ),
+            // Mixed families go out as IPv6, the IPv4 side mapped.
+            (
+                Some(addr("192.0.2.1:56324")),
+                addr("[2001:db8::2]:443"),
+                Some(mapped("192.0.2.1:56324")),
+            ),
+            (
+                Some(addr("[2001:db8::1]:56324")),
+                addr("198.51.100.1:443"),
+                Some(addr("[2001:db8::1]:56324")),
+            ),
+            // An unknown client is PROXY UNKNOWN or LOCAL.
+            (None, addr("198.51.100.1:443"), None),
+        ];
+        for (client, local, expected) in cases {
+            let mut header = encode(ProxyProtocolVersion::V1, client, local);
+            header.extend_from_slice(b"GET");
+            let (mut stream, _client) = received(&header).await;
+            assert_eq!(
+                read_v1(&mut stream).await.unwrap(),
+                expected,
+                "v1 {client:?}"
+            );
+            assert_eq!(rest(&mut stream, 3).await, b"GET");
+
+            let mut header = encode(ProxyProtocolVersion::V2, client, local);
+            header.extend_from_slice(b"GET");
+            let (mut stream, _client) = received(&header).await;
+            assert_eq!(
+                read_v2(&mut stream).await.unwrap(),
+                expected,
+                "v2 {client:?}"
+            );
+            assert_eq!(rest(&mut stream, 3).await, b"GET");
+        }
+        assert_eq!(
+            encode(ProxyProtocolVersion::V1, None, addr("198.51.100.1:443")),
+            b"PROXY UNKNOWN\r\n"
+        );
+        assert_eq!(
+            encode(ProxyProtocolVersion::V2, None, addr("198.51.100.1:443"))[12..],
+            [0x20, 0x00, 0, 0]
+        );
+    }
+}
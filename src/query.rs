@@ -0,0 +1,159 @@
+use url::form_urlencoded;
+
+use crate::config::QueryRewrite;
+
+/// Decoded name of a raw `name=value` query pair.
+fn pair_name(pair: &str) -> String {
+    let raw_name = pair.split_once('=').map_or(pair, |(n, _)| n);
+    form_urlencoded::parse(raw_name.as_bytes())
+        .next()
+        .map(|(n, _)| n.into_owned())
+        .unwrap_or_default()
+}
+
+fn encode_pair(name: &str, value: &str) -> String {
+    form_urlencoded::Serializer::new(String::new())
+        .append_pair(name, value)
+        .finish()
+}
+
+/// Apply the configured rewrite operations, in order, to a raw query string.
+///
+/// Untouched parameters keep their original position and encoding, as do the values of renamed
+/// ones. Returns `None` when the resulting query is empty.
+pub fn rewrite_query<'a>(
+    query: Option<&str>,
+    rules: impl IntoIterator<Item = &'a QueryRewrite>,
+) -> Option<String> {
+    let mut pairs: Vec<String> = query
+        .unwrap_or("")
+        .split('&')
+        .filter(|p| !p.is_empty())
+        .map(str::to_string)
+        .collect();
+
+    for rule in rules {
+        match rule {
+            QueryRewrite::Add { name, value } => pairs.push(encode_pair(name, value)),
+            QueryRewrite::Set { name, value } => {
+                // Replace the first occurrence in place and drop any duplicates.
+                match pairs.iter().position(|p| pair_name(p) == *name) {
+                    Some(first) => {
+                        pairs[first] = encode_pair(name, value);
+                        let mut idx = 0;
+                        pairs.retain(|p| {
+                            let keep = idx == first || pair_name(p) != *name;
+                            idx += 1;
+                            keep
+                        });
+                    }
+                    None => pairs.push(encode_pair(name, value)),
+                }
+            }
+            QueryRewrite::Remove { name } => pairs.retain(|p| pair_name(p) != *name),
+            QueryRewrite::RemoveMatching { pattern } => {
+                pairs.retain(|p| !pattern.is_match(&pair_name(p)))
+            }
+            QueryRewrite::Rename { from, to } => {
+                for pair in pairs.iter_mut().filter(|p| pair_name(p) == *from) {
+                    let name: String = form_urlencoded::byte_serialize(to.as_bytes()).collect();
+                    *pair = match pair.split_once('=') {
+                        Some((_, value)) => format!("{}={}", name, value),
+                        None => name,
+                    };
+                }
+            }
+        }
+    }
+
+    if pairs.is_empty() {
+        None
+    } else {
+        Some(pairs.join("&"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::QueryRoute;
+    use regex::Regex;
+
+    fn set(name: &str, value: &str) -> QueryRewrite {
+        QueryRewrite::Set {
+            name: name.into(),
+            value: value.into(),
+        }
+    }
+
+    fn remove(name: &str) -> QueryRewrite {
+        QueryRewrite::Remove { name: name.into() }
+    }
+
+    fn rename(from: &str, to: &str) -> QueryRewrite {
+        QueryRewrite::Rename {
+            from: from.into(),
+            to: to.into(),
+        }
+    }
+
+    #[test]
+    fn rewrites() {
+        let utm = || QueryRewrite::RemoveMatching {
+            pattern: Regex::new("^utm_(.*)$").unwrap(),
+        };
+        let cases: Vec<(&str, Vec<QueryRewrite>, Option<&str>)> = vec![
+            // Set replaces the first occurrence where it stands and drops the rest.
+            ("a=1&b=2&a=3", vec![set("a", "x")], Some("a=x&b=2")),
+            (
+                "b=2&a=1&c=3&a=4&a=5",
+                vec![set("a", "x")],
+                Some("b=2&a=x&c=3"),
+            ),
+            ("b=2", vec![set("a", "x y")], Some("b=2&a=x+y")),
+            // Remove and RemoveMatching take every occurrence.
+            ("a=1&b=2&a=3&a", vec![remove("a")], Some("b=2")),
+            ("a=1&a=2", vec![remove("a")], None),
+            (
+                "utm_source=x&id=1&utm_medium=y&utm_source=z",
+                vec![utm()],
+                Some("id=1"),
+            ),
+            ("utm=1&xutm_a=2", vec![utm()], Some("utm=1&xutm_a=2")),
+            // Rename keeps each value exactly as the client encoded it.
+            (
+                "p=a%2Fb&q=1&p=c+d&p",
+                vec![rename("p", "path")],
+                Some("path=a%2Fb&q=1&path=c+d&path"),
+            ),
+            ("p%5B%5D=1", vec![rename("p[]", "p")], Some("p=1")),
+            // Names are compared decoded.
+            ("a%20b=1", vec![remove("a b")], None),
+        ];
+        for (query, rules, expected) in cases {
+            assert_eq!(
+                rewrite_query(Some(query), &rules).as_deref(),
+                expected,
+                "{query}"
+            );
+        }
+    }
+
+    #[test]
+    fn global_rules_run_before_route_rules() {
+        let global = [rename("id", "item"), set("v", "2")];
+        let route = QueryRoute {
+            path_prefix: "/api".into(),
+            rewrites: vec![remove("id"), set("item", "x"), remove("v")],
+        };
+        // The route sees the renamed parameter, not the original one, and has the last word.
+        assert_eq!(
+            rewrite_query(Some("id=7&v=1"), global.iter().chain(&route.rewrites)).as_deref(),
+            Some("item=x")
+        );
+        assert_eq!(
+            rewrite_query(Some("id=7&v=1"), route.rewrites.iter().chain(&global)).as_deref(),
+            Some("item=x&v=2")
+        );
+    }
+}
@@ -0,0 +1,127 @@
+use axum::{
+    body::Body,
+    http::{Request, StatusCode},
+};
+use dashmap::DashMap;
+use std::net::IpAddr;
+use std::time::Instant;
+
+use crate::access;
+use crate::config::{MissingIpPolicy, RateLimitKey, RateLimitRule};
+use crate::proxy::AppState;
+
+// Bucket shared by every request without an attributable IP (MissingIpPolicy::SharedBucket).
+const MISSING_IP_BUCKET: &str = "<unknown>";
+
+// Bucket shared by new keys once a rule's map is full (`rate_limit_max_entries`).
+const OVERFLOW_BUCKET: &str = "<overflow>";
+
+/// A single rate-limit rule together with its token buckets.
+pub struct RateLimiter {
+    pub rule: RateLimitRule,
+    // Token buckets keyed by the rule's dimension value (tokens, last_seen)
+    pub buckets: DashMap<String, (f64, Instant)>,
+}
+
+impl RateLimiter {
+    pub fn new(rule: RateLimitRule) -> Self {
+        Self {
+            rule,
+            buckets: DashMap::new(),
+        }
+    }
+
+    /// Resolve the bucket key for this request from `ip`, its client address as resolved
+    /// through `trusted_proxies`, or None if the rule does not apply to it.
+    fn bucket_key(&self, req: &Request<Body>, ip: Option<IpAddr>) -> Option<String> {
+        match &self.rule.key {
+            RateLimitKey::Ip => ip.map(|ip| ip.to_string()),
+            RateLimitKey::Header(name) => req
+                .headers()
+                .get(name.as_str())
+                .and_then(|v| v.to_str().ok())
+                .map(|v| v.trim().to_string()),
+            RateLimitKey::Global => Some(String::new()),
+        }
+    }
+}
+
+/// Evaluate every configured rule; the request is rejected if any of them trips.
+///
+/// Tokens are only consumed when all applicable rules allow the request, so a rejection by one
+/// rule doesn't drain the buckets of the others. Clients in `rate_limit_exempt_ips` skip every
+/// rule.
+pub fn check_rate_limit(state: &AppState, req: &Request<Body>) -> Result<(), StatusCode> {
+    if state.rate_limiters.is_empty() {
+        return Ok(());
+    }
+    // X-Forwarded-For only counts from trusted_proxies, so a client can't pick its own bucket.
+    let ip = access::client_ip(req, &state.trusted_proxies);
+    if ip.is_some_and(|ip| {
+        state
+            .rate_limit_exempt_ips
+            .iter()
+            .any(|net| net.contains(&ip))
+    }) {
+        return Ok(());
+    }
+
+    let now = Instant::now();
+
+    // Hold the bucket guards until the verdict is known. Each rule has its own map, so
+    // holding several guards at once can't deadlock a shard.
+    let mut guards = Vec::with_capacity(state.rate_limiters.len());
+    for limiter in state.rate_limiters.iter() {
+        let key = match limiter.bucket_key(req, ip) {
+            Some(k) => k,
+            None if limiter.rule.key == RateLimitKey::Ip => {
+                tracing::debug!(
+                    "no client IP for rate limiting, applying rate_limit_on_missing_ip = {:?}",
+                    state.rate_limit_on_missing_ip
+                );
+                match state.rate_limit_on_missing_ip {
+                    MissingIpPolicy::Allow => continue,
+                    MissingIpPolicy::Deny => return Err(StatusCode::TOO_MANY_REQUESTS),
+                    MissingIpPolicy::SharedBucket => MISSING_IP_BUCKET.to_string(),
+                }
+            }
+            None => continue, // Rule doesn't apply (e.g. header absent); allow
+        };
+
+        // Bound the number of distinct buckets: once the map is full, unseen keys share one
+        // overflow bucket instead of growing the map (e.g. under a spoofed-IP flood).
+        let key = match state.rate_limit_max_entries {
+            Some(max) if limiter.buckets.len() >= max && !limiter.buckets.contains_key(&key) => {
+                tracing::debug!(
+                    "rate limit map for {:?} is full, using shared overflow bucket",
+                    limiter.rule.key
+                );
+                OVERFLOW_BUCKET.to_string()
+            }
+            _ => key,
+        };
+
+        // New buckets start with 0 tokens to avoid allowing a large initial burst.
+        let mut entry = limiter.buckets.entry(key).or_insert((0.0, now));
+        let rate_per_sec = limiter.rule.per_minute / 60.0;
+        let elapsed = now.duration_since(entry.1).as_secs_f64();
+        entry.0 = (entry.0 + elapsed * rate_per_sec).min(limiter.rule.burst);
+        entry.1 = now;
+
+        if entry.0 < 1.0 {
+            tracing::debug!(
+                "rate limit exceeded for {:?} key '{}'",
+                limiter.rule.key,
+                entry.key()
+            );
+            return Err(StatusCode::TOO_MANY_REQUESTS);
+        }
+        guards.push(entry);
+    }
+
+    for mut entry in guards {
+        entry.0 -= 1.0;
+    }
+
+    Ok(())
+}
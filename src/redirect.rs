@@ -0,0 +1,239 @@
+//! `[[servers.redirects]]`: redirects the proxy answers itself, without asking a backend.
+
+use axum::{
+    extract::Request,
+    http::{StatusCode, header},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use percent_encoding::{AsciiSet, CONTROLS, percent_decode_str, utf8_percent_encode};
+use std::sync::Arc;
+use url::Url;
+
+use crate::config::{RedirectFrom, RedirectPart, RedirectRule};
+
+// Escaped in captured text put into a target: what can't stand in a URL, plus the characters
+// that would start a query or fragment. '/' stays, so captured sub-paths keep their shape,
+// except where it would double a slash (see `expand`).
+const CAPTURE: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?')
+    .add(b'\\')
+    .add(b'^')
+    .add(b'`')
+    .add(b'{')
+    .add(b'|')
+    .add(b'}');
+
+impl RedirectRule {
+    // The target for `path`, if the rule matches it (query string not yet added).
+    fn target(&self, path: &str) -> Option<String> {
+        let captures = match &self.from {
+            RedirectFrom::Exact(p) => return (p == path).then(|| self.expand(&[])),
+            RedirectFrom::Pattern(re) => re.captures(path)?,
+        };
+        let groups: Vec<&str> = captures
+            .iter()
+            .map(|m| m.map_or("", |m| m.as_str()))
+            .collect();
+        Some(self.expand(&groups))
+    }
+
+    // `to` with the captures filled in. A capture starting with '/' right after a '/' (or at
+    // the start) gets it escaped, so `/go//evil.com` can't turn `/$1` into `//evil.com`.
+    fn expand(&self, groups: &[&str]) -> String {
+        let mut out = String::new();
+        for part in &self.to {
+            match part {
+                RedirectPart::Literal(s) => out.push_str(s),
+                RedirectPart::Capture(n) => {
+                    let text = groups.get(*n).copied().unwrap_or("");
+                    let text = match text.strip_prefix('/') {
+                        Some(rest) if out.is_empty() || out.ends_with('/') => {
+                            out.push_str("%2F");
+                            rest
+                        }
+                        _ => text,
+                    };
+                    out.extend(utf8_percent_encode(text, CAPTURE));
+                }
+            }
+        }
+        out
+    }
+
+    // Whether `target` stays where `to` points: a path that isn't protocol-relative, or a URL
+    // on the origin `to` names before any capture.
+    fn stays_put(&self, target: &str) -> bool {
+        if target.starts_with('/') {
+            return !target.starts_with("//") && !target.starts_with("/\\");
+        }
+        match (Url::parse(target), Url::parse(&self.expand(&[]))) {
+            (Ok(target), Ok(written)) => target.origin() == written.origin(),
+            _ => false,
+        }
+    }
+}
+
+/// A server's `redirects`, checked in order.
+#[derive(Debug)]
+pub struct Redirects {
+    rules: Vec<RedirectRule>,
+}
+
+impl Redirects {
+    pub fn new(rules: Vec<RedirectRule>) -> Self {
+        Self { rules }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Exact `from` paths that an earlier rule already catches, with that rule's index, so
+    /// the later rule never fires.
+    pub fn shadowed(&self) -> Vec<(usize, usize)> {
+        self.rules
+            .iter()
+            .enumerate()
+            .filter_map(|(i, rule)| {
+                let RedirectFrom::Exact(path) = &rule.from else {
+                    return None;
+                };
+                let earlier = self.rules[..i].iter().position(|r| r.from.is_match(path))?;
+                Some((i, earlier))
+            })
+            .collect()
+    }
+
+    // The redirect for `req`, if a rule matches.
+    fn check(&self, req: &Request) -> Option<Response> {
+        let path = percent_decode_str(req.uri().path()).decode_utf8_lossy();
+        let (rule, mut target) = self
+            .rules
+            .iter()
+            .find_map(|rule| Some((rule, rule.target(&path)?)))?;
+        if !rule.stays_put(&target) {
+            tracing::warn!(
+                "redirect to '{}' would send {} off-site as {}, not redirecting",
+                rule.to_raw,
+                path,
+                target
+            );
+            return None;
+        }
+        if let Some(query) = req.uri().query().filter(|_| !rule.drop_query) {
+            target.push(if target.contains('?') { '&' } else { '?' });
+            target.push_str(query);
+        }
+        // A target built from captures can land on the path it came from; serve that
+        // request normally rather than send the client round in circles.
+        let target_path = target.split('?').next().unwrap_or(&target);
+        if target.starts_with('/') && percent_decode_str(target_path).decode_utf8_lossy() == path {
+            tracing::warn!(
+                "redirect to '{}' would send {} to itself, not redirecting",
+                rule.to_raw,
+                path
+            );
+            return None;
+        }
+        tracing::debug!("redirecting {} to {} ({})", path, target, rule.status);
+        let status = StatusCode::from_u16(rule.status).unwrap_or(StatusCode::FOUND);
+        Some((status, [(header::LOCATION, target)]).into_response())
+    }
+}
+
+/// Middleware answering requests that match a `redirects` rule, ahead of routes, static files
+/// and the proxy.
+pub async fn handle(redirects: Arc<Redirects>, req: Request, next: Next) -> Response {
+    match redirects.check(&req) {
+        Some(resp) => resp,
+        None => next.run(req).await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use regex::Regex;
+
+    fn rule(from: &str, to: Vec<RedirectPart>) -> RedirectRule {
+        RedirectRule {
+            from: RedirectFrom::Pattern(Regex::new(from).unwrap()),
+            to_raw: String::new(),
+            to,
+            status: 302,
+            drop_query: false,
+        }
+    }
+
+    fn location(redirects: &Redirects, uri: &str) -> Option<String> {
+        let req = Request::builder().uri(uri).body(Body::empty()).unwrap();
+        let resp = redirects.check(&req)?;
+        Some(
+            resp.headers()[header::LOCATION]
+                .to_str()
+                .unwrap()
+                .to_string(),
+        )
+    }
+
+    #[test]
+    fn captures_cannot_make_a_protocol_relative_target() {
+        let redirects = Redirects::new(vec![rule(
+            "^/go/(.*)$",
+            vec![RedirectPart::Literal("/".into()), RedirectPart::Capture(1)],
+        )]);
+        assert_eq!(
+            location(&redirects, "/go//evil.com").as_deref(),
+            Some("/%2Fevil.com")
+        );
+        assert_eq!(
+            location(&redirects, "/go/%2Fevil.com").as_deref(),
+            Some("/%2Fevil.com")
+        );
+        assert_eq!(
+            location(&redirects, "/go/%5Cevil.com").as_deref(),
+            Some("/%5Cevil.com")
+        );
+        assert_eq!(location(&redirects, "/go/a/b").as_deref(), Some("/a/b"));
+    }
+
+    #[test]
+    fn captures_keep_inner_slashes() {
+        let redirects = Redirects::new(vec![rule(
+            "^/old(.*)$",
+            vec![
+                RedirectPart::Literal("/new".into()),
+                RedirectPart::Capture(1),
+            ],
+        )]);
+        assert_eq!(
+            location(&redirects, "/old/a/b?x=1").as_deref(),
+            Some("/new/a/b?x=1")
+        );
+    }
+
+    #[test]
+    fn captures_cannot_change_the_host() {
+        let redirects = Redirects::new(vec![rule(
+            "^/old(.*)$",
+            vec![
+                RedirectPart::Literal("https://example.com".into()),
+                RedirectPart::Capture(1),
+            ],
+        )]);
+        assert_eq!(
+            location(&redirects, "/old/a").as_deref(),
+            Some("https://example.com/a")
+        );
+        assert_eq!(location(&redirects, "/old@evil.com"), None);
+        assert_eq!(location(&redirects, "/old.evil.com/a"), None);
+    }
+}
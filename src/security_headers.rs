@@ -0,0 +1,108 @@
+use axum::http::{HeaderName, HeaderValue, Response};
+
+use crate::config::ConfigEntry;
+
+const EXPECT_CT: HeaderName = HeaderName::from_static("expect-ct");
+const REPORT_TO: HeaderName = HeaderName::from_static("report-to");
+const NEL: HeaderName = HeaderName::from_static("nel");
+
+/// Reporting headers (`Expect-CT`, `Report-To`, `NEL`) added to proxied and static responses.
+///
+/// A header the backend already set is left as it is.
+#[derive(Debug, Clone)]
+pub struct SecurityHeaders {
+    headers: Vec<(HeaderName, HeaderValue)>,
+}
+
+impl SecurityHeaders {
+    /// `None` when the server has no `[servers.security_headers]` or it is disabled.
+    pub fn from_config(cfg: &ConfigEntry) -> Option<Self> {
+        let sh = cfg.security_headers.as_ref()?;
+        // Values were checked during validation.
+        let headers: Vec<_> = [
+            (EXPECT_CT, &sh.expect_ct),
+            (REPORT_TO, &sh.report_to),
+            (NEL, &sh.nel),
+        ]
+        .into_iter()
+        .filter_map(|(name, v)| {
+            let value = HeaderValue::from_str(v.as_deref()?).ok()?;
+            Some((name, value))
+        })
+        .collect();
+        (!headers.is_empty()).then_some(Self { headers })
+    }
+
+    pub fn apply<B>(&self, resp: &mut Response<B>) {
+        for (name, value) in &self.headers {
+            if !resp.headers().contains_key(name) {
+                resp.headers_mut().insert(name.clone(), value.clone());
+            }
+        }
+    }
+}
+
+/// Check an `Expect-CT` value: `max-age=<secs>` plus optional `enforce` and `report-uri="<url>"`.
+pub fn check_expect_ct(value: &str) -> Result<(), String> {
+    let mut max_age = false;
+    for directive in value.split(',').map(str::trim) {
+        let (name, arg) = match directive.split_once('=') {
+            Some((n, a)) => (n.trim(), Some(a.trim())),
+            None => (directive, None),
+        };
+        match (name.to_ascii_lowercase().as_str(), arg) {
+            ("max-age", Some(secs)) if secs.parse::<u64>().is_ok() => max_age = true,
+            ("enforce", None) => {}
+            ("report-uri", Some(uri)) => {
+                let uri = uri
+                    .strip_prefix('"')
+                    .and_then(|u| u.strip_suffix('"'))
+                    .ok_or("report-uri must be a quoted URL")?;
+                url::Url::parse(uri).map_err(|e| format!("invalid report-uri: {}", e))?;
+            }
+            _ => return Err(format!("unexpected directive '{}'", directive)),
+        }
+    }
+    if !max_age {
+        return Err("max-age is required".to_string());
+    }
+    Ok(())
+}
+
+/// Check a `Report-To` value: one or more comma-separated JSON endpoint groups, each with
+/// `max_age` and a non-empty `endpoints` list of `{ "url": ... }` objects.
+pub fn check_report_to(value: &str) -> Result<(), String> {
+    let groups: Vec<serde_json::Value> = serde_json::from_str(&format!("[{}]", value))
+        .map_err(|e| format!("not a list of JSON objects: {}", e))?;
+    for group in &groups {
+        if !group.get("max_age").is_some_and(|v| v.is_u64()) {
+            return Err("each group needs a numeric max_age".to_string());
+        }
+        let endpoints = group
+            .get("endpoints")
+            .and_then(|v| v.as_array())
+            .filter(|e| !e.is_empty())
+            .ok_or("each group needs a non-empty endpoints list")?;
+        for endpoint in endpoints {
+            let url = endpoint
+                .get("url")
+                .and_then(|v| v.as_str())
+                .ok_or("each endpoint needs a url")?;
+            url::Url::parse(url).map_err(|e| format!("invalid endpoint url '{}': {}", url, e))?;
+        }
+    }
+    Ok(())
+}
+
+/// Check a `NEL` value: a JSON object naming a `report_to` group and a numeric `max_age`.
+pub fn check_nel(value: &str) -> Result<(), String> {
+    let policy: serde_json::Value =
+        serde_json::from_str(value).map_err(|e| format!("not a JSON object: {}", e))?;
+    if !policy.get("report_to").is_some_and(|v| v.is_string()) {
+        return Err("report_to group name is required".to_string());
+    }
+    if !policy.get("max_age").is_some_and(|v| v.is_u64()) {
+        return Err("numeric max_age is required".to_string());
+    }
+    Ok(())
+}
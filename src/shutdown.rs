@@ -0,0 +1,138 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use tokio::time::Instant;
+use tracing::{info, warn};
+
+use crate::transfers::Transfers;
+
+/// Listens for process termination signals (CTRL+C everywhere, SIGTERM on unix).
+pub struct ShutdownSignals {
+    #[cfg(unix)]
+    sigterm: Option<tokio::signal::unix::Signal>,
+}
+
+impl ShutdownSignals {
+    pub fn new() -> Self {
+        #[cfg(unix)]
+        {
+            use tokio::signal::unix::{SignalKind, signal};
+            let sigterm = signal(SignalKind::terminate())
+                .map_err(|e| warn!("failed to install SIGTERM handler: {}", e))
+                .ok();
+            Self { sigterm }
+        }
+        #[cfg(not(unix))]
+        {
+            Self {}
+        }
+    }
+
+    /// Wait for the next signal and return its name.
+    pub async fn recv(&mut self) -> &'static str {
+        let ctrl_c = async {
+            if let Err(e) = tokio::signal::ctrl_c().await {
+                warn!("failed to install CTRL+C handler: {}", e);
+                std::future::pending::<()>().await;
+            }
+        };
+
+        #[cfg(unix)]
+        let term = async {
+            match self.sigterm.as_mut() {
+                Some(s) => {
+                    s.recv().await;
+                }
+                None => std::future::pending::<()>().await,
+            }
+        };
+        #[cfg(not(unix))]
+        let term = std::future::pending::<()>();
+
+        tokio::select! {
+            _ = ctrl_c => "SIGINT",
+            _ = term => "SIGTERM",
+        }
+    }
+}
+
+// How often shutdown progress is logged while waiting for connections to finish.
+const PROGRESS_EVERY: Duration = Duration::from_secs(1);
+
+/// Drive graceful shutdown of every listener sharing `handle`.
+///
+/// On the first signal readiness is flipped to failing and in-flight connections get `grace`
+/// to finish, with progress logged every second; a second signal (or the deadline) closes
+/// whatever is left immediately. With `max_grace` set, the deadline is pushed back a second at a
+/// time (up to `max_grace` in total) for as long as streaming transfers keep completing.
+pub async fn watch(
+    handle: axum_server::Handle,
+    ready: Arc<AtomicBool>,
+    transfers: Arc<Transfers>,
+    grace: Duration,
+    max_grace: Option<Duration>,
+) {
+    let mut signals = ShutdownSignals::new();
+
+    let name = signals.recv().await;
+    info!(
+        "{} received, shutting down gracefully (grace period {:?})",
+        name, grace
+    );
+    ready.store(false, Ordering::SeqCst);
+    crate::systemd::notify("STOPPING=1");
+    // The deadline is enforced below rather than by the server, so it can be extended.
+    handle.graceful_shutdown(None);
+
+    let started = Instant::now();
+    let hard_deadline = started + max_grace.unwrap_or(grace).max(grace);
+    let mut deadline = started + grace;
+    let mut last_active = transfers.active();
+
+    loop {
+        let wake = (Instant::now() + PROGRESS_EVERY).min(deadline);
+        tokio::select! {
+            name = signals.recv() => {
+                warn!(
+                    "{} received during shutdown, terminating immediately with {} connection(s) open ({} transfer(s) in flight)",
+                    name,
+                    handle.connection_count(),
+                    transfers.active()
+                );
+                handle.shutdown();
+                return;
+            }
+            _ = tokio::time::sleep_until(wake) => {}
+        }
+
+        let connections = handle.connection_count();
+        if connections == 0 {
+            return;
+        }
+        let active = transfers.active();
+        let now = Instant::now();
+        if now < deadline {
+            info!(
+                "waiting for {} in-flight transfer(s) on {} connection(s)",
+                active, connections
+            );
+        } else if active > 0 && active < last_active && now < hard_deadline {
+            deadline = (now + PROGRESS_EVERY).min(hard_deadline);
+            info!(
+                "grace period extended: {} in-flight transfer(s) still completing ({} finished in the last second)",
+                active,
+                last_active - active
+            );
+        } else {
+            warn!(
+                "grace period expired after {:?}, aborting {} in-flight transfer(s) on {} connection(s)",
+                now - started,
+                active,
+                connections
+            );
+            handle.shutdown();
+            return;
+        }
+        last_active = active;
+    }
+}
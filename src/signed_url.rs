@@ -0,0 +1,244 @@
+//! `signed_urls`: time-limited links to files under `static_dir`.
+//!
+//! Below each of `required_prefixes` a request must carry `expires` (Unix seconds) and `sig`,
+//! the hex HMAC-SHA256 of `<path>\n<expires>` under the server's secret. Everything else under
+//! `/static` stays public. [`UrlSigner::sign`] (or `serava sign-url`) produces such links.
+
+use axum::{
+    extract::{OriginalUri, Request},
+    http::StatusCode,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use percent_encoding::percent_decode_str;
+use ring::hmac;
+use std::fmt;
+use std::sync::Arc;
+
+use crate::config::SignedUrlsConfig;
+use crate::path::{has_path_prefix, normalize_path};
+use crate::signing::{to_hex, unix_now};
+
+pub const EXPIRES_PARAM: &str = "expires";
+pub const SIGNATURE_PARAM: &str = "sig";
+
+/// Why a request for a protected path was refused.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rejection {
+    /// `expires` or `sig` is absent or not well-formed.
+    Missing,
+    Expired,
+    BadSignature,
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Rejection::Missing => "missing or malformed signature",
+            Rejection::Expired => "link expired",
+            Rejection::BadSignature => "signature mismatch",
+        })
+    }
+}
+
+/// Signs and verifies links with one secret.
+pub struct UrlSigner {
+    key: hmac::Key,
+}
+
+impl fmt::Debug for UrlSigner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("UrlSigner").finish_non_exhaustive()
+    }
+}
+
+fn message(path: &str, expires: u64) -> String {
+    format!("{}\n{}", path, expires)
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+impl UrlSigner {
+    pub fn new(secret: &[u8]) -> Self {
+        Self {
+            key: hmac::Key::new(hmac::HMAC_SHA256, secret),
+        }
+    }
+
+    /// Lowercase hex signature for `path` (as it appears in the URL, percent-encoded) valid
+    /// until `expires`.
+    pub fn signature(&self, path: &str, expires: u64) -> String {
+        to_hex(hmac::sign(&self.key, message(path, expires).as_bytes()).as_ref())
+    }
+
+    /// `path?expires=<expires>&sig=<signature>`.
+    pub fn sign(&self, path: &str, expires: u64) -> String {
+        format!(
+            "{}?{}={}&{}={}",
+            path,
+            EXPIRES_PARAM,
+            expires,
+            SIGNATURE_PARAM,
+            self.signature(path, expires)
+        )
+    }
+
+    /// Check the `expires`/`sig` parameters in `query` against `path` at time `now`.
+    pub fn verify(&self, path: &str, query: Option<&str>, now: u64) -> Result<(), Rejection> {
+        let (mut expires, mut sig) = (None, None);
+        for (name, value) in url::form_urlencoded::parse(query.unwrap_or("").as_bytes()) {
+            match &*name {
+                EXPIRES_PARAM if expires.is_none() => expires = Some(value),
+                SIGNATURE_PARAM if sig.is_none() => sig = Some(value),
+                _ => {}
+            }
+        }
+        let expires: u64 = expires
+            .and_then(|e| e.parse().ok())
+            .ok_or(Rejection::Missing)?;
+        let sig = sig.and_then(|s| decode_hex(&s)).ok_or(Rejection::Missing)?;
+        // ring compares in constant time.
+        hmac::verify(&self.key, message(path, expires).as_bytes(), &sig)
+            .map_err(|_| Rejection::BadSignature)?;
+        if expires <= now {
+            return Err(Rejection::Expired);
+        }
+        Ok(())
+    }
+}
+
+/// A server's signer and the prefixes it guards.
+#[derive(Debug)]
+pub struct SignedUrls {
+    signer: UrlSigner,
+    required_prefixes: Vec<String>,
+}
+
+impl SignedUrls {
+    pub fn new(config: &SignedUrlsConfig) -> Self {
+        Self {
+            signer: UrlSigner::new(config.secret.expose().as_bytes()),
+            required_prefixes: config.required_prefixes.clone(),
+        }
+    }
+
+    // Whether `path` needs a signature. The prefixes are compared with the path as the file
+    // server will resolve it, so `//` or percent-encoding can't step around them.
+    fn is_protected(&self, path: &str) -> bool {
+        let decoded = percent_decode_str(path).decode_utf8_lossy();
+        match normalize_path(&decoded) {
+            Some(normalized) => self
+                .required_prefixes
+                .iter()
+                .any(|p| has_path_prefix(&normalized, p)),
+            None => true,
+        }
+    }
+}
+
+/// Middleware on the static file service refusing unsigned, tampered or expired requests
+/// below a protected prefix with 403.
+pub async fn handle(urls: Arc<SignedUrls>, req: Request, next: Next) -> Response {
+    // The static service is nested under /static; the prefixes name the full path.
+    let uri = req
+        .extensions()
+        .get::<OriginalUri>()
+        .map_or_else(|| req.uri().clone(), |u| u.0.clone());
+    if !urls.is_protected(uri.path()) {
+        return next.run(req).await;
+    }
+    match urls.signer.verify(uri.path(), uri.query(), unix_now()) {
+        Ok(()) => next.run(req).await,
+        Err(e) => {
+            tracing::warn!("refusing {}: {}", uri.path(), e);
+            StatusCode::FORBIDDEN.into_response()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: u64 = 1_700_000_000;
+
+    fn query(link: &str) -> Option<&str> {
+        link.split_once('?').map(|(_, q)| q)
+    }
+
+    #[test]
+    fn signed_link_verifies_until_it_expires() {
+        let signer = UrlSigner::new(b"secret");
+        let link = signer.sign("/static/private/report.pdf", NOW + 60);
+        let q = query(&link);
+        assert_eq!(signer.verify("/static/private/report.pdf", q, NOW), Ok(()));
+        assert_eq!(
+            signer.verify("/static/private/report.pdf", q, NOW + 60),
+            Err(Rejection::Expired)
+        );
+    }
+
+    #[test]
+    fn tampering_is_refused() {
+        let signer = UrlSigner::new(b"secret");
+        let link = signer.sign("/static/private/a.pdf", NOW + 60);
+        let q = query(&link).unwrap();
+        assert_eq!(
+            signer.verify("/static/private/b.pdf", Some(q), NOW),
+            Err(Rejection::BadSignature)
+        );
+        let later = q.replace(&format!("{}", NOW + 60), &format!("{}", NOW + 6000));
+        assert_eq!(
+            signer.verify("/static/private/a.pdf", Some(&later), NOW),
+            Err(Rejection::BadSignature)
+        );
+        let other = UrlSigner::new(b"other").sign("/static/private/a.pdf", NOW + 60);
+        assert_eq!(
+            signer.verify("/static/private/a.pdf", query(&other), NOW),
+            Err(Rejection::BadSignature)
+        );
+    }
+
+    #[test]
+    fn missing_or_malformed_parameters_are_refused() {
+        let signer = UrlSigner::new(b"secret");
+        for q in [
+            None,
+            Some("expires=1"),
+            Some("sig=00"),
+            Some("expires=x&sig=00"),
+            Some("expires=1&sig=zz"),
+            Some("expires=1&sig=0"),
+        ] {
+            assert_eq!(
+                signer.verify("/static/private/a", q, NOW),
+                Err(Rejection::Missing),
+                "{:?}",
+                q
+            );
+        }
+    }
+
+    #[test]
+    fn only_required_prefixes_are_protected() {
+        let urls = SignedUrls {
+            signer: UrlSigner::new(b"secret"),
+            required_prefixes: vec!["/static/private".into()],
+        };
+        assert!(urls.is_protected("/static/private/a.pdf"));
+        assert!(urls.is_protected("/static//private/a.pdf"));
+        assert!(urls.is_protected("/static/%70rivate/a.pdf"));
+        assert!(urls.is_protected("/static/public/../private/a.pdf"));
+        assert!(urls.is_protected("/static/../../a"));
+        assert!(!urls.is_protected("/static/public/a.pdf"));
+        assert!(!urls.is_protected("/static/privateer/a.pdf"));
+    }
+}
@@ -0,0 +1,105 @@
+use axum::{
+    extract::{OriginalUri, Request},
+    http::{StatusCode, Uri, header},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use percent_encoding::percent_decode_str;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+
+/// Ordered default documents (`index_files`) for directory requests on the static mount.
+///
+/// A directory request is rewritten to the first listed file that exists in any of the static
+/// directories; if none does, it is passed on unchanged and the static service (built without
+/// its own `index.html` default) answers 404. Directories requested without a trailing slash
+/// are redirected first, as `ServeDir` would.
+#[derive(Debug, Clone)]
+pub struct IndexFiles {
+    roots: Arc<Vec<PathBuf>>,
+    names: Arc<Vec<String>>,
+}
+
+impl IndexFiles {
+    pub fn new(roots: Vec<PathBuf>, names: Vec<String>) -> Self {
+        Self {
+            roots: Arc::new(roots),
+            names: Arc::new(names),
+        }
+    }
+
+    // Relative filesystem path for a request path, rejecting anything that would leave a root.
+    fn resolve(&self, path: &str) -> Option<PathBuf> {
+        let decoded = percent_decode_str(path.trim_start_matches('/'))
+            .decode_utf8()
+            .ok()?;
+        let mut out = PathBuf::new();
+        for component in Path::new(&*decoded).components() {
+            match component {
+                Component::Normal(c) => out.push(c),
+                Component::CurDir => {}
+                _ => return None,
+            }
+        }
+        Some(out)
+    }
+}
+
+pub async fn handle(index: IndexFiles, mut req: Request, next: Next) -> Response {
+    let path = req.uri().path().to_string();
+    let Some(relative) = index.resolve(&path) else {
+        return next.run(req).await;
+    };
+    let mut dirs = Vec::new();
+    for root in index.roots.iter() {
+        let dir = root.join(&relative);
+        if tokio::fs::metadata(&dir).await.is_ok_and(|m| m.is_dir()) {
+            dirs.push(dir);
+        }
+    }
+    if dirs.is_empty() {
+        return next.run(req).await;
+    }
+
+    if !path.ends_with('/') {
+        // The path seen here has the mount prefix stripped; redirect using the original one.
+        let original = req
+            .extensions()
+            .get::<OriginalUri>()
+            .map_or(req.uri(), |o| &o.0);
+        let location = match original.query() {
+            Some(q) => format!("{}/?{}", original.path(), q),
+            None => format!("{}/", original.path()),
+        };
+        return (
+            StatusCode::TEMPORARY_REDIRECT,
+            [(header::LOCATION, location)],
+        )
+            .into_response();
+    }
+
+    for name in index.names.iter() {
+        let mut found = false;
+        for dir in &dirs {
+            if tokio::fs::metadata(dir.join(name))
+                .await
+                .is_ok_and(|m| m.is_file())
+            {
+                found = true;
+                break;
+            }
+        }
+        if !found {
+            continue;
+        }
+        let rewritten = match req.uri().query() {
+            Some(q) => format!("{}{}?{}", path, name, q),
+            None => format!("{}{}", path, name),
+        };
+        if let Ok(uri) = rewritten.parse::<Uri>() {
+            *req.uri_mut() = uri;
+        }
+        break;
+    }
+    next.run(req).await
+}
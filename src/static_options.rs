@@ -0,0 +1,98 @@
+use axum::{
+    Router,
+    body::Body,
+    extract::Request,
+    http::{Method, StatusCode, header},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use tower_service::Service;
+
+/// Answer `OPTIONS` on the static mount directly: `204` with `Allow` when the file exists,
+/// `404` otherwise. Other methods pass through to the static service.
+///
+/// `probe` must serve the same directories but answer a missing file with a plain 404 instead
+/// of the 404 page, so it is reported as missing.
+pub async fn handle(mut probe: Router, req: Request, next: Next) -> Response {
+    if req.method() != Method::OPTIONS {
+        return next.run(req).await;
+    }
+
+    let mut head = Request::new(Body::empty());
+    *head.method_mut() = Method::HEAD;
+    *head.uri_mut() = req.uri().clone();
+    // ServeDir is always ready, so there is no need to poll it first.
+    let exists = match probe.call(head).await {
+        Ok(resp) => resp.status().is_success(),
+        Err(e) => match e {},
+    };
+
+    if exists {
+        (
+            StatusCode::NO_CONTENT,
+            [(header::ALLOW, "GET, HEAD, OPTIONS")],
+        )
+            .into_response()
+    } else {
+        StatusCode::NOT_FOUND.into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tower_http::services::ServeDir;
+
+    // The static mount as lib.rs builds it: a directory with `app.js`, whose misses (and any
+    // request the layer passes on) answer "next".
+    fn mount() -> (Router, std::path::PathBuf) {
+        let dir =
+            std::env::temp_dir().join(format!("serava-static-options-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("app.js"), "1").unwrap();
+        let probe = Router::new().fallback_service(
+            ServeDir::new(&dir)
+                .fallback(Router::new().fallback(|| async { StatusCode::NOT_FOUND })),
+        );
+        let app = Router::new()
+            .fallback(|| async { "next" })
+            .layer(axum::middleware::from_fn(move |req, next| {
+                handle(probe.clone(), req, next)
+            }));
+        (app, dir)
+    }
+
+    async fn call(app: &mut Router, method: Method, path: &str) -> Response {
+        let req = Request::builder()
+            .method(method)
+            .uri(path)
+            .body(Body::empty())
+            .unwrap();
+        match app.call(req).await {
+            Ok(resp) => resp,
+            Err(e) => match e {},
+        }
+    }
+
+    #[tokio::test]
+    async fn options_on_existing_and_missing_files() {
+        let (mut app, dir) = mount();
+
+        let resp = call(&mut app, Method::OPTIONS, "/app.js").await;
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+        assert_eq!(resp.headers()[header::ALLOW], "GET, HEAD, OPTIONS");
+
+        let resp = call(&mut app, Method::OPTIONS, "/missing.js").await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        assert!(resp.headers().get(header::ALLOW).is_none());
+
+        // Other methods are left to the static service.
+        let resp = call(&mut app, Method::GET, "/app.js").await;
+        let body = axum::body::to_bytes(resp.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(&body[..], b"next");
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,55 @@
+//! `static_extensions`: serve requests for known static file types from the static directories
+//! at their own path, ahead of the proxy, so mixed apps don't need everything under `/static`.
+
+use axum::{
+    Router,
+    body::Body,
+    extract::Request,
+    http::{Method, StatusCode},
+    middleware::Next,
+    response::Response,
+};
+use tower_service::Service;
+
+/// The extensions served locally and the service looking them up in the static directories.
+#[derive(Debug, Clone)]
+pub struct StaticExtensions {
+    // Lowercase, without the dot
+    extensions: Vec<String>,
+    // Answers 404 for a file in none of the directories
+    files: Router,
+}
+
+impl StaticExtensions {
+    pub fn new(extensions: Vec<String>, files: Router) -> Self {
+        Self { extensions, files }
+    }
+
+    // Whether the last segment of `path` ends in one of the extensions.
+    fn matches(&self, path: &str) -> bool {
+        let name = path.rsplit('/').next().unwrap_or_default();
+        name.rsplit_once('.').is_some_and(|(stem, ext)| {
+            !stem.is_empty() && self.extensions.iter().any(|e| e.eq_ignore_ascii_case(ext))
+        })
+    }
+}
+
+/// Serve GET and HEAD requests for a listed extension from the static directories. A file found
+/// in none of them is passed on to the proxy, as is everything else.
+pub async fn handle(mut routes: StaticExtensions, req: Request, next: Next) -> Response {
+    if !matches!(*req.method(), Method::GET | Method::HEAD) || !routes.matches(req.uri().path()) {
+        return next.run(req).await;
+    }
+    // Bodiless, so the request can be handed on whole if the file isn't there.
+    let (parts, body) = req.into_parts();
+    let probe = Request::from_parts(parts.clone(), Body::empty());
+    // A Router is always ready, so there's no need to poll_ready first.
+    let resp = match routes.files.call(probe).await {
+        Ok(resp) => resp,
+        Err(never) => match never {},
+    };
+    if resp.status() == StatusCode::NOT_FOUND {
+        return next.run(Request::from_parts(parts, body)).await;
+    }
+    resp
+}
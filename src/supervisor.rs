@@ -0,0 +1,116 @@
+use serde::Serialize;
+use std::future::Future;
+use std::io;
+use std::net::{SocketAddr, TcpListener};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::config::RestartPolicy;
+
+// A run lasting this long counts as healthy and resets the consecutive-failure count.
+const STABLE_AFTER: Duration = Duration::from_secs(60);
+
+/// Restart bookkeeping for one accept loop, reported by the stats endpoint.
+#[derive(Debug, Clone, Serialize)]
+pub struct ServerTaskStatus {
+    pub listen: SocketAddr,
+    pub restarts: u32,
+    pub last_failure: Option<String>,
+    /// The restart limit was reached and the task is no longer running.
+    pub failed: bool,
+}
+
+/// Status of every supervised accept loop in the process.
+#[derive(Debug, Default)]
+pub struct Supervisor {
+    tasks: Mutex<Vec<ServerTaskStatus>>,
+}
+
+impl Supervisor {
+    pub fn snapshot(&self) -> Vec<ServerTaskStatus> {
+        self.lock().clone()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Vec<ServerTaskStatus>> {
+        self.tasks.lock().unwrap_or_else(|p| p.into_inner())
+    }
+
+    fn register(&self, listen: SocketAddr) -> usize {
+        let mut tasks = self.lock();
+        tasks.push(ServerTaskStatus {
+            listen,
+            restarts: 0,
+            last_failure: None,
+            failed: false,
+        });
+        tasks.len() - 1
+    }
+
+    fn update(&self, id: usize, f: impl FnOnce(&mut ServerTaskStatus)) {
+        if let Some(task) = self.lock().get_mut(id) {
+            f(task);
+        }
+    }
+
+    /// Run `serve` on a fresh handle to `listener` until it returns `Ok` (shutdown), restarting
+    /// it with backoff when it fails or panics.
+    ///
+    /// Returns an error once `policy.limit` consecutive failures have been reached.
+    pub async fn supervise<F, Fut>(
+        &self,
+        listener: TcpListener,
+        policy: &RestartPolicy,
+        mut serve: F,
+    ) -> Result<(), String>
+    where
+        F: FnMut(TcpListener) -> Fut,
+        Fut: Future<Output = io::Result<()>> + Send + 'static,
+    {
+        let listen = listener
+            .local_addr()
+            .map_err(|e| format!("listener has no local address: {}", e))?;
+        let id = self.register(listen);
+        let mut failures = 0;
+        let mut delay = policy.backoff;
+
+        loop {
+            let started = Instant::now();
+            // Each run gets its own descriptor, so the socket outlives a failed serve loop.
+            let result = match listener.try_clone() {
+                Ok(dup) => match tokio::spawn(serve(dup)).await {
+                    Ok(Ok(())) => return Ok(()),
+                    Ok(Err(e)) => e.to_string(),
+                    Err(e) if e.is_panic() => "server task panicked".to_string(),
+                    Err(e) => e.to_string(),
+                },
+                Err(e) => format!("failed to reopen listener: {}", e),
+            };
+
+            if started.elapsed() >= STABLE_AFTER {
+                failures = 0;
+                delay = policy.backoff;
+            }
+            failures += 1;
+            self.update(id, |t| t.last_failure = Some(result.clone()));
+
+            if failures > policy.limit {
+                self.update(id, |t| t.failed = true);
+                return Err(format!(
+                    "server {} failed {} times in a row, giving up: {}",
+                    listen, failures, result
+                ));
+            }
+            tracing::error!(
+                "server {} failed: {}; restarting in {:?} ({}/{})",
+                listen,
+                result,
+                delay,
+                failures,
+                policy.limit
+            );
+            tokio::time::sleep(delay).await;
+            delay = delay.saturating_mul(2);
+            self.update(id, |t| t.restarts += 1);
+        }
+    }
+}
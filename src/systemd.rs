@@ -0,0 +1,149 @@
+//! systemd integration: socket activation (`LISTEN_FDS`) and `sd_notify` status messages.
+//!
+//! Everything here is a no-op on non-Linux targets or when systemd did not set the
+//! corresponding environment variables.
+
+use std::net::{SocketAddr, TcpListener};
+use std::time::Duration;
+use tracing::{info, warn};
+
+/// A listening socket handed over by systemd.
+#[derive(Debug)]
+pub struct ActivatedSocket {
+    /// `FileDescriptorName=` from the socket unit (systemd defaults it to the unit name).
+    pub name: Option<String>,
+    pub local_addr: SocketAddr,
+    pub listener: TcpListener,
+}
+
+/// Collect the TCP sockets passed via `LISTEN_FDS`, if they are meant for this process.
+#[cfg(target_os = "linux")]
+pub fn listen_fds() -> Vec<ActivatedSocket> {
+    use std::os::fd::FromRawFd;
+
+    // First passed descriptor, per sd_listen_fds(3).
+    const SD_LISTEN_FDS_START: i32 = 3;
+
+    let for_us = std::env::var("LISTEN_PID")
+        .ok()
+        .and_then(|p| p.parse::<u32>().ok())
+        .is_some_and(|pid| pid == std::process::id());
+    if !for_us {
+        return Vec::new();
+    }
+
+    let count = match std::env::var("LISTEN_FDS")
+        .ok()
+        .and_then(|n| n.parse::<i32>().ok())
+    {
+        Some(n) if n > 0 => n,
+        _ => return Vec::new(),
+    };
+    let names: Vec<String> = std::env::var("LISTEN_FDNAMES")
+        .map(|v| v.split(':').map(str::to_string).collect())
+        .unwrap_or_default();
+
+    let mut sockets = Vec::with_capacity(count as usize);
+    for i in 0..count {
+        let fd = SD_LISTEN_FDS_START + i;
+        // SAFETY: systemd guarantees descriptors 3..3+LISTEN_FDS are open and owned by us.
+        let listener = unsafe { TcpListener::from_raw_fd(fd) };
+        match listener.local_addr() {
+            Ok(local_addr) => {
+                let name = names.get(i as usize).cloned();
+                info!(
+                    "received systemd socket fd={} addr={} name={:?}",
+                    fd, local_addr, name
+                );
+                sockets.push(ActivatedSocket {
+                    name,
+                    local_addr,
+                    listener,
+                });
+            }
+            Err(e) => warn!("ignoring systemd fd={}: not a TCP listener ({})", fd, e),
+        }
+    }
+    sockets
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn listen_fds() -> Vec<ActivatedSocket> {
+    Vec::new()
+}
+
+/// Take the activated sockets for a server: by `name` when given, otherwise by address.
+///
+/// Usually one; a binary upgrade hands over every `SO_REUSEPORT` acceptor of a server.
+pub fn take_listeners(
+    sockets: &mut Vec<ActivatedSocket>,
+    addr: SocketAddr,
+    name: Option<&str>,
+) -> Vec<TcpListener> {
+    let (taken, rest) =
+        std::mem::take(sockets)
+            .into_iter()
+            .partition::<Vec<_>, _>(|s| match name {
+                Some(name) => s.name.as_deref() == Some(name),
+                None => s.local_addr == addr,
+            });
+    *sockets = rest;
+    taken.into_iter().map(|s| s.listener).collect()
+}
+
+/// Send a state string (e.g. `READY=1`) to the service manager.
+#[cfg(target_os = "linux")]
+pub fn notify(state: &str) {
+    use std::os::linux::net::SocketAddrExt;
+    use std::os::unix::net::{SocketAddr as UnixAddr, UnixDatagram};
+
+    let path = match std::env::var_os("NOTIFY_SOCKET") {
+        Some(p) => p,
+        None => return,
+    };
+
+    let result = (|| {
+        let bytes = path.as_encoded_bytes();
+        let addr = match bytes.strip_prefix(b"@") {
+            Some(abstract_name) => UnixAddr::from_abstract_name(abstract_name)?,
+            None => UnixAddr::from_pathname(&path)?,
+        };
+        UnixDatagram::unbound()?.send_to_addr(state.as_bytes(), &addr)
+    })();
+
+    if let Err(e) = result {
+        warn!("sd_notify({}) failed: {}", state, e);
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn notify(_state: &str) {}
+
+/// Watchdog ping interval (half of `WATCHDOG_USEC`) when systemd expects keep-alives from us.
+pub fn watchdog_interval() -> Option<Duration> {
+    if !cfg!(target_os = "linux") {
+        return None;
+    }
+    if let Some(pid) = std::env::var("WATCHDOG_PID")
+        .ok()
+        .and_then(|p| p.parse::<u32>().ok())
+        && pid != std::process::id()
+    {
+        return None;
+    }
+    let usec = std::env::var("WATCHDOG_USEC")
+        .ok()?
+        .parse::<u64>()
+        .ok()
+        .filter(|&u| u > 0)?;
+    Some(Duration::from_micros(usec / 2))
+}
+
+/// Ping the watchdog forever at `interval`.
+pub async fn watchdog(interval: Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        ticker.tick().await;
+        notify("WATCHDOG=1");
+    }
+}
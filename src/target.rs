@@ -0,0 +1,90 @@
+use axum::{
+    body::Body,
+    http::{HeaderValue, Request, Uri, Version, header, uri::Authority},
+};
+use std::fmt;
+
+/// Why a request target was refused.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TargetError {
+    /// `GET http://host/x` over HTTP/1 while `allow_absolute_form` is off.
+    AbsoluteForm,
+    /// The absolute-form authority names a different host than the Host header.
+    HostMismatch,
+    /// The authority carries `user:pass@`.
+    Userinfo,
+    /// Whitespace, control characters, a fragment, or a path not starting with `/`.
+    Malformed,
+}
+
+impl fmt::Display for TargetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            TargetError::AbsoluteForm => "absolute-form request target",
+            TargetError::HostMismatch => "request target authority does not match Host",
+            TargetError::Userinfo => "request target contains userinfo",
+            TargetError::Malformed => "malformed request target",
+        })
+    }
+}
+
+/// Reduce the request target to origin-form (path and query), so match rules, the cache key and
+/// the upstream URL only ever see the path.
+///
+/// HTTP/1 absolute-form targets are refused unless `allow_absolute_form` is set, and then only
+/// when their authority matches the Host header. HTTP/2 requests always carry scheme and
+/// authority in the URI and are reduced to their path and query.
+pub fn to_origin_form(
+    req: &mut Request<Body>,
+    allow_absolute_form: bool,
+) -> Result<(), TargetError> {
+    let uri = req.uri().clone();
+    if uri.authority().is_some_and(|a| a.as_str().contains('@')) {
+        return Err(TargetError::Userinfo);
+    }
+    let origin = uri.path_and_query().map_or("/", |pq| pq.as_str());
+    if !origin.starts_with('/')
+        || origin
+            .chars()
+            .any(|c| c == '#' || c.is_whitespace() || c.is_control())
+    {
+        return Err(TargetError::Malformed);
+    }
+
+    let Some(authority) = uri.authority() else {
+        return Ok(());
+    };
+    if req.version() < Version::HTTP_2 {
+        if !allow_absolute_form {
+            return Err(TargetError::AbsoluteForm);
+        }
+        let default_port = if uri.scheme_str() == Some("https") {
+            443
+        } else {
+            80
+        };
+        let host = req
+            .headers()
+            .get(header::HOST)
+            .and_then(|h| h.to_str().ok())
+            .and_then(|h| h.parse::<Authority>().ok());
+        let same = host.is_some_and(|h| {
+            h.host().eq_ignore_ascii_case(authority.host())
+                && h.port_u16().unwrap_or(default_port)
+                    == authority.port_u16().unwrap_or(default_port)
+        });
+        if !same {
+            return Err(TargetError::HostMismatch);
+        }
+    } else if !req.headers().contains_key(header::HOST)
+        && let Ok(host) = HeaderValue::from_str(authority.as_str())
+    {
+        // The authority is about to be dropped; keep it as Host, as an HTTP/1 hop would
+        // (RFC 9113, 8.3.1), so checks after this one see the same thing on every version.
+        req.headers_mut().insert(header::HOST, host);
+    }
+
+    let origin: Uri = origin.parse().map_err(|_| TargetError::Malformed)?;
+    *req.uri_mut() = origin;
+    Ok(())
+}
@@ -0,0 +1,150 @@
+use axum_server::tls_rustls::RustlsConfig;
+use ring::aead::{self, Aad, LessSafeKey, NONCE_LEN, Nonce, UnboundKey};
+use ring::rand::{SecureRandom, SystemRandom};
+use rustls::ServerConfig;
+use rustls::crypto::GetRandomFailed;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::{NoServerSessionStorage, ProducesTickets, ServerSessionMemoryCache};
+use std::io;
+use std::sync::Arc;
+
+use crate::config::TlsConfig;
+
+const KEY_NAME_LEN: usize = 16;
+// Room for the first request head of a resumed connection; anything longer waits for the
+// handshake as usual.
+const MAX_EARLY_DATA_BYTES: u32 = 16 * 1024;
+
+/// Session ticket encrypter with a random ChaCha20-Poly1305 key.
+///
+/// Instances are created and rotated by rustls' `TicketRotator`; tickets are
+/// `key_name || nonce || ciphertext+tag`.
+struct AeadTicketer {
+    key: LessSafeKey,
+    key_name: [u8; KEY_NAME_LEN],
+    rng: SystemRandom,
+}
+
+impl std::fmt::Debug for AeadTicketer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // Never print key material
+        f.debug_struct("AeadTicketer").finish_non_exhaustive()
+    }
+}
+
+impl AeadTicketer {
+    fn generate() -> Result<Box<dyn ProducesTickets>, GetRandomFailed> {
+        let rng = SystemRandom::new();
+        let mut key_bytes = [0u8; 32];
+        let mut key_name = [0u8; KEY_NAME_LEN];
+        rng.fill(&mut key_bytes).map_err(|_| GetRandomFailed)?;
+        rng.fill(&mut key_name).map_err(|_| GetRandomFailed)?;
+        let key =
+            UnboundKey::new(&aead::CHACHA20_POLY1305, &key_bytes).map_err(|_| GetRandomFailed)?;
+        Ok(Box::new(Self {
+            key: LessSafeKey::new(key),
+            key_name,
+            rng,
+        }))
+    }
+}
+
+impl ProducesTickets for AeadTicketer {
+    fn enabled(&self) -> bool {
+        true
+    }
+
+    fn lifetime(&self) -> u32 {
+        // The lifetime is enforced by the rotator wrapping this ticketer.
+        0
+    }
+
+    fn encrypt(&self, plain: &[u8]) -> Option<Vec<u8>> {
+        let mut nonce_bytes = [0u8; NONCE_LEN];
+        self.rng.fill(&mut nonce_bytes).ok()?;
+
+        let mut out = Vec::with_capacity(KEY_NAME_LEN + NONCE_LEN + plain.len() + 16);
+        out.extend_from_slice(&self.key_name);
+        out.extend_from_slice(&nonce_bytes);
+
+        let mut in_out = plain.to_vec();
+        self.key
+            .seal_in_place_append_tag(
+                Nonce::assume_unique_for_key(nonce_bytes),
+                Aad::from(&self.key_name),
+                &mut in_out,
+            )
+            .ok()?;
+        out.extend_from_slice(&in_out);
+        Some(out)
+    }
+
+    fn decrypt(&self, cipher: &[u8]) -> Option<Vec<u8>> {
+        let (key_name, rest) = cipher.split_at_checked(KEY_NAME_LEN)?;
+        if key_name != self.key_name {
+            return None;
+        }
+        let (nonce_bytes, sealed) = rest.split_at_checked(NONCE_LEN)?;
+        let nonce = Nonce::try_assume_unique_for_key(nonce_bytes).ok()?;
+
+        let mut in_out = sealed.to_vec();
+        let plain_len = self
+            .key
+            .open_in_place(nonce, Aad::from(&self.key_name), &mut in_out)
+            .ok()?
+            .len();
+        in_out.truncate(plain_len);
+        Some(in_out)
+    }
+}
+
+/// Build the rustls server config for a listener, applying the session resumption settings.
+pub fn load_rustls_config(tls: &TlsConfig) -> io::Result<RustlsConfig> {
+    let mut config = server_config(tls)?;
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    if tls.early_data.is_some() {
+        config.max_early_data_size = MAX_EARLY_DATA_BYTES;
+    }
+    Ok(RustlsConfig::from_config(Arc::new(config)))
+}
+
+/// QUIC server config for the HTTP/3 listener, sharing the certificate and resumption settings
+/// of the TCP listener. 0-RTT stays off, since early data can be replayed.
+#[cfg(feature = "http3")]
+pub fn load_quic_config(tls: &TlsConfig) -> io::Result<quinn::ServerConfig> {
+    let mut config = server_config(tls)?;
+    config.alpn_protocols = vec![b"h3".to_vec()];
+    let crypto =
+        quinn::crypto::rustls::QuicServerConfig::try_from(config).map_err(io::Error::other)?;
+    Ok(quinn::ServerConfig::with_crypto(Arc::new(crypto)))
+}
+
+fn server_config(tls: &TlsConfig) -> io::Result<ServerConfig> {
+    let certs = CertificateDer::pem_file_iter(&tls.cert)
+        .and_then(|it| it.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| io::Error::other(format!("{}: {}", tls.cert.display(), e)))?;
+    let key = PrivateKeyDer::from_pem_file(&tls.key)
+        .map_err(|e| io::Error::other(format!("{}: {}", tls.key.display(), e)))?;
+
+    let mut config = ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .map_err(io::Error::other)?;
+
+    // Stateful resumption (server-side session cache). 0 disables it.
+    config.session_storage = if tls.session_cache_size == 0 {
+        Arc::new(NoServerSessionStorage {})
+    } else {
+        ServerSessionMemoryCache::new(tls.session_cache_size)
+    };
+
+    // Stateless resumption (session tickets), with keys rotated every `ticket_lifetime_secs`.
+    if let Some(lifetime) = tls.ticket_lifetime_secs {
+        let rotator = rustls::TicketRotator::new(lifetime, AeadTicketer::generate)
+            .map_err(io::Error::other)?;
+        config.ticketer = Arc::new(rotator);
+    }
+
+    Ok(config)
+}
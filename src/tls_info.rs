@@ -0,0 +1,115 @@
+//! The negotiated TLS version and cipher suite of each connection, for `forward_tls_info`.
+
+use axum::http::{HeaderName, Request};
+use futures::future::BoxFuture;
+use rustls::ProtocolVersion;
+use std::io;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tokio_rustls::server::TlsStream;
+use tower_service::Service;
+
+pub const TLS_VERSION_HEADER: HeaderName = HeaderName::from_static("x-tls-version");
+pub const TLS_CIPHER_HEADER: HeaderName = HeaderName::from_static("x-tls-cipher");
+
+/// Request extension describing the TLS session the request arrived on.
+#[derive(Debug, Clone)]
+pub struct TlsInfo {
+    /// `TLSv1.2` or `TLSv1.3`.
+    pub version: &'static str,
+    /// IANA name of the cipher suite, e.g. `TLS_AES_128_GCM_SHA256`.
+    pub cipher: Arc<str>,
+}
+
+impl TlsInfo {
+    /// The TLS session of the connection `req` arrived on, if it was recorded.
+    pub fn of<B>(req: &Request<B>) -> Option<&TlsInfo> {
+        req.extensions().get::<TlsInfo>()
+    }
+
+    fn of_connection(conn: &rustls::ServerConnection) -> Option<Self> {
+        let version = match conn.protocol_version()? {
+            ProtocolVersion::TLSv1_2 => "TLSv1.2",
+            ProtocolVersion::TLSv1_3 => "TLSv1.3",
+            _ => return None,
+        };
+        let name = conn.negotiated_cipher_suite()?.suite().as_str()?;
+        // rustls marks TLS 1.3 suites with a `TLS13_` prefix the IANA names don't have.
+        let cipher = match name.strip_prefix("TLS13_") {
+            Some(rest) => format!("TLS_{}", rest).into(),
+            None => name.into(),
+        };
+        Some(Self { version, cipher })
+    }
+}
+
+/// Per-connection service attaching the connection's [`TlsInfo`] to each request.
+#[derive(Debug, Clone)]
+pub struct TlsInfoService<S> {
+    inner: S,
+    info: Option<TlsInfo>,
+}
+
+impl<S, B> Service<Request<B>> for TlsInfoService<S>
+where
+    S: Service<Request<B>>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: Request<B>) -> Self::Future {
+        if let Some(info) = &self.info {
+            req.extensions_mut().insert(info.clone());
+        }
+        self.inner.call(req)
+    }
+}
+
+/// axum-server acceptor wrapping the rustls acceptor `inner`, recording each connection's
+/// [`TlsInfo`] when `enabled`.
+#[derive(Debug, Clone)]
+pub struct TlsInfoAcceptor<A> {
+    inner: A,
+    enabled: bool,
+}
+
+impl<A> TlsInfoAcceptor<A> {
+    pub fn new(inner: A, enabled: bool) -> Self {
+        Self { inner, enabled }
+    }
+}
+
+impl<A, I, S, T> axum_server::accept::Accept<I, S> for TlsInfoAcceptor<A>
+where
+    A: axum_server::accept::Accept<I, S, Stream = TlsStream<T>>,
+    A::Future: Send + 'static,
+{
+    type Stream = TlsStream<T>;
+    type Service = TlsInfoService<A::Service>;
+    type Future = BoxFuture<'static, io::Result<(Self::Stream, Self::Service)>>;
+
+    fn accept(&self, stream: I, service: S) -> Self::Future {
+        let accept = self.inner.accept(stream, service);
+        let enabled = self.enabled;
+        Box::pin(async move {
+            let (stream, service) = accept.await?;
+            let info = if enabled {
+                TlsInfo::of_connection(stream.get_ref().1)
+            } else {
+                None
+            };
+            Ok((
+                stream,
+                TlsInfoService {
+                    inner: service,
+                    info,
+                },
+            ))
+        })
+    }
+}
@@ -0,0 +1,80 @@
+use axum::body::{Body, HttpBody};
+use axum::http::Response;
+use bytes::Bytes;
+use hyper::body::{Frame, SizeHint};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::task::{Context, Poll};
+
+/// Proxied response bodies still streaming from a backend to a client, across all servers.
+///
+/// Graceful shutdown watches this to report progress and to decide whether waiting longer
+/// would let transfers finish instead of cutting them off.
+#[derive(Debug, Default)]
+pub struct Transfers {
+    active: AtomicUsize,
+}
+
+/// Counts one streaming body as in flight until dropped, i.e. until the body has been sent,
+/// failed, or was abandoned by the client.
+#[derive(Debug)]
+pub struct TransferGuard(Arc<Transfers>);
+
+impl Drop for TransferGuard {
+    fn drop(&mut self) {
+        self.0.active.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl Transfers {
+    pub fn start(self: &Arc<Self>) -> TransferGuard {
+        self.active.fetch_add(1, Ordering::Relaxed);
+        TransferGuard(self.clone())
+    }
+
+    pub fn active(&self) -> usize {
+        self.active.load(Ordering::Relaxed)
+    }
+}
+
+/// Keep `guard` alive until the body of `resp` has been sent in full, failed, or was dropped by
+/// a client that went away, rather than only until the response head is ready.
+pub fn hold_until_sent<G>(resp: Response<Body>, guard: G) -> Response<Body>
+where
+    G: Send + Unpin + 'static,
+{
+    let (parts, inner) = resp.into_parts();
+    Response::from_parts(
+        parts,
+        Body::new(GuardedBody {
+            inner,
+            _guard: guard,
+        }),
+    )
+}
+
+struct GuardedBody<G> {
+    inner: Body,
+    _guard: G,
+}
+
+impl<G: Unpin> HttpBody for GuardedBody<G> {
+    type Data = Bytes;
+    type Error = axum::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, axum::Error>>> {
+        Pin::new(&mut self.inner).poll_frame(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
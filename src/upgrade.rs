@@ -0,0 +1,280 @@
+//! Zero-downtime binary upgrade (unix, `upgrade` feature).
+//!
+//! On `SIGUSR2` the running process re-executes its own binary path with the same arguments,
+//! passing every bound listener as an inherited descriptor listed in `SERAVA_UPGRADE_FDS`. The
+//! successor picks them up through the same path as systemd socket activation, and writes a
+//! byte to the `SERAVA_UPGRADE_READY_FD` pipe once it is serving. Only then does the old process
+//! stop accepting and drain in-flight requests; if the successor exits or never reports ready,
+//! it is killed and the old process carries on as before.
+//!
+//! Without the feature (or off unix) everything here is a no-op.
+
+use std::net::TcpListener;
+use std::time::Duration;
+
+#[cfg(all(unix, feature = "upgrade"))]
+use tracing::{error, info, warn};
+
+use crate::systemd::ActivatedSocket;
+
+#[cfg(all(unix, feature = "upgrade"))]
+const FDS_ENV: &str = "SERAVA_UPGRADE_FDS";
+#[cfg(all(unix, feature = "upgrade"))]
+const READY_FD_ENV: &str = "SERAVA_UPGRADE_READY_FD";
+#[cfg(all(unix, feature = "upgrade"))]
+const PARENT_PID_ENV: &str = "SERAVA_UPGRADE_PARENT_PID";
+
+// How long a successor gets to bind its servers and report ready.
+#[cfg(all(unix, feature = "upgrade"))]
+const READY_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Duplicates of every bound listener, kept so they can be handed to a successor process.
+#[derive(Debug, Default)]
+pub struct HandoffSockets {
+    #[cfg(all(unix, feature = "upgrade"))]
+    sockets: Vec<(TcpListener, Option<String>)>,
+}
+
+impl HandoffSockets {
+    /// Keep `listener` for handoff; `name` is the systemd socket name the server selects by.
+    #[cfg(all(unix, feature = "upgrade"))]
+    pub fn register(&mut self, listener: &TcpListener, name: Option<&str>) {
+        match listener.try_clone() {
+            Ok(dup) => self.sockets.push((dup, name.map(str::to_string))),
+            Err(e) => warn!("cannot keep listener for binary upgrade: {}", e),
+        }
+    }
+
+    #[cfg(not(all(unix, feature = "upgrade")))]
+    pub fn register(&mut self, _listener: &TcpListener, _name: Option<&str>) {}
+}
+
+/// Whether this process was started by a binary upgrade of a still-running instance.
+#[cfg(all(unix, feature = "upgrade"))]
+pub fn is_upgrade_child() -> bool {
+    std::env::var(PARENT_PID_ENV)
+        .ok()
+        .and_then(|p| p.parse::<u32>().ok())
+        .is_some_and(|pid| pid == std::os::unix::process::parent_id())
+}
+
+#[cfg(not(all(unix, feature = "upgrade")))]
+pub fn is_upgrade_child() -> bool {
+    false
+}
+
+/// Listeners inherited from the process that started this one, in socket-activation form.
+#[cfg(all(unix, feature = "upgrade"))]
+pub fn inherited_fds() -> Vec<ActivatedSocket> {
+    use std::os::fd::FromRawFd;
+
+    if !is_upgrade_child() {
+        return Vec::new();
+    }
+    let spec = std::env::var(FDS_ENV).unwrap_or_default();
+
+    let mut sockets = Vec::new();
+    for entry in spec.split(',').filter(|e| !e.is_empty()) {
+        let (fd, name) = match entry.split_once(':') {
+            Some((fd, name)) => (fd, Some(name.to_string())),
+            None => (entry, None),
+        };
+        let Ok(fd) = fd.parse::<i32>() else {
+            warn!("ignoring malformed {} entry '{}'", FDS_ENV, entry);
+            continue;
+        };
+        // Don't leak the descriptor into whatever we exec next; a later upgrade re-passes it.
+        if let Err(e) = set_cloexec(fd, true) {
+            warn!("ignoring inherited fd={}: {}", fd, e);
+            continue;
+        }
+        // SAFETY: the parent passed these descriptors to us and nothing else owns them.
+        let listener = unsafe { TcpListener::from_raw_fd(fd) };
+        match listener.local_addr() {
+            Ok(local_addr) => {
+                info!(
+                    "inherited listener fd={} addr={} name={:?} from previous instance",
+                    fd, local_addr, name
+                );
+                sockets.push(ActivatedSocket {
+                    name,
+                    local_addr,
+                    listener,
+                });
+            }
+            Err(e) => warn!("ignoring inherited fd={}: not a TCP listener ({})", fd, e),
+        }
+    }
+    sockets
+}
+
+#[cfg(not(all(unix, feature = "upgrade")))]
+pub fn inherited_fds() -> Vec<ActivatedSocket> {
+    Vec::new()
+}
+
+/// Tell the previous instance that every server is up, so it can start draining.
+#[cfg(all(unix, feature = "upgrade"))]
+pub fn notify_parent() {
+    use std::io::Write;
+    use std::os::fd::FromRawFd;
+
+    if !is_upgrade_child() {
+        return;
+    }
+    let Some(fd) = std::env::var(READY_FD_ENV)
+        .ok()
+        .and_then(|v| v.parse::<i32>().ok())
+    else {
+        return;
+    };
+    // SAFETY: the parent passed us the write end of its readiness pipe; we close it here.
+    let mut pipe = unsafe { std::fs::File::from_raw_fd(fd) };
+    if let Err(e) = pipe.write_all(b"1") {
+        warn!("failed to signal readiness to previous instance: {}", e);
+    }
+}
+
+#[cfg(not(all(unix, feature = "upgrade")))]
+pub fn notify_parent() {}
+
+/// Handle `SIGUSR2` by starting a successor and, once it is serving, draining this process.
+#[cfg(all(unix, feature = "upgrade"))]
+pub async fn watch(handoff: HandoffSockets, handle: axum_server::Handle, grace: Duration) {
+    use tokio::signal::unix::{SignalKind, signal};
+
+    let mut sigusr2 = match signal(SignalKind::user_defined2()) {
+        Ok(s) => s,
+        Err(e) => {
+            warn!(
+                "failed to install SIGUSR2 handler, binary upgrade disabled: {}",
+                e
+            );
+            return;
+        }
+    };
+
+    while sigusr2.recv().await.is_some() {
+        info!(
+            "SIGUSR2 received, starting new instance with {} listener(s)",
+            handoff.sockets.len()
+        );
+        match spawn_successor(&handoff).await {
+            Ok(pid) => {
+                info!(
+                    "new instance (pid {}) is serving, draining connections (grace period {:?})",
+                    pid, grace
+                );
+                crate::systemd::notify(&format!("MAINPID={}", pid));
+                handle.graceful_shutdown(Some(grace));
+                return;
+            }
+            Err(e) => error!("binary upgrade failed, continuing to serve: {}", e),
+        }
+    }
+}
+
+#[cfg(not(all(unix, feature = "upgrade")))]
+pub async fn watch(_handoff: HandoffSockets, _handle: axum_server::Handle, _grace: Duration) {}
+
+#[cfg(all(unix, feature = "upgrade"))]
+async fn spawn_successor(handoff: &HandoffSockets) -> std::io::Result<u32> {
+    use std::io::{self, Read};
+    use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+    use std::os::unix::process::CommandExt;
+    use std::process::Command;
+
+    let mut pipe = [0; 2];
+    // SAFETY: `pipe` has room for the two descriptors pipe(2) writes.
+    if unsafe { libc::pipe(pipe.as_mut_ptr()) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: pipe(2) just returned these as fresh descriptors owned by us.
+    let (ready_rx, ready_tx) =
+        unsafe { (OwnedFd::from_raw_fd(pipe[0]), OwnedFd::from_raw_fd(pipe[1])) };
+    // Only the successor may inherit the write end, and only via `pre_exec` below.
+    set_cloexec(ready_rx.as_raw_fd(), true)?;
+    set_cloexec(ready_tx.as_raw_fd(), true)?;
+
+    let spec = handoff
+        .sockets
+        .iter()
+        .map(|(l, name)| match name {
+            Some(name) => format!("{}:{}", l.as_raw_fd(), name),
+            None => l.as_raw_fd().to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join(",");
+    let inherit: Vec<RawFd> = handoff
+        .sockets
+        .iter()
+        .map(|(l, _)| l.as_raw_fd())
+        .chain(std::iter::once(ready_tx.as_raw_fd()))
+        .collect();
+
+    // Built in its own scope: `Command` and `ArgsOs` are not `Send`.
+    let mut child = {
+        // argv[0] rather than current_exe(): the latter still names the old, replaced binary.
+        let mut args = std::env::args_os();
+        let program = args
+            .next()
+            .ok_or_else(|| io::Error::other("cannot determine own program path"))?;
+        let mut cmd = Command::new(program);
+        cmd.args(args)
+            .env(FDS_ENV, spec)
+            .env(READY_FD_ENV, ready_tx.as_raw_fd().to_string())
+            .env(PARENT_PID_ENV, std::process::id().to_string());
+        // SAFETY: only async-signal-safe fcntl calls run between fork and exec.
+        unsafe {
+            cmd.pre_exec(move || {
+                for &fd in &inherit {
+                    set_cloexec(fd, false)?;
+                }
+                Ok(())
+            });
+        }
+        cmd.spawn()?
+    };
+    // Keep only the child's copy of the write end, so EOF means it exited without reporting.
+    drop(ready_tx);
+
+    let mut ready_rx = std::fs::File::from(ready_rx);
+    let wait = tokio::task::spawn_blocking(move || {
+        let mut buf = [0u8; 1];
+        ready_rx.read(&mut buf).map(|n| n == 1)
+    });
+    let failure = match tokio::time::timeout(READY_TIMEOUT, wait).await {
+        Ok(Ok(Ok(true))) => return Ok(child.id()),
+        Ok(Ok(Ok(false))) => "new instance exited before it was ready".to_string(),
+        Ok(Ok(Err(e))) => format!("reading readiness pipe: {}", e),
+        Ok(Err(e)) => format!("readiness wait failed: {}", e),
+        Err(_) => format!("new instance not ready after {:?}", READY_TIMEOUT),
+    };
+
+    let _ = child.kill();
+    let status = tokio::task::spawn_blocking(move || child.wait()).await;
+    if let Ok(Ok(status)) = status {
+        warn!("new instance stopped: {}", status);
+    }
+    Err(io::Error::other(failure))
+}
+
+#[cfg(all(unix, feature = "upgrade"))]
+fn set_cloexec(fd: std::os::fd::RawFd, on: bool) -> std::io::Result<()> {
+    // SAFETY: F_GETFD/F_SETFD only touch the descriptor flags of `fd`.
+    unsafe {
+        let flags = libc::fcntl(fd, libc::F_GETFD);
+        if flags == -1 {
+            return Err(std::io::Error::last_os_error());
+        }
+        let flags = if on {
+            flags | libc::FD_CLOEXEC
+        } else {
+            flags & !libc::FD_CLOEXEC
+        };
+        if libc::fcntl(fd, libc::F_SETFD, flags) == -1 {
+            return Err(std::io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
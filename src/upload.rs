@@ -0,0 +1,33 @@
+//! Request-body forwarding with a bound on how much upload data the proxy holds at once.
+//!
+//! The client body is only read when the upstream connection asks for more, so a slow backend
+//! already slows the client down through TCP flow control. What that leaves unbounded is the
+//! size of a single chunk: hyper may hand over one large read buffer at a time. Splitting
+//! chunks to at most `max_buffered` bytes caps the unsent upload data held per request.
+
+use bytes::Bytes;
+use futures::{Stream, StreamExt, stream};
+use std::io;
+
+/// Re-chunk `body` so no piece is larger than `max_buffered`, reading the next client chunk
+/// only after the previous one has been fully handed to the upstream connection.
+pub fn bounded<S, E>(body: S, max_buffered: usize) -> impl Stream<Item = io::Result<Bytes>>
+where
+    S: Stream<Item = Result<Bytes, E>> + Unpin,
+    E: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    let max = max_buffered.max(1);
+    stream::unfold(
+        (body, Bytes::new()),
+        move |(mut body, mut pending)| async move {
+            while pending.is_empty() {
+                match body.next().await? {
+                    Ok(chunk) => pending = chunk,
+                    Err(e) => return Some((Err(io::Error::other(e)), (body, Bytes::new()))),
+                }
+            }
+            let piece = pending.split_to(pending.len().min(max));
+            Some((Ok(piece), (body, pending)))
+        },
+    )
+}
@@ -0,0 +1,174 @@
+use axum::http::StatusCode;
+use std::error::Error as StdError;
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Category of a failed upstream request, used for status mapping, logs and counters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UpstreamErrorKind {
+    Dns,
+    ConnectionRefused,
+    ConnectionReset,
+    Tls,
+    Timeout,
+    /// The request couldn't be completed at the HTTP level (e.g. sending its body failed).
+    Protocol,
+    /// The backend's response couldn't be parsed: bad status line, invalid headers, ...
+    MalformedResponse,
+    Other,
+}
+
+impl UpstreamErrorKind {
+    pub const ALL: [UpstreamErrorKind; 8] = [
+        UpstreamErrorKind::Dns,
+        UpstreamErrorKind::ConnectionRefused,
+        UpstreamErrorKind::ConnectionReset,
+        UpstreamErrorKind::Tls,
+        UpstreamErrorKind::Timeout,
+        UpstreamErrorKind::Protocol,
+        UpstreamErrorKind::MalformedResponse,
+        UpstreamErrorKind::Other,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            UpstreamErrorKind::Dns => "dns",
+            UpstreamErrorKind::ConnectionRefused => "connection_refused",
+            UpstreamErrorKind::ConnectionReset => "connection_reset",
+            UpstreamErrorKind::Tls => "tls",
+            UpstreamErrorKind::Timeout => "timeout",
+            UpstreamErrorKind::Protocol => "protocol",
+            UpstreamErrorKind::MalformedResponse => "upstream_protocol_error",
+            UpstreamErrorKind::Other => "other",
+        }
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            UpstreamErrorKind::Dns => "DNS resolution failed",
+            UpstreamErrorKind::ConnectionRefused => "connection refused",
+            UpstreamErrorKind::ConnectionReset => "connection reset",
+            UpstreamErrorKind::Tls => "TLS handshake failed",
+            UpstreamErrorKind::Timeout => "timed out",
+            UpstreamErrorKind::Protocol => "protocol error",
+            UpstreamErrorKind::MalformedResponse => "malformed response",
+            UpstreamErrorKind::Other => "error",
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            UpstreamErrorKind::Timeout => StatusCode::GATEWAY_TIMEOUT,
+            _ => StatusCode::BAD_GATEWAY,
+        }
+    }
+}
+
+/// Classify a reqwest error by walking its source chain.
+pub fn classify(err: &reqwest::Error) -> UpstreamErrorKind {
+    if err.is_timeout() {
+        return UpstreamErrorKind::Timeout;
+    }
+
+    if let Some(kind) = classify_chain(err.source()) {
+        return kind;
+    }
+
+    if err.is_connect() {
+        UpstreamErrorKind::Other
+    } else if err.is_request() || err.is_body() || err.is_decode() {
+        UpstreamErrorKind::Protocol
+    } else {
+        UpstreamErrorKind::Other
+    }
+}
+
+/// [`classify`] for errors that need not come from reqwest, such as those of the direct
+/// connections to `send_proxy_protocol` backends.
+pub fn classify_any(err: &(dyn StdError + 'static)) -> UpstreamErrorKind {
+    if let Some(err) = err.downcast_ref::<reqwest::Error>() {
+        return classify(err);
+    }
+    match classify_chain(Some(err)) {
+        Some(kind) => kind,
+        None if err.downcast_ref::<hyper::Error>().is_some() => UpstreamErrorKind::Protocol,
+        None => UpstreamErrorKind::Other,
+    }
+}
+
+// The kind of the first error in the chain from `source` that tells which kind it is.
+fn classify_chain(mut source: Option<&(dyn StdError + 'static)>) -> Option<UpstreamErrorKind> {
+    while let Some(e) = source {
+        if e.downcast_ref::<hyper::Error>()
+            .is_some_and(hyper::Error::is_parse)
+        {
+            return Some(UpstreamErrorKind::MalformedResponse);
+        }
+        if e.downcast_ref::<rustls::Error>().is_some() {
+            return Some(UpstreamErrorKind::Tls);
+        }
+        if let Some(io_err) = e.downcast_ref::<io::Error>() {
+            if io_err
+                .get_ref()
+                .is_some_and(|inner| inner.downcast_ref::<rustls::Error>().is_some())
+            {
+                return Some(UpstreamErrorKind::Tls);
+            }
+            match io_err.kind() {
+                io::ErrorKind::ConnectionRefused => {
+                    return Some(UpstreamErrorKind::ConnectionRefused);
+                }
+                io::ErrorKind::ConnectionReset
+                | io::ErrorKind::ConnectionAborted
+                | io::ErrorKind::BrokenPipe => return Some(UpstreamErrorKind::ConnectionReset),
+                io::ErrorKind::TimedOut => return Some(UpstreamErrorKind::Timeout),
+                _ => {}
+            }
+        }
+        // hyper-util reports resolver failures as a ConnectError with this message
+        if e.to_string().starts_with("dns error") {
+            return Some(UpstreamErrorKind::Dns);
+        }
+        source = e.source();
+    }
+    None
+}
+
+/// `err` followed by each of its sources, joined with `: `.
+///
+/// reqwest's own message only says the request failed; the cause (which header was invalid,
+/// say) is further down the chain.
+pub fn error_chain(err: &dyn StdError) -> String {
+    let mut out = err.to_string();
+    let mut source = err.source();
+    while let Some(e) = source {
+        out.push_str(": ");
+        out.push_str(&e.to_string());
+        source = e.source();
+    }
+    out
+}
+
+/// Response extension marking a 502 sent because the backend's response was malformed, so
+/// `error_pages` can swap in its `upstream_protocol_error` page.
+#[derive(Debug, Clone, Copy)]
+pub struct MalformedResponse;
+
+/// Per-category upstream failure counters.
+#[derive(Debug, Default)]
+pub struct UpstreamErrorCounters {
+    counts: [AtomicU64; UpstreamErrorKind::ALL.len()],
+}
+
+impl UpstreamErrorCounters {
+    pub fn record(&self, kind: UpstreamErrorKind) {
+        self.counts[kind as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> Vec<(&'static str, u64)> {
+        UpstreamErrorKind::ALL
+            .iter()
+            .map(|k| (k.as_str(), self.counts[*k as usize].load(Ordering::Relaxed)))
+            .collect()
+    }
+}